@@ -3,6 +3,7 @@ use raytracing::color::color;
 use raytracing::lights::{Light, PointLight};
 use raytracing::live_preview::{live_preview, Message};
 use raytracing::materials::Phong;
+use raytracing::random::Prng;
 use raytracing::ray::{hit, IntersectionState, Ray};
 use raytracing::shapes::sphere;
 use raytracing::tuple::{point, vector};
@@ -59,8 +60,12 @@ fn main() {
                     n2: 1.0,
                     mat1: None,
                     mat2: None,
+                    texture_coords: None,
+                    vertex_color: None,
                 };
-                let color = obj.material().lighting(light.incoming_at(p), &comps, false);
+                let color =
+                    obj.material()
+                        .lighting(light.incoming_at(p, &mut Prng::new(0)), &comps, false);
                 canvas.set_pixel(i, j, color);
                 tx.send(Message::set_pixel(i, j, color)).unwrap();
             }
@@ -9,7 +9,9 @@ use raytracing::world::World;
 use std::f64::consts::PI;
 use std::fs::File;
 
-fn main() {
+/// The scene of the chapter, which `tests/scheme_reference.rs` compares to the image of the
+/// Scheme implementation
+pub fn scene() -> (World, Camera) {
     let mut world = World::empty();
 
     world.add_light(AmbientLight::new(color(0.1, 0.1, 0.1)));
@@ -82,6 +84,11 @@ fn main() {
     );
     camera.set_allowed_standard_error(1e-2);
 
+    (world, camera)
+}
+
+fn main() {
+    let (world, camera) = scene();
     let image = camera.render_live(&world, "Chapter 8");
 
     let mut f = File::create("pictures/chapter-08.png").unwrap();
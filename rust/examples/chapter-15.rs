@@ -5,7 +5,7 @@ use raytracing::materials::Phong;
 use raytracing::matrix::{rotation_x, rotation_y, scaling, translation};
 use raytracing::obj_loader::ObjParser;
 use raytracing::pattern::checkers_pattern;
-use raytracing::shapes::{build_bounding_tree, plane, Group};
+use raytracing::shapes::{plane, Group};
 use raytracing::tuple::{point, vector};
 use raytracing::world::World;
 use std::f64::consts::PI;
@@ -39,10 +39,9 @@ fn main() {
         .unwrap()
         .read_to_string(&mut data)
         .unwrap();
-    let teapot: Group = ObjParser::parse_str(&data).into();
+    let teapot: Group = ObjParser::parse_str(&data).unwrap().into();
     let teapot = teapot
         .with_transform(rotation_y(PI / 4.0) * rotation_x(-PI / 2.0) * scaling(0.2, 0.2, 0.2));
-    let teapot = build_bounding_tree(teapot, 10);
     world.add_item(teapot);
 
     world.finalize_scene();
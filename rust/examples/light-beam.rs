@@ -1,5 +1,6 @@
 use raytracing::camera::Camera;
 use raytracing::color::color;
+use raytracing::integrators::PhotonMapping;
 use raytracing::lights::{AmbientLight, Beam};
 use raytracing::materials::Phong;
 use raytracing::shapes::plane;
@@ -37,10 +38,6 @@ fn main() {
     camera.set_allowed_standard_error(1e2);
     camera.set_min_samples(10);
 
-    world.enable_direct_illumination(true);
-    world.enable_direct_photon_map(false);
-    world.enable_diffuse_photon_map(false);
-    world.enable_caustic_photon_map(false);
     let image = camera.render_live(&world, "Light Beam example");
     let mut f = File::create("pictures/light-beam-01-trace_direct_only.png").unwrap();
     image.write_png(&mut f).unwrap();
@@ -49,11 +46,13 @@ fn main() {
         image.average_brightness()
     );
 
-    world.enable_direct_illumination(false);
-    world.enable_direct_photon_map(true);
-    world.enable_diffuse_photon_map(false);
-    world.enable_caustic_photon_map(true);
-    world.compute_photon_map(75_000, 1, 0.1);
+    let mut integrator = PhotonMapping::new();
+    integrator.enable_direct_illumination(false);
+    integrator.enable_direct_photon_map(true);
+    integrator.enable_diffuse_photon_map(false);
+    integrator.enable_caustic_photon_map(true);
+    integrator.compute_photon_map(&world, 75_000, 1, 0.1);
+    world.set_integrator(integrator);
     let image = camera.render_live(&world, "Light Beam example");
     let mut f = File::create("pictures/light-beam-02-direct_and_caustic_photons.png").unwrap();
     image.write_png(&mut f).unwrap();
@@ -62,11 +61,13 @@ fn main() {
         image.average_brightness()
     );
 
-    world.enable_direct_illumination(true);
-    world.enable_direct_photon_map(false);
-    world.enable_diffuse_photon_map(true);
-    world.enable_caustic_photon_map(true);
-    world.compute_photon_map(75_000, 1, 0.1);
+    let mut integrator = PhotonMapping::new();
+    integrator.enable_direct_illumination(true);
+    integrator.enable_direct_photon_map(false);
+    integrator.enable_diffuse_photon_map(true);
+    integrator.enable_caustic_photon_map(true);
+    integrator.compute_photon_map(&world, 75_000, 1, 0.1);
+    world.set_integrator(integrator);
     let image = camera.render_live(&world, "Light Beam example");
     let mut f =
         File::create("pictures/light-beam-03_traced_direct_diffuse_and_caustic_photons.png")
@@ -1,7 +1,8 @@
-use rand::{distributions::Distribution, thread_rng};
+use rand::distributions::Distribution;
 use rand_distr::UnitSphere;
 use raytracing::color::color;
 use raytracing::lights::{Beam, DiscLight, Light, PointLight, RealisticPointLight};
+use raytracing::random::Prng;
 use raytracing::tuple::{point, vector, Vector};
 use std::collections::BTreeMap;
 use std::f64::consts::PI;
@@ -68,7 +69,7 @@ fn main() {
         )),
     );
 
-    let rng = &mut thread_rng();
+    let rng = &mut Prng::new(0);
 
     let origin = point(0, 0, 0);
 
@@ -82,14 +83,14 @@ fn main() {
             for l in lights.values_mut() {
                 l.cumulative_intensity_r2 += l
                     .source
-                    .incoming_at(origin + sample_dir * 2.0)
+                    .incoming_at(origin + sample_dir * 2.0, rng)
                     .intensity()
                     .sum()
                     / 3.0
                     * sphere_surface_area(2.0);
                 l.cumulative_intensity_r4 += l
                     .source
-                    .incoming_at(origin + sample_dir * 4)
+                    .incoming_at(origin + sample_dir * 4, rng)
                     .intensity()
                     .sum()
                     / 3.0
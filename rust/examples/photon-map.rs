@@ -1,9 +1,11 @@
 use raytracing::camera::Camera;
 use raytracing::color::color;
+use raytracing::integrators::{PathTracing, PhotonMapping};
 use raytracing::lights::DiscLight;
 use raytracing::materials::Phong;
 use raytracing::matrix::{rotation_x, rotation_y, rotation_z, scaling, translation};
 use raytracing::shapes::{cube, plane, sphere};
+use raytracing::tone_mapping::{ToneCurve, ToneMapper};
 use raytracing::tuple::{point, vector};
 use raytracing::world::World;
 use std::f64::consts::PI;
@@ -77,47 +79,69 @@ fn main() {
     camera.set_allowed_standard_error(1e-2);
     camera.set_min_samples(100);
 
-    world.enable_direct_illumination(true);
-    world.enable_direct_photon_map(false);
-    world.enable_diffuse_photon_map(false);
-    world.enable_caustic_photon_map(false);
-    let image = camera.render_live(&world, "Photon Map Example: direct light only");
-
-    let mut f = File::create("pictures/photon-map-01-trace_direct_only.png").unwrap();
-    image.write_png(&mut f).unwrap();
+    let mut image = camera.render_live(&world, "Photon Map Example: direct light only");
     log::debug!(
         "Average brightness (ray trace): {:?}",
         image.average_brightness()
     );
-
-    world.enable_direct_illumination(false);
-    world.enable_direct_photon_map(true);
-    world.enable_diffuse_photon_map(false);
-    world.enable_caustic_photon_map(true);
-    world.compute_photon_map(75_000_000, 100, 0.1);
-    let image = camera.render_live(&world, "Photon Map Example: direct and caustic photons");
-    let mut f = File::create("pictures/photon-map-02-direct_and_caustic_photons.png").unwrap();
+    // The light is very bright, so that the photons carry enough power. All images get the
+    // exposure of the first one, so that they can be compared.
+    let exposure = ToneMapper::new()
+        .with_auto_exposure(0.18)
+        .exposure_scale(&image);
+    let tone_mapper = ToneMapper::new()
+        .with_exposure(exposure.log2())
+        .with_curve(ToneCurve::Aces)
+        .with_srgb(true);
+    tone_mapper.apply(&mut image);
+    let mut f = File::create("pictures/photon-map-01-trace_direct_only.png").unwrap();
     image.write_png(&mut f).unwrap();
+
+    let mut integrator = PhotonMapping::new();
+    integrator.enable_direct_illumination(false);
+    integrator.enable_direct_photon_map(true);
+    integrator.enable_diffuse_photon_map(false);
+    integrator.enable_caustic_photon_map(true);
+    integrator.compute_photon_map(&world, 75_000_000, 100, 0.1);
+    world.set_integrator(integrator);
+    let mut image = camera.render_live(&world, "Photon Map Example: direct and caustic photons");
     log::debug!(
         "Average brightness (photon map): {:?}",
         image.average_brightness()
     );
+    tone_mapper.apply(&mut image);
+    let mut f = File::create("pictures/photon-map-02-direct_and_caustic_photons.png").unwrap();
+    image.write_png(&mut f).unwrap();
 
-    world.enable_direct_illumination(true);
-    world.enable_direct_photon_map(false);
-    world.enable_diffuse_photon_map(true);
-    world.enable_caustic_photon_map(true);
-    world.compute_photon_map(75_000_000, 100, 0.1);
-    let image = camera.render_live(
+    let mut integrator = PhotonMapping::new();
+    integrator.enable_direct_illumination(true);
+    integrator.enable_direct_photon_map(false);
+    integrator.enable_diffuse_photon_map(true);
+    integrator.enable_caustic_photon_map(true);
+    integrator.compute_photon_map(&world, 75_000_000, 100, 0.1);
+    world.set_integrator(integrator);
+    let mut image = camera.render_live(
         &world,
         "Photon Map Example: direct light and global illumination",
     );
+    log::debug!(
+        "Average brightness (ray + indirect photons): {:?}",
+        image.average_brightness()
+    );
+    tone_mapper.apply(&mut image);
     let mut f =
         File::create("pictures/photon-map-03_traced_direct_diffuse_and_caustic_photons.png")
             .unwrap();
     image.write_png(&mut f).unwrap();
+
+    // Path tracing gives the ground truth that the photon mapped images should match.
+    world.set_integrator(PathTracing::default());
+    let mut image = camera.render_live(&world, "Photon Map Example: path traced reference");
     log::debug!(
-        "Average brightness (ray + indirect photons): {:?}",
+        "Average brightness (path trace): {:?}",
         image.average_brightness()
     );
+    tone_mapper.apply(&mut image);
+    let mut f = File::create("pictures/photon-map-04-path_traced_reference.png").unwrap();
+    image.write_png(&mut f).unwrap();
 }
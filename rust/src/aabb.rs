@@ -42,6 +42,22 @@ impl Aabb {
         self.max_p - self.min_p
     }
 
+    pub fn surface_area(&self) -> f64 {
+        let d = self.size();
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    /// True if the box is neither empty nor infinitely large.
+    pub fn is_finite(&self) -> bool {
+        let d = self.size();
+        d.x().is_finite()
+            && d.y().is_finite()
+            && d.z().is_finite()
+            && d.x() >= 0.0
+            && d.y() >= 0.0
+            && d.z() >= 0.0
+    }
+
     pub fn intersect(&self, r: &Ray) -> Option<(f64, f64)> {
         check_axis(
             self.min_p.x(),
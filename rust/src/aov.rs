@@ -0,0 +1,347 @@
+//! Auxiliary images ("arbitrary output variables") that are rendered along with the color image.
+//!
+//! They help to debug scenes and guide denoisers. Except for the sample count, they describe the
+//! first surface that the ray through the center of a pixel hits:
+//! - `Depth`: the distance from the camera, or infinity for the background
+//! - `Normal`: the world-space normal facing the camera, with x, y and z in the color channels
+//! - `Albedo`: the surface color of the material
+//! - `ObjectId`: 1 + the index of the object in the finalized world, 0 for the background; the
+//!   shapes of a CSG pair are one object
+//! - `MaterialId`: shapes with similar materials share an id, counted from 1 in the order of
+//!   the objects
+//! - `SampleCount`: the number of samples taken by the adaptive multisampling
+
+use crate::canvas::{Canvas, ImageFormat};
+use crate::color::{color, Color, BLACK};
+use crate::materials::Material;
+use crate::ray::Ray;
+use crate::shapes::Shape;
+use crate::world::World;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Aov {
+    Depth,
+    Normal,
+    Albedo,
+    ObjectId,
+    MaterialId,
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::SampleCount,
+    ];
+
+    /// The name used on the command line and in file names
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object-id",
+            Aov::MaterialId => "material-id",
+            Aov::SampleCount => "samples",
+        }
+    }
+}
+
+impl std::str::FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .iter()
+            .copied()
+            .find(|aov| aov.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Aov::ALL.iter().map(|aov| aov.name()).collect();
+                format!("unknown AOV `{}` (expected {})", s, names.join(", "))
+            })
+    }
+}
+
+/// One canvas for each requested AOV, holding the raw values.
+pub struct AovBuffers {
+    buffers: Vec<(Aov, Canvas)>,
+}
+
+impl AovBuffers {
+    pub fn new(aovs: &[Aov], width: u32, height: u32) -> Self {
+        let mut buffers: Vec<(Aov, Canvas)> = vec![];
+        for &aov in aovs {
+            if buffers.iter().all(|(a, _)| *a != aov) {
+                buffers.push((aov, Canvas::new(width, height)));
+            }
+        }
+        AovBuffers { buffers }
+    }
+
+    pub fn aovs(&self) -> impl Iterator<Item = Aov> + '_ {
+        self.buffers.iter().map(|(aov, _)| *aov)
+    }
+
+    pub fn get(&self, aov: Aov) -> Option<&Canvas> {
+        self.buffers
+            .iter()
+            .find(|(a, _)| *a == aov)
+            .map(|(_, canvas)| canvas)
+    }
+
+    /// Drop the buffers of all AOVs that are not in `aovs`.
+    pub fn retain(&mut self, aovs: &[Aov]) {
+        self.buffers.retain(|(aov, _)| aovs.contains(aov));
+    }
+
+    /// Store the values of a pixel, in the order of `aovs()`.
+    pub fn set_pixel(&mut self, x: u32, y: u32, values: &[Color]) {
+        for ((_, canvas), &value) in self.buffers.iter_mut().zip(values) {
+            canvas.set_pixel(x, y, value);
+        }
+    }
+
+    /// An image of an AOV for display: depth is shown from white (near) to black (far),
+    /// normals are mapped from [-1, 1] to [0, 1], every id gets its own color and sample counts
+    /// are relative to the maximum.
+    pub fn visualize(&self, aov: Aov) -> Option<Canvas> {
+        let raw = self.get(aov)?;
+        let max = raw
+            .flat()
+            .map(|c| c.red())
+            .filter(|v| v.is_finite())
+            .fold(0.0, f64::max);
+        let mut image = Canvas::new(raw.width(), raw.height());
+        for (y, row) in raw.rows().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                let value = c.red();
+                let display = match aov {
+                    Aov::Depth if value.is_finite() && max > 0.0 => {
+                        let v = 1.0 - 0.9 * value / max;
+                        color(v, v, v)
+                    }
+                    Aov::Depth => BLACK,
+                    Aov::Normal => (c + color(1, 1, 1)) * 0.5,
+                    Aov::Albedo => c,
+                    Aov::ObjectId | Aov::MaterialId => id_color(value as u32),
+                    Aov::SampleCount => color(value, value, value) / max.max(1.0),
+                };
+                image.set_pixel(x as u32, y as u32, display);
+            }
+        }
+        Some(image)
+    }
+
+    /// Write every AOV next to `path`, inserting its name before the extension (`image.png`
+    /// becomes `image.depth.png` and so on). PNG and PPM files get the visualized AOVs, the
+    /// other formats the raw values.
+    pub fn write_images(&self, path: &Path) -> std::io::Result<()> {
+        let format = ImageFormat::from_path(path)?;
+        for aov in self.aovs() {
+            let path = aov_path(path, aov);
+            match format {
+                ImageFormat::Png | ImageFormat::Ppm => {
+                    self.visualize(aov).unwrap().write_image(&path)?
+                }
+                _ => self.get(aov).unwrap().write_image(&path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The file name of an AOV image next to the image at `path`
+pub fn aov_path(path: &Path, aov: Aov) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}.{}", stem, aov.name());
+    if let Some(extension) = path.extension() {
+        name = format!("{}.{}", name, extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+/// A distinct, stable color for every id, black for 0
+fn id_color(id: u32) -> Color {
+    if id == 0 {
+        return BLACK;
+    }
+    // the golden angle spreads consecutive ids around the color wheel
+    let hue = (id as f64 * 137.507_764) % 360.0;
+    Color::from_hsv(hue, 0.7, 0.95)
+}
+
+/// Computes the AOVs of pixels.
+pub struct AovSampler<'a> {
+    world: &'a World,
+    aovs: Vec<Aov>,
+    /// The object and material ids of every shape, by the address of the shape
+    ids: HashMap<usize, (u32, u32)>,
+}
+
+impl<'a> AovSampler<'a> {
+    pub fn new(world: &'a World, buffers: &AovBuffers) -> Self {
+        let aovs: Vec<_> = buffers.aovs().collect();
+        let mut ids = HashMap::new();
+        if aovs.contains(&Aov::ObjectId) || aovs.contains(&Aov::MaterialId) {
+            let mut materials: Vec<&dyn Material> = vec![];
+            for (i, object) in world.objects().iter().enumerate() {
+                object.for_each_shape(&mut |shape| {
+                    let material = shape.material();
+                    let material_id = match materials.iter().position(|m| m.is_similar(material)) {
+                        Some(id) => id,
+                        None => {
+                            materials.push(material);
+                            materials.len() - 1
+                        }
+                    };
+                    ids.insert(address(shape), (i as u32 + 1, material_id as u32 + 1));
+                });
+            }
+        }
+        AovSampler { world, aovs, ids }
+    }
+
+    /// The values of all AOVs for a pixel, given the ray through its center and the number of
+    /// samples it took
+    pub fn sample(&self, ray: &Ray, samples: u32) -> Vec<Color> {
+        let xs: Vec<_> = self.world.nearest_hit(ray).into_iter().collect();
+        let comps = xs.first().map(|hit| hit.prepare_computations(ray, &xs));
+        let gray = |v: f64| color(v, v, v);
+        self.aovs
+            .iter()
+            .map(|aov| match (aov, &comps) {
+                (Aov::SampleCount, _) => gray(samples as f64),
+                (Aov::Depth, None) => gray(f64::INFINITY),
+                (_, None) => BLACK,
+                (Aov::Depth, Some(comps)) => gray(comps.t),
+                (Aov::Normal, Some(comps)) => {
+                    let n = comps.normalv;
+                    color(n.x(), n.y(), n.z())
+                }
+                (Aov::Albedo, Some(comps)) => comps.obj.material().color_at(comps),
+                (Aov::ObjectId, Some(comps)) => gray(self.id_of(comps.obj).0 as f64),
+                (Aov::MaterialId, Some(comps)) => gray(self.id_of(comps.obj).1 as f64),
+            })
+            .collect()
+    }
+
+    fn id_of(&self, shape: &Shape) -> (u32, u32) {
+        self.ids.get(&address(shape)).copied().unwrap_or((0, 0))
+    }
+}
+
+fn address(shape: &Shape) -> usize {
+    shape as *const Shape as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::materials::Phong;
+    use crate::matrix::translation;
+    use crate::progress::RenderControl;
+    use crate::shapes::sphere;
+    use crate::tuple::{point, vector};
+    use std::f64::consts::PI;
+
+    /// Two red spheres and a blue one, seen from the front
+    fn scene() -> (World, Camera) {
+        let red = Phong::default().with_rgb(1.0, 0.0, 0.0);
+        let blue = Phong::default().with_rgb(0.0, 0.0, 1.0);
+        let mut world = World::default();
+        world.add_item(
+            sphere()
+                .with_material(red.clone())
+                .with_transform(translation(-3, 0, 0)),
+        );
+        world.add_item(
+            sphere()
+                .with_material(blue)
+                .with_transform(translation(3, 0, 0)),
+        );
+        world.add_item(
+            sphere()
+                .with_material(red)
+                .with_transform(translation(0, 3, 0)),
+        );
+        world.finalize_scene();
+        let mut camera = Camera::new(9, 9, PI / 3.0).with_view_transform(
+            point(0, 0, -9),
+            point(0, 0, 0),
+            vector(0, 1, 0),
+        );
+        camera.set_min_samples(2);
+        (world, camera)
+    }
+
+    /// The geometric AOVs describe the surface in the center of the pixel
+    #[test]
+    fn render_aovs() {
+        let (world, camera) = scene();
+        let (image, aovs) = camera.render_with_aovs(&world, &Aov::ALL, &RenderControl::default());
+        assert_eq!(aovs.aovs().collect::<Vec<_>>(), Aov::ALL.to_vec());
+
+        let value = |aov, x, y| aovs.get(aov).unwrap().get_pixel(x, y);
+        // the default world's outer sphere is in the center
+        assert!((value(Aov::Depth, 4, 4).red() - 8.0).abs() < 1e-6);
+        assert!((value(Aov::Normal, 4, 4).blue() + 1.0).abs() < 1e-6);
+        assert_eq!(value(Aov::Albedo, 4, 4), color(0.8, 1.0, 0.6));
+        assert_eq!(value(Aov::ObjectId, 4, 4).red(), 1.0);
+
+        // the red sphere on the left, the blue one on the right and the red one on top
+        let (left, right, top) = ((1, 4), (7, 4), (4, 1));
+        let id = |aov, (x, y)| value(aov, x, y).red();
+        assert_eq!(id(Aov::ObjectId, left), 3.0);
+        assert_eq!(id(Aov::ObjectId, right), 4.0);
+        assert_eq!(id(Aov::ObjectId, top), 5.0);
+        assert_eq!(id(Aov::MaterialId, left), id(Aov::MaterialId, top));
+        assert_ne!(id(Aov::MaterialId, left), id(Aov::MaterialId, right));
+
+        // the corners show the background
+        assert_eq!(value(Aov::Depth, 0, 0).red(), f64::INFINITY);
+        assert_eq!(id(Aov::ObjectId, (0, 0)), 0.0);
+        assert!(value(Aov::SampleCount, 0, 0).red() >= 2.0);
+
+        assert_eq!(image.get_pixel(0, 0), BLACK);
+    }
+
+    /// Visualized AOVs are displayable, raw AOVs are written next to the image
+    #[test]
+    fn visualize_and_write() {
+        let (world, camera) = scene();
+        let (_, aovs) = camera.render_with_aovs(
+            &world,
+            &[Aov::Depth, Aov::ObjectId],
+            &RenderControl::default(),
+        );
+        let depth = aovs.visualize(Aov::Depth).unwrap();
+        assert_eq!(depth.get_pixel(0, 0), BLACK);
+        assert!(depth.get_pixel(4, 4).red() > 0.0);
+        assert_ne!(
+            aovs.visualize(Aov::ObjectId).unwrap().get_pixel(1, 4),
+            aovs.visualize(Aov::ObjectId).unwrap().get_pixel(7, 4)
+        );
+        assert!(aovs.visualize(Aov::Normal).is_none());
+
+        let dir = std::env::temp_dir().join(format!("raytracing-aov-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        aovs.write_images(&dir.join("image.pfm")).unwrap();
+        let depth = Canvas::read_image(dir.join("image.depth.pfm"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(depth.unwrap().get_pixel(0, 0).red(), f64::INFINITY);
+
+        assert_eq!(
+            aov_path(Path::new("out/image.png"), Aov::ObjectId),
+            Path::new("out/image.object-id.png")
+        );
+        assert_eq!("samples".parse(), Ok(Aov::SampleCount));
+        assert!("color".parse::<Aov>().is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use raytracing::aov::Aov;
+use raytracing::camera::Camera;
+use raytracing::canvas::ImageFormat;
+use raytracing::denoise::Denoiser;
+use raytracing::preview::PngPreview;
+use raytracing::progress::{Progress, RenderControl};
+use raytracing::scene::load_scene;
+use raytracing::tiles::{Rect, TiledRender};
+use raytracing::tone_mapping::{ToneCurve, ToneMapper};
+use raytracing::world::World;
+use std::path::Path;
+use std::process::exit;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: render [options] <scene.yml|scene.scm|scene.gltf|scene.glb> <output.png|.ppm|.hdr|.pfm|.exr>
+
+options:
+  --live                  show the image while it is rendered (only logs the progress if
+                          built without the live-preview feature); clicking a pixel prints
+                          the object, material and shadows seen there
+  --preview <file.png>    write the partially rendered image to this file every few seconds
+  --navigate              move the camera in a window first (drag, arrows, WASD, +/-);
+                          P prints the camera for the scene file, clicking prints what is
+                          seen at a pixel, closing the window renders the image from the
+                          new view
+  --progressive           render the whole image with one sample per pixel first, then
+                          refine it in passes; stop it by closing the window
+  --exposure <stops>      brighten (or darken, if negative) the image
+  --auto-exposure         scale the image to an average luminance of middle gray
+  --tone-curve <curve>    clip (default), reinhard, filmic or aces
+  --srgb                  encode the colors for sRGB displays
+  --aov <name>            also write an auxiliary image (depth, normal, albedo, object-id,
+                          material-id or samples) next to the output, e.g. `out.depth.png`;
+                          can be repeated, but not combined with --live or --preview
+  --denoise               smooth the noise of renders with few samples, guided by the
+                          albedo, normal and depth; not combined with --live or --preview
+  --progress              show the progress and the estimated remaining time; not combined
+                          with --live
+  --time-limit <seconds>  stop refining pixels after this time; not combined with --live
+  --region <x,y,w,h>      render only this rectangle of the image, leaving the rest black
+  --tile-size <pixels>    the size of the tiles for --region and --checkpoint (default 32)
+  --checkpoint <file>     save the progress to this file every minute, and resume from it
+                          if it exists
+
+The last three options can't be combined with --live, --preview, --aov or --denoise. Tone mapping only
+applies to PNG and PPM files; the other formats keep the linear colors.";
+
+/// How often --preview writes the image
+const PREVIEW_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often --checkpoint saves the progress
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[cfg(feature = "live-preview")]
+fn navigate_camera(camera: &Camera, world: &World) -> Camera {
+    raytracing::live_preview::navigate(camera, world, "render")
+}
+
+#[cfg(not(feature = "live-preview"))]
+fn navigate_camera(_camera: &Camera, _world: &World) -> Camera {
+    eprintln!("--navigate needs a window; build with the live-preview feature");
+    exit(2);
+}
+
+/// Overwrite the current line of the terminal with the progress
+fn show_progress(progress: &Progress) {
+    let eta = match progress.eta {
+        Some(eta) => format_duration(eta),
+        None => "unknown".to_string(),
+    };
+    eprint!(
+        "\r{:5.1}% of {} pixels, {} samples, {} elapsed, {} left   ",
+        100.0 * progress.fraction(),
+        progress.pixels_total,
+        progress.samples,
+        format_duration(progress.elapsed),
+        eta
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The value of an option, or exit with the usage
+fn option_value<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+    match value.map(|v| v.parse()) {
+        Some(Ok(value)) => value,
+        _ => invalid_option(option),
+    }
+}
+
+/// Exit with the usage for an option whose value can't be used
+fn invalid_option(option: &str) -> ! {
+    eprintln!("invalid or missing value for {}\n{}", option, USAGE);
+    exit(2);
+}
+
+fn main() {
+    pretty_env_logger::init();
+
+    let mut live = false;
+    let mut preview: Option<String> = None;
+    let mut navigate = false;
+    let mut progressive = false;
+    let mut tone_mapper = ToneMapper::new();
+    let mut aovs: Vec<Aov> = vec![];
+    let mut denoise = false;
+    let mut region: Option<Rect> = None;
+    let mut tile_size = 32;
+    let mut checkpoint: Option<String> = None;
+    let mut progress = false;
+    let mut time_limit: Option<Duration> = None;
+    let mut paths = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--live" => live = true,
+            "--preview" => preview = Some(option_value(&arg, args.next())),
+            "--navigate" => navigate = true,
+            "--progressive" => progressive = true,
+            "--exposure" => {
+                tone_mapper = tone_mapper.with_exposure(option_value(&arg, args.next()));
+            }
+            "--auto-exposure" => tone_mapper = tone_mapper.with_auto_exposure(0.18),
+            "--tone-curve" => {
+                let curve: ToneCurve = option_value(&arg, args.next());
+                tone_mapper = tone_mapper.with_curve(curve);
+            }
+            "--srgb" => tone_mapper = tone_mapper.with_srgb(true),
+            "--aov" => aovs.push(option_value(&arg, args.next())),
+            "--denoise" => denoise = true,
+            "--progress" => progress = true,
+            "--time-limit" => match Duration::try_from_secs_f64(option_value(&arg, args.next())) {
+                Ok(limit) => time_limit = Some(limit),
+                Err(_) => invalid_option(&arg),
+            },
+            "--region" => region = Some(option_value(&arg, args.next())),
+            "--tile-size" => tile_size = option_value(&arg, args.next()),
+            "--checkpoint" => checkpoint = Some(option_value(&arg, args.next())),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let tiled = region.is_some() || checkpoint.is_some();
+    let interactive = live || preview.is_some();
+    let conflicting = live && (preview.is_some() || progress || time_limit.is_some())
+        || interactive && (denoise || tiled || !aovs.is_empty())
+        || tiled && (denoise || !aovs.is_empty());
+    let (scene_path, output_path) = match paths.as_slice() {
+        [scene, output] if !conflicting => (Path::new(scene), Path::new(output)),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+
+    let format = match ImageFormat::from_path(output_path) {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("{}: {}", output_path.display(), e);
+            exit(2);
+        }
+    };
+
+    let mut scene = match load_scene(scene_path) {
+        Ok(scene) => scene,
+        Err(e) if e.position().is_some() => {
+            eprintln!("{}:{}", scene_path.display(), e);
+            exit(1);
+        }
+        Err(e) => {
+            eprintln!("{}: {}", scene_path.display(), e);
+            exit(1);
+        }
+    };
+
+    if navigate {
+        scene.camera = navigate_camera(&scene.camera, &scene.world);
+    }
+    if progressive {
+        scene.camera.set_progressive(true);
+    }
+
+    let mut control = RenderControl::new();
+    if progress {
+        control = control.with_observer(&show_progress);
+    }
+    if let Some(limit) = time_limit {
+        control = control.with_time_budget(limit);
+    }
+
+    let mut image = if live {
+        scene.camera.render_live(&scene.world, "render")
+    } else if let Some(path) = &preview {
+        let (width, height) = (scene.camera.hsize(), scene.camera.vsize());
+        let preview = PngPreview::new(path, width, height, PREVIEW_INTERVAL);
+        let (image, _) = scene
+            .camera
+            .render_with_preview(&scene.world, &preview, &control);
+        image
+    } else if tiled {
+        let mut render = TiledRender::new(&scene.camera, &scene.world)
+            .with_tile_size(tile_size)
+            .with_control(control);
+        if let Some(region) = region {
+            render = render.with_region(region);
+        }
+        if let Some(checkpoint) = &checkpoint {
+            render = render.with_checkpoint_file(checkpoint, CHECKPOINT_INTERVAL);
+        }
+        match render.render().and_then(|checkpoint| checkpoint.image()) {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+        }
+    } else if denoise || !aovs.is_empty() {
+        let mut rendered = aovs.clone();
+        if denoise {
+            rendered.extend_from_slice(&Denoiser::GUIDES);
+        }
+        let (mut image, mut buffers) =
+            scene
+                .camera
+                .render_with_aovs(&scene.world, &rendered, &control);
+        if denoise {
+            image = Denoiser::default().denoise(&image, &buffers);
+        }
+        buffers.retain(&aovs);
+        if let Err(e) = buffers.write_images(output_path) {
+            eprintln!("{}: {}", output_path.display(), e);
+            exit(1);
+        }
+        image
+    } else {
+        scene.camera.render_with_control(&scene.world, &control).0
+    };
+    if progress {
+        eprintln!();
+    }
+    if let ImageFormat::Png | ImageFormat::Ppm = format {
+        tone_mapper.apply(&mut image);
+    }
+
+    if let Err(e) = image.write_image(output_path) {
+        eprintln!("{}: {}", output_path.display(), e);
+        exit(1);
+    }
+}
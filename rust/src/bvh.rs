@@ -0,0 +1,649 @@
+//! A bounding volume hierarchy (BVH) over the items of a scene.
+//!
+//! The tree is built top-down. At every node, the items are split into two halves such that
+//! the expected cost of intersecting a random ray with both halves is minimal. This cost is
+//! estimated with the surface area heuristic (SAH): the probability that a ray which hits the
+//! node also hits a child is the ratio of their surface areas.
+//!
+//! The hierarchy only stores indices and bounding boxes; the items themselves are owned by
+//! the world. The nodes are stored in a flat array in depth-first order and traversed with an
+//! explicit stack, which keeps traversal free of allocations and recursion.
+
+use crate::aabb::Aabb;
+use crate::ray::Ray;
+use std::time::{Duration, Instant};
+
+/// Number of candidate split positions per axis
+const N_BINS: usize = 16;
+
+/// Cost of testing a ray against the bounding box of a node, relative to intersecting an item
+const TRAVERSAL_COST: f64 = 0.5;
+
+#[derive(Debug, Clone)]
+pub struct Bvh {
+    /// The nodes in depth-first order; the root comes first.
+    nodes: Vec<Node>,
+    /// Indices of the bounded items, ordered such that every leaf covers a contiguous range
+    items: Vec<usize>,
+    unbounded: Vec<usize>,
+    stats: BvhStats,
+}
+
+/// A node of the flattened tree. The first child of an inner node is stored right after it,
+/// so only the index of the second child is needed.
+#[derive(Debug, Clone)]
+struct Node {
+    aabb: Aabb,
+    /// Index of the first item of a leaf, or of the second child of an inner node
+    offset: usize,
+    /// Number of items in a leaf; zero for inner nodes
+    n_items: usize,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.n_items > 0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BvhStats {
+    /// Number of items in the tree
+    pub n_items: usize,
+    /// Items without finite bounds (such as planes), which are tested against every ray
+    pub n_unbounded: usize,
+    pub n_nodes: usize,
+    pub n_leaves: usize,
+    pub max_depth: usize,
+    pub max_leaf_items: usize,
+    /// Expected cost of a ray that hits the scene bounds, in units of item intersections
+    pub sah_cost: f64,
+    pub build_time: Duration,
+}
+
+impl std::fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} items ({} unbounded), {} nodes, {} leaves with up to {} items, depth {}, SAH cost {:.2}, built in {:?}",
+            self.n_items,
+            self.n_unbounded,
+            self.n_nodes,
+            self.n_leaves,
+            self.max_leaf_items,
+            self.max_depth,
+            self.sah_cost,
+            self.build_time
+        )
+    }
+}
+
+impl Bvh {
+    /// Build a hierarchy over items with the given bounding boxes. Nodes with more than
+    /// `max_leaf_size` items are split, unless their items cannot be separated.
+    pub fn build(aabbs: &[Aabb], max_leaf_size: usize) -> Self {
+        let start = Instant::now();
+        let max_leaf_size = max_leaf_size.max(1);
+
+        let (mut items, unbounded): (Vec<usize>, Vec<usize>) =
+            (0..aabbs.len()).partition(|&i| aabbs[i].is_finite());
+
+        let mut nodes = vec![];
+        if !items.is_empty() {
+            build_node(aabbs, &mut items, 0, max_leaf_size, &mut nodes);
+        }
+
+        let mut bvh = Bvh {
+            nodes,
+            items,
+            unbounded,
+            stats: BvhStats::default(),
+        };
+        bvh.stats = bvh.collect_stats(aabbs.len());
+        bvh.stats.build_time = start.elapsed();
+        bvh
+    }
+
+    pub fn stats(&self) -> &BvhStats {
+        &self.stats
+    }
+
+    /// Call `visit` with the index of every item whose bounding box is hit by the ray. The ray
+    /// is treated as a line, so items behind its origin are visited, too.
+    pub fn traverse(&self, ray: &Ray, mut visit: impl FnMut(usize)) {
+        for &i in &self.unbounded {
+            visit(i);
+        }
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = Stack::new();
+        stack.push(0);
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node.aabb.intersect(ray).is_none() {
+                continue;
+            }
+            if node.is_leaf() {
+                for &i in self.leaf_items(node) {
+                    visit(i);
+                }
+            } else {
+                stack.push(node.offset);
+                stack.push(n + 1);
+            }
+        }
+    }
+
+    /// Find the closest hit in front of the ray origin. `hit` is called with the index of an
+    /// item and returns the distance and data of the item's closest hit at `t >= 0`, if any.
+    ///
+    /// Nodes are visited front to back, and nodes that start beyond the closest hit found so
+    /// far are skipped, so usually only few items near the ray origin are intersected.
+    pub fn nearest<T>(
+        &self,
+        ray: &Ray,
+        mut hit: impl FnMut(usize) -> Option<(f64, T)>,
+    ) -> Option<(f64, T)> {
+        let mut closest: Option<(f64, T)> = None;
+        let mut consider = |i: usize, closest: &mut Option<(f64, T)>| {
+            if let Some((t, data)) = hit(i) {
+                if closest.as_ref().map(|&(tc, _)| t < tc).unwrap_or(true) {
+                    *closest = Some((t, data));
+                }
+            }
+        };
+
+        for &i in &self.unbounded {
+            consider(i, &mut closest);
+        }
+
+        let entry = |n: usize| {
+            self.nodes[n]
+                .aabb
+                .intersect(ray)
+                .filter(|&(_, tmax)| tmax >= 0.0)
+                .map(|(tmin, _)| tmin)
+        };
+
+        let mut stack = Stack::new();
+        if let Some(t) = self.nodes.first().and_then(|_| entry(0)) {
+            stack.push((0, t));
+        }
+        while let Some((n, t_entry)) = stack.pop() {
+            if closest
+                .as_ref()
+                .map(|&(tc, _)| t_entry > tc)
+                .unwrap_or(false)
+            {
+                continue;
+            }
+            let node = &self.nodes[n];
+            if node.is_leaf() {
+                for &i in self.leaf_items(node) {
+                    consider(i, &mut closest);
+                }
+                continue;
+            }
+            // push the farther child first, so that the nearer one is visited next
+            let children = [(n + 1, entry(n + 1)), (node.offset, entry(node.offset))];
+            let (near, far) = match (children[0].1, children[1].1) {
+                (Some(a), Some(b)) if b < a => (children[1], children[0]),
+                _ => (children[0], children[1]),
+            };
+            for &(child, t) in &[far, near] {
+                if let Some(t) = t {
+                    stack.push((child, t));
+                }
+            }
+        }
+
+        closest
+    }
+
+    /// Call `hit` with the index of every item whose bounding box the ray passes within
+    /// `[0, t_max]`, until it returns `true`. Returns whether it did. This answers shadow queries,
+    /// where any blocker will do.
+    pub fn any(&self, ray: &Ray, t_max: f64, mut hit: impl FnMut(usize) -> bool) -> bool {
+        if self.unbounded.iter().any(|&i| hit(i)) {
+            return true;
+        }
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let mut stack = Stack::new();
+        stack.push(0);
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            match node.aabb.intersect(ray) {
+                Some((tmin, tmax)) if tmax >= 0.0 && tmin <= t_max => {}
+                _ => continue,
+            }
+            if node.is_leaf() {
+                if self.leaf_items(node).iter().any(|&i| hit(i)) {
+                    return true;
+                }
+            } else {
+                stack.push(node.offset);
+                stack.push(n + 1);
+            }
+        }
+        false
+    }
+
+    fn leaf_items(&self, leaf: &Node) -> &[usize] {
+        &self.items[leaf.offset..leaf.offset + leaf.n_items]
+    }
+
+    fn collect_stats(&self, n_items: usize) -> BvhStats {
+        let mut stats = BvhStats {
+            n_items,
+            n_unbounded: self.unbounded.len(),
+            n_nodes: self.nodes.len(),
+            ..BvhStats::default()
+        };
+        let root_area = match self.nodes.first() {
+            Some(root) => root.aabb.surface_area(),
+            None => return stats,
+        };
+
+        let mut stack = vec![(0, 1)];
+        while let Some((n, depth)) = stack.pop() {
+            let node = &self.nodes[n];
+            let p_hit = if root_area > 0.0 {
+                node.aabb.surface_area() / root_area
+            } else {
+                1.0
+            };
+            stats.max_depth = stats.max_depth.max(depth);
+            if node.is_leaf() {
+                stats.n_leaves += 1;
+                stats.max_leaf_items = stats.max_leaf_items.max(node.n_items);
+                stats.sah_cost += p_hit * node.n_items as f64;
+            } else {
+                stats.sah_cost += p_hit * TRAVERSAL_COST;
+                stack.push((n + 1, depth + 1));
+                stack.push((node.offset, depth + 1));
+            }
+        }
+        stats
+    }
+}
+
+/// Number of entries a traversal stack holds before it spills to the heap. Balanced trees
+/// over millions of items stay well below.
+const STACK_SIZE: usize = 64;
+
+/// A LIFO stack that lives on the call stack, unless it grows unusually deep.
+struct Stack<T> {
+    fixed: [Option<T>; STACK_SIZE],
+    len: usize,
+    spill: Vec<T>,
+}
+
+impl<T: Copy> Stack<T> {
+    fn new() -> Self {
+        Stack {
+            fixed: [None; STACK_SIZE],
+            len: 0,
+            spill: vec![],
+        }
+    }
+
+    fn push(&mut self, x: T) {
+        if self.len < STACK_SIZE {
+            self.fixed[self.len] = Some(x);
+            self.len += 1;
+        } else {
+            self.spill.push(x);
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if let Some(x) = self.spill.pop() {
+            return Some(x);
+        }
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.fixed[self.len]
+    }
+}
+
+/// Append the subtree over `items` to `nodes` in depth-first order. `first` is the position of
+/// `items` in the item array of the tree.
+fn build_node(
+    aabbs: &[Aabb],
+    items: &mut [usize],
+    first: usize,
+    max_leaf_size: usize,
+    nodes: &mut Vec<Node>,
+) {
+    let leaf = Node {
+        aabb: merge_all(aabbs, items),
+        offset: first,
+        n_items: items.len(),
+    };
+
+    if items.len() <= max_leaf_size {
+        nodes.push(leaf);
+        return;
+    }
+
+    let n_left = match find_split(aabbs, items) {
+        Some((axis, split_position)) => partition(items, |&i| {
+            axis_of(&aabbs[i].center(), axis) < split_position
+        }),
+        None => 0,
+    };
+
+    if n_left == 0 || n_left == items.len() {
+        nodes.push(leaf);
+        return;
+    }
+
+    let this = nodes.len();
+    nodes.push(Node { n_items: 0, ..leaf });
+    let (left, right) = items.split_at_mut(n_left);
+    build_node(aabbs, left, first, max_leaf_size, nodes);
+    nodes[this].offset = nodes.len();
+    build_node(aabbs, right, first + n_left, max_leaf_size, nodes);
+}
+
+/// Find the axis and the position of the split with the lowest SAH cost. The items are sorted
+/// into bins by the centers of their bounding boxes, and only the bin boundaries are
+/// considered. Returns `None` if all centers coincide.
+fn find_split(aabbs: &[Aabb], items: &[usize]) -> Option<(usize, f64)> {
+    let centers = items
+        .iter()
+        .fold(Aabb::empty(), |b, &i| b.extend(aabbs[i].center()));
+
+    let mut best: Option<(f64, usize, f64)> = None;
+
+    for axis in 0..3 {
+        let lo = axis_of(&centers.min_p, axis);
+        let extent = axis_of(&centers.max_p, axis) - lo;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let bin_of = |i: usize| {
+            let x = (axis_of(&aabbs[i].center(), axis) - lo) / extent;
+            ((x * N_BINS as f64) as usize).min(N_BINS - 1)
+        };
+
+        let mut bins = vec![(Aabb::empty(), 0usize); N_BINS];
+        for &i in items {
+            let bin = &mut bins[bin_of(i)];
+            bin.0 = bin.0.merge(&aabbs[i]);
+            bin.1 += 1;
+        }
+
+        // area and count of everything right of each bin boundary
+        let mut right = vec![(0.0, 0); N_BINS];
+        let mut acc = (Aabb::empty(), 0);
+        for b in (1..N_BINS).rev() {
+            acc = (acc.0.merge(&bins[b].0), acc.1 + bins[b].1);
+            right[b] = (acc.0.surface_area(), acc.1);
+        }
+
+        let mut left = (Aabb::empty(), 0);
+        for b in 1..N_BINS {
+            left = (left.0.merge(&bins[b - 1].0), left.1 + bins[b - 1].1);
+            let (right_area, n_right) = right[b];
+            if left.1 == 0 || n_right == 0 {
+                continue;
+            }
+            let cost = left.0.surface_area() * left.1 as f64 + right_area * n_right as f64;
+            if best.map(|(c, _, _)| cost < c).unwrap_or(true) {
+                let position = lo + extent * b as f64 / N_BINS as f64;
+                best = Some((cost, axis, position));
+            }
+        }
+    }
+
+    best.map(|(_, axis, position)| (axis, position))
+}
+
+fn merge_all(aabbs: &[Aabb], items: &[usize]) -> Aabb {
+    items.iter().fold(Aabb::empty(), |b, &i| b.merge(&aabbs[i]))
+}
+
+fn axis_of(p: &crate::tuple::Point, axis: usize) -> f64 {
+    match axis {
+        0 => p.x(),
+        1 => p.y(),
+        _ => p.z(),
+    }
+}
+
+/// Reorder `items` so that all items that satisfy the predicate come first, and return their
+/// number.
+fn partition(items: &mut [usize], pred: impl Fn(&usize) -> bool) -> usize {
+    let mut n = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, n);
+            n += 1;
+        }
+    }
+    n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::tuple::{point, vector};
+    use std::f64::INFINITY;
+
+    fn unit_box_at(x: f64, y: f64, z: f64) -> Aabb {
+        Aabb::new(x - 0.5, x + 0.5, y - 0.5, y + 0.5, z - 0.5, z + 0.5)
+    }
+
+    fn visited(bvh: &Bvh, ray: &Ray) -> Vec<usize> {
+        let mut items = vec![];
+        bvh.traverse(ray, |i| items.push(i));
+        items.sort_unstable();
+        items
+    }
+
+    /// The tree respects the leaf size and covers all items
+    #[test]
+    fn build_statistics() {
+        let aabbs: Vec<_> = (0..100)
+            .map(|i| unit_box_at((i % 10) as f64 * 2.0, (i / 10) as f64 * 2.0, 0.0))
+            .collect();
+        let bvh = Bvh::build(&aabbs, 4);
+        let stats = bvh.stats();
+        assert_eq!(stats.n_items, 100);
+        assert_eq!(stats.n_unbounded, 0);
+        assert!(stats.max_leaf_items <= 4);
+        assert_eq!(stats.n_nodes, 2 * stats.n_leaves - 1);
+        assert!(stats.max_depth <= 10);
+        assert!(stats.sah_cost < 10.0);
+    }
+
+    /// With one item per leaf, traversal visits exactly the items whose boxes the ray line passes
+    /// through
+    #[test]
+    fn traversal() {
+        let aabbs: Vec<_> = (0..64)
+            .map(|i| {
+                unit_box_at(
+                    (i % 4) as f64 * 3.0,
+                    (i / 4 % 4) as f64 * 3.0,
+                    (i / 16) as f64 * 3.0,
+                )
+            })
+            .collect();
+        let bvh = Bvh::build(&aabbs, 1);
+
+        let rays = [
+            Ray::new(point(0, 0, -10), vector(0, 0, 1)),
+            Ray::new(point(0, 0, 20), vector(0, 0, 1)),
+            Ray::new(point(-5, 3, 3), vector(1, 0.1, 0.05)),
+            Ray::new(point(10, 10, 10), vector(-1, -1, -1)),
+            Ray::new(point(100, 100, 100), vector(0, 1, 0)),
+        ];
+        for ray in &rays {
+            let expected: Vec<_> = (0..aabbs.len())
+                .filter(|&i| aabbs[i].intersect(ray).is_some())
+                .collect();
+            assert_eq!(visited(&bvh, ray), expected);
+        }
+    }
+
+    /// Items without finite bounds are visited by every ray
+    #[test]
+    fn unbounded_items() {
+        let aabbs = vec![
+            unit_box_at(0.0, 0.0, 0.0),
+            Aabb::new(-INFINITY, INFINITY, -0.1, 0.1, -INFINITY, INFINITY),
+            Aabb::empty(),
+        ];
+        let bvh = Bvh::build(&aabbs, 1);
+        assert_eq!(bvh.stats().n_unbounded, 2);
+        let ray = Ray::new(point(5, 5, 5), vector(0, 1, 0));
+        assert_eq!(visited(&bvh, &ray), vec![1, 2]);
+    }
+
+    /// The surface area heuristic separates distant clusters first
+    #[test]
+    fn split_clusters() {
+        let mut aabbs: Vec<_> = (0..10)
+            .map(|i| unit_box_at(i as f64 * 0.1, 0.0, 0.0))
+            .collect();
+        aabbs.extend((0..3).map(|i| unit_box_at(100.0 + i as f64 * 0.1, 0.0, 0.0)));
+        let bvh = Bvh::build(&aabbs, 1);
+        let root = &bvh.nodes[0];
+        assert!(!root.is_leaf());
+        assert_almost_eq!(bvh.nodes[1].aabb.max_p.x(), 1.4);
+        assert_almost_eq!(bvh.nodes[root.offset].aabb.min_p.x(), 99.5);
+    }
+
+    /// A hit function for items that are spheres of radius 0.5 around their box centers
+    fn sphere_hit<'a>(
+        aabbs: &'a [Aabb],
+        ray: &'a Ray,
+    ) -> impl Fn(usize) -> Option<(f64, usize)> + 'a {
+        move |i| {
+            let oc = ray.origin() - aabbs[i].center();
+            let d = ray.direction();
+            let (a, b, c) = (d.dot(&d), 2.0 * d.dot(&oc), oc.dot(&oc) - 0.25);
+            let disc = b * b - 4.0 * a * c;
+            if disc < 0.0 {
+                return None;
+            }
+            let ts = [
+                (-b - disc.sqrt()) / (2.0 * a),
+                (-b + disc.sqrt()) / (2.0 * a),
+            ];
+            ts.iter().cloned().find(|&t| t >= 0.0).map(|t| (t, i))
+        }
+    }
+
+    /// The nearest hit agrees with testing all items, and skips items behind the closest hit
+    #[test]
+    fn nearest_hit() {
+        let aabbs: Vec<_> = (0..64)
+            .map(|i| {
+                unit_box_at(
+                    (i % 4) as f64 * 3.0,
+                    (i / 4 % 4) as f64 * 3.0,
+                    (i / 16) as f64 * 3.0,
+                )
+            })
+            .collect();
+        let bvh = Bvh::build(&aabbs, 1);
+
+        let rays = [
+            Ray::new(point(0, 0, -10), vector(0, 0, 1)),
+            Ray::new(point(0, 0, 4), vector(0, 0, 1)),
+            Ray::new(point(9, 9, 20), vector(0, 0, -1)),
+            Ray::new(point(-5, 3, 3), vector(1, 0.01, 0.0)),
+            Ray::new(point(0, 0, 0), vector(1, 1, 1)),
+            Ray::new(point(100, 100, 100), vector(0, 1, 0)),
+        ];
+        for ray in &rays {
+            let hit = sphere_hit(&aabbs, ray);
+            let expected = (0..aabbs.len())
+                .filter_map(|i| hit(i))
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+            let mut n_tested = 0;
+            let nearest = bvh.nearest(ray, |i| {
+                n_tested += 1;
+                hit(i)
+            });
+            assert_eq!(nearest.map(|(_, i)| i), expected.map(|(_, i)| i));
+            assert!(n_tested <= 4, "tested {} items", n_tested);
+        }
+    }
+
+    /// Unbounded items take part in the nearest hit query
+    #[test]
+    fn nearest_hit_unbounded() {
+        let aabbs = vec![
+            unit_box_at(0.0, 0.0, 5.0),
+            Aabb::new(-INFINITY, INFINITY, -INFINITY, INFINITY, 2.0, 2.0),
+        ];
+        let bvh = Bvh::build(&aabbs, 1);
+        let ray = Ray::new(point(0, 0, 0), vector(0, 0, 1));
+        let plane_hit = |i| if i == 1 { Some((2.0, i)) } else { None };
+        assert_eq!(
+            bvh.nearest(&ray, |i| plane_hit(i).or_else(|| Some((4.5, i))))
+                .map(|x| x.1),
+            Some(1)
+        );
+        assert_eq!(
+            bvh.nearest(&ray, |i| Some((4.5, i)).filter(|_| i == 0))
+                .map(|x| x.1),
+            Some(0)
+        );
+    }
+
+    /// The traversal stack spills to the heap when it grows deep, and stays last in first out
+    #[test]
+    fn stack_spill() {
+        let mut stack = Stack::new();
+        for i in 0..STACK_SIZE * 2 {
+            stack.push(i);
+        }
+        stack.push(999);
+        assert_eq!(stack.pop(), Some(999));
+        for i in (0..STACK_SIZE * 2).rev() {
+            assert_eq!(stack.pop(), Some(i));
+        }
+        assert_eq!(stack.pop(), None);
+    }
+
+    /// The any-hit query stops at the first blocker and ignores boxes beyond the maximum
+    /// distance
+    #[test]
+    fn any_hit() {
+        let aabbs: Vec<_> = (0..10)
+            .map(|i| unit_box_at(0.0, 0.0, i as f64 * 2.0))
+            .collect();
+        let bvh = Bvh::build(&aabbs, 1);
+        let ray = Ray::new(point(0, 0, -1), vector(0, 0, 1));
+
+        let mut tested = vec![];
+        assert!(!bvh.any(&ray, 4.0, |i| {
+            tested.push(i);
+            false
+        }));
+        tested.sort_unstable();
+        assert_eq!(tested, vec![0, 1]);
+
+        let mut n_tested = 0;
+        assert!(bvh.any(&ray, INFINITY, |_| {
+            n_tested += 1;
+            true
+        }));
+        assert_eq!(n_tested, 1);
+    }
+}
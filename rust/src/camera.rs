@@ -1,19 +1,34 @@
+use crate::aov::{Aov, AovBuffers, AovSampler};
 use crate::canvas::Canvas;
 use crate::color::{Color, BLACK};
-use crate::live_preview::{live_preview, Message};
+#[cfg(feature = "live-preview")]
+use crate::live_preview::WindowPreview;
 use crate::matrix::Matrix;
+use crate::preview::Preview;
+use crate::progress::{Progress, RenderControl, Tracker};
+use crate::random::Prng;
 use crate::ray::Ray;
+use crate::tiles::PixelStats;
 use crate::tuple::{point, vector, Point, Vector};
 use crate::world::World;
 //use rand::seq::SliceRandom;
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use rayon::prelude::*;
-use std::sync::Mutex;
+
+/// How the camera maps the scene onto the image
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Projection {
+    /// Rays from a single point (or from a lens, with an aperture) through the pixels
+    Perspective,
+    /// Parallel rays from the pixels of a view plane, without any foreshortening
+    Orthographic,
+}
 
 #[derive(Debug, Clone)]
 pub struct Camera {
     hsize: u32,
     vsize: u32,
+    projection: Projection,
     field_of_view: f64,
 
     transform: Matrix,
@@ -28,6 +43,10 @@ pub struct Camera {
 
     focal_distance: f64,
     aperture_size: f64,
+
+    progressive: bool,
+
+    seed: u64,
 }
 
 impl Camera {
@@ -49,6 +68,7 @@ impl Camera {
         Camera {
             hsize,
             vsize,
+            projection: Projection::Perspective,
             field_of_view,
             transform: Matrix::Identity,
             inv_transform: Matrix::Identity,
@@ -59,6 +79,45 @@ impl Camera {
             pixel_min_samples: 5,
             focal_distance: 3e100,
             aperture_size: 0.0,
+            progressive: false,
+            seed: 0,
+        }
+    }
+
+    /// A camera with an orthographic projection. The view plane is `view_width` units wide
+    /// and centered on the origin of the camera space; rays start on it and go along the
+    /// negative z axis, like the center ray of a perspective camera.
+    pub fn orthographic(hsize: u32, vsize: u32, view_width: f64) -> Self {
+        let half_width = view_width / 2.0;
+        Camera {
+            projection: Projection::Orthographic,
+            pixel_size: view_width / hsize as f64,
+            half_width,
+            half_height: half_width * vsize as f64 / hsize as f64,
+            ..Camera::new(hsize, vsize, 0.0)
+        }
+    }
+
+    /// A copy of the camera with another image size and field of view
+    pub fn resized(&self, hsize: u32, vsize: u32, field_of_view: f64) -> Self {
+        self.with_geometry_of(Camera::new(hsize, vsize, field_of_view))
+    }
+
+    /// A copy of the orthographic camera with another image size and view width
+    pub fn resized_orthographic(&self, hsize: u32, vsize: u32, view_width: f64) -> Self {
+        self.with_geometry_of(Camera::orthographic(hsize, vsize, view_width))
+    }
+
+    fn with_geometry_of(&self, geometry: Camera) -> Self {
+        Camera {
+            hsize: geometry.hsize,
+            vsize: geometry.vsize,
+            projection: geometry.projection,
+            field_of_view: geometry.field_of_view,
+            pixel_size: geometry.pixel_size,
+            half_width: geometry.half_width,
+            half_height: geometry.half_height,
+            ..self.clone()
         }
     }
 
@@ -70,10 +129,22 @@ impl Camera {
         self.vsize
     }
 
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// The field of view in radians, across the longer side of the image. Zero for
+    /// orthographic cameras.
     pub fn field_of_view(&self) -> f64 {
         self.field_of_view
     }
 
+    /// The width of the view plane of an orthographic camera. For perspective cameras, the
+    /// width of the image plane at distance one.
+    pub fn view_width(&self) -> f64 {
+        self.half_width * 2.0
+    }
+
     pub fn transform(&self) -> &Matrix {
         &self.transform
     }
@@ -104,6 +175,16 @@ impl Camera {
         self.pixel_min_samples = n;
     }
 
+    /// Set the seed of the random numbers used for sampling pixels. Rendering the same scene
+    /// with the same seed always gives the same image.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
     pub fn pixel_size(&self) -> f64 {
         self.pixel_size
     }
@@ -116,13 +197,25 @@ impl Camera {
         self.focal_distance = d;
     }
 
-    pub fn ray_for_pixel(&self, px: u32, py: u32, randomize: bool) -> Ray {
+    /// In progressive mode, the whole image is rendered with one sample per pixel first. Then
+    /// more passes add a sample to every pixel whose standard error is still too large, until
+    /// all pixels are done or the render is stopped. Previews show every pass, so a render can
+    /// be stopped as soon as it looks good enough. Renders with AOVs or in tiles are never
+    /// progressive.
+    pub fn set_progressive(&mut self, progressive: bool) {
+        self.progressive = progressive;
+    }
+
+    pub fn is_progressive(&self) -> bool {
+        self.progressive
+    }
+
+    pub fn ray_for_pixel(&self, px: u32, py: u32, randomize: bool, rng: &mut Prng) -> Ray {
         let x_offset;
         let y_offset;
         if randomize {
-            let mut rnd = thread_rng();
-            x_offset = (px as f64 + rnd.gen::<f64>()) * self.pixel_size;
-            y_offset = (py as f64 + rnd.gen::<f64>()) * self.pixel_size;
+            x_offset = (px as f64 + rng.gen::<f64>()) * self.pixel_size;
+            y_offset = (py as f64 + rng.gen::<f64>()) * self.pixel_size;
         } else {
             x_offset = (px as f64 + 0.5) * self.pixel_size;
             y_offset = (py as f64 + 0.5) * self.pixel_size;
@@ -130,7 +223,10 @@ impl Camera {
         let world_x = self.half_width - x_offset;
         let world_y = self.half_height - y_offset;
         let pixel = self.inv_transform * point(world_x, world_y, -1.0);
-        let origin = self.inv_transform * point(0.0, 0.0, 0.0);
+        let origin = match self.projection {
+            Projection::Perspective => self.inv_transform * point(0.0, 0.0, 0.0),
+            Projection::Orthographic => self.inv_transform * point(world_x, world_y, 0.0),
+        };
         let primary_ray = Ray::new(origin, (pixel - origin).normalized());
 
         if self.aperture_size == 0.0 {
@@ -138,11 +234,15 @@ impl Camera {
         } else {
             let focal_point = primary_ray.position(self.focal_distance);
 
-            let mut rnd = thread_rng();
-            let ap_pixel = pixel
+            // parallel rays have their lens on the view plane
+            let lens = match self.projection {
+                Projection::Perspective => pixel,
+                Projection::Orthographic => origin,
+            };
+            let ap_pixel = lens
                 + vector(
-                    (rnd.gen::<f64>() - 0.5) * self.aperture_size,
-                    (rnd.gen::<f64>() - 0.5) * self.aperture_size,
+                    (rng.gen::<f64>() - 0.5) * self.aperture_size,
+                    (rng.gen::<f64>() - 0.5) * self.aperture_size,
                     0.0,
                 );
             let secondary_ray = Ray::new(ap_pixel, (focal_point - ap_pixel).normalized());
@@ -152,35 +252,182 @@ impl Camera {
     }
 
     pub fn render(&self, world: &World) -> Canvas {
+        self.render_with_control(world, &RenderControl::default()).0
+    }
+
+    /// Render with progress reports, cancellation or a time budget (see `progress`). Returns
+    /// the image and the final progress.
+    pub fn render_with_control(
+        &self,
+        world: &World,
+        control: &RenderControl,
+    ) -> (Canvas, Progress) {
+        let tracker = self.start_tracking(control);
+        let canvas = self.render_tracked(world, &tracker, |_, _, _| ());
+        (canvas, tracker.finish())
+    }
+
+    fn render_tracked(
+        &self,
+        world: &World,
+        tracker: &Tracker,
+        pixel_callback: impl Sync + Fn(u32, u32, Color),
+    ) -> Canvas {
+        if self.progressive {
+            return self.render_progressive(world, tracker, pixel_callback);
+        }
         let mut canvas = Canvas::new(self.hsize, self.vsize);
-        for (x, y, c) in self.trace_pixels(world, |_, _, _| ()) {
+        for (x, y, c) in self.trace_tracked_pixels(world, tracker, pixel_callback) {
             canvas.add_to_pixel(x, y, c);
         }
         canvas
     }
 
-    pub fn render_live(&self, world: &World, window_name: &'static str) -> Canvas {
+    /// Render in passes over all unfinished pixels, see `set_progressive`. `pixel_callback`
+    /// gets the new average color of every pixel that was sampled in a pass.
+    fn render_progressive(
+        &self,
+        world: &World,
+        tracker: &Tracker,
+        pixel_callback: impl Sync + Fn(u32, u32, Color),
+    ) -> Canvas {
+        let width = self.hsize as usize;
+        let mut pixels: Vec<(PixelStats, Prng)> = (0..self.vsize as u64)
+            .flat_map(|y| (0..width as u64).map(move |x| (x, y)))
+            .map(|(x, y)| (PixelStats::default(), Prng::stream(self.seed, &[x, y])))
+            .collect();
+        let mut unfinished = vec![true; pixels.len()];
+        let max_variance = self.pixel_allowed_standard_error * self.pixel_allowed_standard_error;
+
+        let mut first_pass = true;
+        while unfinished.contains(&true) && (first_pass || tracker.may_refine()) {
+            let samples = pixels
+                .par_iter_mut()
+                .zip(unfinished.par_iter_mut())
+                .enumerate()
+                .filter(|(_, (_, unfinished))| **unfinished && !tracker.is_cancelled())
+                .map(|(i, ((stats, rng), unfinished))| {
+                    let (x, y) = ((i % width) as u32, (i / width) as u32);
+                    // the first sample goes through the center of the pixel, like in
+                    // `pixel_stats`
+                    let ray = self.ray_for_pixel(x, y, stats.samples > 0, rng);
+                    stats.add(world.trace(&ray, rng).unwrap_or(BLACK));
+                    pixel_callback(x, y, stats.mean());
+                    if stats.samples >= self.pixel_min_samples as u32
+                        && stats.variance_of_mean() <= max_variance
+                    {
+                        *unfinished = false;
+                        tracker.pixel_done(0);
+                    }
+                })
+                .count();
+            tracker.samples_done(samples as u64);
+            first_pass = false;
+        }
+
         let mut canvas = Canvas::new(self.hsize, self.vsize);
-        let (h, tx) = live_preview(self.hsize, self.vsize, window_name);
-        // It sucks to wrap the Sender in a Mutex. Ideally, each thread would have one copy
-        // of tx, but there does not seem to be an easy way to accomplish this with Rayon.
-        let tx = Mutex::new(tx);
-        for (x, y, c) in self.trace_pixels(world, move |x, y, c| {
-            tx.lock()
-                .unwrap()
-                .send(Message::set_pixel(x, y, c))
-                .unwrap();
-        }) {
-            canvas.add_to_pixel(x, y, c);
+        for (target, (stats, _)) in canvas.rows_mut().flatten().zip(&pixels) {
+            *target = stats.mean();
         }
-        h.join().unwrap();
         canvas
     }
 
+    fn start_tracking<'a>(&self, control: &'a RenderControl) -> Tracker<'a> {
+        control.start(self.hsize as u64 * self.vsize as u64, 0, 0, 0)
+    }
+
+    /// Render the image together with the auxiliary buffers `aovs`.
+    pub fn render_with_aovs(
+        &self,
+        world: &World,
+        aovs: &[Aov],
+        control: &RenderControl,
+    ) -> (Canvas, AovBuffers) {
+        let mut canvas = Canvas::new(self.hsize, self.vsize);
+        let mut buffers = AovBuffers::new(aovs, self.hsize, self.vsize);
+        let sampler = AovSampler::new(world, &buffers);
+        let tracker = self.start_tracking(control);
+        let coordinates: Vec<_> = (0..self.vsize)
+            .flat_map(|y| (0..self.hsize).map(move |x| (x, y)))
+            .collect();
+        let pixels: Vec<_> = coordinates
+            .par_iter()
+            .filter(|_| !tracker.is_cancelled())
+            .map(|&(x, y)| {
+                let stats = self.pixel_stats(x, y, world, &tracker);
+                tracker.pixel_done(stats.samples);
+                let rng = &mut Prng::new(self.seed);
+                let ray = self.ray_for_pixel(x, y, false, rng);
+                (x, y, stats.mean(), sampler.sample(&ray, stats.samples))
+            })
+            .collect();
+        for (x, y, color, values) in pixels {
+            canvas.set_pixel(x, y, color);
+            buffers.set_pixel(x, y, &values);
+        }
+        (canvas, buffers)
+    }
+
+    /// Render while showing the image in a window. Closing the window cancels the render.
+    #[cfg(feature = "live-preview")]
+    pub fn render_live(&self, world: &World, window_name: &'static str) -> Canvas {
+        let preview =
+            WindowPreview::new(self.hsize, self.vsize, window_name).with_picking(self, world);
+        self.render_with_preview(world, &preview, &RenderControl::default())
+            .0
+    }
+
+    /// Without the `live-preview` feature there is no window; the progress is logged instead.
+    #[cfg(not(feature = "live-preview"))]
+    pub fn render_live(&self, world: &World, window_name: &'static str) -> Canvas {
+        let log_progress = |progress: &Progress| {
+            log::info!(
+                "{}: {:.0}% done after {:.0?}",
+                window_name,
+                100.0 * progress.fraction(),
+                progress.elapsed
+            )
+        };
+        let control = RenderControl::new()
+            .with_observer(&log_progress)
+            .with_report_interval(std::time::Duration::from_secs(5));
+        self.render_with_control(world, &control).0
+    }
+
+    /// Render while passing the finished pixels to `preview`. Closing the preview cancels the
+    /// render.
+    pub fn render_with_preview(
+        &self,
+        world: &World,
+        preview: &dyn Preview,
+        control: &RenderControl,
+    ) -> (Canvas, Progress) {
+        let tracker = self.start_tracking(control);
+        let canvas = self.render_tracked(world, &tracker, |x, y, c| {
+            preview.set_pixel(x, y, c);
+            if preview.is_closed() {
+                tracker.cancel();
+            }
+        });
+        preview.finish();
+        (canvas, tracker.finish())
+    }
+
     pub fn trace_pixels(
         &self,
         world: &World,
         pixel_callback: impl Sync + Fn(u32, u32, Color),
+    ) -> Vec<(u32, u32, Color)> {
+        let control = RenderControl::default();
+        self.trace_tracked_pixels(world, &self.start_tracking(&control), pixel_callback)
+    }
+
+    /// Trace all pixels, except those left when the render is cancelled
+    fn trace_tracked_pixels(
+        &self,
+        world: &World,
+        tracker: &Tracker,
+        pixel_callback: impl Sync + Fn(u32, u32, Color),
     ) -> Vec<(u32, u32, Color)> {
         let coordinates: Vec<_> = (0..self.vsize)
             .flat_map(|y| (0..self.hsize).map(move |x| (x, y)))
@@ -189,28 +436,42 @@ impl Camera {
 
         coordinates
             .par_iter()
-            .map(|&(x, y)| (x, y, self.multisample(x, y, world)))
+            .filter(|_| !tracker.is_cancelled())
+            .map(|&(x, y)| {
+                let stats = self.pixel_stats(x, y, world, tracker);
+                tracker.pixel_done(stats.samples);
+                (x, y, stats.mean())
+            })
             .inspect(|&(x, y, color)| pixel_callback(x, y, color))
             .collect()
     }
 
     /*fn simple_sample(&self, x: u32, y: u32, world: &World) -> Color {
         world
-            .trace(&self.ray_for_pixel(x, y, false))
+            .trace(&self.ray_for_pixel(x, y, false, rng), rng)
             .unwrap_or(BLACK)
     }*/
 
-    fn multisample(&self, x: u32, y: u32, world: &World) -> Color {
+    /// Sample a pixel until the standard error of its color is small enough, or the tracker
+    /// tells to stop refining.
+    pub(crate) fn pixel_stats(
+        &self,
+        x: u32,
+        y: u32,
+        world: &World,
+        tracker: &Tracker,
+    ) -> PixelStats {
+        let rng = &mut Prng::stream(self.seed, &[x as u64, y as u64]);
         let c = world
-            .trace(&self.ray_for_pixel(x, y, false))
+            .trace(&self.ray_for_pixel(x, y, false, rng), rng)
             .unwrap_or(BLACK);
         let mut color_sum_of_squares = c;
         let mut color_sum = c;
         let mut n = 1.0;
 
-        while n < self.pixel_min_samples as f64 {
+        while n < self.pixel_min_samples as f64 && tracker.may_refine() {
             let c = world
-                .trace(&self.ray_for_pixel(x, y, true))
+                .trace(&self.ray_for_pixel(x, y, true, rng), rng)
                 .unwrap_or(BLACK);
             color_sum = color_sum + c;
             color_sum_of_squares = color_sum_of_squares + c * c;
@@ -219,16 +480,21 @@ impl Camera {
 
         while color_variance_of_mean(n, color_sum, color_sum_of_squares)
             > self.pixel_allowed_standard_error * self.pixel_allowed_standard_error
+            && tracker.may_refine()
         {
             let c = world
-                .trace(&self.ray_for_pixel(x, y, true))
+                .trace(&self.ray_for_pixel(x, y, true, rng), rng)
                 .unwrap_or(BLACK);
             color_sum = color_sum + c;
             color_sum_of_squares = color_sum_of_squares + c * c;
             n += 1.0;
         }
 
-        color_sum / n
+        PixelStats {
+            sum: color_sum,
+            sum_of_squares: color_sum_of_squares,
+            samples: n as u32,
+        }
     }
 }
 
@@ -280,7 +546,7 @@ mod tests {
     #[test]
     fn ray_center() {
         let c = Camera::new(201, 101, PI / 2.0);
-        let r = c.ray_for_pixel(100, 50, false);
+        let r = c.ray_for_pixel(100, 50, false, &mut Prng::new(0));
         assert_almost_eq!(r.origin(), point(0, 0, 0));
         assert_almost_eq!(r.direction(), vector(0, 0, -1));
     }
@@ -289,7 +555,7 @@ mod tests {
     #[test]
     fn ray_corner() {
         let c = Camera::new(201, 101, PI / 2.0);
-        let r = c.ray_for_pixel(0, 0, false);
+        let r = c.ray_for_pixel(0, 0, false, &mut Prng::new(0));
         assert_almost_eq!(r.origin(), point(0, 0, 0));
         assert_almost_eq!(r.direction(), vector(0.66519, 0.33259, -0.66851));
     }
@@ -299,11 +565,59 @@ mod tests {
     fn ray_transformed() {
         let mut c = Camera::new(201, 101, PI / 2.0);
         c.set_transform(rotation_y(PI / 4.0) * translation(0, -2, 5));
-        let r = c.ray_for_pixel(100, 50, false);
+        let r = c.ray_for_pixel(100, 50, false, &mut Prng::new(0));
         assert_almost_eq!(r.origin(), point(0, 2, -5));
         assert_almost_eq!(r.direction(), vector(FRAC_1_SQRT_2, 0, -FRAC_1_SQRT_2));
     }
 
+    /// Orthographic rays are parallel and start on a view plane of the given width
+    #[test]
+    fn ray_orthographic() {
+        let mut c = Camera::orthographic(201, 101, 4.02);
+        assert_eq!(c.projection(), Projection::Orthographic);
+        assert_almost_eq!(c.pixel_size(), 0.02);
+        assert_almost_eq!(c.view_width(), 4.02);
+        let r = c.ray_for_pixel(100, 50, false, &mut Prng::new(0));
+        assert_almost_eq!(r.origin(), point(0, 0, 0));
+        assert_almost_eq!(r.direction(), vector(0, 0, -1));
+        let r = c.ray_for_pixel(0, 0, false, &mut Prng::new(0));
+        assert_almost_eq!(r.origin(), point(2, 1, 0));
+        assert_almost_eq!(r.direction(), vector(0, 0, -1));
+
+        c.set_transform(rotation_y(PI / 4.0) * translation(0, -2, 5));
+        let r = c.ray_for_pixel(0, 50, false, &mut Prng::new(0));
+        assert_almost_eq!(
+            r.origin(),
+            point(2.0 * FRAC_1_SQRT_2, 2, -5.0 + 2.0 * FRAC_1_SQRT_2)
+        );
+        assert_almost_eq!(r.direction(), vector(FRAC_1_SQRT_2, 0, -FRAC_1_SQRT_2));
+
+        let resized = c.resized_orthographic(20, 10, 2.0);
+        assert_eq!(resized.projection(), Projection::Orthographic);
+        assert_almost_eq!(resized.pixel_size(), 0.1);
+        assert_almost_eq!(*resized.transform(), *c.transform());
+    }
+
+    /// Spheres keep their size in an orthographic image, wherever they are
+    #[test]
+    fn render_orthographic() {
+        let mut c = Camera::orthographic(20, 10, 4.0).with_view_transform(
+            point(0, 0, -5),
+            point(0, 0, 0),
+            vector(0, 1, 0),
+        );
+        c.set_min_samples(4);
+        let image = c.render(&World::default());
+        let covered = |y: u32| {
+            (0..20)
+                .filter(|&x| !image.get_pixel(x, y).is_black())
+                .count()
+        };
+        // the unit sphere covers the middle 2 of 4 units
+        assert_eq!(covered(5), 10);
+        assert!(image.get_pixel(0, 0).is_black());
+    }
+
     /// Rendering a world with the camera
     #[test]
     fn render() {
@@ -317,4 +631,36 @@ mod tests {
         let image = c.render(&World::default());
         assert_almost_eq!(image.get_pixel(5, 5), color(0.38066, 0.47583, 0.2855))
     }
+
+    /// Progressive rendering refines all pixels to the same error as normal rendering
+    #[test]
+    fn render_progressive() {
+        let mut c = Camera::new(11, 11, PI / 2.0).with_view_transform(
+            point(0, 0, -5),
+            point(0, 0, 0),
+            vector(0, 1, 0),
+        );
+        c.set_allowed_standard_error(0.01);
+        let world = World::default();
+        let expected = c.render(&world);
+        c.set_progressive(true);
+
+        let updates = std::sync::atomic::AtomicUsize::new(0);
+        let (image, progress) = c.render_with_control(&world, &RenderControl::default());
+        assert_eq!(progress.pixels_done, 121);
+        assert!(progress.samples >= 5 * 121);
+        for (a, b) in image.flat().zip(expected.flat()) {
+            assert!((a - b).sum().abs() < 0.1);
+        }
+
+        // without time, there is a single pass with one sample per pixel
+        let control = RenderControl::new().with_time_budget(std::time::Duration::from_secs(0));
+        let tracker = c.start_tracking(&control);
+        let image = c.render_tracked(&world, &tracker, |_, _, _| {
+            updates.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        assert_eq!(tracker.finish().samples, 121);
+        assert_eq!(updates.into_inner(), 121);
+        assert_almost_eq!(image.get_pixel(5, 5), color(0.38066, 0.47583, 0.2855));
+    }
 }
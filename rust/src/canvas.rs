@@ -1,10 +1,11 @@
 use crate::color::{color, Color, BLACK};
-use std::io::Write;
+use std::io::{Error, ErrorKind, Read, Write};
 
 pub fn canvas(w: u32, h: u32) -> Canvas {
     Canvas::new(w, h)
 }
 
+#[derive(Clone)]
 pub struct Canvas {
     width: u32,
     height: u32,
@@ -13,11 +14,23 @@ pub struct Canvas {
 
 impl Canvas {
     pub fn new(width: u32, height: u32) -> Self {
-        Canvas {
+        Canvas::try_new(width, height).expect("canvas too large")
+    }
+
+    /// A black canvas, or an error instead of aborting if there is not enough memory for it
+    pub fn try_new(width: u32, height: u32) -> std::io::Result<Self> {
+        let too_large = || Error::new(ErrorKind::OutOfMemory, "canvas too large");
+        let n = (width as usize)
+            .checked_mul(height as usize)
+            .ok_or_else(too_large)?;
+        let mut data = vec![];
+        data.try_reserve_exact(n).map_err(|_| too_large())?;
+        data.resize(n, color(0, 0, 0));
+        Ok(Canvas {
             width,
             height,
-            data: vec![color(0, 0, 0); (width * height) as usize],
-        }
+            data,
+        })
     }
 
     pub fn width(&self) -> u32 {
@@ -54,12 +67,24 @@ impl Canvas {
         self.rows().skip(y as usize).next().unwrap()[x as usize]
     }
 
+    /// The rows from top to bottom. An empty canvas has none, even if only one side is zero.
     pub fn rows(&self) -> impl Iterator<Item = &[Color]> + '_ {
-        self.data.chunks_exact(self.width as usize)
+        self.data.chunks_exact(self.width.max(1) as usize)
     }
 
     pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [Color]> + '_ {
-        self.data.chunks_exact_mut(self.width as usize)
+        self.data.chunks_exact_mut(self.width.max(1) as usize)
+    }
+
+    /// Image files cannot hold an empty canvas
+    fn check_writable(&self) -> std::io::Result<()> {
+        if self.data.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "an empty canvas cannot be written",
+            ));
+        }
+        Ok(())
     }
 
     pub fn flat(&self) -> impl Iterator<Item = Color> + '_ {
@@ -67,6 +92,7 @@ impl Canvas {
     }
 
     pub fn write_ppm(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        self.check_writable()?;
         let mut line_guard = MaxWidthWriter::new(70, writer);
         self.write_ppm_header(&mut line_guard)?;
         self.write_ppm_data(&mut line_guard)
@@ -93,6 +119,7 @@ impl Canvas {
     }
 
     pub fn write_png(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        self.check_writable()?;
         let mut encoder = png::Encoder::new(writer, self.width, self.height);
         encoder.set_color(png::ColorType::RGB);
         encoder.set_depth(png::BitDepth::Eight);
@@ -108,6 +135,700 @@ impl Canvas {
         writer.write_image_data(&data).unwrap();
         Ok(())
     }
+
+    /// Write a Radiance RGBE image. Unlike PNG and PPM files, it keeps colors brighter than 1.
+    pub fn write_hdr(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        self.check_writable()?;
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+        let run_length_encoded = (8..0x8000).contains(&self.width);
+        for row in self.rows() {
+            let pixels: Vec<[u8; 4]> = row.iter().map(|&c| to_rgbe(c)).collect();
+            if !run_length_encoded {
+                for pixel in &pixels {
+                    writer.write_all(pixel)?;
+                }
+                continue;
+            }
+            writer.write_all(&[2, 2, (self.width >> 8) as u8, self.width as u8])?;
+            for channel in 0..4 {
+                let values: Vec<u8> = pixels.iter().map(|p| p[channel]).collect();
+                write_rle(writer, &values)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a Radiance RGBE image with flat or run-length encoded scanlines in the standard
+    /// orientation (`-Y height +X width`).
+    pub fn read_hdr(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let mut lines = bytes.split(|&b| b == b'\n');
+        let mut pos = 0;
+        let mut next_line = || {
+            let line = lines.next()?;
+            pos += line.len() + 1;
+            Some(String::from_utf8_lossy(line).into_owned())
+        };
+
+        if !next_line().is_some_and(|magic| magic.starts_with("#?")) {
+            return Err(invalid_data("not a Radiance HDR file"));
+        }
+        loop {
+            match next_line() {
+                None => return Err(invalid_data("unexpected end of file")),
+                Some(line) if line.is_empty() => break,
+                Some(line) if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" => {
+                    return Err(invalid_data("unsupported pixel format"))
+                }
+                Some(_) => {}
+            }
+        }
+        let resolution = next_line().unwrap_or_default();
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", h, "+X", w] => match (h.parse(), w.parse()) {
+                (Ok(h), Ok(w)) => (h, w),
+                _ => return Err(invalid_data("invalid image size")),
+            },
+            _ => return Err(invalid_data("unsupported image orientation")),
+        };
+
+        let mut data = bytes.get(pos..).unwrap_or(&[]);
+        // run-length encoded scanlines take at least 8 bytes for every 127 pixels
+        if pixel_count(width, height)? / 16 > data.len() {
+            return Err(invalid_data("unexpected end of pixel data"));
+        }
+        let mut canvas = Canvas::new(width, height);
+        let width = width as usize;
+        let mut pixels = vec![[0; 4]; width];
+        for row in canvas.data.chunks_exact_mut(width) {
+            let is_rle = (8..0x8000).contains(&width)
+                && data.len() >= 4
+                && data[..2] == [2, 2]
+                && ((data[2] as usize) << 8 | data[3] as usize) == width;
+            if is_rle {
+                data = &data[4..];
+                for channel in 0..4 {
+                    data = read_rle(data, &mut pixels, channel)?;
+                }
+            } else {
+                if data.len() < 4 * width {
+                    return Err(invalid_data("unexpected end of pixel data"));
+                }
+                for (pixel, rgbe) in pixels.iter_mut().zip(data.chunks_exact(4)) {
+                    pixel.copy_from_slice(rgbe);
+                }
+                data = &data[4 * width..];
+            }
+            for (c, &rgbe) in row.iter_mut().zip(&pixels) {
+                *c = from_rgbe(rgbe);
+            }
+        }
+        Ok(canvas)
+    }
+
+    /// Write a little-endian color PFM image of 32-bit floats.
+    pub fn write_pfm(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        self.check_writable()?;
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        // the rows are stored from the bottom to the top
+        for row in self.data.chunks_exact(self.width as usize).rev() {
+            for c in row {
+                for &value in &[c.red(), c.green(), c.blue()] {
+                    writer.write_all(&(value as f32).to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a color (`PF`) or grayscale (`Pf`) PFM image of either byte order.
+    pub fn read_pfm(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let mut pfm = PpmTokens {
+            bytes: &bytes,
+            pos: 0,
+        };
+
+        let channels = match pfm.token()? {
+            b"PF" => 3,
+            b"Pf" => 1,
+            _ => return Err(invalid_data("not a PFM file")),
+        };
+        let width = pfm.number()?;
+        let height = pfm.number()?;
+        let scale: f64 = std::str::from_utf8(pfm.token()?)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid_data("expected a number"))?;
+        let little_endian = scale < 0.0;
+
+        let n = pixel_count(width, height)?
+            .checked_mul(channels)
+            .ok_or_else(|| invalid_data("image too large"))?;
+        let data = bytes.get(pfm.pos + 1..).unwrap_or(&[]);
+        if data.len() / 4 < n {
+            return Err(invalid_data("unexpected end of pixel data"));
+        }
+        let samples: Vec<f64> = data
+            .chunks_exact(4)
+            .take(n)
+            .map(|b| {
+                let b = [b[0], b[1], b[2], b[3]];
+                if little_endian {
+                    f32::from_le_bytes(b) as f64
+                } else {
+                    f32::from_be_bytes(b) as f64
+                }
+            })
+            .collect();
+
+        let mut canvas = Canvas::new(width, height);
+        let rows = samples.chunks_exact(width as usize * channels);
+        for (row, samples) in canvas.data.chunks_exact_mut(width as usize).rev().zip(rows) {
+            for (pixel, s) in row.iter_mut().zip(samples.chunks_exact(channels)) {
+                *pixel = match s {
+                    [gray] => color(*gray, *gray, *gray),
+                    _ => color(s[0], s[1], s[2]),
+                };
+            }
+        }
+        Ok(canvas)
+    }
+
+    /// Write an uncompressed OpenEXR image with 32-bit float `R`, `G` and `B` channels.
+    pub fn write_exr(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        self.check_writable()?;
+        let mut header = vec![];
+        header.extend_from_slice(&EXR_MAGIC.to_le_bytes());
+        header.extend_from_slice(&2u32.to_le_bytes());
+
+        let mut channels = vec![];
+        for name in &[b"B", b"G", b"R"] {
+            channels.extend_from_slice(&name[..]);
+            channels.push(0);
+            channels.extend_from_slice(&EXR_FLOAT.to_le_bytes());
+            channels.extend_from_slice(&[0; 4]);
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+        let window: Vec<u8> = [0, 0, self.width as i32 - 1, self.height as i32 - 1]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+        let attributes: [(&str, &str, &[u8]); 8] = [
+            ("channels", "chlist", &channels),
+            ("compression", "compression", &[0]),
+            ("dataWindow", "box2i", &window),
+            ("displayWindow", "box2i", &window),
+            ("lineOrder", "lineOrder", &[0]),
+            ("pixelAspectRatio", "float", &1.0f32.to_le_bytes()),
+            ("screenWindowCenter", "v2f", &[0; 8]),
+            ("screenWindowWidth", "float", &1.0f32.to_le_bytes()),
+        ];
+        for (name, kind, value) in &attributes {
+            header.extend_from_slice(name.as_bytes());
+            header.push(0);
+            header.extend_from_slice(kind.as_bytes());
+            header.push(0);
+            header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            header.extend_from_slice(value);
+        }
+        header.push(0);
+        writer.write_all(&header)?;
+
+        // one scanline per chunk, each with its y coordinate and size
+        let line_size = 3 * 4 * self.width as usize;
+        let first_line = header.len() + 8 * self.height as usize;
+        for y in 0..self.height as usize {
+            let offset = first_line + y * (8 + line_size);
+            writer.write_all(&(offset as u64).to_le_bytes())?;
+        }
+        for (y, row) in self.rows().enumerate() {
+            writer.write_all(&(y as i32).to_le_bytes())?;
+            writer.write_all(&(line_size as i32).to_le_bytes())?;
+            for channel in &[Color::blue, Color::green, Color::red] {
+                for c in row {
+                    writer.write_all(&(channel(c) as f32).to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read an uncompressed single-part scanline OpenEXR image. The `R`, `G` and `B` channels
+    /// (or a luminance channel `Y`) may be stored as half or full floats; other channels are
+    /// ignored.
+    pub fn read_exr(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let mut exr = ExrReader {
+            bytes: &bytes,
+            pos: 0,
+        };
+
+        if exr.u32()? != EXR_MAGIC {
+            return Err(invalid_data("not an OpenEXR file"));
+        }
+        let version = exr.u32()?;
+        if version & 0xff != 2 || version & 0x1a00 != 0 {
+            return Err(invalid_data(
+                "unsupported OpenEXR file (tiled, deep or multi-part)",
+            ));
+        }
+
+        let mut channels = vec![];
+        let mut window = None;
+        loop {
+            let name = exr.string()?;
+            if name.is_empty() {
+                break;
+            }
+            exr.string()?;
+            let size = exr.u32()? as usize;
+            let mut value = ExrReader {
+                bytes: exr.take(size)?,
+                pos: 0,
+            };
+            match name {
+                "channels" => loop {
+                    let name = value.string()?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let pixel_type = value.u32()?;
+                    value.take(12)?;
+                    channels.push((name.to_string(), pixel_type));
+                },
+                "compression" if value.take(1)? != [0] => {
+                    return Err(invalid_data("compressed OpenEXR files are not supported"))
+                }
+                "dataWindow" => {
+                    let mut next = || value.u32().map(|v| v as i32);
+                    window = Some([next()?, next()?, next()?, next()?]);
+                }
+                _ => {}
+            }
+        }
+        let [xmin, ymin, xmax, ymax] = window.ok_or_else(|| invalid_data("missing data window"))?;
+        if xmax < xmin || ymax < ymin {
+            return Err(invalid_data("invalid data window"));
+        }
+        let extent = |min: i32, max: i32| match max as i64 - min as i64 + 1 {
+            n if n <= u32::MAX as i64 => Ok(n as u32),
+            _ => Err(invalid_data("invalid data window")),
+        };
+        let (width, height) = (extent(xmin, xmax)?, extent(ymin, ymax)?);
+        if channels.is_empty() {
+            return Err(invalid_data("missing channels"));
+        }
+
+        // the offset table and the y coordinate and size of each scanline take 16 bytes per
+        // scanline, the channels 2 or 4 bytes per pixel
+        let pixel_size: usize = channels
+            .iter()
+            .map(|&(_, pixel_type)| if pixel_type == EXR_HALF { 2 } else { 4 })
+            .sum();
+        let size = pixel_count(width, height)?
+            .checked_mul(pixel_size)
+            .and_then(|size| size.checked_add((height as usize).checked_mul(16)?))
+            .ok_or_else(|| invalid_data("image too large"))?;
+        if size > exr.bytes.len() - exr.pos {
+            return Err(invalid_data("unexpected end of pixel data"));
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        exr.take(8 * height as usize)?;
+        for _ in 0..height {
+            let y = exr.u32()? as i32 - ymin;
+            exr.u32()?;
+            let row = canvas
+                .data
+                .chunks_exact_mut(width as usize)
+                .nth(y as usize)
+                .ok_or_else(|| invalid_data("scanline outside of the data window"))?;
+            for (name, pixel_type) in &channels {
+                let mut values = vec![0.0; width as usize];
+                for value in &mut values {
+                    *value = match *pixel_type {
+                        EXR_HALF => half_to_f64(exr.u16()?),
+                        EXR_FLOAT => f32::from_bits(exr.u32()?) as f64,
+                        _ => exr.u32()? as f64,
+                    };
+                }
+                for (pixel, value) in row.iter_mut().zip(values) {
+                    let (r, g, b) = (pixel.red(), pixel.green(), pixel.blue());
+                    *pixel = match name.as_str() {
+                        "R" => color(value, g, b),
+                        "G" => color(r, value, b),
+                        "B" => color(r, g, value),
+                        "Y" => color(value, value, value),
+                        _ => *pixel,
+                    };
+                }
+            }
+        }
+        Ok(canvas)
+    }
+
+    /// Write an image file in the format given by the file extension: PNG, PPM, Radiance HDR,
+    /// PFM or OpenEXR.
+    pub fn write_image(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path)?;
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        match format {
+            ImageFormat::Png => self.write_png(&mut file)?,
+            ImageFormat::Ppm => self.write_ppm(&mut file)?,
+            ImageFormat::Hdr => self.write_hdr(&mut file)?,
+            ImageFormat::Pfm => self.write_pfm(&mut file)?,
+            ImageFormat::Exr => self.write_exr(&mut file)?,
+        }
+        file.flush()
+    }
+
+    /// Read a plain (`P3`) or binary (`P6`) PPM image.
+    pub fn read_ppm(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let mut ppm = PpmTokens {
+            bytes: &bytes,
+            pos: 0,
+        };
+
+        let magic = ppm.token()?;
+        let binary = match magic {
+            b"P3" => false,
+            b"P6" => true,
+            _ => return Err(invalid_data("not a PPM file")),
+        };
+        let width = ppm.number()?;
+        let height = ppm.number()?;
+        let maxval = ppm.number()?;
+        if maxval == 0 || maxval > 65535 {
+            return Err(invalid_data("invalid maximum color value"));
+        }
+
+        let n = pixel_count(width, height)?
+            .checked_mul(3)
+            .ok_or_else(|| invalid_data("image too large"))?;
+        let samples: Vec<u32> = if binary {
+            // exactly one whitespace character separates the header from the data
+            let data = bytes.get(ppm.pos + 1..).unwrap_or(&[]);
+            let sample_size = if maxval < 256 { 1 } else { 2 };
+            if data.len() / sample_size < n {
+                return Err(invalid_data("unexpected end of pixel data"));
+            }
+            data.chunks_exact(sample_size)
+                .take(n)
+                .map(|s| s.iter().fold(0, |acc, &b| acc * 256 + b as u32))
+                .collect()
+        } else {
+            (0..n).map(|_| ppm.number()).collect::<Result<_, _>>()?
+        };
+
+        let mut canvas = Canvas::new(width, height);
+        for (pixel, rgb) in canvas.data.iter_mut().zip(samples.chunks_exact(3)) {
+            *pixel = color(rgb[0], rgb[1], rgb[2]) / maxval;
+        }
+        Ok(canvas)
+    }
+
+    /// Read an image file in the format given by the file extension: PNG, PPM, Radiance HDR,
+    /// PFM or OpenEXR.
+    pub fn read_image(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path)?;
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        match format {
+            ImageFormat::Png => Canvas::read_png(&mut file),
+            ImageFormat::Ppm => Canvas::read_ppm(&mut file),
+            ImageFormat::Hdr => Canvas::read_hdr(&mut file),
+            ImageFormat::Pfm => Canvas::read_pfm(&mut file),
+            ImageFormat::Exr => Canvas::read_exr(&mut file),
+        }
+    }
+
+    /// Read a PNG image. Alpha channels are ignored.
+    pub fn read_png(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data)?;
+
+        let (color_type, bit_depth) = reader.output_color_type();
+        let (sample_size, maxval) = match bit_depth {
+            png::BitDepth::Sixteen => (2, 65535.0),
+            _ => (1, 255.0),
+        };
+        let samples: Vec<f64> = data
+            .chunks_exact(sample_size)
+            .map(|s| s.iter().fold(0, |acc, &b| acc * 256 + b as u32) as f64 / maxval)
+            .collect();
+
+        let channels = color_type.samples();
+        let mut canvas = Canvas::new(info.width, info.height);
+        for (y, row) in samples
+            .chunks(info.line_size / sample_size)
+            .take(info.height as usize)
+            .enumerate()
+        {
+            for (x, pixel) in row
+                .chunks_exact(channels)
+                .take(info.width as usize)
+                .enumerate()
+            {
+                let c = match color_type {
+                    png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+                        color(pixel[0], pixel[0], pixel[0])
+                    }
+                    _ => color(pixel[0], pixel[1], pixel[2]),
+                };
+                canvas.set_pixel(x as u32, y as u32, c);
+            }
+        }
+        Ok(canvas)
+    }
+}
+
+/// The number of pixels of an image. Readers check it against the size of the pixel data
+/// before allocating a canvas, so that corrupt headers do not exhaust the memory.
+fn pixel_count(width: u32, height: u32) -> std::io::Result<usize> {
+    if width == 0 || height == 0 {
+        return Err(invalid_data("the image is empty"));
+    }
+    (width as usize)
+        .checked_mul(height as usize)
+        .ok_or_else(|| invalid_data("image too large"))
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// The image file formats, named by their file extensions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Hdr,
+    Pfm,
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &std::path::Path) -> std::io::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase);
+        match extension.as_deref() {
+            Some("png") => Ok(ImageFormat::Png),
+            Some("ppm") => Ok(ImageFormat::Ppm),
+            Some("hdr") => Ok(ImageFormat::Hdr),
+            Some("pfm") => Ok(ImageFormat::Pfm),
+            Some("exr") => Ok(ImageFormat::Exr),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "unsupported image format (expected .png, .ppm, .hdr, .pfm or .exr)",
+            )),
+        }
+    }
+}
+
+/// Encode a color with a shared exponent. Negative components become 0.
+fn to_rgbe(c: Color) -> [u8; 4] {
+    let (r, g, b) = (c.red().max(0.0), c.green().max(0.0), c.blue().max(0.0));
+    let v = r.max(g).max(b);
+    if v < 1e-32 {
+        return [0; 4];
+    }
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / 2f64.powi(e) >= 1.0 {
+        e += 1;
+    }
+    let scale = 256.0 / 2f64.powi(e);
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (e + 128).clamp(0, 255) as u8,
+    ]
+}
+
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return BLACK;
+    }
+    let f = 2f64.powi(e as i32 - 136);
+    color(r as f64 + 0.5, g as f64 + 0.5, b as f64 + 0.5) * f
+}
+
+/// Write one channel of a scanline as runs of equal bytes and literal sequences.
+fn write_rle(writer: &mut impl Write, values: &[u8]) -> std::io::Result<()> {
+    let mut i = 0;
+    while i < values.len() {
+        let run = values[i..]
+            .iter()
+            .take(127)
+            .take_while(|&&v| v == values[i])
+            .count();
+        if run >= 3 {
+            writer.write_all(&[128 + run as u8, values[i]])?;
+            i += run;
+            continue;
+        }
+        // a literal sequence ends where a run of at least three bytes starts
+        let mut end = i + 1;
+        while end < values.len()
+            && end - i < 128
+            && !(end + 2 < values.len()
+                && values[end] == values[end + 1]
+                && values[end] == values[end + 2])
+        {
+            end += 1;
+        }
+        writer.write_all(&[(end - i) as u8])?;
+        writer.write_all(&values[i..end])?;
+        i = end;
+    }
+    Ok(())
+}
+
+/// Read one run-length encoded channel of a scanline and return the remaining data.
+fn read_rle<'a>(
+    mut data: &'a [u8],
+    pixels: &mut [[u8; 4]],
+    channel: usize,
+) -> std::io::Result<&'a [u8]> {
+    let truncated = || invalid_data("unexpected end of pixel data");
+    let mut x = 0;
+    while x < pixels.len() {
+        let (&count, rest) = data.split_first().ok_or_else(truncated)?;
+        let (count, is_run) = if count > 128 {
+            (count as usize - 128, true)
+        } else {
+            (count as usize, false)
+        };
+        if count == 0 || x + count > pixels.len() {
+            return Err(invalid_data("invalid run length"));
+        }
+        let targets = pixels[x..x + count].iter_mut();
+        if is_run {
+            let &value = rest.first().ok_or_else(truncated)?;
+            targets.for_each(|pixel| pixel[channel] = value);
+            data = &rest[1..];
+        } else {
+            let values = rest.get(..count).ok_or_else(truncated)?;
+            for (pixel, &value) in targets.zip(values) {
+                pixel[channel] = value;
+            }
+            data = &rest[count..];
+        }
+        x += count;
+    }
+    Ok(data)
+}
+
+const EXR_MAGIC: u32 = 20_000_630;
+const EXR_HALF: u32 = 1;
+const EXR_FLOAT: u32 = 2;
+
+fn half_to_f64(h: u16) -> f64 {
+    let sign = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((h >> 10) & 0x1f) as i32;
+    let mantissa = (h & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+/// Reads the little-endian values of an OpenEXR file.
+struct ExrReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ExrReader<'a> {
+    fn take(&mut self, n: usize) -> std::io::Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| invalid_data("unexpected end of file"))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> std::io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    /// A null-terminated string
+    fn string(&mut self) -> std::io::Result<&'a str> {
+        let length = self.bytes[self.pos.min(self.bytes.len())..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| invalid_data("unexpected end of file"))?;
+        let bytes = self.take(length + 1)?;
+        std::str::from_utf8(&bytes[..length]).map_err(|_| invalid_data("invalid attribute name"))
+    }
+}
+
+/// Splits the header and plain pixel data of a PPM file into whitespace separated tokens,
+/// skipping comments.
+struct PpmTokens<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PpmTokens<'a> {
+    fn token(&mut self) -> std::io::Result<&'a [u8]> {
+        loop {
+            match self.bytes.get(self.pos) {
+                Some(b'#') => {
+                    while self.bytes.get(self.pos).is_some_and(|&b| b != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => self.pos += 1,
+                Some(_) => break,
+                None => return Err(invalid_data("unexpected end of file")),
+            }
+        }
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| !b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+        Ok(&self.bytes[start..self.pos])
+    }
+
+    fn number(&mut self) -> std::io::Result<u32> {
+        std::str::from_utf8(self.token()?)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid_data("expected a number"))
+    }
 }
 
 struct MaxWidthWriter<'a, T: Write> {
@@ -269,4 +990,179 @@ P3
         c.write_ppm(&mut buf).unwrap();
         assert_eq!(buf.last(), Some(&b'\n'))
     }
+
+    /// Reading a plain PPM file
+    #[test]
+    fn read_plain_ppm() {
+        let ppm = "P3\n# a comment\n2 1\n# another one\n100\n100 0 50\n0 25 100\n";
+        let c = Canvas::read_ppm(&mut ppm.as_bytes()).unwrap();
+        assert_eq!(c.width(), 2);
+        assert_eq!(c.height(), 1);
+        assert_eq!(c.get_pixel(0, 0), color(1, 0, 0.5));
+        assert_eq!(c.get_pixel(1, 0), color(0, 0.25, 1));
+    }
+
+    /// Reading a binary PPM file
+    #[test]
+    fn read_binary_ppm() {
+        let mut ppm = b"P6 1 2 255\n".to_vec();
+        ppm.extend_from_slice(&[255, 0, 51, 0, 10, 255]);
+        let c = Canvas::read_ppm(&mut &ppm[..]).unwrap();
+        assert_eq!(c.get_pixel(0, 0), color(1, 0, 0.2));
+        assert_eq!(c.get_pixel(0, 1), Color::from_u8(0, 10, 255));
+    }
+
+    /// Invalid PPM files are rejected
+    #[test]
+    fn read_invalid_ppm() {
+        assert!(Canvas::read_ppm(&mut &b"P5 1 1 255 0"[..]).is_err());
+        assert!(Canvas::read_ppm(&mut &b"P3 2 1 255 0 0 0 0 0"[..]).is_err());
+
+        let huge = b"P6 4294967295 4294967295 255 0";
+        let e = Canvas::read_ppm(&mut &huge[..]).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        let huge = b"P3 100000 100000 255 0 0 0";
+        let e = Canvas::read_ppm(&mut &huge[..]).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+
+    /// Images survive a round trip through PPM and PNG files
+    #[test]
+    fn round_trip() {
+        let mut c = canvas(3, 2);
+        c.set_pixel(0, 0, Color::from_u8(255, 128, 0));
+        c.set_pixel(2, 1, Color::from_u8(1, 2, 3));
+
+        let mut ppm = vec![];
+        c.write_ppm(&mut ppm).unwrap();
+        let mut png = vec![];
+        c.write_png(&mut png).unwrap();
+
+        for d in vec![
+            Canvas::read_ppm(&mut &ppm[..]).unwrap(),
+            Canvas::read_png(&mut &png[..]).unwrap(),
+        ] {
+            assert_eq!(d.width(), 3);
+            assert_eq!(d.height(), 2);
+            assert!(c.flat().zip(d.flat()).all(|(a, b)| a.to_u8() == b.to_u8()));
+        }
+    }
+
+    fn bright_canvas(width: u32) -> Canvas {
+        let mut c = canvas(width, 2);
+        for x in 0..width {
+            c.set_pixel(x, 0, color(1000.0, 0.5, 0.0));
+            c.set_pixel(x, 1, color(x as f64 / 7.0, 0.25, 3.0));
+        }
+        c
+    }
+
+    /// Colors brighter than 1 survive a round trip through Radiance HDR files, with flat and
+    /// run-length encoded scanlines
+    #[test]
+    fn hdr_round_trip() {
+        for &width in &[3, 20] {
+            let c = bright_canvas(width);
+            let mut hdr = vec![];
+            c.write_hdr(&mut hdr).unwrap();
+            let d = Canvas::read_hdr(&mut &hdr[..]).unwrap();
+            assert_eq!((d.width(), d.height()), (width, 2));
+            for (a, b) in c.flat().zip(d.flat()) {
+                let max = a.red().max(a.green()).max(a.blue());
+                let error = (a - b).red().abs() + (a - b).green().abs() + (a - b).blue().abs();
+                assert!(error < 0.02 * max, "{:?} != {:?}", a, b);
+            }
+        }
+        assert!(Canvas::read_hdr(&mut &b"#?RADIANCE\n\n+X 1 -Y 1\n\0\0\0\0"[..]).is_err());
+        assert!(Canvas::read_hdr(&mut &b"#?RADIANCE\n\n-Y 1 +X 1\n\0\0"[..]).is_err());
+    }
+
+    /// PFM and OpenEXR files keep 32-bit floats
+    #[test]
+    fn float_round_trip() {
+        let c = bright_canvas(5);
+        let mut pfm = vec![];
+        c.write_pfm(&mut pfm).unwrap();
+        let mut exr = vec![];
+        c.write_exr(&mut exr).unwrap();
+        for d in vec![
+            Canvas::read_pfm(&mut &pfm[..]).unwrap(),
+            Canvas::read_exr(&mut &exr[..]).unwrap(),
+        ] {
+            assert_eq!((d.width(), d.height()), (5, 2));
+            for (a, b) in c.flat().zip(d.flat()) {
+                assert_eq!(a.red() as f32 as f64, b.red());
+                assert_eq!(a.green() as f32 as f64, b.green());
+                assert_eq!(a.blue() as f32 as f64, b.blue());
+            }
+        }
+        assert!(Canvas::read_pfm(&mut &pfm[..pfm.len() - 1]).is_err());
+        assert!(Canvas::read_exr(&mut &exr[..exr.len() - 1]).is_err());
+    }
+
+    /// Empty or huge image sizes in corrupt headers are rejected instead of allocating
+    /// canvases for them, and empty canvases are not written
+    #[test]
+    fn oversized_headers() {
+        let is_invalid = |result: std::io::Result<Canvas>| match result {
+            Err(e) => e.kind() == ErrorKind::InvalidData,
+            Ok(_) => false,
+        };
+
+        let hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 100000 +X 100000\n\0\0\0\0";
+        assert!(is_invalid(Canvas::read_hdr(&mut &hdr[..])));
+        let hdr = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 0\n";
+        assert!(is_invalid(Canvas::read_hdr(&mut &hdr[..])));
+
+        let mut pfm = b"PF\n100000 100000\n-1.0\n".to_vec();
+        pfm.extend_from_slice(&[0; 12]);
+        assert!(is_invalid(Canvas::read_pfm(&mut &pfm[..])));
+        assert!(is_invalid(Canvas::read_pfm(&mut &b"PF\n0 1\n-1.0\n"[..])));
+
+        assert!(is_invalid(Canvas::read_ppm(&mut &b"P3\n0 1\n255\n"[..])));
+        assert!(is_invalid(Canvas::read_ppm(&mut &b"P6\n1 0\n255\n"[..])));
+
+        let mut exr = vec![];
+        bright_canvas(5).write_exr(&mut exr).unwrap();
+        let tag = b"dataWindow\0box2i\0\x10\0\0\0";
+        let window = exr.windows(tag.len()).position(|w| w == tag).unwrap() + tag.len();
+        for &[xmin, xmax] in &[[0, 99999], [i32::MIN, i32::MAX]] {
+            let mut exr = exr.clone();
+            exr[window..window + 4].copy_from_slice(&xmin.to_le_bytes());
+            exr[window + 8..window + 12].copy_from_slice(&xmax.to_le_bytes());
+            exr[window + 12..window + 16].copy_from_slice(&99999i32.to_le_bytes());
+            assert!(is_invalid(Canvas::read_exr(&mut &exr[..])));
+        }
+
+        for &(width, height) in &[(0, 1), (1, 0)] {
+            let empty = Canvas::new(width, height);
+            assert_eq!(empty.rows().count(), 0);
+            let writers: [fn(&Canvas, &mut Vec<u8>) -> std::io::Result<()>; 5] = [
+                |c, w| c.write_ppm(w),
+                |c, w| c.write_png(w),
+                |c, w| c.write_hdr(w),
+                |c, w| c.write_pfm(w),
+                |c, w| c.write_exr(w),
+            ];
+            for write in &writers {
+                let error = write(&empty, &mut vec![]).err().unwrap();
+                assert_eq!(error.kind(), ErrorKind::InvalidInput);
+            }
+        }
+    }
+
+    /// Big-endian grayscale PFM files and half floats are understood
+    #[test]
+    fn other_encodings() {
+        let mut pfm = b"Pf\n1 2\n1.0\n".to_vec();
+        pfm.extend_from_slice(&2.5f32.to_be_bytes());
+        pfm.extend_from_slice(&0.5f32.to_be_bytes());
+        let c = Canvas::read_pfm(&mut &pfm[..]).unwrap();
+        assert_eq!(c.get_pixel(0, 0), color(0.5, 0.5, 0.5));
+        assert_eq!(c.get_pixel(0, 1), color(2.5, 2.5, 2.5));
+
+        assert_eq!(half_to_f64(0x3c00), 1.0);
+        assert_eq!(half_to_f64(0xc100), -2.5);
+        assert_eq!(half_to_f64(0x0001), 2f64.powi(-24));
+    }
 }
@@ -59,6 +59,10 @@ impl Color {
         )
     }
 
+    pub fn from_u8(r: u8, g: u8, b: u8) -> Self {
+        Color::new(r as f64 / 255.0, g as f64 / 255.0, b as f64 / 255.0)
+    }
+
     pub fn clip(self, lo: f64, hi: f64) -> Self {
         Color::new(
             self.red.max(lo).min(hi),
@@ -98,6 +102,10 @@ impl Color {
     pub fn sum(&self) -> f64 {
         self.red + self.green + self.blue
     }
+
+    pub fn is_black(&self) -> bool {
+        self.red <= 0.0 && self.green <= 0.0 && self.blue <= 0.0
+    }
 }
 
 impl Add for Color {
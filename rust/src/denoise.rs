@@ -0,0 +1,318 @@
+//! Removing the noise of renders with few samples per pixel.
+//!
+//! The denoiser is a joint bilateral filter: every pixel becomes a weighted average of its
+//! neighbours, where the weights fall off with the distance on the image and with the
+//! differences of the colors and of the auxiliary buffers (see `aov`). The albedo, normal and
+//! depth buffers have no noise of their own, so they keep the edges of objects, shadows behind
+//! creases and textures sharp while the noise on smooth surfaces is averaged away.
+//!
+//! If there is an albedo buffer, the image is divided by it before filtering and multiplied with
+//! it afterwards, so that the filter only smooths the lighting and not the textures.
+//!
+//! ```no_run
+//! # use raytracing::{camera::Camera, world::World};
+//! use raytracing::aov::Aov;
+//! use raytracing::denoise::Denoiser;
+//! use raytracing::progress::RenderControl;
+//!
+//! # let (camera, world) = (Camera::new(1, 1, 1.0), World::default());
+//! let control = RenderControl::default();
+//! let (image, guides) = camera.render_with_aovs(&world, &Denoiser::GUIDES, &control);
+//! let image = Denoiser::default().denoise(&image, &guides);
+//! ```
+
+use crate::aov::{Aov, AovBuffers};
+use crate::canvas::Canvas;
+use crate::color::{color, Color, BLACK};
+use rayon::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct Denoiser {
+    radius: u32,
+    sigma_spatial: f64,
+    sigma_color: f64,
+    sigma_albedo: f64,
+    sigma_normal: f64,
+    sigma_depth: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            radius: 5,
+            sigma_spatial: 3.0,
+            sigma_color: 0.3,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.2,
+            sigma_depth: 0.05,
+        }
+    }
+}
+
+impl Denoiser {
+    /// The auxiliary buffers that guide the filter
+    pub const GUIDES: [Aov; 3] = [Aov::Albedo, Aov::Normal, Aov::Depth];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The filter averages over a square of `2 * radius + 1` pixels
+    pub fn with_radius(mut self, radius: u32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// The standard deviation of the falloff with the distance in pixels
+    pub fn with_sigma_spatial(mut self, sigma: f64) -> Self {
+        self.sigma_spatial = sigma;
+        self
+    }
+
+    /// The falloff with the difference of colors, which are compressed to [0, 1) first so
+    /// that bright highlights do not dominate. Larger values remove more noise.
+    pub fn with_sigma_color(mut self, sigma: f64) -> Self {
+        self.sigma_color = sigma;
+        self
+    }
+
+    /// The falloff with the difference of albedos
+    pub fn with_sigma_albedo(mut self, sigma: f64) -> Self {
+        self.sigma_albedo = sigma;
+        self
+    }
+
+    /// The falloff with the difference of normals
+    pub fn with_sigma_normal(mut self, sigma: f64) -> Self {
+        self.sigma_normal = sigma;
+        self
+    }
+
+    /// The falloff with the difference of depths, relative to the depth of the pixel
+    pub fn with_sigma_depth(mut self, sigma: f64) -> Self {
+        self.sigma_depth = sigma;
+        self
+    }
+
+    /// Filter an image. Guides that are missing from `guides` are not used.
+    pub fn denoise(&self, image: &Canvas, guides: &AovBuffers) -> Canvas {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let albedo = guides.get(Aov::Albedo);
+        let normal = guides.get(Aov::Normal);
+        let depth = guides.get(Aov::Depth);
+        for guide in albedo.iter().chain(&normal).chain(&depth) {
+            assert_eq!(
+                (guide.width(), guide.height()),
+                (image.width(), image.height()),
+                "guide of different size"
+            );
+        }
+
+        let pixels = |canvas: Option<&Canvas>| canvas.map(|c| c.flat().collect::<Vec<_>>());
+        let (albedo, normal, depth) = (pixels(albedo), pixels(normal), pixels(depth));
+        let colors: Vec<Color> = image.flat().collect();
+        // the lighting without the surface colors
+        let irradiance: Vec<Color> = match &albedo {
+            Some(albedo) => colors
+                .iter()
+                .zip(albedo)
+                .map(|(&c, &a)| demodulate(c, a))
+                .collect(),
+            None => colors.clone(),
+        };
+        let compressed: Vec<Color> = irradiance.iter().map(|&c| compress(c)).collect();
+
+        let r = self.radius as i64;
+        let falloff = |d2: f64, sigma: f64| (-d2 / (2.0 * sigma * sigma)).exp();
+        let rows: Vec<Vec<Color>> = (0..height)
+            .into_par_iter()
+            .map(|y| {
+                (0..width)
+                    .map(|x| {
+                        let i = y * width + x;
+                        let mut sum = BLACK;
+                        let mut total_weight = 0.0;
+                        for dy in -r..=r {
+                            for dx in -r..=r {
+                                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                                if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                                    continue;
+                                }
+                                let j = ny as usize * width + nx as usize;
+                                let mut weight =
+                                    falloff((dx * dx + dy * dy) as f64, self.sigma_spatial)
+                                        * falloff(
+                                            distance2(compressed[i], compressed[j]),
+                                            self.sigma_color,
+                                        );
+                                if let Some(albedo) = &albedo {
+                                    weight *=
+                                        falloff(distance2(albedo[i], albedo[j]), self.sigma_albedo);
+                                }
+                                if let Some(normal) = &normal {
+                                    weight *=
+                                        falloff(distance2(normal[i], normal[j]), self.sigma_normal);
+                                }
+                                if let Some(depth) = &depth {
+                                    weight *= depth_weight(
+                                        depth[i].red(),
+                                        depth[j].red(),
+                                        self.sigma_depth,
+                                    );
+                                }
+                                sum = sum + irradiance[j] * weight;
+                                total_weight += weight;
+                            }
+                        }
+                        // the pixel itself always has a positive weight
+                        let filtered = sum / total_weight;
+                        match &albedo {
+                            Some(albedo) => remodulate(filtered, albedo[i]),
+                            None => filtered,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut output = Canvas::new(image.width(), image.height());
+        for (target, row) in output.rows_mut().zip(rows) {
+            target.copy_from_slice(&row);
+        }
+        output
+    }
+}
+
+/// Albedo channels below this are too dark to divide by
+const MIN_ALBEDO: f64 = 1e-3;
+
+fn demodulate(c: Color, albedo: Color) -> Color {
+    let channel = |c: f64, a: f64| if a > MIN_ALBEDO { c / a } else { c };
+    color(
+        channel(c.red(), albedo.red()),
+        channel(c.green(), albedo.green()),
+        channel(c.blue(), albedo.blue()),
+    )
+}
+
+fn remodulate(c: Color, albedo: Color) -> Color {
+    let channel = |c: f64, a: f64| if a > MIN_ALBEDO { c * a } else { c };
+    color(
+        channel(c.red(), albedo.red()),
+        channel(c.green(), albedo.green()),
+        channel(c.blue(), albedo.blue()),
+    )
+}
+
+fn compress(c: Color) -> Color {
+    let channel = |x: f64| {
+        let x = x.max(0.0);
+        x / (1.0 + x)
+    };
+    color(channel(c.red()), channel(c.green()), channel(c.blue()))
+}
+
+fn distance2(a: Color, b: Color) -> f64 {
+    let d = a - b;
+    d.red() * d.red() + d.green() * d.green() + d.blue() * d.blue()
+}
+
+/// Pixels of the background (at infinite depth) are only averaged with each other
+fn depth_weight(a: f64, b: f64, sigma: f64) -> f64 {
+    match (a.is_finite(), b.is_finite()) {
+        (true, true) => {
+            let relative = (a - b) / a.max(1e-9);
+            (-relative * relative / (2.0 * sigma * sigma)).exp()
+        }
+        (false, false) => 1.0,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Prng;
+    use rand::Rng;
+
+    /// A noisy gray image whose left half is a different object than its right half
+    fn noisy_image() -> (Canvas, AovBuffers) {
+        let (width, height) = (16, 8);
+        let mut rng = Prng::new(1);
+        let mut image = Canvas::new(width, height);
+        let mut guides = AovBuffers::new(&Denoiser::GUIDES, width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let left = x < width / 2;
+                let base = if left { 0.2 } else { 0.8 };
+                let noise = rng.gen_range(-0.1, 0.1);
+                image.set_pixel(x, y, color(base + noise, base + noise, base + noise));
+                let normal = if left {
+                    color(0, 0, -1)
+                } else {
+                    color(1, 0, 0)
+                };
+                guides.set_pixel(x, y, &[color(1, 1, 1), normal, color(5, 5, 5)]);
+            }
+        }
+        (image, guides)
+    }
+
+    fn deviation(image: &Canvas, x_range: std::ops::Range<u32>, expected: f64) -> f64 {
+        let mut sum = 0.0;
+        let mut n = 0.0;
+        for y in 0..image.height() {
+            for x in x_range.clone() {
+                sum += (image.get_pixel(x, y).red() - expected).abs();
+                n += 1.0;
+            }
+        }
+        sum / n
+    }
+
+    /// The noise is reduced, but the edge between the objects stays sharp
+    #[test]
+    fn removes_noise_and_keeps_edges() {
+        let (image, guides) = noisy_image();
+        let denoised = Denoiser::default().denoise(&image, &guides);
+        assert!(deviation(&denoised, 0..8, 0.2) < 0.4 * deviation(&image, 0..8, 0.2));
+        assert!(deviation(&denoised, 8..16, 0.8) < 0.4 * deviation(&image, 8..16, 0.8));
+        assert!((denoised.get_pixel(7, 4).red() - 0.2).abs() < 0.05);
+        assert!((denoised.get_pixel(8, 4).red() - 0.8).abs() < 0.05);
+    }
+
+    /// Textures are kept by filtering the lighting only
+    #[test]
+    fn keeps_textures() {
+        let (width, height) = (8, 8);
+        let mut image = Canvas::new(width, height);
+        let mut guides = AovBuffers::new(&[Aov::Albedo], width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let albedo = if (x + y) % 2 == 0 {
+                    color(1, 0.5, 0.5)
+                } else {
+                    color(0.25, 0.25, 1)
+                };
+                image.set_pixel(x, y, albedo * 0.5);
+                guides.set_pixel(x, y, &[albedo]);
+            }
+        }
+        let denoised = Denoiser::default()
+            .with_sigma_albedo(10.0)
+            .denoise(&image, &guides);
+        assert!(distance2(denoised.get_pixel(3, 3), color(0.5, 0.25, 0.25)) < 1e-9);
+        assert!(distance2(denoised.get_pixel(4, 3), color(0.125, 0.125, 0.5)) < 1e-9);
+    }
+
+    /// Without guides, the filter still smooths by color
+    #[test]
+    fn without_guides() {
+        let (image, _) = noisy_image();
+        let guides = AovBuffers::new(&[], image.width(), image.height());
+        let denoised = Denoiser::new().with_radius(2).denoise(&image, &guides);
+        assert!(deviation(&denoised, 0..8, 0.2) < deviation(&image, 0..8, 0.2));
+        assert_eq!(depth_weight(f64::INFINITY, 1.0, 0.1), 0.0);
+        assert_eq!(depth_weight(f64::INFINITY, f64::INFINITY, 0.1), 1.0);
+    }
+}
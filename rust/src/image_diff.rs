@@ -0,0 +1,109 @@
+//! Comparing images, for example renderings of the same scene by different implementations.
+
+use crate::canvas::Canvas;
+use crate::color::color;
+
+pub struct ImageDiff {
+    /// Root mean square of the differences of all color channels of all pixels.
+    pub rmse: f64,
+    /// The largest difference of a single color channel.
+    pub max_error: f64,
+    /// Number of pixels where a color channel differs by more than the tolerance.
+    pub mismatched_pixels: usize,
+    /// White where the pixels mismatch, black elsewhere.
+    pub mask: Canvas,
+}
+
+impl ImageDiff {
+    /// The fraction of all pixels that mismatch.
+    pub fn mismatch_ratio(&self) -> f64 {
+        self.mismatched_pixels as f64 / (self.mask.width() * self.mask.height()) as f64
+    }
+}
+
+/// Compare two images pixel by pixel. Colors are clipped to the displayable range first, so
+/// that a rendering can be compared to an image that was read from a file.
+///
+/// Panics if the images are not of the same size.
+pub fn compare(a: &Canvas, b: &Canvas, tolerance: f64) -> ImageDiff {
+    assert_eq!(
+        (a.width(), a.height()),
+        (b.width(), b.height()),
+        "images of different size"
+    );
+
+    let mut mask = Canvas::new(a.width(), a.height());
+    let mut sum_of_squares = 0.0;
+    let mut max_error: f64 = 0.0;
+    let mut mismatched_pixels = 0;
+
+    for (y, (row_a, row_b)) in a.rows().zip(b.rows()).enumerate() {
+        for (x, (&ca, &cb)) in row_a.iter().zip(row_b).enumerate() {
+            let d = ca.clip(0.0, 1.0) - cb.clip(0.0, 1.0);
+            let error = d.red().abs().max(d.green().abs()).max(d.blue().abs());
+            sum_of_squares += (d * d).sum();
+            max_error = max_error.max(error);
+            if error > tolerance {
+                mismatched_pixels += 1;
+                mask.set_pixel(x as u32, y as u32, color(1, 1, 1));
+            }
+        }
+    }
+
+    let n_samples = (a.width() * a.height() * 3) as f64;
+    ImageDiff {
+        rmse: (sum_of_squares / n_samples).sqrt(),
+        max_error,
+        mismatched_pixels,
+        mask,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::color::Color;
+
+    fn uniform(c: Color) -> Canvas {
+        let mut canvas = Canvas::new(4, 2);
+        canvas.clear(c);
+        canvas
+    }
+
+    /// Identical images do not differ
+    #[test]
+    fn identical_images() {
+        let a = uniform(color(0.2, 0.4, 0.6));
+        let diff = compare(&a, &a, 0.0);
+        assert_eq!(diff.rmse, 0.0);
+        assert_eq!(diff.max_error, 0.0);
+        assert_eq!(diff.mismatched_pixels, 0);
+        assert!(diff.mask.flat().all(|c| c == color(0, 0, 0)));
+    }
+
+    /// The error statistics are computed over all color channels
+    #[test]
+    fn error_statistics() {
+        let a = uniform(color(0, 0, 0));
+        let mut b = uniform(color(0, 0, 0));
+        b.set_pixel(1, 0, color(0.5, 0, 0));
+        b.set_pixel(3, 1, color(0.1, 0.1, 0.1));
+
+        let diff = compare(&a, &b, 0.2);
+        assert_almost_eq!(diff.rmse, ((0.25 + 0.03) / 24.0f64).sqrt());
+        assert_almost_eq!(diff.max_error, 0.5);
+        assert_eq!(diff.mismatched_pixels, 1);
+        assert_almost_eq!(diff.mismatch_ratio(), 0.125);
+        assert_eq!(diff.mask.get_pixel(1, 0), color(1, 1, 1));
+        assert_eq!(diff.mask.get_pixel(3, 1), color(0, 0, 0));
+    }
+
+    /// Colors outside the displayable range are clipped before comparing
+    #[test]
+    fn clipped_colors() {
+        let a = uniform(color(1.5, -0.5, 1));
+        let b = uniform(color(1, 0, 1));
+        assert_eq!(compare(&a, &b, 0.0).max_error, 0.0);
+    }
+}
@@ -0,0 +1,54 @@
+use super::Integrator;
+use crate::color::{color, Color};
+use crate::random::Prng;
+use crate::ray::Ray;
+use crate::world::World;
+
+/// Show properties of the visible surfaces instead of their lighting, to inspect a scene.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugView {
+    /// Surface normals, with the components mapped from [-1, 1] to [0, 1]
+    Normals,
+    /// The color of the surface, without any lighting
+    Albedo,
+}
+
+impl Integrator for DebugView {
+    fn trace(&self, world: &World, ray: &Ray, _rng: &mut Prng) -> Option<Color> {
+        // refractive indices do not matter here, so the nearest hit is all we need
+        let xs = [world.nearest_hit(ray)?];
+        let comps = xs[0].prepare_computations(ray, &xs);
+        Some(match self {
+            DebugView::Normals => {
+                let n = comps.normalv;
+                color(n.x() + 1.0, n.y() + 1.0, n.z() + 1.0) / 2
+            }
+            DebugView::Albedo => comps.obj.material().color_at(&comps),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::tuple::{point, vector};
+
+    /// Debug views show the surface properties at the first hit
+    #[test]
+    fn debug_views() {
+        let w = World::default();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let rng = &mut Prng::new(0);
+        assert_almost_eq!(
+            DebugView::Normals.trace(&w, &r, rng).unwrap(),
+            color(0.5, 0.5, 0)
+        );
+        assert_almost_eq!(
+            DebugView::Albedo.trace(&w, &r, rng).unwrap(),
+            color(0.8, 1.0, 0.6)
+        );
+        let r = Ray::new(point(0, 0, -5), vector(0, 1, 0));
+        assert_eq!(DebugView::Normals.trace(&w, &r, rng), None);
+    }
+}
@@ -0,0 +1,24 @@
+//! Integrators compute the light that arrives at the camera along a ray. They decide how light
+//! is transported through the scene, while the materials only describe how a single surface
+//! scatters light. Thus, every material works with every integrator.
+
+mod debug_view;
+mod path_tracing;
+mod photon_mapping;
+mod whitted;
+
+pub use debug_view::DebugView;
+pub use path_tracing::PathTracing;
+pub use photon_mapping::PhotonMapping;
+pub use whitted::{RecursiveTracing, Whitted};
+
+use crate::color::Color;
+use crate::random::Prng;
+use crate::ray::Ray;
+use crate::world::World;
+
+pub trait Integrator: 'static + std::fmt::Debug + Sync {
+    /// The light arriving along the ray, or `None` if the ray leaves the scene without hitting
+    /// anything.
+    fn trace(&self, world: &World, ray: &Ray, rng: &mut Prng) -> Option<Color>;
+}
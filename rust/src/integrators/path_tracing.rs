@@ -0,0 +1,102 @@
+use super::Integrator;
+use crate::color::{color, Color, BLACK};
+use crate::lights::IncomingLight;
+use crate::random::Prng;
+use crate::ray::{hit, IntersectionState, Ray};
+use crate::world::World;
+use rand::Rng;
+use std::f64::consts::PI;
+
+/// Paths are not terminated by Russian roulette before this many bounces.
+const MIN_PATH_LENGTH: u32 = 3;
+
+/// Monte-Carlo path tracing. Paths bounce randomly off diffuse surfaces and light sources are
+/// sampled at every bounce. Slow to converge, but unbiased apart from the limited path length,
+/// which makes it a reference for the other integrators.
+#[derive(Debug, Clone)]
+pub struct PathTracing {
+    max_path_length: u32,
+}
+
+impl Default for PathTracing {
+    fn default() -> Self {
+        PathTracing::new(10)
+    }
+}
+
+impl PathTracing {
+    /// Paths are terminated after at most `max_path_length` bounces.
+    pub fn new(max_path_length: u32) -> Self {
+        PathTracing { max_path_length }
+    }
+}
+
+impl Integrator for PathTracing {
+    /// Follow a random path from the camera through the scene, collecting the light emitted by
+    /// surfaces and the direct light sampled at each bounce. Paths that survive the first few
+    /// bounces are continued with a probability that decreases with their contribution
+    /// (Russian roulette), which keeps the estimate unbiased.
+    fn trace(&self, world: &World, ray: &Ray, rng: &mut Prng) -> Option<Color> {
+        let mut ray = *ray;
+        let mut throughput = color(1, 1, 1);
+        let mut radiance = BLACK;
+
+        for bounce in 0..=self.max_path_length {
+            let xs = world.hit_intersections(&ray);
+            let i = match hit(&xs) {
+                Some(i) => i,
+                None if bounce == 0 => return None,
+                None => break,
+            };
+            let comps = i.prepare_computations(&ray, &xs);
+            let material = comps.obj.material();
+
+            radiance = radiance
+                + throughput * (material.emission(&comps) + direct_light(world, &comps, rng));
+
+            if bounce >= MIN_PATH_LENGTH {
+                let p_continue = throughput
+                    .red()
+                    .max(throughput.green())
+                    .max(throughput.blue())
+                    .min(0.95);
+                if rng.gen::<f64>() >= p_continue {
+                    break;
+                }
+                throughput = throughput / p_continue;
+            }
+
+            match material.sample_scattering(&comps, rng) {
+                Some((next_ray, attenuation)) => {
+                    ray = next_ray;
+                    throughput = throughput * attenuation;
+                }
+                None => break,
+            }
+        }
+
+        Some(radiance)
+    }
+}
+
+/// The direct light from all light sources, as reflected by the surface.
+///
+/// `Material::lighting` follows the book and reflects `diffuse * cos` of the light arriving
+/// along a ray, which is π times the reflection of a Lambertian surface of that albedo. The
+/// cosine-weighted bounces of `Material::sample_scattering` reflect like a Lambertian surface,
+/// so the light of rays is divided by π to weigh direct and bounced light alike. Ambient light
+/// stands for uniform light from all directions and is left as it is.
+fn direct_light(world: &World, comps: &IntersectionState, rng: &mut Prng) -> Color {
+    world.lights().iter().fold(BLACK, |color, light| {
+        let mut incoming_light = light.incoming_at(comps.over_point, rng);
+        let visibility = world.light_visibility(&incoming_light, comps.over_point);
+        if let IncomingLight::Ray(lr) = &mut incoming_light {
+            lr.color = lr.color * visibility / PI;
+        }
+        color
+            + comps
+                .obj
+                .material()
+                .lighting(incoming_light, comps, visibility.is_black())
+    })
+}
@@ -0,0 +1,322 @@
+use super::{Integrator, RecursiveTracing};
+use crate::approx_eq::EPSILON;
+use crate::color::{Color, BLACK};
+use crate::photon_map::{PhotonKind, PhotonMap, StoredPhoton, TravellingPhoton};
+use crate::random::Prng;
+use crate::ray::{hit, origin_object, IntersectionState, Ray};
+use crate::world::World;
+use rand::distributions::{Distribution, WeightedIndex};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::f64::consts::PI;
+
+/// Recursive ray tracing, where the light that surfaces scatter diffusely is estimated from a
+/// photon map. Direct light can be either ray traced or taken from the photon map.
+///
+/// The photon map must be computed with `compute_photon_map` before rendering; without a
+/// photon map only the ray traced direct light is rendered.
+#[derive(Debug)]
+pub struct PhotonMapping {
+    max_depth: u32,
+    seed: u64,
+    photon_map: Option<(PhotonMap, usize)>,
+    direct_illumination_enabled: bool,
+    direct_photon_map_enabled: bool,
+    diffuse_photon_map_enabled: bool,
+    caustic_photon_map_enabled: bool,
+}
+
+impl Default for PhotonMapping {
+    fn default() -> Self {
+        PhotonMapping {
+            max_depth: 10,
+            seed: 0,
+            photon_map: None,
+            direct_illumination_enabled: true,
+            direct_photon_map_enabled: false,
+            diffuse_photon_map_enabled: true,
+            caustic_photon_map_enabled: true,
+        }
+    }
+}
+
+impl PhotonMapping {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enable_direct_illumination(&mut self, flag: bool) {
+        self.direct_illumination_enabled = flag;
+    }
+
+    pub fn enable_direct_photon_map(&mut self, flag: bool) {
+        self.direct_photon_map_enabled = flag;
+    }
+
+    pub fn enable_diffuse_photon_map(&mut self, flag: bool) {
+        self.diffuse_photon_map_enabled = flag;
+    }
+
+    pub fn enable_caustic_photon_map(&mut self, flag: bool) {
+        self.caustic_photon_map_enabled = flag;
+    }
+
+    /// Set the seed of the random numbers that are used to compute the photon map.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    pub fn photon_map_enabled(&self) -> bool {
+        self.direct_photon_map_enabled
+            || self.caustic_photon_map_enabled
+            || self.diffuse_photon_map_enabled
+    }
+
+    pub fn direct_photon_map_only(&self) -> bool {
+        self.direct_photon_map_enabled
+            && !self.caustic_photon_map_enabled
+            && !self.diffuse_photon_map_enabled
+    }
+
+    pub fn get_photon_map(&self) -> Option<(&PhotonMap, usize)> {
+        self.photon_map.as_ref().map(|(pm, n)| (pm, *n))
+    }
+
+    pub fn drop_photon_map(&mut self) {
+        self.photon_map = None;
+    }
+
+    /// Trace `n_photons` photons from the lights of `world` and store those of the enabled
+    /// kinds. When rendering, the light at a point is estimated from the `n_nearest` photons
+    /// within `max_search_radius`.
+    pub fn compute_photon_map(
+        &mut self,
+        world: &World,
+        n_photons: usize,
+        n_nearest: usize,
+        max_search_radius: f64,
+    ) {
+        self.drop_photon_map(); // Save some memory
+
+        log::info!("Tracing {} photons", n_photons);
+        let photons = (0..n_photons)
+            .into_par_iter()
+            .map(|i| {
+                let rng = &mut Prng::stream(self.seed, &[i as u64]);
+                let photon = emit_photon(world, rng);
+                self.trace_photon(world, photon, rng)
+            })
+            .flatten()
+            .map(|photon| photon.scale_power(1.0 / n_photons as f64))
+            .collect();
+
+        log::info!("Balancing photon map");
+        let mut map = PhotonMap::from_vec(photons);
+        map.set_max_search_radius(max_search_radius);
+        self.photon_map = Some((map, n_nearest));
+
+        log::info!("Photon map complete");
+    }
+
+    pub fn trace_photon(
+        &self,
+        world: &World,
+        mut photon: TravellingPhoton,
+        rng: &mut Prng,
+    ) -> Vec<StoredPhoton> {
+        let mut hits = vec![];
+        loop {
+            if photon.power() < EPSILON {
+                return hits;
+            }
+
+            match photon.kind() {
+                PhotonKind::Direct => {}
+                _ if self.direct_photon_map_only() => return hits,
+                _ => {}
+            }
+
+            let xs = world.hit_intersections(photon.ray());
+
+            let maybe_hit = hit(&xs);
+
+            if let Some(obj) = origin_object(&xs) {
+                if let Some(p) = obj.material().transform_photon(photon, maybe_hit) {
+                    photon = p;
+                    continue;
+                }
+            }
+
+            let hit = match maybe_hit {
+                Some(h) => h,
+                None => return hits,
+            };
+
+            let comps = hit.prepare_computations(photon.ray(), &xs);
+
+            let (store_photon, next_photon) = self.photon_hit(photon, &comps, rng);
+
+            if let Some(p) = store_photon {
+                if match p.kind() {
+                    PhotonKind::Direct if self.direct_photon_map_enabled => true,
+                    PhotonKind::Diffuse if self.diffuse_photon_map_enabled => true,
+                    PhotonKind::Caustic if self.caustic_photon_map_enabled => true,
+                    _ => false,
+                } {
+                    hits.push(p.store(comps.point));
+                }
+            }
+
+            if let Some(p) = next_photon {
+                photon = p;
+            } else {
+                return hits;
+            }
+        }
+    }
+
+    /// Decide what happens to a photon that hits a surface. Returns the photon to store, if
+    /// the surface is diffuse, and the photon that travels on, if it is not absorbed.
+    fn photon_hit(
+        &self,
+        photon: TravellingPhoton,
+        comps: &IntersectionState,
+        rng: &mut Prng,
+    ) -> (Option<TravellingPhoton>, Option<TravellingPhoton>) {
+        let mut stored_photon = None;
+
+        let scattering = comps.obj.material().photon_scattering(comps);
+        let diffuse_reflectance = scattering.diffuse;
+
+        let mut pd_avg = diffuse_reflectance.sum() / 3.0;
+
+        if pd_avg > EPSILON {
+            stored_photon = Some(photon);
+        }
+
+        if !self.diffuse_photon_map_enabled {
+            pd_avg = 0.0;
+        }
+
+        // Materials that scatter more light than they receive never absorb photons; their
+        // fractions are only relative probabilities.
+        let p_absorb = (1.0 - pd_avg - scattering.mirror - scattering.transmission).max(0.0);
+
+        let dist =
+            WeightedIndex::new([p_absorb, pd_avg, scattering.mirror, scattering.transmission])
+                .unwrap();
+        let next_photon = match dist.sample(rng) {
+            0 => None,
+            1 => Some(photon.scatter(comps.over_point, comps.normalv, diffuse_reflectance, rng)),
+            2 => Some(photon.reflect(comps.over_point, comps.normalv)),
+            3 => Some(photon.refract(comps.under_point, comps.normalv, comps.n1, comps.n2)),
+            _ => unreachable!(),
+        };
+
+        (stored_photon, next_photon)
+    }
+}
+
+/// Emit a photon from one of the lights, chosen with probability proportional to their power.
+pub fn emit_photon(world: &World, rng: &mut Prng) -> TravellingPhoton {
+    let lights = world.lights();
+    let dist = WeightedIndex::new(lights.iter().map(|light| light.power())).unwrap();
+    let idx = dist.sample(rng);
+    lights[idx].emit_photon(rng).into()
+}
+
+impl RecursiveTracing for PhotonMapping {
+    fn surface_light(&self, world: &World, comps: &IntersectionState, rng: &mut Prng) -> Color {
+        let mut surface = BLACK;
+
+        if self.photon_map_enabled() {
+            if let Some((pm, n_nearest)) = self.get_photon_map() {
+                let (photons, square_radius) = pm.find_nearest(n_nearest, comps.point);
+                let total_light = photons.iter().fold(BLACK, |color, photon| {
+                    color + comps.normalv.dot(&photon.direction()).max(0.0) * photon.power()
+                });
+                let surface_color = comps.obj.material().color_at(comps);
+                surface = surface + surface_color * total_light / (PI * square_radius);
+            }
+        }
+
+        if self.direct_illumination_enabled {
+            surface = surface + world.direct_light(comps, rng);
+        }
+
+        surface
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn trace(&self, world: &World, ray: &Ray, rng: &mut Prng) -> Option<Color> {
+        self.color_at(world, ray, self.max_depth, rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::color::color;
+    use crate::lights::PointLight;
+    use crate::materials::Phong;
+    use crate::shapes::plane;
+    use crate::tuple::{point, vector};
+
+    /// Only photons of the enabled kinds are stored
+    #[test]
+    fn stored_photon_kinds() {
+        let mut w = World::empty();
+        w.add_light(PointLight::new(point(0, 1, 0), color(1, 1, 1)));
+        w.add_item(plane());
+
+        let trace_all = |pm: &PhotonMapping| {
+            let rng = &mut Prng::new(0);
+            (0..100)
+                .flat_map(|_| pm.trace_photon(&w, emit_photon(&w, rng), rng))
+                .collect::<Vec<_>>()
+        };
+
+        // photons that come directly from the light are not stored by default
+        let mut pm = PhotonMapping::new();
+        assert!(trace_all(&pm).is_empty());
+
+        pm.enable_direct_photon_map(true);
+        let photons = trace_all(&pm);
+        assert!(!photons.is_empty());
+        assert!(photons.len() <= 100);
+        for photon in photons {
+            assert_almost_eq!(photon.position().y(), 0.0);
+        }
+    }
+    /// Photons are mirrored by specular surfaces. Surfaces that scatter more light than they
+    /// receive never absorb photons.
+    #[test]
+    fn photon_hit_probabilities() {
+        let mut w = World::empty();
+        w.add_item(plane().with_material(Phong::default().with_reflective(0.5)));
+        let ray = Ray::new(point(0, 1, 0), vector(0, -1, 0));
+        let xs = w.intersect(&ray);
+        let comps = xs[0].prepare_computations(&ray, &xs);
+        let photon = TravellingPhoton::new(point(0, 1, 0), vector(0, -1, 0), color(1, 1, 1));
+
+        let pm = PhotonMapping::new();
+        let rng = &mut Prng::new(0);
+        let n = 1000;
+        let mut mirrored = 0;
+        for _ in 0..n {
+            let (stored, next) = pm.photon_hit(photon, &comps, rng);
+            assert!(stored.is_some());
+            // diffuse 0.9 and specular 0.9 add up to more than 1
+            let next = next.expect("photon absorbed");
+            if next.is_caustic() {
+                mirrored += 1;
+            }
+        }
+        assert!(
+            (mirrored as f64 / n as f64 - 0.5).abs() < 0.05,
+            "{}",
+            mirrored
+        );
+    }
+}
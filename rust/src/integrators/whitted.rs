@@ -0,0 +1,123 @@
+use super::Integrator;
+use crate::color::{Color, BLACK};
+use crate::random::Prng;
+use crate::ray::{hit, IntersectionState, Ray};
+use crate::world::World;
+
+/// Integrators that follow mirror reflection and refraction recursively. They differ in how
+/// they compute the light that a surface scatters diffusely.
+pub trait RecursiveTracing {
+    /// The light that the surface scatters diffusely towards the eye.
+    fn surface_light(&self, world: &World, comps: &IntersectionState, rng: &mut Prng) -> Color;
+
+    fn color_at(
+        &self,
+        world: &World,
+        ray: &Ray,
+        remaining_bounces: u32,
+        rng: &mut Prng,
+    ) -> Option<Color> {
+        let xs = world.hit_intersections(ray);
+        hit(&xs).map(|i| {
+            self.shade_hit(
+                world,
+                &i.prepare_computations(ray, &xs),
+                remaining_bounces,
+                rng,
+            )
+        })
+    }
+
+    fn shade_hit(
+        &self,
+        world: &World,
+        comps: &IntersectionState,
+        remaining_bounces: u32,
+        rng: &mut Prng,
+    ) -> Color {
+        let surface = self.surface_light(world, comps, rng).clip(0.0, 1.0);
+        let emissive = comps.obj.material().emission(comps);
+        let reflected = self.reflected_color(world, comps, remaining_bounces, rng);
+        let refracted = self.refracted_color(world, comps, remaining_bounces, rng);
+        surface + reflected + refracted + emissive
+    }
+
+    fn reflected_color(
+        &self,
+        world: &World,
+        comps: &IntersectionState,
+        remaining_bounces: u32,
+        rng: &mut Prng,
+    ) -> Color {
+        let r = comps.obj.material().scattering(comps).mirror;
+        if r == 0.0 || remaining_bounces == 0 {
+            BLACK
+        } else {
+            self.color_at(
+                world,
+                &Ray::new(comps.over_point, comps.reflectv),
+                remaining_bounces - 1,
+                rng,
+            )
+            .map(|c| c * r)
+            .unwrap_or(BLACK)
+        }
+    }
+
+    fn refracted_color(
+        &self,
+        world: &World,
+        comps: &IntersectionState,
+        remaining_bounces: u32,
+        rng: &mut Prng,
+    ) -> Color {
+        let t = comps.obj.material().scattering(comps).transmission;
+        if remaining_bounces == 0 || t == 0.0 {
+            return BLACK;
+        }
+        match comps.refraction_direction() {
+            None => BLACK,
+            Some(direction) => self
+                .color_at(
+                    world,
+                    &Ray::new(comps.under_point, direction),
+                    remaining_bounces - 1,
+                    rng,
+                )
+                .map(|c| c * t)
+                .unwrap_or(BLACK),
+        }
+    }
+}
+
+/// Classic recursive ray tracing, as in the book: direct light from the light sources, mirror
+/// reflection and refraction.
+#[derive(Debug, Clone)]
+pub struct Whitted {
+    max_depth: u32,
+}
+
+impl Default for Whitted {
+    fn default() -> Self {
+        Whitted::new(10)
+    }
+}
+
+impl Whitted {
+    /// Rays are reflected or refracted at most `max_depth` times.
+    pub fn new(max_depth: u32) -> Self {
+        Whitted { max_depth }
+    }
+}
+
+impl RecursiveTracing for Whitted {
+    fn surface_light(&self, world: &World, comps: &IntersectionState, rng: &mut Prng) -> Color {
+        world.direct_light(comps, rng)
+    }
+}
+
+impl Integrator for Whitted {
+    fn trace(&self, world: &World, ray: &Ray, rng: &mut Prng) -> Option<Color> {
+        self.color_at(world, ray, self.max_depth, rng)
+    }
+}
@@ -4,19 +4,36 @@ pub mod approx_eq;
 pub mod ray;
 
 pub mod aabb;
+pub mod aov;
+pub mod bvh;
 pub mod camera;
 pub mod canvas;
 pub mod color;
 pub mod cosine_distribution;
+pub mod denoise;
+pub mod image_diff;
+pub mod integrators;
 pub mod lights;
+#[cfg(feature = "live-preview")]
 pub mod live_preview;
 pub mod materials;
 pub mod math;
 pub mod matrix;
+pub mod mtl_loader;
+pub mod navigation;
 pub mod obj_loader;
 pub mod partial_sort;
 pub mod pattern;
 pub mod photon_map;
+pub mod picking;
+pub mod ply_loader;
+pub mod preview;
+pub mod progress;
+pub mod random;
+pub mod scene;
 pub mod shapes;
+pub mod stl_loader;
+pub mod tiles;
+pub mod tone_mapping;
 pub mod tuple;
 pub mod world;
@@ -1,9 +1,10 @@
 use crate::approx_eq::ApproximateEq;
 use crate::color::{color, Color};
 use crate::cosine_distribution::CosineDistribution;
+use crate::random::Prng;
 use crate::tuple::{vector, Point, Vector};
 use rand::distributions::Distribution;
-use rand::thread_rng;
+use rand::Rng;
 use rand_distr::{StandardNormal, UnitDisc, UnitSphere};
 use std::any::Any;
 use std::f64::consts::PI;
@@ -13,10 +14,10 @@ use std::f64::consts::PI;
 pub trait Light: Sync + 'static {
     fn as_any(&self) -> &dyn Any;
     fn is_similar(&self, other: &dyn Light) -> bool;
-    fn incoming_at(&self, point: Point) -> IncomingLight;
+    fn incoming_at(&self, point: Point, rng: &mut Prng) -> IncomingLight;
 
     fn power(&self) -> f64;
-    fn emit_photon(&self) -> LightRay;
+    fn emit_photon(&self, rng: &mut Prng) -> LightRay;
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -86,7 +87,7 @@ impl Light for PointLight {
             .unwrap_or(false)
     }
 
-    fn incoming_at(&self, point: Point) -> IncomingLight {
+    fn incoming_at(&self, point: Point, _rng: &mut Prng) -> IncomingLight {
         IncomingLight::Ray(LightRay {
             origin: self.position,
             direction: (self.position - point).normalized(),
@@ -101,8 +102,8 @@ impl Light for PointLight {
         PointLight::compute_power(self.intensity) * 1.0
     }
 
-    fn emit_photon(&self) -> LightRay {
-        let p: [f64; 3] = UnitSphere.sample(&mut thread_rng());
+    fn emit_photon(&self, rng: &mut Prng) -> LightRay {
+        let p: [f64; 3] = UnitSphere.sample(rng);
         LightRay {
             origin: self.position,
             direction: p.into(),
@@ -150,7 +151,7 @@ impl Light for RealisticPointLight {
             .unwrap_or(false)
     }
 
-    fn incoming_at(&self, point: Point) -> IncomingLight {
+    fn incoming_at(&self, point: Point, _rng: &mut Prng) -> IncomingLight {
         let distance2 = (self.position - point).square_len();
         IncomingLight::Ray(LightRay {
             origin: self.position,
@@ -166,8 +167,8 @@ impl Light for RealisticPointLight {
         PointLight::compute_power(self.intensity) * 1.0
     }
 
-    fn emit_photon(&self) -> LightRay {
-        let p: [f64; 3] = UnitSphere.sample(&mut thread_rng());
+    fn emit_photon(&self, rng: &mut Prng) -> LightRay {
+        let p: [f64; 3] = UnitSphere.sample(rng);
         LightRay {
             origin: self.position,
             direction: p.into(),
@@ -227,7 +228,7 @@ impl Light for Beam {
             .unwrap_or(false)
     }
 
-    fn incoming_at(&self, point: Point) -> IncomingLight {
+    fn incoming_at(&self, point: Point, _rng: &mut Prng) -> IncomingLight {
         let direction = point - self.position;
         if direction.dot(&self.direction) < 0.0 {
             return IncomingLight::NoLight;
@@ -249,8 +250,7 @@ impl Light for Beam {
         PointLight::compute_power(self.intensity) * 1.0
     }
 
-    fn emit_photon(&self) -> LightRay {
-        let rng = &mut thread_rng();
+    fn emit_photon(&self, rng: &mut Prng) -> LightRay {
         let u: f64 = StandardNormal.sample(rng);
         let v: f64 = StandardNormal.sample(rng);
         LightRay {
@@ -261,6 +261,118 @@ impl Light for Beam {
     }
 }
 
+/// A point light that only shines into a cone around its direction. The intensity falls off
+/// smoothly between the inner and the outer cone angle, as with glTF spot lights.
+#[derive(Debug)]
+pub struct SpotLight {
+    position: Point,
+    direction: Vector,
+    cos_inner: f64,
+    cos_outer: f64,
+    intensity: Color,
+}
+
+impl SpotLight {
+    /// The cone angles are measured from the direction, in radians.
+    pub fn new(
+        position: Point,
+        direction: Vector,
+        inner_angle: f64,
+        outer_angle: f64,
+        intensity: Color,
+    ) -> Self {
+        SpotLight {
+            position,
+            direction: direction.normalized(),
+            cos_inner: inner_angle.cos(),
+            cos_outer: outer_angle.cos(),
+            intensity,
+        }
+    }
+
+    pub fn position(&self) -> Point {
+        self.position
+    }
+
+    pub fn direction(&self) -> Vector {
+        self.direction
+    }
+
+    pub fn intensity(&self) -> Color {
+        self.intensity
+    }
+
+    /// The fraction of the intensity that is emitted in the given (normalized) direction
+    fn falloff(&self, direction: Vector) -> f64 {
+        let cos_angle = direction.dot(&self.direction);
+        if cos_angle <= self.cos_outer {
+            return 0.0;
+        }
+        let scale = 1.0 / (self.cos_inner - self.cos_outer).max(1e-4);
+        let t = ((cos_angle - self.cos_outer) * scale).min(1.0);
+        t * t
+    }
+}
+
+impl Light for SpotLight {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn is_similar(&self, other: &dyn Light) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Self>()
+            .map(|other| {
+                self.position().approx_eq(&other.position())
+                    && self.direction.approx_eq(&other.direction)
+                    && self.cos_inner.approx_eq(&other.cos_inner)
+                    && self.cos_outer.approx_eq(&other.cos_outer)
+                    && self.intensity().approx_eq(&other.intensity())
+            })
+            .unwrap_or(false)
+    }
+
+    fn incoming_at(&self, point: Point, _rng: &mut Prng) -> IncomingLight {
+        let direction = (self.position - point).normalized();
+        let falloff = self.falloff(-direction);
+        if falloff <= 0.0 {
+            return IncomingLight::NoLight;
+        }
+        IncomingLight::Ray(LightRay {
+            origin: self.position,
+            direction,
+            color: self.intensity * falloff,
+        })
+    }
+
+    /// A point light that only emits into the solid angle of its outer cone
+    fn power(&self) -> f64 {
+        PointLight::compute_power(self.intensity) * (1.0 - self.cos_outer) / 2.0
+    }
+
+    fn emit_photon(&self, rng: &mut Prng) -> LightRay {
+        // directions are uniformly distributed over the cap of the sphere inside the cone
+        let helper = if self.direction.x().abs() < 0.9 {
+            vector(1, 0, 0)
+        } else {
+            vector(0, 1, 0)
+        };
+        let u = self.direction.cross(&helper).normalized();
+        let v = self.direction.cross(&u);
+        let cos_theta = rng.gen_range(self.cos_outer, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = rng.gen_range(0.0, 2.0 * PI);
+        let direction =
+            self.direction * cos_theta + u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin());
+        LightRay {
+            origin: self.position,
+            direction,
+            color: self.intensity * self.falloff(direction),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct AmbientLight {
     intensity: Color,
@@ -289,7 +401,7 @@ impl Light for AmbientLight {
             .unwrap_or(false)
     }
 
-    fn incoming_at(&self, _: Point) -> IncomingLight {
+    fn incoming_at(&self, _: Point, _rng: &mut Prng) -> IncomingLight {
         IncomingLight::Omni(self.intensity())
     }
 
@@ -299,7 +411,7 @@ impl Light for AmbientLight {
         0.0
     }
 
-    fn emit_photon(&self) -> LightRay {
+    fn emit_photon(&self, _rng: &mut Prng) -> LightRay {
         unimplemented!()
     }
 }
@@ -350,8 +462,8 @@ impl Light for SphereLight {
             .unwrap_or(false)
     }
 
-    fn incoming_at(&self, point: Point) -> IncomingLight {
-        let p: [f64; 3] = UnitSphere.sample(&mut thread_rng());
+    fn incoming_at(&self, point: Point, rng: &mut Prng) -> IncomingLight {
+        let p: [f64; 3] = UnitSphere.sample(rng);
         let origin = self.position + self.radius * vector(p[0], p[1], p[2]);
 
         IncomingLight::Ray(LightRay {
@@ -368,8 +480,7 @@ impl Light for SphereLight {
         PointLight::compute_power(self.intensity) * 1.0
     }
 
-    fn emit_photon(&self) -> LightRay {
-        let rng = &mut thread_rng();
+    fn emit_photon(&self, rng: &mut Prng) -> LightRay {
         let p: Vector = UnitSphere.sample(rng).into();
         let d = CosineDistribution::new(p).sample(rng);
         LightRay {
@@ -435,8 +546,7 @@ impl Light for DiscLight {
             .unwrap_or(false)
     }
 
-    fn incoming_at(&self, point: Point) -> IncomingLight {
-        let rng = &mut thread_rng();
+    fn incoming_at(&self, point: Point, rng: &mut Prng) -> IncomingLight {
         let [u, v]: [f64; 2] = UnitDisc.sample(rng);
         let origin = self.position + self.width * u + self.height * v;
 
@@ -459,8 +569,7 @@ impl Light for DiscLight {
         PointLight::compute_power(self.intensity) * 1.0
     }
 
-    fn emit_photon(&self) -> LightRay {
-        let rng = &mut thread_rng();
+    fn emit_photon(&self, rng: &mut Prng) -> LightRay {
         let [u, v]: [f64; 2] = UnitDisc.sample(rng);
         let origin = self.position + self.width * u + self.height * v;
 
@@ -505,6 +614,33 @@ mod tests {
         assert_almost_eq!(beam.intensity, color(1, 1, 1));
     }
 
+    /// A spot light is full bright inside the inner cone, fades out towards the outer cone and
+    /// is dark outside
+    #[test]
+    fn spot_light_cone() {
+        let spot = SpotLight::new(
+            point(0, 0, 0),
+            vector(0, 0, 2),
+            PI / 8.0,
+            PI / 4.0,
+            color(1, 1, 1),
+        );
+        let incoming = |p| spot.incoming_at(p, &mut Prng::new(0)).intensity();
+        assert_almost_eq!(incoming(point(0, 0, 5)), color(1, 1, 1));
+        assert_almost_eq!(incoming(point(0, 1, 5)), color(1, 1, 1));
+        assert_almost_eq!(incoming(point(0, 5, 5)), color(0, 0, 0));
+        assert_almost_eq!(incoming(point(0, 0, -5)), color(0, 0, 0));
+        let dim = incoming(point(3, 0, 5)).red();
+        assert!(dim > 0.0 && dim < 1.0);
+
+        let mut rng = Prng::new(1);
+        for _ in 0..100 {
+            let photon = spot.emit_photon(&mut rng);
+            assert!(photon.direction.dot(&spot.direction()) >= (PI / 4.0).cos() - 1e-9);
+            assert_almost_eq!(photon.direction.len(), 1.0);
+        }
+    }
+
     /// beam profile
     #[test]
     fn beam_no_backlight() {
@@ -515,7 +651,8 @@ mod tests {
             color(1, 1, 1),
         );
         assert_almost_eq!(
-            beam.incoming_at(point(0, 0, -10)).intensity(),
+            beam.incoming_at(point(0, 0, -10), &mut Prng::new(0))
+                .intensity(),
             color(0, 0, 0)
         );
     }
@@ -530,19 +667,23 @@ mod tests {
             color(1, 1, 1),
         );
         assert_almost_eq!(
-            beam.incoming_at(point(0, 0, 10)).intensity(),
+            beam.incoming_at(point(0, 0, 10), &mut Prng::new(0))
+                .intensity(),
             color(1, 1, 1)
         );
         assert_almost_eq!(
-            beam.incoming_at(point(0.83255, 0, 10)).intensity(),
+            beam.incoming_at(point(0.83255, 0, 10), &mut Prng::new(0))
+                .intensity(),
             color(0.5, 0.5, 0.5)
         );
         assert_almost_eq!(
-            beam.incoming_at(point(0, -0.83255, 10)).intensity(),
+            beam.incoming_at(point(0, -0.83255, 10), &mut Prng::new(0))
+                .intensity(),
             color(0.5, 0.5, 0.5)
         );
         assert_almost_eq!(
-            beam.incoming_at(point(-0.58871, 0.58871, 10)).intensity(),
+            beam.incoming_at(point(-0.58871, 0.58871, 10), &mut Prng::new(0))
+                .intensity(),
             color(0.5, 0.5, 0.5)
         );
     }
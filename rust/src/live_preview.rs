@@ -1,11 +1,35 @@
+//! A window that shows an image while it is rendered, using SDL2. Only available with the
+//! `live-preview` feature.
+//!
+//! `navigate` also lets the user move the camera in the window:
+//!
+//! | Input                      | Action                              |
+//! |----------------------------|-------------------------------------|
+//! | left drag, arrow keys      | orbit around the target             |
+//! | right drag, A/D, R/F       | pan left/right, up/down             |
+//! | mouse wheel, W/S           | dolly towards/away from the target  |
+//! | +/-                        | zoom in/out (change field of view)  |
+//! | P                          | print the camera as YAML            |
+//!
+//! In every window, clicking a pixel prints what is seen there (see `picking`).
+
+use crate::camera::Camera;
+use crate::navigation::{Input, Navigation};
+use crate::picking::Pick;
+use crate::preview::Preview;
+use crate::progress::RenderControl;
+use crate::world::World;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum::RGB888;
-use sdl2::rect::Point;
+use sdl2::rect::{Point, Rect};
 use sdl2::render::RenderTarget;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::mpsc;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::Mutex;
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
@@ -14,6 +38,7 @@ use std::time::{Duration, Instant};
 pub enum Message {
     Clear(u8, u8, u8),
     SetPixel(i32, i32, u8, u8, u8),
+    FillRect(i32, i32, u32, u32, u8, u8, u8),
 }
 
 impl Message {
@@ -26,6 +51,11 @@ impl Message {
         let (r, g, b) = c.to_u8();
         Message::SetPixel(x as i32, y as i32, r, g, b)
     }
+
+    pub fn fill_rect(x: u32, y: u32, width: u32, height: u32, c: crate::color::Color) -> Self {
+        let (r, g, b) = c.to_u8();
+        Message::FillRect(x as i32, y as i32, width, height, r, g, b)
+    }
 }
 
 pub fn live_preview(
@@ -33,7 +63,28 @@ pub fn live_preview(
     height: u32,
     window_name: &'static str,
 ) -> (JoinHandle<()>, Sender<Message>) {
+    let (h, tx, _) = live_preview_with_input(width, height, window_name);
+    (h, tx)
+}
+
+/// Radians of orbit per pixel that the mouse is dragged
+const ORBIT_PER_PIXEL: f64 = 0.01;
+/// Radians of orbit per key press
+const ORBIT_STEP: f64 = 0.1;
+/// Pan per key press, relative to the distance to the target
+const PAN_STEP: f64 = 0.05;
+const DOLLY_STEP: f64 = 0.8;
+const ZOOM_STEP: f64 = 0.8;
+
+/// Like `live_preview`, but also returns the inputs of the user. The input channel is closed
+/// when the window is closed.
+pub fn live_preview_with_input(
+    width: u32,
+    height: u32,
+    window_name: &'static str,
+) -> (JoinHandle<()>, Sender<Message>, Receiver<Input>) {
     let (tx, rx) = mpsc::channel();
+    let (input_tx, input_rx) = mpsc::channel();
 
     let aspect = width as f64 / height as f64;
 
@@ -82,6 +133,13 @@ pub fn live_preview(
             })
             .unwrap();
 
+        let mut translator = InputTranslator {
+            width,
+            height,
+            win_w,
+            win_h,
+            click: false,
+        };
         let mut event_pump = sdl_context.event_pump().unwrap();
         'running: loop {
             for event in event_pump.poll_iter() {
@@ -91,7 +149,12 @@ pub fn live_preview(
                         keycode: Some(Keycode::Escape),
                         ..
                     } => break 'running,
-                    _ => {}
+                    event => {
+                        if let Some(input) = translator.translate(&event) {
+                            // nobody may be listening
+                            input_tx.send(input).ok();
+                        }
+                    }
                 }
             }
 
@@ -105,6 +168,9 @@ pub fn live_preview(
                     Message::SetPixel(x, y, r, g, b) => window_canvas
                         .with_texture_canvas(&mut tex, set_pixel(x, y, r, g, b))
                         .unwrap(),
+                    Message::FillRect(x, y, w, h, r, g, b) => window_canvas
+                        .with_texture_canvas(&mut tex, fill_rect(x, y, w, h, r, g, b))
+                        .unwrap(),
                 }
                 if Instant::now() > deadline {
                     break;
@@ -117,7 +183,328 @@ pub fn live_preview(
         }
     });
 
-    (h, tx)
+    (h, tx, input_rx)
+}
+
+/// Turns SDL events into inputs
+struct InputTranslator {
+    /// The size of the image
+    width: u32,
+    height: u32,
+    /// The size of the window
+    win_w: u32,
+    win_h: u32,
+    /// Whether the left mouse button is down and the mouse has not moved since
+    click: bool,
+}
+
+impl InputTranslator {
+    fn translate(&mut self, event: &Event) -> Option<Input> {
+        match *event {
+            Event::MouseButtonDown {
+                mouse_btn: MouseButton::Left,
+                ..
+            } => {
+                self.click = true;
+                None
+            }
+            Event::MouseButtonUp {
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } if self.click => {
+                self.click = false;
+                let to_image = |v: i32, win: u32, size: u32| {
+                    ((v.max(0) as u64 * size as u64 / win as u64) as u32).min(size - 1)
+                };
+                Some(Input::Pick {
+                    x: to_image(x, self.win_w, self.width),
+                    y: to_image(y, self.win_h, self.height),
+                })
+            }
+            Event::MouseMotion {
+                mousestate,
+                xrel,
+                yrel,
+                ..
+            } => {
+                self.click = false;
+                if mousestate.left() {
+                    Some(Input::Orbit {
+                        yaw: -xrel as f64 * ORBIT_PER_PIXEL,
+                        pitch: yrel as f64 * ORBIT_PER_PIXEL,
+                    })
+                } else if mousestate.right() {
+                    // drag the scene along with the mouse
+                    Some(Input::Pan {
+                        right: -xrel as f64 / self.win_w as f64,
+                        up: yrel as f64 / self.win_h as f64,
+                    })
+                } else {
+                    None
+                }
+            }
+            _ => translate_key(event),
+        }
+    }
+}
+
+fn translate_key(event: &Event) -> Option<Input> {
+    match *event {
+        Event::MouseWheel { y, .. } if y != 0 => Some(Input::Dolly(DOLLY_STEP.powi(y))),
+        Event::KeyDown {
+            keycode: Some(key), ..
+        } => match key {
+            Keycode::Left => Some(Input::Orbit {
+                yaw: -ORBIT_STEP,
+                pitch: 0.0,
+            }),
+            Keycode::Right => Some(Input::Orbit {
+                yaw: ORBIT_STEP,
+                pitch: 0.0,
+            }),
+            Keycode::Up => Some(Input::Orbit {
+                yaw: 0.0,
+                pitch: ORBIT_STEP,
+            }),
+            Keycode::Down => Some(Input::Orbit {
+                yaw: 0.0,
+                pitch: -ORBIT_STEP,
+            }),
+            Keycode::W => Some(Input::Dolly(DOLLY_STEP)),
+            Keycode::S => Some(Input::Dolly(1.0 / DOLLY_STEP)),
+            Keycode::A => Some(Input::Pan {
+                right: -PAN_STEP,
+                up: 0.0,
+            }),
+            Keycode::D => Some(Input::Pan {
+                right: PAN_STEP,
+                up: 0.0,
+            }),
+            Keycode::R => Some(Input::Pan {
+                right: 0.0,
+                up: PAN_STEP,
+            }),
+            Keycode::F => Some(Input::Pan {
+                right: 0.0,
+                up: -PAN_STEP,
+            }),
+            Keycode::Plus | Keycode::Equals | Keycode::KpPlus => Some(Input::Zoom(ZOOM_STEP)),
+            Keycode::Minus | Keycode::KpMinus => Some(Input::Zoom(1.0 / ZOOM_STEP)),
+            Keycode::P => Some(Input::PrintCamera),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A `Preview` in a window. The window stays open after the render until the user closes it.
+pub struct WindowPreview<'a> {
+    handle: Mutex<Option<JoinHandle<()>>>,
+    // It sucks to wrap the Sender in a Mutex. Ideally, each thread would have one copy
+    // of tx, but there does not seem to be an easy way to accomplish this with Rayon.
+    tx: Mutex<Sender<Message>>,
+    inputs: Mutex<Receiver<Input>>,
+    closed: AtomicBool,
+    picking: Option<(&'a Camera, &'a World)>,
+}
+
+impl<'a> WindowPreview<'a> {
+    pub fn new(width: u32, height: u32, window_name: &'static str) -> Self {
+        let (h, tx, inputs) = live_preview_with_input(width, height, window_name);
+        WindowPreview {
+            handle: Mutex::new(Some(h)),
+            tx: Mutex::new(tx),
+            inputs: Mutex::new(inputs),
+            closed: AtomicBool::new(false),
+            picking: None,
+        }
+    }
+
+    /// Print what `camera` sees at the pixels that the user clicks
+    pub fn with_picking(mut self, camera: &'a Camera, world: &'a World) -> Self {
+        self.picking = Some((camera, world));
+        self
+    }
+
+    fn handle_input(&self, input: Input) {
+        if let (Input::Pick { x, y }, Some((camera, world))) = (input, self.picking) {
+            println!("{}", Pick::new(camera, world, x, y));
+        }
+    }
+}
+
+impl Preview for WindowPreview<'_> {
+    fn set_pixel(&self, x: u32, y: u32, c: crate::color::Color) {
+        let sent = self.tx.lock().unwrap().send(Message::set_pixel(x, y, c));
+        if sent.is_err() {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+        // another thread is handling the inputs right now
+        if let Ok(inputs) = self.inputs.try_lock() {
+            inputs.try_iter().for_each(|input| self.handle_input(input));
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the window is closed
+    fn finish(&self) {
+        self.inputs
+            .lock()
+            .unwrap()
+            .iter()
+            .for_each(|input| self.handle_input(input));
+        if let Some(h) = self.handle.lock().unwrap().take() {
+            h.join().unwrap();
+        }
+    }
+}
+
+/// The image size is divided by this for the first, quick render after the camera moved
+const NAVIGATION_SCALE: u32 = 4;
+
+/// Show `camera` in a window in which the user can move it (see the module documentation).
+/// Every move restarts a progressive render, first at a lower resolution. Returns the camera
+/// at the view where the window was closed.
+pub fn navigate(camera: &Camera, world: &World, window_name: &'static str) -> Camera {
+    let (h, tx, inputs) = live_preview_with_input(camera.hsize(), camera.vsize(), window_name);
+    let mut navigation = Navigation::from_camera(camera, world);
+    let preview = NavigationPreview {
+        world,
+        view: Mutex::new(camera.clone()),
+        tx: Mutex::new(tx),
+        inputs: Mutex::new(inputs),
+        pending: Mutex::new(vec![]),
+        interrupted: AtomicBool::new(false),
+        window_closed: AtomicBool::new(false),
+        scale: AtomicU32::new(1),
+    };
+
+    let mut complete = false;
+    loop {
+        if !complete {
+            complete = preview.render(&navigation, camera, world);
+        }
+        if preview.window_closed.load(Ordering::Relaxed) {
+            break;
+        }
+        let inputs = match preview.wait_for_inputs() {
+            Some(inputs) => inputs,
+            None => break,
+        };
+        for input in inputs {
+            match input {
+                Input::PrintCamera => {
+                    println!("{}", navigation.to_yaml(camera.hsize(), camera.vsize()))
+                }
+                Input::Pick { x, y } => {
+                    println!("{}", Pick::new(&navigation.camera(camera, 1), world, x, y))
+                }
+                _ => {
+                    if navigation.apply(input) {
+                        complete = false;
+                    }
+                }
+            }
+        }
+    }
+
+    h.join().unwrap();
+    navigation.camera(camera, 1)
+}
+
+/// Shows renders at a lower resolution scaled up to the window, and interrupts them when there
+/// is new input other than picking.
+struct NavigationPreview<'a> {
+    world: &'a World,
+    /// The camera of the running render, at the full resolution
+    view: Mutex<Camera>,
+    // see `WindowPreview`
+    tx: Mutex<Sender<Message>>,
+    inputs: Mutex<Receiver<Input>>,
+    /// Inputs received during a render
+    pending: Mutex<Vec<Input>>,
+    interrupted: AtomicBool,
+    window_closed: AtomicBool,
+    scale: AtomicU32,
+}
+
+impl NavigationPreview<'_> {
+    /// Render at the reduced and then at the full resolution. Returns whether the render was
+    /// done without being interrupted.
+    fn render(&self, navigation: &Navigation, camera: &Camera, world: &World) -> bool {
+        self.interrupted.store(false, Ordering::Relaxed);
+        *self.view.lock().unwrap() = navigation.camera(camera, 1);
+        for &scale in &[NAVIGATION_SCALE, 1] {
+            let mut scaled = navigation.camera(camera, scale);
+            scaled.set_progressive(true);
+            self.scale.store(scale, Ordering::Relaxed);
+            let (_, progress) = scaled.render_with_preview(world, self, &RenderControl::default());
+            if progress.cancelled {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Block until there is input. Returns `None` once the window is closed.
+    fn wait_for_inputs(&self) -> Option<Vec<Input>> {
+        let mut inputs = std::mem::take(&mut *self.pending.lock().unwrap());
+        let receiver = self.inputs.lock().unwrap();
+        if inputs.is_empty() {
+            inputs.push(receiver.recv().ok()?);
+        }
+        inputs.extend(receiver.try_iter());
+        Some(inputs)
+    }
+}
+
+impl Preview for NavigationPreview<'_> {
+    fn set_pixel(&self, x: u32, y: u32, c: crate::color::Color) {
+        let scale = self.scale.load(Ordering::Relaxed);
+        let message = if scale == 1 {
+            Message::set_pixel(x, y, c)
+        } else {
+            Message::fill_rect(x * scale, y * scale, scale, scale, c)
+        };
+        if self.tx.lock().unwrap().send(message).is_err() {
+            self.window_closed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// New input interrupts the render, like closing the window
+    fn is_closed(&self) -> bool {
+        if self.interrupted.load(Ordering::Relaxed) {
+            return true;
+        }
+        // another thread is checking right now
+        let receiver = match self.inputs.try_lock() {
+            Ok(receiver) => receiver,
+            Err(_) => return false,
+        };
+        loop {
+            match receiver.try_recv() {
+                Ok(Input::Pick { x, y }) => {
+                    let view = self.view.lock().unwrap();
+                    println!("{}", Pick::new(&view, self.world, x, y));
+                }
+                Ok(input) => self.pending.lock().unwrap().push(input),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.window_closed.store(true, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+        let interrupted =
+            self.window_closed.load(Ordering::Relaxed) || !self.pending.lock().unwrap().is_empty();
+        self.interrupted.store(interrupted, Ordering::Relaxed);
+        interrupted
+    }
 }
 
 fn clear_canvas<T: RenderTarget>(r: u8, g: u8, b: u8) -> impl FnOnce(&mut sdl2::render::Canvas<T>) {
@@ -139,3 +526,18 @@ fn set_pixel<T: RenderTarget>(
         canvas.draw_point(Point::new(x, y)).unwrap();
     }
 }
+
+fn fill_rect<T: RenderTarget>(
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    r: u8,
+    g: u8,
+    b: u8,
+) -> impl FnOnce(&mut sdl2::render::Canvas<T>) {
+    move |canvas| {
+        canvas.set_draw_color(Color::RGB(r, g, b));
+        canvas.fill_rect(Rect::new(x, y, width, height)).unwrap();
+    }
+}
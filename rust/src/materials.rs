@@ -1,31 +1,78 @@
-use crate::approx_eq::{ApproximateEq, EPSILON};
-use crate::color::{color, Color, BLACK};
+use crate::approx_eq::ApproximateEq;
+use crate::color::{color, Color, BLACK, WHITE};
+use crate::cosine_distribution::CosineDistribution;
 use crate::lights::IncomingLight;
 use crate::pattern::Pattern;
 use crate::photon_map::TravellingPhoton;
+use crate::random::Prng;
 use crate::ray::{Intersection, IntersectionState, Ray};
 use crate::tuple::Vector;
-use crate::world::World;
-use rand::distributions::{Distribution, WeightedIndex};
-use rand::thread_rng;
+use rand::distributions::Distribution;
+use rand::Rng;
 use std::any::Any;
-use std::f64::consts::PI;
 
+/// How a surface scatters light. Materials only describe the surface; how light is
+/// transported through the scene is up to the `Integrator`.
 pub trait Material: 'static + std::fmt::Debug + Sync {
     fn as_any(&self) -> &dyn Any;
     fn box_clone(&self) -> Box<dyn Material>;
     fn is_similar(&self, other: &dyn Material) -> bool;
 
     fn color_at(&self, comps: &IntersectionState) -> Color;
+
+    /// The light from a light source that is reflected towards the eye.
     fn lighting(&self, light: IncomingLight, comps: &IntersectionState, in_shadow: bool) -> Color;
-    fn shade_hit(&self, world: &World, comps: &IntersectionState, remaining_bounces: u32) -> Color;
 
-    fn photon_hit(
-        &self,
-        photon: TravellingPhoton,
-        comps: &IntersectionState,
-        enable_diffuse: bool,
-    ) -> (Option<TravellingPhoton>, Option<TravellingPhoton>);
+    /// Light emitted by the surface itself.
+    fn emission(&self, comps: &IntersectionState) -> Color;
+
+    /// The fractions of the incoming light that are scattered diffusely, mirrored and
+    /// refracted.
+    fn scattering(&self, comps: &IntersectionState) -> Scattering;
+
+    /// The fractions of the incoming light that photons are scattered with by the
+    /// `PhotonMapping` integrator. The same as `scattering`, unless the material treats photons
+    /// differently.
+    fn photon_scattering(&self, comps: &IntersectionState) -> Scattering {
+        self.scattering(comps)
+    }
+
+    /// Randomly choose how a path continues after hitting the surface. Returns the next ray
+    /// and the factor by which light coming back along it is attenuated, or `None` if the path
+    /// is absorbed. Direct light is not included; it is sampled separately by `lighting`.
+    ///
+    /// The path is diffusely scattered, mirrored or refracted, with probabilities proportional
+    /// to the respective fractions of `scattering`. If they sum to less than one, the remainder
+    /// is the probability of absorption.
+    fn sample_scattering(&self, comps: &IntersectionState, rng: &mut Prng) -> Option<(Ray, Color)> {
+        let s = self.scattering(comps);
+        let pd_avg = s.diffuse.sum() / 3.0;
+        let total = pd_avg + s.mirror + s.transmission;
+        let scale = total.max(1.0);
+
+        let x = rng.gen::<f64>() * scale;
+        if x < pd_avg {
+            let direction = CosineDistribution::new(comps.normalv).sample(rng);
+            Some((
+                Ray::new(comps.over_point, direction),
+                s.diffuse * (scale / pd_avg),
+            ))
+        } else if x < pd_avg + s.mirror {
+            Some((
+                Ray::new(comps.over_point, comps.reflectv),
+                color(scale, scale, scale),
+            ))
+        } else if x < total {
+            // under total internal reflection, the transmitted light is reflected instead
+            let ray = match comps.refraction_direction() {
+                Some(direction) => Ray::new(comps.under_point, direction),
+                None => Ray::new(comps.over_point, comps.reflectv),
+            };
+            Some((ray, color(scale, scale, scale)))
+        } else {
+            None
+        }
+    }
 
     fn transform_photon(
         &self,
@@ -36,6 +83,30 @@ pub trait Material: 'static + std::fmt::Debug + Sync {
     }
 
     fn refractive_index(&self) -> f64;
+
+    /// Whether light can pass through the surface. Refractive indices are only needed at such
+    /// surfaces, which lets integrators skip computing them elsewhere.
+    fn is_transparent(&self) -> bool {
+        true
+    }
+
+    /// The fraction of light that passes straight through the surface, which lets partially
+    /// transparent objects cast lighter shadows. It is only used if the world has transparent
+    /// shadows enabled; otherwise, all objects block light completely.
+    fn shadow_transmittance(&self) -> Color {
+        BLACK
+    }
+}
+
+/// The fractions of the light hitting a surface that are scattered in different ways.
+#[derive(Debug, Copy, Clone)]
+pub struct Scattering {
+    /// Reflected into all directions, with cosine falloff
+    pub diffuse: Color,
+    /// Reflected like by a perfect mirror
+    pub mirror: f64,
+    /// Refracted into the object
+    pub transmission: f64,
 }
 
 impl Clone for Box<dyn Material> {
@@ -236,10 +307,37 @@ impl Phong {
         }
     }
 
+    /// The surface color at a hit. Vertex colors tint the color of the material.
     pub fn color_at(&self, comps: &IntersectionState) -> Color {
-        match &self.color {
+        let color = match &self.color {
             SurfaceColor::Flat(c) => *c,
-            SurfaceColor::Pattern(p) => comps.obj.pattern_at(p, comps.point),
+            SurfaceColor::Pattern(p) => match comps.texture_coords {
+                Some((u, v)) if p.is_uv_mapped() => p.at_uv(u, v),
+                _ => comps.obj.pattern_at(p, comps.point),
+            },
+        };
+        match comps.vertex_color {
+            Some(tint) => color * tint,
+            None => color,
+        }
+    }
+
+    /// Transparent surfaces that also reflect split the light between mirror and refraction
+    /// according to Schlick's approximation of the Fresnel equations.
+    fn scattering_with_mirror(&self, mirror: f64, comps: &IntersectionState) -> Scattering {
+        let mut mirror = mirror;
+        let mut transmission = self.transparency();
+
+        if self.reflective() > 0.0 && self.transparency() > 0.0 {
+            let r = comps.schlick();
+            mirror *= r;
+            transmission *= 1.0 - r;
+        }
+
+        Scattering {
+            diffuse: self.diffuse() * self.color_at(comps),
+            mirror,
+            transmission,
         }
     }
 
@@ -286,54 +384,6 @@ impl Phong {
             }
         }
     }
-
-    pub fn reflected_color(
-        &self,
-        world: &World,
-        comps: &IntersectionState,
-        remaining_bounces: u32,
-    ) -> Color {
-        let r = self.reflective();
-        if r == 0.0 || remaining_bounces == 0 {
-            BLACK
-        } else {
-            world
-                .color_at(
-                    &Ray::new(comps.over_point, comps.reflectv),
-                    remaining_bounces - 1,
-                )
-                .map(|c| c * r)
-                .unwrap_or(BLACK)
-        }
-    }
-
-    pub fn refracted_color(
-        &self,
-        world: &World,
-        comps: &IntersectionState,
-        remaining_bounces: u32,
-    ) -> Color {
-        if remaining_bounces == 0 || self.transparency() == 0.0 {
-            color(0, 0, 0)
-        } else {
-            let n_ratio = comps.n1 / comps.n2;
-            let cos_i = comps.eyev.dot(&comps.normalv);
-            let sin2_t = n_ratio * n_ratio * (1.0 - cos_i * cos_i);
-            if sin2_t > 1.0 {
-                color(0, 0, 0)
-            } else {
-                let cos_t = (1.0 - sin2_t).sqrt();
-                let direction = comps.normalv * (n_ratio * cos_i - cos_t) - comps.eyev * n_ratio;
-                world
-                    .color_at(
-                        &Ray::new(comps.under_point, direction),
-                        remaining_bounces - 1,
-                    )
-                    .map(|c| c * self.transparency())
-                    .unwrap_or(BLACK)
-            }
-        }
-    }
 }
 
 impl Material for Phong {
@@ -368,104 +418,31 @@ impl Material for Phong {
         )
     }
 
-    fn shade_hit(&self, world: &World, comps: &IntersectionState, remaining_bounces: u32) -> Color {
-        let surface_color = self.color_at(&comps);
-
-        let mut surface = BLACK;
-
-        if world.photon_map_enabled() {
-            if let Some((pm, n_nearest)) = world.get_photon_map() {
-                let (photons, square_radius) = pm.find_nearest(n_nearest, comps.point);
-                let total_light = photons.iter().fold(BLACK, |color, photon| {
-                    color + comps.normalv.dot(&photon.direction()).max(0.0) * photon.power()
-                });
-                surface = surface + surface_color * total_light / (PI * square_radius);
-            }
-        }
-
-        if world.direct_illumination_enabled() {
-            surface = surface
-                + world.lights().iter().fold(BLACK, |color, light| {
-                    let incoming_light = light.incoming_at(comps.over_point);
-                    let in_shadow = world.is_shadowed(&incoming_light, comps.over_point);
-                    color
-                        + comps
-                            .obj
-                            .material()
-                            .lighting(incoming_light, &comps, in_shadow)
-                });
-        }
-
-        surface = surface.clip(0.0, 1.0);
-
-        let emissive = surface_color * self.emissive;
-
-        let reflected = self.reflected_color(world, &comps, remaining_bounces);
-        let refracted = self.refracted_color(world, &comps, remaining_bounces);
-
-        if self.reflective() > 0.0 && self.transparency() > 0.0 {
-            let reflectance = comps.schlick();
-            surface + reflected * reflectance + refracted * (1.0 - reflectance) + emissive
-        } else {
-            surface + reflected + refracted + emissive
-        }
+    fn emission(&self, comps: &IntersectionState) -> Color {
+        self.color_at(comps) * self.emissive
     }
 
-    fn photon_hit(
-        &self,
-        photon: TravellingPhoton,
-        comps: &IntersectionState,
-        enable_diffuse: bool,
-    ) -> (Option<TravellingPhoton>, Option<TravellingPhoton>) {
-        let mut stored_photon = None;
-        let next_photon;
-
-        let diffuse_reflectance = self.diffuse() * self.color_at(comps);
-        let mut specular_reflectance = self.specular().max(self.reflective()); // TODO: Is this correct?
-        let mut transmittance = self.transparency();
-
-        let mut pd_avg = diffuse_reflectance.sum() / 3.0;
-
-        if pd_avg > EPSILON {
-            stored_photon = Some(photon);
-        }
-
-        if !enable_diffuse {
-            pd_avg = 0.0;
-        }
-
-        if self.reflective() > 0.0 && self.transparency() > 0.0 {
-            let r = comps.schlick();
-            specular_reflectance *= r;
-            transmittance *= 1.0 - r;
-        }
-
-        let p_absorb = 1.0 - pd_avg - specular_reflectance - transmittance;
-        assert!(p_absorb >= 0.0);
-        assert!(p_absorb <= 1.0);
-
-        let dist =
-            WeightedIndex::new(&[p_absorb, pd_avg, specular_reflectance, transmittance]).unwrap();
-        match dist.sample(&mut thread_rng()) {
-            0 => next_photon = None,
-            1 => {
-                next_photon =
-                    Some(photon.scatter(comps.over_point, comps.normalv, diffuse_reflectance))
-            }
-            2 => next_photon = Some(photon.reflect(comps.over_point, comps.normalv)),
-            3 => {
-                next_photon =
-                    Some(photon.refract(comps.under_point, comps.normalv, comps.n1, comps.n2))
-            }
-            _ => unreachable!(),
-        };
+    fn scattering(&self, comps: &IntersectionState) -> Scattering {
+        self.scattering_with_mirror(self.reflective(), comps)
+    }
 
-        (stored_photon, next_photon)
+    /// Photons are mirrored by specular surfaces, too: the specular highlights of the Phong
+    /// model stand for the reflection of the light sources.
+    fn photon_scattering(&self, comps: &IntersectionState) -> Scattering {
+        self.scattering_with_mirror(self.specular().max(self.reflective()), comps)
     }
 
     fn refractive_index(&self) -> f64 {
         Phong::refractive_index(self)
     }
+
+    fn is_transparent(&self) -> bool {
+        self.transparency() > 0.0
+    }
+
+    fn shadow_transmittance(&self) -> Color {
+        WHITE * self.transparency()
+    }
 }
 
 #[cfg(test)]
@@ -475,7 +452,9 @@ mod tests {
     use crate::color::color;
     use crate::lights::Light;
     use crate::lights::PointLight;
+    use crate::shapes::sphere;
     use crate::tuple::{point, vector};
+    use std::f64::consts::FRAC_1_SQRT_2;
 
     /// The default material
     #[test]
@@ -501,7 +480,7 @@ mod tests {
         let light = PointLight::new(point(0, 0, -10), color(1, 1, 1));
         let result = m.lighting(
             color(1.0, 1.0, 1.0),
-            light.incoming_at(pos),
+            light.incoming_at(pos, &mut Prng::new(0)),
             eyev,
             normalv,
             false,
@@ -523,7 +502,7 @@ mod tests {
         let light = PointLight::new(point(0, 0, -10), color(1, 1, 1));
         let result = m.lighting(
             color(1.0, 1.0, 1.0),
-            light.incoming_at(pos),
+            light.incoming_at(pos, &mut Prng::new(0)),
             eyev,
             normalv,
             false,
@@ -541,7 +520,7 @@ mod tests {
         let light = PointLight::new(point(0, 10, -10), color(1, 1, 1));
         let result = m.lighting(
             color(1.0, 1.0, 1.0),
-            light.incoming_at(pos),
+            light.incoming_at(pos, &mut Prng::new(0)),
             eyev,
             normalv,
             false,
@@ -563,7 +542,7 @@ mod tests {
         let light = PointLight::new(point(0, 10, -10), color(1, 1, 1));
         let result = m.lighting(
             color(1.0, 1.0, 1.0),
-            light.incoming_at(pos),
+            light.incoming_at(pos, &mut Prng::new(0)),
             eyev,
             normalv,
             false,
@@ -581,7 +560,7 @@ mod tests {
         let light = PointLight::new(point(0, 0, 10), color(1, 1, 1));
         let result = m.lighting(
             color(1.0, 1.0, 1.0),
-            light.incoming_at(pos),
+            light.incoming_at(pos, &mut Prng::new(0)),
             eyev,
             normalv,
             false,
@@ -589,6 +568,32 @@ mod tests {
         assert_almost_eq!(result, BLACK);
     }
 
+    /// Paths that cannot leave a transparent object are reflected back inside
+    #[test]
+    fn sample_total_internal_reflection() {
+        let s = sphere().with_material(
+            Phong::default()
+                .with_diffuse(0.0)
+                .with_transparency(1.0)
+                .with_refractive_index(1.5),
+        );
+        let r = Ray::new(point(0, 0, FRAC_1_SQRT_2), vector(0, 1, 0));
+        let xs = intersections![
+            Intersection::new(-FRAC_1_SQRT_2, &s),
+            Intersection::new(FRAC_1_SQRT_2, &s)
+        ];
+        let comps = xs[1].prepare_computations(&r, &xs);
+        assert!(comps.refraction_direction().is_none());
+
+        let rng = &mut Prng::new(0);
+        for _ in 0..10 {
+            let (ray, weight) = s.material().sample_scattering(&comps, rng).unwrap();
+            assert_almost_eq!(ray.direction(), comps.reflectv);
+            assert_almost_eq!(ray.origin(), comps.over_point);
+            assert_almost_eq!(weight, color(1, 1, 1));
+        }
+    }
+
     /// Lighting with the surface in shadow
     #[test]
     fn shadow() {
@@ -599,7 +604,7 @@ mod tests {
         let light = PointLight::new(point(0, 0, -10), color(1, 1, 1));
         let result = m.lighting(
             color(1.0, 1.0, 1.0),
-            light.incoming_at(pos),
+            light.incoming_at(pos, &mut Prng::new(0)),
             eyev,
             normalv,
             true,
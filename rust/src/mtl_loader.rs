@@ -0,0 +1,412 @@
+//! Reading material libraries (MTL files) that accompany Wavefront OBJ files.
+//!
+//! The materials are mapped onto `Phong` materials:
+//!
+//! - `Kd` becomes the surface color, with a diffuse factor of one
+//! - the average of `Ks` becomes the specular factor, and `Ns` the shininess
+//! - `Ke` becomes the emission, relative to the surface color
+//! - `d` (dissolve) and `Tr` set the transparency, `Ni` the refractive index
+//! - `illum` 0 and 1 turn off highlights, and `illum` 3 and above make the surface reflect
+//!   with the strength of `Ks`
+//! - `map_Kd` replaces the surface color with an image texture
+//!
+//! Properties that are not specified keep the values of `Phong::default()`. Other records,
+//! such as ambient colors and other texture maps, are ignored.
+
+use crate::canvas::Canvas;
+use crate::color::{color, Color};
+use crate::materials::Phong;
+use crate::pattern::image_pattern;
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Default, Clone)]
+pub struct MaterialLibrary {
+    materials: BTreeMap<String, Phong>,
+    ignored: Vec<String>,
+}
+
+/// An error in an MTL file, with the line it was found on (counted from 1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtlError {
+    pub line: usize,
+    pub kind: MtlErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MtlErrorKind {
+    /// A value that should have been a number
+    InvalidNumber(String),
+    /// A record with fewer values than required
+    MissingValues {
+        keyword: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// A material property before the first `newmtl` record
+    NoMaterial,
+    /// A texture image that could not be read
+    Texture { file: String, message: String },
+}
+
+impl fmt::Display for MtlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            MtlErrorKind::InvalidNumber(s) => write!(f, "expected a number, found `{}`", s),
+            MtlErrorKind::MissingValues {
+                keyword,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` needs at least {} values, found {}",
+                keyword, expected, found
+            ),
+            MtlErrorKind::NoMaterial => write!(f, "material property before `newmtl`"),
+            MtlErrorKind::Texture { file, message } => {
+                write!(f, "unable to read texture `{}`: {}", file, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MtlError {}
+
+/// The properties of a material as given in the file, before mapping them onto `Phong`
+#[derive(Default)]
+struct MtlMaterial {
+    diffuse: Option<Color>,
+    specular: Option<Color>,
+    shininess: Option<f64>,
+    emission: Option<Color>,
+    transparency: Option<f64>,
+    refractive_index: Option<f64>,
+    illum: Option<u32>,
+    texture: Option<Canvas>,
+}
+
+impl MaterialLibrary {
+    pub fn new() -> Self {
+        MaterialLibrary::default()
+    }
+
+    /// Parse the contents of an MTL file. Texture images named by `map_Kd` are read with
+    /// `read_image`, which receives the file name as written in the library.
+    pub fn parse_str(
+        input: &str,
+        mut read_image: impl FnMut(&str) -> std::io::Result<Canvas>,
+    ) -> Result<Self, MtlError> {
+        let mut library = MaterialLibrary::new();
+        let mut current: Option<(String, MtlMaterial)> = None;
+
+        for (n, line) in input.lines().enumerate() {
+            let error = |kind| MtlError { line: n + 1, kind };
+
+            let content = line.split('#').next().unwrap_or("");
+            let mut fields = content.split_whitespace();
+            let keyword = match fields.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let args: Vec<&str> = fields.collect();
+
+            if keyword == "newmtl" {
+                if let Some((name, m)) = current.take() {
+                    library.materials.insert(name, m.into_phong());
+                }
+                let name = args.join(" ");
+                if name.is_empty() {
+                    return Err(error(missing_values("newmtl", 1, 0)));
+                }
+                current = Some((name, MtlMaterial::default()));
+                continue;
+            }
+
+            let m = match (&mut current, keyword) {
+                (Some((_, m)), _) => m,
+                (None, "Kd")
+                | (None, "Ks")
+                | (None, "Ke")
+                | (None, "Ns")
+                | (None, "Ni")
+                | (None, "d")
+                | (None, "Tr")
+                | (None, "illum")
+                | (None, "map_Kd") => return Err(error(MtlErrorKind::NoMaterial)),
+                (None, _) => {
+                    library.ignored.push(line.trim().to_string());
+                    continue;
+                }
+            };
+
+            match keyword {
+                "Kd" => m.diffuse = Some(parse_color("Kd", &args).map_err(error)?),
+                "Ks" => m.specular = Some(parse_color("Ks", &args).map_err(error)?),
+                "Ke" => m.emission = Some(parse_color("Ke", &args).map_err(error)?),
+                "Ns" => m.shininess = Some(parse_number("Ns", &args).map_err(error)?),
+                "Ni" => m.refractive_index = Some(parse_number("Ni", &args).map_err(error)?),
+                "d" => {
+                    // `d -halo 0.5` describes a dissolve that depends on the view angle
+                    let args = if args.first() == Some(&"-halo") {
+                        &args[1..]
+                    } else {
+                        &args[..]
+                    };
+                    m.transparency = Some(1.0 - parse_number("d", args).map_err(error)?)
+                }
+                "Tr" => m.transparency = Some(parse_number("Tr", &args).map_err(error)?),
+                "illum" => {
+                    let illum = parse_number("illum", &args).map_err(error)?;
+                    m.illum = Some(illum.max(0.0) as u32)
+                }
+                "map_Kd" => {
+                    // options such as `-s 2 2 1` precede the file name, and are not supported
+                    let file = match args.last() {
+                        Some(file) => file,
+                        None => return Err(error(missing_values("map_Kd", 1, 0))),
+                    };
+                    let image = read_image(file).map_err(|e| {
+                        error(MtlErrorKind::Texture {
+                            file: file.to_string(),
+                            message: e.to_string(),
+                        })
+                    })?;
+                    m.texture = Some(image)
+                }
+                _ => library.ignored.push(line.trim().to_string()),
+            }
+        }
+
+        if let Some((name, m)) = current {
+            library.materials.insert(name, m.into_phong());
+        }
+        Ok(library)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Phong> {
+        self.materials.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.materials.keys().map(String::as_str)
+    }
+
+    /// Add the materials of another library, replacing materials of the same name.
+    pub fn extend(&mut self, other: MaterialLibrary) {
+        self.materials.extend(other.materials);
+        self.ignored.extend(other.ignored);
+    }
+
+    /// Unrecognized records
+    pub fn ignored(&self) -> &[String] {
+        &self.ignored
+    }
+}
+
+impl MtlMaterial {
+    fn into_phong(self) -> Phong {
+        let mut phong = Phong::default();
+        let average = |c: Color| c.sum() / 3.0;
+
+        if let Some(kd) = self.diffuse {
+            phong = phong.with_color(kd).with_diffuse(1.0);
+        }
+        if let Some(ks) = self.specular {
+            phong = phong.with_specular(average(ks));
+        }
+        if let Some(ns) = self.shininess {
+            phong = phong.with_shininess(ns);
+        }
+        if let Some(ke) = self.emission.filter(|ke| !ke.is_black()) {
+            // Phong emits light in its surface color
+            match self.diffuse.filter(|kd| !kd.is_black()) {
+                Some(kd) => phong = phong.with_emissive(ke.sum() / kd.sum()),
+                None => phong = phong.with_color(ke).with_emissive(1.0).with_diffuse(0.0),
+            }
+        }
+        if let Some(transparency) = self.transparency {
+            phong = phong.with_transparency(transparency.clamp(0.0, 1.0));
+        }
+        if let Some(ni) = self.refractive_index {
+            phong = phong.with_refractive_index(ni);
+        }
+        match self.illum {
+            Some(0) => {
+                phong = phong
+                    .with_emissive(1.0)
+                    .with_diffuse(0.0)
+                    .with_specular(0.0)
+            }
+            Some(1) => phong = phong.with_specular(0.0),
+            Some(illum) if illum >= 3 => {
+                let reflective = self.specular.map(average).unwrap_or(0.0);
+                phong = phong.with_reflective(reflective)
+            }
+            _ => {}
+        }
+        if let Some(image) = self.texture {
+            phong = phong.with_pattern(image_pattern(image));
+        }
+        phong
+    }
+}
+
+fn missing_values(keyword: &'static str, expected: usize, found: usize) -> MtlErrorKind {
+    MtlErrorKind::MissingValues {
+        keyword,
+        expected,
+        found,
+    }
+}
+
+fn parse_number(keyword: &'static str, args: &[&str]) -> Result<f64, MtlErrorKind> {
+    match args.first() {
+        Some(s) => s
+            .parse()
+            .map_err(|_| MtlErrorKind::InvalidNumber(s.to_string())),
+        None => Err(missing_values(keyword, 1, 0)),
+    }
+}
+
+/// A color of three components, or a single value for gray.
+fn parse_color(keyword: &'static str, args: &[&str]) -> Result<Color, MtlErrorKind> {
+    let values = args
+        .iter()
+        .take(3)
+        .map(|s| {
+            s.parse()
+                .map_err(|_| MtlErrorKind::InvalidNumber(s.to_string()))
+        })
+        .collect::<Result<Vec<f64>, _>>()?;
+    match values[..] {
+        [] => Err(missing_values(keyword, 1, 0)),
+        [gray] => Ok(color(gray, gray, gray)),
+        [_, _] => Err(missing_values(keyword, 3, 2)),
+        _ => Ok(color(values[0], values[1], values[2])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::materials::SurfaceColor;
+
+    fn no_images(file: &str) -> std::io::Result<Canvas> {
+        panic!("unexpected texture {}", file)
+    }
+
+    /// Material properties are mapped onto Phong parameters
+    #[test]
+    fn phong_parameters() {
+        let data = "\
+            # a comment
+            newmtl red
+            Ka 0.1 0.1 0.1
+            Kd 0.8 0.1 0.1
+            Ks 0.3 0.3 0.3
+            Ns 50
+            illum 2
+
+            newmtl glass
+            Kd 0 0 0
+            Ks 0.9 0.9 0.9
+            d 0.1
+            Ni 1.5
+            illum 4
+
+            newmtl lamp
+            Ke 2 2 1.5
+            Tr 0.25
+
+            newmtl matte gray
+            Kd 0.5
+            illum 1";
+        let lib = MaterialLibrary::parse_str(data, no_images).unwrap();
+        assert_eq!(
+            lib.names().collect::<Vec<_>>(),
+            vec!["glass", "lamp", "matte gray", "red"]
+        );
+        assert_eq!(lib.ignored(), &["Ka 0.1 0.1 0.1"]);
+
+        let red = lib.get("red").unwrap();
+        assert_almost_eq!(red.color(), &SurfaceColor::Flat(color(0.8, 0.1, 0.1)));
+        assert_almost_eq!(red.diffuse(), 1.0);
+        assert_almost_eq!(red.specular(), 0.3);
+        assert_almost_eq!(red.shininess(), 50.0);
+        assert_almost_eq!(red.reflective(), 0.0);
+        assert_almost_eq!(red.transparency(), 0.0);
+
+        let glass = lib.get("glass").unwrap();
+        assert_almost_eq!(glass.transparency(), 0.9);
+        assert_almost_eq!(glass.refractive_index(), 1.5);
+        assert_almost_eq!(glass.reflective(), 0.9);
+
+        let lamp = lib.get("lamp").unwrap();
+        assert_almost_eq!(lamp.color(), &SurfaceColor::Flat(color(2, 2, 1.5)));
+        assert_almost_eq!(lamp.emissive(), 1.0);
+        assert_almost_eq!(lamp.diffuse(), 0.0);
+        assert_almost_eq!(lamp.transparency(), 0.25);
+
+        let gray = lib.get("matte gray").unwrap();
+        assert_almost_eq!(gray.color(), &SurfaceColor::Flat(color(0.5, 0.5, 0.5)));
+        assert_almost_eq!(gray.specular(), 0.0);
+    }
+
+    /// Diffuse texture maps become image patterns
+    #[test]
+    fn texture_map() {
+        let data = "\
+            newmtl wood
+            Kd 1 1 1
+            map_Kd -s 1 1 1 textures/wood.png";
+        let mut requested = vec![];
+        let lib = MaterialLibrary::parse_str(data, |file| {
+            requested.push(file.to_string());
+            let mut image = Canvas::new(1, 1);
+            image.set_pixel(0, 0, color(0.5, 0.25, 0));
+            Ok(image)
+        })
+        .unwrap();
+        assert_eq!(requested, vec!["textures/wood.png"]);
+        match lib.get("wood").unwrap().color() {
+            SurfaceColor::Pattern(p) => {
+                assert!(p.is_uv_mapped());
+                assert_almost_eq!(p.at_uv(0.3, 0.6), color(0.5, 0.25, 0));
+            }
+            _ => panic!("expected a texture"),
+        }
+    }
+
+    /// Malformed input is reported with the line number
+    #[test]
+    fn malformed_input() {
+        use MtlErrorKind::*;
+        let cases = vec![
+            ("Kd 1 1 1", 1, NoMaterial),
+            ("newmtl", 1, missing_values("newmtl", 1, 0)),
+            ("newmtl a\nKd 1 x 1", 2, InvalidNumber("x".into())),
+            ("newmtl a\nKd 1 1", 2, missing_values("Kd", 3, 2)),
+            ("newmtl a\nNs", 2, missing_values("Ns", 1, 0)),
+            ("newmtl a\n\nmap_Kd", 3, missing_values("map_Kd", 1, 0)),
+            (
+                "newmtl a\nmap_Kd missing.png",
+                2,
+                Texture {
+                    file: "missing.png".into(),
+                    message: "not found".into(),
+                },
+            ),
+        ];
+        for (input, line, kind) in cases {
+            let result = MaterialLibrary::parse_str(input, |_| {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    "not found",
+                ))
+            });
+            assert_eq!(result.err(), Some(MtlError { line, kind }), "{}", input);
+        }
+    }
+}
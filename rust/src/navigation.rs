@@ -0,0 +1,279 @@
+//! Moving a camera around a scene interactively.
+//!
+//! `Navigation` keeps the camera as a position (`from`), a point it orbits around (`to`), an
+//! up vector and a field of view, like the camera of a scene file. `Input`s orbit, pan, dolly
+//! and zoom it. The live preview window (`live_preview::navigate`) turns keys and mouse moves
+//! into inputs, but the navigation itself does not depend on a window.
+
+use crate::camera::{Camera, Projection};
+use crate::random::Prng;
+use crate::tuple::{point, vector, Point, Vector};
+use crate::world::World;
+use std::f64::consts::PI;
+
+/// A change of the camera, or a request to print something
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Input {
+    /// Move around the target: `yaw` (radians) around the up vector, `pitch` towards it
+    Orbit { yaw: f64, pitch: f64 },
+    /// Move the camera and the target sideways, in multiples of their distance
+    Pan { right: f64, up: f64 },
+    /// Multiply the distance to the target
+    Dolly(f64),
+    /// Multiply the field of view
+    Zoom(f64),
+    /// Print the camera as it would appear in a scene file
+    PrintCamera,
+    /// Print what is seen at a pixel of the full size image (see `picking`)
+    Pick { x: u32, y: u32 },
+}
+
+/// The smallest angle between the view direction and the up vector
+const MIN_POLAR_ANGLE: f64 = 0.01;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Navigation {
+    from: Point,
+    to: Point,
+    up: Vector,
+    field_of_view: f64,
+    /// The width of the view plane, for orthographic cameras
+    view_width: Option<f64>,
+}
+
+impl Navigation {
+    pub fn new(from: Point, to: Point, up: Vector, field_of_view: f64) -> Self {
+        Navigation {
+            from,
+            to,
+            up: up.normalized(),
+            field_of_view,
+            view_width: None,
+        }
+    }
+
+    /// Start at the view of `camera`, orbiting around the first object in the center of the
+    /// image (or the origin of the world, if there is none).
+    pub fn from_camera(camera: &Camera, world: &World) -> Self {
+        let inv = camera.inv_transform();
+        let from = *inv * point(0, 0, 0);
+        let forward = (*inv * vector(0, 0, -1)).normalized();
+        let up = *inv * vector(0, 1, 0);
+
+        let center = camera.ray_for_pixel(
+            camera.hsize() / 2,
+            camera.vsize() / 2,
+            false,
+            &mut Prng::new(0),
+        );
+        let distance = match world.nearest_hit(&center) {
+            Some(hit) => hit.t,
+            None => (from - point(0, 0, 0)).len(),
+        };
+        let mut navigation = Navigation::new(
+            from,
+            from + forward * distance.max(1e-3),
+            up,
+            camera.field_of_view(),
+        );
+        if camera.projection() == Projection::Orthographic {
+            navigation.view_width = Some(camera.view_width());
+        }
+        navigation
+    }
+
+    pub fn from(&self) -> Point {
+        self.from
+    }
+
+    pub fn to(&self) -> Point {
+        self.to
+    }
+
+    pub fn up(&self) -> Vector {
+        self.up
+    }
+
+    pub fn field_of_view(&self) -> f64 {
+        self.field_of_view
+    }
+
+    pub fn view_width(&self) -> Option<f64> {
+        self.view_width
+    }
+
+    /// Apply an input. Returns whether the camera changed.
+    pub fn apply(&mut self, input: Input) -> bool {
+        match input {
+            Input::Orbit { yaw, pitch } => self.orbit(yaw, pitch),
+            Input::Pan { right, up } => self.pan(right, up),
+            Input::Dolly(factor) => self.dolly(factor),
+            Input::Zoom(factor) => self.zoom(factor),
+            Input::PrintCamera | Input::Pick { .. } => return false,
+        }
+        true
+    }
+
+    pub fn orbit(&mut self, yaw: f64, pitch: f64) {
+        let offset = rotate(self.from - self.to, self.up, yaw);
+        // stop short of looking straight along the up vector, where the view flips
+        let polar = (offset.dot(&self.up) / offset.len())
+            .clamp(-1.0, 1.0)
+            .acos();
+        let new_polar = (polar - pitch).clamp(MIN_POLAR_ANGLE, PI - MIN_POLAR_ANGLE);
+        let axis = offset.cross(&self.up);
+        let offset = if axis.len() > 0.0 {
+            rotate(offset, axis.normalized(), polar - new_polar)
+        } else {
+            offset
+        };
+        self.from = self.to + offset;
+    }
+
+    pub fn pan(&mut self, right: f64, up: f64) {
+        let offset = self.to - self.from;
+        let forward = offset.normalized();
+        // `Matrix::view` calls `forward x up` left, and so does the image
+        let left = forward.cross(&self.up).normalized();
+        let true_up = left.cross(&forward);
+        let shift = (true_up * up - left * right) * offset.len();
+        self.from = self.from + shift;
+        self.to = self.to + shift;
+    }
+
+    pub fn dolly(&mut self, factor: f64) {
+        let offset = self.from - self.to;
+        let distance = (offset.len() * factor).max(1e-3);
+        self.from = self.to + offset.normalized() * distance;
+    }
+
+    /// Change the field of view, or the view width of orthographic cameras
+    pub fn zoom(&mut self, factor: f64) {
+        match &mut self.view_width {
+            Some(width) => *width = (*width * factor).max(1e-6),
+            None => self.field_of_view = (self.field_of_view * factor).clamp(0.01, PI - 0.01),
+        }
+    }
+
+    /// A copy of `camera` at the navigated view, with the image size divided by `scale`
+    pub fn camera(&self, camera: &Camera, scale: u32) -> Camera {
+        let width = (camera.hsize() / scale).max(1);
+        let height = (camera.vsize() / scale).max(1);
+        match self.view_width {
+            Some(view_width) => camera.resized_orthographic(width, height, view_width),
+            None => camera.resized(width, height, self.field_of_view),
+        }
+        .with_view_transform(self.from, self.to, self.up)
+    }
+
+    /// The camera as an entry of a YAML scene file
+    pub fn to_yaml(&self, width: u32, height: u32) -> String {
+        let coordinates = |x: f64, y: f64, z: f64| format!("[{:.6}, {:.6}, {:.6}]", x, y, z);
+        let projection = match self.view_width {
+            Some(view_width) => {
+                format!("projection: orthographic\n  view-width: {:.10}", view_width)
+            }
+            None => format!("field-of-view: {:.10}", self.field_of_view),
+        };
+        format!(
+            "- add: camera\n  width: {}\n  height: {}\n  {}\n  from: {}\n  to: {}\n  up: {}\n",
+            width,
+            height,
+            projection,
+            coordinates(self.from.x(), self.from.y(), self.from.z()),
+            coordinates(self.to.x(), self.to.y(), self.to.z()),
+            coordinates(self.up.x(), self.up.y(), self.up.z()),
+        )
+    }
+}
+
+/// Rotate `v` by `angle` around the unit vector `axis` (Rodrigues' formula)
+fn rotate(v: Vector, axis: Vector, angle: f64) -> Vector {
+    let (sin, cos) = angle.sin_cos();
+    v * cos + axis.cross(&v) * sin + axis * (axis.dot(&v) * (1.0 - cos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::matrix::Matrix;
+
+    fn navigation() -> Navigation {
+        Navigation::new(point(0, 0, -5), point(0, 0, 0), vector(0, 1, 0), PI / 3.0)
+    }
+
+    /// Orbiting keeps the distance to the target and stops above the top
+    #[test]
+    fn orbit() {
+        let mut nav = navigation();
+        nav.orbit(PI / 2.0, 0.0);
+        assert_almost_eq!(nav.from(), point(-5, 0, 0));
+        nav.orbit(0.0, PI / 4.0);
+        let s = 5.0 * (PI / 4.0).sin();
+        assert_almost_eq!(nav.from(), point(-s, s, 0));
+        nav.orbit(0.0, PI);
+        assert!((nav.from() - nav.to()).len() - 5.0 < 1e-9);
+        assert!(nav.from().y() < 5.0 && nav.from().y() > 4.99);
+        assert_almost_eq!(nav.to(), point(0, 0, 0));
+    }
+
+    /// Panning moves camera and target in the image plane, dolly and zoom change distance and
+    /// field of view
+    #[test]
+    fn pan_dolly_zoom() {
+        let mut nav = navigation();
+        assert!(nav.apply(Input::Pan {
+            right: 0.2,
+            up: 0.1
+        }));
+        assert_almost_eq!(nav.from(), point(1, 0.5, -5));
+        assert_almost_eq!(nav.to(), point(1, 0.5, 0));
+        assert!(nav.apply(Input::Dolly(0.5)));
+        assert_almost_eq!(nav.from(), point(1, 0.5, -2.5));
+        assert!(nav.apply(Input::Zoom(0.5)));
+        assert!((nav.field_of_view() - PI / 6.0).abs() < 1e-12);
+        assert!(!nav.apply(Input::PrintCamera));
+
+        // panning to the right moved towards +x, which is on the right of the image
+        let camera = navigation().camera(&Camera::new(100, 50, PI / 3.0), 1);
+        let ray = camera.ray_for_pixel(99, 25, false, &mut Prng::new(0));
+        assert!(ray.direction().x() > 0.0);
+    }
+
+    /// The navigation starts at the view of a camera and produces cameras of any size
+    #[test]
+    fn cameras() {
+        let camera = Camera::new(101, 51, PI / 2.0).with_view_transform(
+            point(0, 0, -5),
+            point(0, 0, 0),
+            vector(0, 1, 0),
+        );
+        let world = World::default();
+        let nav = Navigation::from_camera(&camera, &world);
+        assert_almost_eq!(nav.from(), point(0, 0, -5));
+        assert_almost_eq!(nav.to(), point(0, 0, -1));
+        assert_almost_eq!(nav.up(), vector(0, 1, 0));
+
+        let small = nav.camera(&camera, 4);
+        assert_eq!((small.hsize(), small.vsize()), (25, 12));
+        assert_eq!(small.field_of_view(), PI / 2.0);
+        let expected = Matrix::view(point(0, 0, -5), point(0, 0, -1), vector(0, 1, 0));
+        assert_almost_eq!(*small.transform(), expected);
+
+        let yaml = nav.to_yaml(101, 51);
+        assert!(yaml.contains("from: [0.000000, 0.000000, -5.000000]"));
+        assert!(yaml.contains("to: [0.000000, 0.000000, -1.000000]"));
+
+        // orthographic cameras zoom by changing their view width
+        let camera = Camera::orthographic(101, 51, 4.0).with_transform(*camera.transform());
+        let mut nav = Navigation::from_camera(&camera, &world);
+        nav.zoom(0.5);
+        let zoomed = nav.camera(&camera, 1);
+        assert_eq!(zoomed.projection(), Projection::Orthographic);
+        assert_almost_eq!(zoomed.view_width(), 2.0);
+        assert!(nav
+            .to_yaml(101, 51)
+            .contains("projection: orthographic\n  view-width: 2.0000000000\n"));
+    }
+}
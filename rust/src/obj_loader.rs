@@ -1,13 +1,115 @@
-use crate::shapes::{smooth_triangle, triangle, Group, SceneItem};
+//! Reading triangle meshes from Wavefront OBJ files.
+//!
+//! Vertices (`v`), texture coordinates (`vt`), normals (`vn`) and polygonal faces (`f`) are
+//! read, with any of the index forms `v`, `v/vt`, `v//vn` and `v/vt/vn`. Negative indices count
+//! back from the last element defined so far. Faces are split into triangle fans and collected
+//! in one group per `g` or `o` record. Material names (`usemtl`) and libraries (`mtllib`) are
+//! recorded; the caller loads the libraries and applies them with `set_materials`. Smoothing
+//! groups (`s`) are accepted but have no effect, because smooth shading relies on the normals
+//! in the file. Other records are ignored.
+//!
+//! The faces can be turned into a group with a shape per triangle, or with `meshes` into
+//! indexed meshes that share the vertex data, which needs far less memory for large models.
+
+use crate::mtl_loader::MaterialLibrary;
+use crate::shapes::{Group, Mesh, SceneItem, Shape, SmoothTriangle, Triangle};
 use crate::tuple::{point, vector, Point, Vector};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+const DEFAULT_GROUP: &str = "default_group";
 
 pub struct ObjParser<'a> {
     ignored: Vec<&'a str>,
     vertices: Vec<Point>,
     normals: Vec<Vector>,
-    groups: BTreeMap<&'a str, Group>,
+    texture_coords: Vec<(f64, f64)>,
+    groups: BTreeMap<&'a str, Vec<Face<'a>>>,
     current_group: &'a str,
+    current_material: Option<&'a str>,
+    material_libraries: Vec<&'a str>,
+    materials: MaterialLibrary,
+}
+
+/// An error in an OBJ file, with the line it was found on (counted from 1).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjError {
+    pub line: usize,
+    pub kind: ObjErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjErrorKind {
+    /// A value that should have been a number
+    InvalidNumber(String),
+    /// A number that is infinite or not a number, which cannot be rendered
+    NonFiniteNumber(String),
+    /// A face vertex that is not of the form `v`, `v/vt`, `v//vn` or `v/vt/vn`
+    InvalidFaceVertex(String),
+    /// A record with fewer values than required
+    MissingValues {
+        keyword: &'static str,
+        expected: usize,
+        found: usize,
+    },
+    /// An index of zero, or one that refers to an element that was not defined before
+    IndexOutOfRange {
+        element: &'static str,
+        index: i64,
+        count: usize,
+    },
+    /// A face where only some of the vertices have normals or texture coordinates
+    InconsistentFace,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ObjErrorKind::InvalidNumber(s) => write!(f, "expected a number, found `{}`", s),
+            ObjErrorKind::NonFiniteNumber(s) => write!(f, "`{}` is not a finite number", s),
+            ObjErrorKind::InvalidFaceVertex(s) => write!(f, "invalid face vertex `{}`", s),
+            ObjErrorKind::MissingValues {
+                keyword,
+                expected,
+                found,
+            } => write!(
+                f,
+                "`{}` needs at least {} values, found {}",
+                keyword, expected, found
+            ),
+            ObjErrorKind::IndexOutOfRange {
+                element,
+                index,
+                count,
+            } => write!(
+                f,
+                "{} index {} is out of range, {} defined so far",
+                element, index, count
+            ),
+            ObjErrorKind::InconsistentFace => write!(
+                f,
+                "all vertices of a face must have normals and texture coordinates, or none"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// The indices of the data of a face vertex
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct FaceVertex {
+    vertex: usize,
+    texture_coords: Option<usize>,
+    normal: Option<usize>,
+}
+
+/// A triangle of a face, with the material selected for it
+#[derive(Debug, Copy, Clone)]
+struct Face<'a> {
+    corners: [FaceVertex; 3],
+    material: Option<&'a str>,
 }
 
 impl<'a> ObjParser<'a> {
@@ -16,141 +118,324 @@ impl<'a> ObjParser<'a> {
             ignored: vec![],
             vertices: vec![],
             normals: vec![],
+            texture_coords: vec![],
             groups: {
                 let mut gs = BTreeMap::new();
-                gs.insert("default_group", Group::default());
+                gs.insert(DEFAULT_GROUP, vec![]);
                 gs
             },
-            current_group: "default_group",
+            current_group: DEFAULT_GROUP,
+            current_material: None,
+            material_libraries: vec![],
+            materials: MaterialLibrary::new(),
         }
     }
 
-    pub fn parse_str(input: &str) -> ObjParser {
+    pub fn parse_str(input: &'a str) -> Result<ObjParser<'a>, ObjError> {
         let mut data = ObjParser::empty();
 
-        for line in input.lines() {
-            let line = line.trim();
-
-            match line {
-                _ if line.starts_with("vn") => data.parse_normal(line),
-                _ if line.starts_with('v') => data.parse_vertex(line),
-                _ if line.starts_with('f') => data.parse_face(line),
-                _ if line.starts_with('g') => data.parse_group(line),
-                _ => data.ignored.push(line),
-            }
+        for (n, line) in input.lines().enumerate() {
+            data.parse_line(line)
+                .map_err(|kind| ObjError { line: n + 1, kind })?;
         }
 
-        data
+        Ok(data)
     }
 
+    /// Unrecognized records
     pub fn ignored(&self) -> &[&str] {
         &self.ignored
     }
 
-    pub fn get_group(&self, group: &str) -> &Group {
-        &self.groups[group]
+    /// A group with a triangle for each face of the named OBJ group
+    pub fn get_group(&self, group: &str) -> Group {
+        let mut g = Group::default();
+        for face in &self.groups[group] {
+            g.add_child(self.make_triangle(face));
+        }
+        g
     }
 
-    fn parse_vertex(&mut self, line: &str) {
-        let v: Vec<f64> = line
-            .split_whitespace()
-            .skip(1)
-            .map(str::parse)
-            .filter_map(Result::ok)
-            .collect();
-        self.vertices.push(point(v[0], v[1], v[2]))
+    /// A group with one indexed mesh for each OBJ group and material. Vertices are shared by
+    /// all the faces of a mesh that use them with the same normal and texture coordinates.
+    pub fn meshes(&self) -> Group {
+        let mut group = Group::default();
+        for faces in self.groups.values() {
+            let mut builders: BTreeMap<_, MeshBuilder> = BTreeMap::new();
+            for face in faces {
+                let first = face.corners[0];
+                let key = (
+                    face.material,
+                    first.normal.is_some(),
+                    first.texture_coords.is_some(),
+                );
+                builders.entry(key).or_default().add(face);
+            }
+            for ((material, _, _), builder) in builders {
+                let mut shape = Shape::new(builder.build(self));
+                if let Some(phong) = material.and_then(|m| self.materials.get(m)) {
+                    shape.set_material(phong.clone());
+                }
+                group.add_child(shape);
+            }
+        }
+        group
+    }
+
+    /// The material files named by `mtllib` records
+    pub fn material_libraries(&self) -> &[&str] {
+        &self.material_libraries
     }
 
-    fn parse_normal(&mut self, line: &str) {
-        let v: Vec<f64> = line
-            .split_whitespace()
-            .skip(1)
-            .map(str::parse)
-            .filter_map(Result::ok)
+    /// The name of the material selected with `usemtl` for a face of a group
+    pub fn face_material(&self, group: &str, idx: usize) -> Option<&str> {
+        self.groups
+            .get(group)
+            .and_then(|faces| faces.get(idx))
+            .and_then(|face| face.material)
+    }
+
+    /// Give every face the material it selected with `usemtl`. Faces without a material, or
+    /// with a material that is not in the library, keep the default material.
+    pub fn set_materials(&mut self, library: &MaterialLibrary) {
+        let used: BTreeSet<_> = self
+            .groups
+            .values()
+            .flatten()
+            .filter_map(|face| face.material)
             .collect();
-        self.normals.push(vector(v[0], v[1], v[2]))
-    }
-
-    fn parse_face(&mut self, line: &str) {
-        let elements: Vec<_> = line.split_whitespace().skip(1).collect();
-        if elements[0].contains('/') {
-            let idx: Vec<_> = elements
-                .into_iter()
-                .map(|s| {
-                    let mut s = s.split('/');
-                    let i = s.next().unwrap();
-                    let _ = s.next().unwrap();
-                    let n = s.next().unwrap();
-                    (i.parse().unwrap(), n.parse().unwrap())
-                })
-                .map(|(i, n): (usize, usize)| (i - 1, n - 1))
-                .collect();
-            self.triangulate_smooth_fan(&idx);
-        } else {
-            let idx: Vec<_> = elements
-                .into_iter()
-                .map(str::parse)
-                .filter_map(Result::ok)
-                .map(|i: usize| i - 1)
-                .collect();
-            self.triangulate_fan(&idx);
+        for name in used {
+            if library.get(name).is_none() {
+                log::warn!("material `{}` not found", name);
+            }
         }
+        self.materials = library.clone();
     }
 
-    fn parse_group(&mut self, line: &'a str) {
-        let name = line
-            .split_whitespace()
-            .skip(1)
-            .next()
-            .unwrap_or("default_group");
-        self.current_group = name;
-        if !self.groups.contains_key(name) {
-            self.groups.insert(name, Group::default());
+    fn parse_line(&mut self, line: &'a str) -> Result<(), ObjErrorKind> {
+        let content = line.split('#').next().unwrap_or("");
+        let mut fields = content.split_whitespace();
+        let keyword = match fields.next() {
+            Some(keyword) => keyword,
+            None => return Ok(()),
+        };
+        let args: Vec<&'a str> = fields.collect();
+
+        match keyword {
+            "v" => {
+                let v = parse_numbers("v", &args, 3)?;
+                self.vertices.push(point(v[0], v[1], v[2]))
+            }
+            "vn" => {
+                let v = parse_numbers("vn", &args, 3)?;
+                self.normals.push(vector(v[0], v[1], v[2]))
+            }
+            "vt" => {
+                let v = parse_numbers("vt", &args, 1)?;
+                self.texture_coords
+                    .push((v[0], v.get(1).cloned().unwrap_or(0.0)))
+            }
+            "f" => self.parse_face(&args)?,
+            "g" | "o" => self.select_group(args.first().cloned().unwrap_or(DEFAULT_GROUP)),
+            "usemtl" => match args.first() {
+                Some(&name) => self.current_material = Some(name),
+                None => return Err(missing_values("usemtl", 1, 0)),
+            },
+            "mtllib" => self.material_libraries.extend(args),
+            "s" => {}
+            _ => self.ignored.push(line.trim()),
         }
+        Ok(())
     }
 
-    fn triangulate_fan(&mut self, idx: &[usize]) {
-        for i in 1..idx.len() - 1 {
-            let tri = triangle(
-                self.vertices[idx[0]],
-                self.vertices[idx[i]],
-                self.vertices[idx[i + 1]],
-            );
-            self.groups
-                .get_mut(&self.current_group)
-                .unwrap()
-                .add_child(tri);
+    fn parse_face(&mut self, args: &[&str]) -> Result<(), ObjErrorKind> {
+        if args.len() < 3 {
+            return Err(missing_values("f", 3, args.len()));
+        }
+        let corners = args
+            .iter()
+            .map(|s| self.parse_face_vertex(s))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let has_normals = corners[0].normal.is_some();
+        let has_texture_coords = corners[0].texture_coords.is_some();
+        if corners.iter().any(|c| {
+            c.normal.is_some() != has_normals || c.texture_coords.is_some() != has_texture_coords
+        }) {
+            return Err(ObjErrorKind::InconsistentFace);
         }
+
+        let faces = self.groups.get_mut(&self.current_group).unwrap();
+        for i in 1..corners.len() - 1 {
+            faces.push(Face {
+                corners: [corners[0], corners[i], corners[i + 1]],
+                material: self.current_material,
+            });
+        }
+        Ok(())
     }
 
-    fn triangulate_smooth_fan(&mut self, idx: &[(usize, usize)]) {
-        for i in 1..idx.len() - 1 {
-            let tri = smooth_triangle(
-                self.vertices[idx[0].0],
-                self.vertices[idx[i].0],
-                self.vertices[idx[i + 1].0],
-                self.normals[idx[0].1],
-                self.normals[idx[i].1],
-                self.normals[idx[i + 1].1],
-            );
-            self.groups
-                .get_mut(&self.current_group)
-                .unwrap()
-                .add_child(tri);
+    fn parse_face_vertex(&self, s: &str) -> Result<FaceVertex, ObjErrorKind> {
+        let parts: Vec<_> = s.split('/').collect();
+        if parts.len() > 3 || parts[0].is_empty() {
+            return Err(ObjErrorKind::InvalidFaceVertex(s.to_string()));
+        }
+
+        let optional = |i: usize, element, count| match parts.get(i) {
+            None | Some(&"") => Ok(None),
+            Some(idx) => resolve_index(idx, element, count).map(Some),
+        };
+
+        Ok(FaceVertex {
+            vertex: resolve_index(parts[0], "vertex", self.vertices.len())?,
+            texture_coords: optional(1, "texture coordinate", self.texture_coords.len())?,
+            normal: optional(2, "normal", self.normals.len())?,
+        })
+    }
+
+    fn make_triangle(&self, face: &Face) -> Shape {
+        let [a, b, c] = face.corners;
+        let (p1, p2, p3) = (
+            self.vertices[a.vertex],
+            self.vertices[b.vertex],
+            self.vertices[c.vertex],
+        );
+        let uvs = match (a.texture_coords, b.texture_coords, c.texture_coords) {
+            (Some(ta), Some(tb), Some(tc)) => Some([
+                self.texture_coords[ta],
+                self.texture_coords[tb],
+                self.texture_coords[tc],
+            ]),
+            _ => None,
+        };
+
+        let mut shape = match (a.normal, b.normal, c.normal) {
+            (Some(na), Some(nb), Some(nc)) => {
+                let tri = SmoothTriangle::new(
+                    p1,
+                    p2,
+                    p3,
+                    self.normals[na],
+                    self.normals[nb],
+                    self.normals[nc],
+                );
+                match uvs {
+                    Some(uvs) => Shape::new(tri.with_texture_coords(uvs)),
+                    None => Shape::new(tri),
+                }
+            }
+            _ => {
+                let tri = Triangle::new(p1, p2, p3);
+                match uvs {
+                    Some(uvs) => Shape::new(tri.with_texture_coords(uvs)),
+                    None => Shape::new(tri),
+                }
+            }
+        };
+        if let Some(phong) = face.material.and_then(|m| self.materials.get(m)) {
+            shape.set_material(phong.clone());
+        }
+        shape
+    }
+
+    fn select_group(&mut self, name: &'a str) {
+        self.current_group = name;
+        self.groups.entry(name).or_default();
+    }
+}
+
+/// Collects the faces of one mesh, numbering each distinct face vertex once
+#[derive(Default)]
+struct MeshBuilder {
+    indices: HashMap<FaceVertex, u32>,
+    corners: Vec<FaceVertex>,
+    triangles: Vec<[u32; 3]>,
+}
+
+impl MeshBuilder {
+    fn add(&mut self, face: &Face) {
+        let mut tri = [0; 3];
+        for (index, &corner) in tri.iter_mut().zip(&face.corners) {
+            let corners = &mut self.corners;
+            *index = *self.indices.entry(corner).or_insert_with(|| {
+                corners.push(corner);
+                corners.len() as u32 - 1
+            });
         }
+        self.triangles.push(tri);
+    }
+
+    /// Corners of a mesh have either all got normals and texture coordinates, or none.
+    fn build(self, parser: &ObjParser) -> Mesh {
+        let vertices = self.corners.iter().map(|c| parser.vertices[c.vertex]);
+        let mut mesh = Mesh::new(vertices.collect(), self.triangles);
+        if self.corners.iter().all(|c| c.normal.is_some()) {
+            let normals = self.corners.iter().filter_map(|c| c.normal);
+            mesh = mesh.with_normals(normals.map(|n| parser.normals[n]).collect());
+        }
+        if self.corners.iter().all(|c| c.texture_coords.is_some()) {
+            let uvs = self.corners.iter().filter_map(|c| c.texture_coords);
+            mesh = mesh.with_texture_coords(uvs.map(|t| parser.texture_coords[t]).collect());
+        }
+        mesh
+    }
+}
+
+fn missing_values(keyword: &'static str, expected: usize, found: usize) -> ObjErrorKind {
+    ObjErrorKind::MissingValues {
+        keyword,
+        expected,
+        found,
     }
 }
 
+fn parse_numbers(
+    keyword: &'static str,
+    args: &[&str],
+    min_count: usize,
+) -> Result<Vec<f64>, ObjErrorKind> {
+    if args.len() < min_count {
+        return Err(missing_values(keyword, min_count, args.len()));
+    }
+    args.iter()
+        .map(|s| match s.parse::<f64>() {
+            Ok(x) if x.is_finite() => Ok(x),
+            Ok(_) => Err(ObjErrorKind::NonFiniteNumber(s.to_string())),
+            Err(_) => Err(ObjErrorKind::InvalidNumber(s.to_string())),
+        })
+        .collect()
+}
+
+/// Turn a 1-based or negative (relative) OBJ index into a 0-based one.
+fn resolve_index(s: &str, element: &'static str, count: usize) -> Result<usize, ObjErrorKind> {
+    let index: i64 = s
+        .parse()
+        .map_err(|_| ObjErrorKind::InvalidNumber(s.to_string()))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(ObjErrorKind::IndexOutOfRange {
+            element,
+            index,
+            count,
+        });
+    }
+    Ok(resolved as usize)
+}
+
 impl From<ObjParser<'_>> for Group {
     fn from(p: ObjParser) -> Group {
         if p.groups.len() == 1 {
-            p.groups.into_iter().map(|(_, g)| g).next().unwrap()
+            p.get_group(DEFAULT_GROUP)
         } else {
             let mut group = Group::default();
             for g in p
                 .groups
-                .into_iter()
-                .map(|(_, g)| g)
+                .keys()
+                .map(|name| p.get_group(name))
                 .filter(Group::aint_empty)
             {
                 group.add_child(g);
@@ -170,7 +455,9 @@ impl From<ObjParser<'_>> for SceneItem {
 mod tests {
     use super::*;
     use crate::approx_eq::ApproximateEq;
-    use crate::shapes::smooth_triangle;
+    use crate::materials::Phong;
+    use crate::ray::{Intersection, Ray};
+    use crate::shapes::{smooth_triangle, triangle};
     use crate::tuple::{point, vector};
 
     /// Ignoring unrecognized lines
@@ -181,7 +468,7 @@ who traveled much faster than light.
 She set out one day
 in a relative way,
 and came back the previous night.";
-        let parser = ObjParser::parse_str(gibberish);
+        let parser = ObjParser::parse_str(gibberish).unwrap();
         assert_eq!(parser.ignored().len(), 5);
     }
 
@@ -193,7 +480,7 @@ and came back the previous night.";
             v -1.0000 0.5000 0.0000
             v 1 0 0
             v 1 1 0";
-        let parser = ObjParser::parse_str(data);
+        let parser = ObjParser::parse_str(data).unwrap();
         assert_almost_eq!(
             parser.vertices,
             vec![
@@ -216,7 +503,7 @@ and came back the previous night.";
 
             f 1 2 3
             f 1 3 4";
-        let parser = ObjParser::parse_str(data);
+        let parser = ObjParser::parse_str(data).unwrap();
         let g = parser.get_group("default_group");
         assert_almost_eq!(
             g.get_child(0).as_shape().unwrap(),
@@ -239,7 +526,7 @@ and came back the previous night.";
             v 0 2 0
 
             f 1 2 3 4 5";
-        let parser = ObjParser::parse_str(data);
+        let parser = ObjParser::parse_str(data).unwrap();
         let g = parser.get_group("default_group");
         assert_almost_eq!(
             g.get_child(0).as_shape().unwrap(),
@@ -258,7 +545,7 @@ and came back the previous night.";
     /// Triangles in groups
     #[test]
     fn parse_groups() {
-        let parser = ObjParser::parse_str(DATA);
+        let parser = ObjParser::parse_str(DATA).unwrap();
         let g1 = parser.get_group("FirstGroup");
         let g2 = parser.get_group("SecondGroup");
         assert_almost_eq!(
@@ -274,11 +561,11 @@ and came back the previous night.";
     /// Converting an OBJ file to a group
     #[test]
     fn convert_to_group() {
-        let parser = ObjParser::parse_str(DATA);
+        let parser = ObjParser::parse_str(DATA).unwrap();
         let group: Group = parser.into();
         let g1 = group.get_child(0).as_group().unwrap();
         let g2 = group.get_child(1).as_group().unwrap();
-        let parser = ObjParser::parse_str(DATA); // parse it again to get the vertex list :)
+        let parser = ObjParser::parse_str(DATA).unwrap(); // parse it again to get the vertex list :)
         assert_almost_eq!(
             g1.get_child(0).as_shape().unwrap(),
             triangle(parser.vertices[0], parser.vertices[1], parser.vertices[2])
@@ -296,7 +583,7 @@ and came back the previous night.";
             vn 0 0 1
             vn 0.707 0 -0.707
             vn 1 2 3";
-        let parser = ObjParser::parse_str(data);
+        let parser = ObjParser::parse_str(data).unwrap();
         assert_almost_eq!(parser.normals[0], vector(0, 0, 1));
         assert_almost_eq!(parser.normals[1], vector(0.707, 0, -0.707));
         assert_almost_eq!(parser.normals[2], vector(1, 2, 3));
@@ -314,9 +601,13 @@ and came back the previous night.";
             vn 1 0 0
             vn 0 1 0
 
+            vt 0 0
+            vt 1 0
+            vt 0 1
+
             f 1//3 2//1 3//2
-            f 1/0/3 2/102/1 3/14/2";
-        let parser = ObjParser::parse_str(data);
+            f 1/1/3 2/2/1 3/3/2";
+        let parser = ObjParser::parse_str(data).unwrap();
         let g = parser.get_group("default_group");
         let t = smooth_triangle(
             parser.vertices[0],
@@ -330,6 +621,237 @@ and came back the previous night.";
         assert_almost_eq!(g.get_child(1).as_shape().unwrap(), t);
     }
 
+    /// Texture coordinates are stored on the triangles
+    #[test]
+    fn texture_coordinates() {
+        let data = "\
+            v 0 1 0
+            v -1 0 0
+            v 1 0 0
+            vt 0.5 1
+            vt 0 0 0
+            vt 1
+            vn 0 0 -1
+            f 1/1 2/2 3/3
+            f 1/1/1 2/2/1 3/3/1";
+        let parser = ObjParser::parse_str(data).unwrap();
+        assert_eq!(
+            parser.texture_coords,
+            vec![(0.5, 1.0), (0.0, 0.0), (1.0, 0.0)]
+        );
+
+        let g = parser.get_group("default_group");
+        for child in 0..2 {
+            let tri = g.get_child(child).as_shape().unwrap();
+            let i = Intersection::new_uv(1.0, 0.45, 0.25, tri);
+            let (u, v) = tri.texture_coords(&i).unwrap();
+            assert_almost_eq!(u, 0.4);
+            assert_almost_eq!(v, 0.3);
+        }
+    }
+
+    /// Negative indices count back from the last element defined so far
+    #[test]
+    fn relative_indices() {
+        let data = "\
+            v 0 1 0
+            v -1 0 0
+            v 1 0 0
+            vn 0 0 1
+            f -3//-1 -2//-1 -1//-1
+            v 5 5 5
+            f -4 -3 -1";
+        let parser = ObjParser::parse_str(data).unwrap();
+        let g = parser.get_group("default_group");
+        let v = &parser.vertices;
+        let n = parser.normals[0];
+        assert_almost_eq!(
+            g.get_child(0).as_shape().unwrap(),
+            smooth_triangle(v[0], v[1], v[2], n, n, n)
+        );
+        assert_almost_eq!(
+            g.get_child(1).as_shape().unwrap(),
+            triangle(v[0], v[1], v[3])
+        );
+    }
+
+    /// Objects start groups, materials are recorded per face, smoothing groups and comments
+    /// are accepted
+    #[test]
+    fn objects_and_materials() {
+        let data = "\
+            mtllib scene.mtl more.mtl
+            v -1 1 0 # a comment
+            v -1 0 0
+            v 1 0 0
+            v 1 1 0
+            o Quad
+            s 1
+            f 1 2 3
+            usemtl red
+            f 1 3 4
+            s off
+            g Other
+            f 1 2 4
+            vp 0.5 0.5";
+        let parser = ObjParser::parse_str(data).unwrap();
+        assert_eq!(parser.material_libraries(), &["scene.mtl", "more.mtl"]);
+        assert_eq!(parser.get_group("Quad").len(), 2);
+        assert_eq!(parser.get_group("Other").len(), 1);
+        assert_eq!(parser.face_material("Quad", 0), None);
+        assert_eq!(parser.face_material("Quad", 1), Some("red"));
+        assert_eq!(parser.face_material("Other", 0), Some("red"));
+        assert_eq!(parser.ignored(), &["vp 0.5 0.5"]);
+    }
+
+    /// Faces get the materials they select from a library
+    #[test]
+    fn apply_materials() {
+        let data = "\
+            v -1 1 0
+            v -1 0 0
+            v 1 0 0
+            f 1 2 3
+            usemtl red
+            f 1 2 3
+            usemtl unknown
+            f 1 2 3";
+        let library =
+            MaterialLibrary::parse_str("newmtl red\nKd 1 0 0", |_| unreachable!()).unwrap();
+        let mut parser = ObjParser::parse_str(data).unwrap();
+        parser.set_materials(&library);
+        let g = parser.get_group("default_group");
+        let material = |idx| {
+            g.get_child(idx)
+                .as_shape()
+                .unwrap()
+                .material()
+                .as_any()
+                .downcast_ref::<Phong>()
+                .unwrap()
+                .clone()
+        };
+        assert_almost_eq!(material(0), Phong::default());
+        assert_almost_eq!(material(1), library.get("red").unwrap().clone());
+        assert_almost_eq!(material(2), Phong::default());
+    }
+
+    /// Meshes share the vertices of their faces and are split by material
+    #[test]
+    fn meshes() {
+        let data = "\
+            v -1 1 0
+            v -1 0 0
+            v 1 0 0
+            v 1 1 0
+            vn 0 0 -1
+            f 1//1 2//1 3//1 4//1
+            usemtl red
+            f 1 2 4";
+        let library =
+            MaterialLibrary::parse_str("newmtl red\nKd 1 0 0", |_| unreachable!()).unwrap();
+        let mut parser = ObjParser::parse_str(data).unwrap();
+        parser.set_materials(&library);
+        let meshes = parser.meshes();
+        assert_eq!(meshes.len(), 2);
+
+        let expected = Mesh::new(parser.vertices.clone(), vec![[0, 1, 2], [0, 2, 3]])
+            .with_normals(vec![vector(0, 0, -1); 4]);
+        assert_almost_eq!(meshes.get_child(0), Shape::new(expected));
+
+        let expected = Mesh::new(
+            vec![parser.vertices[0], parser.vertices[1], parser.vertices[3]],
+            vec![[0, 1, 2]],
+        );
+        let red = library.get("red").unwrap().clone();
+        assert_almost_eq!(meshes.get_child(1), Shape::new(expected).with_material(red));
+    }
+
+    /// Malformed input is reported with the line number instead of panicking
+    #[test]
+    fn malformed_input() {
+        use ObjErrorKind::*;
+        let vertices = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nvt 0 0\n";
+        let cases = vec![
+            ("v 1 2", 1, missing_values("v", 3, 2)),
+            ("v 1 x 2", 1, InvalidNumber("x".into())),
+            ("v 1 1 1e309", 1, NonFiniteNumber("1e309".into())),
+            ("vn nan 0 1", 1, NonFiniteNumber("nan".into())),
+            ("vt -inf", 1, NonFiniteNumber("-inf".into())),
+            ("vn 1 2", 1, missing_values("vn", 3, 2)),
+            ("vt", 1, missing_values("vt", 1, 0)),
+            ("f 1 2", 6, missing_values("f", 3, 2)),
+            ("f 1 2 4", 6, out_of_range("vertex", 4, 3)),
+            ("f 0 1 2", 6, out_of_range("vertex", 0, 3)),
+            ("f -4 1 2", 6, out_of_range("vertex", -4, 3)),
+            ("f 1//2 2//1 3//1", 6, out_of_range("normal", 2, 1)),
+            ("f 1/2 2/1 3/1", 6, out_of_range("texture coordinate", 2, 1)),
+            ("f 1 2/1 3", 6, InconsistentFace),
+            ("f 1//1 2//1 3", 6, InconsistentFace),
+            ("f 1/1/1/1 2 3", 6, InvalidFaceVertex("1/1/1/1".into())),
+            ("f /1 2 3", 6, InvalidFaceVertex("/1".into())),
+            ("f 1 a 3", 6, InvalidNumber("a".into())),
+            ("f 1 2 3\nusemtl", 7, missing_values("usemtl", 1, 0)),
+        ];
+        for (input, line, kind) in cases {
+            let data = if line == 1 {
+                input.to_string()
+            } else {
+                format!("{}{}", vertices, input)
+            };
+            let err = ObjParser::parse_str(&data).err();
+            assert_eq!(err, Some(ObjError { line, kind }), "{}", input);
+        }
+
+        let err = ObjParser::parse_str("v 0 0 0\nf 1 1 2").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "line 2: vertex index 2 is out of range, 1 defined so far"
+        );
+    }
+
+    fn out_of_range(element: &'static str, index: i64, count: usize) -> ObjErrorKind {
+        ObjErrorKind::IndexOutOfRange {
+            element,
+            index,
+            count,
+        }
+    }
+
+    /// The teapot has smooth, textured faces, split into triangles
+    #[test]
+    fn teapot() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../data/teapot.obj");
+        let data = std::fs::read_to_string(path).unwrap();
+        let parser = ObjParser::parse_str(&data).unwrap();
+        assert_eq!(parser.vertices.len(), 3241);
+        assert_eq!(parser.normals.len(), 3242);
+        assert_eq!(parser.texture_coords.len(), 1588);
+        assert!(parser.ignored().is_empty());
+
+        let g = parser.get_group("default_group");
+        assert_eq!(g.len(), 3120 * 2 + 80);
+        let tri = g.get_child(0).as_shape().unwrap();
+        assert!(tri.texture_coords(&Intersection::new(0.0, tri)).is_some());
+
+        // a single mesh, whose vertices are shared by the faces, finds the same surface
+        let meshes = parser.meshes();
+        assert_eq!(meshes.len(), 1);
+        let mesh = meshes.get_child(0).as_shape().unwrap();
+        let mut n_hits = 0;
+        for k in 0..20 {
+            let x = -3.0 + k as f64 * 0.3;
+            let r = Ray::new(point(x, 10, 1), vector(0, -1, 0));
+            let mut expected: Vec<_> = g.intersect(&r).iter().map(|i| i.t).collect();
+            let mut actual: Vec<_> = mesh.intersect(&r).iter().map(|i| i.t).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            actual.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_almost_eq!(actual, expected);
+            n_hits += actual.len();
+        }
+        assert!(n_hits > 0);
+    }
+
     const DATA: &'static str = "\
         v -1 1 0
         v -1 0 0
@@ -1,7 +1,8 @@
 use crate::approx_eq::EPSILON;
+use crate::canvas::Canvas;
 use crate::color::Color;
 use crate::matrix::Matrix;
-use crate::tuple::Point;
+use crate::tuple::{point, Point};
 use std::sync::Arc;
 
 pub fn stripe_pattern(a: Color, b: Color) -> Pattern {
@@ -34,10 +35,30 @@ pub fn checkers_pattern(a: Color, b: Color) -> Pattern {
     })
 }
 
+/// An image wrapped around a surface by its texture coordinates. The image covers the unit
+/// square, with `v` pointing up, and repeats outside. Colors are interpolated bilinearly
+/// between the pixel centers.
+pub fn image_pattern(image: Canvas) -> Pattern {
+    let (w, h) = (image.width() as i64, image.height() as i64);
+    Pattern::new_uv(move |u, v| {
+        let x = (u - u.floor()) * w as f64 - 0.5;
+        let y = (1.0 - (v - v.floor())) * h as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel =
+            |x: i64, y: i64| image.get_pixel(x.rem_euclid(w) as u32, y.rem_euclid(h) as u32);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1, y0) * fx;
+        let bottom = texel(x0, y0 + 1) * (1.0 - fx) + texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    })
+}
+
 #[derive(Clone)]
 pub struct Pattern {
     func: Arc<dyn Sync + Send + Fn(Point) -> Color>,
     inv_transform: Matrix,
+    uv_mapped: bool,
 }
 
 impl Pattern {
@@ -45,6 +66,16 @@ impl Pattern {
         Pattern {
             func: Arc::new(f),
             inv_transform: Matrix::identity(),
+            uv_mapped: false,
+        }
+    }
+
+    /// A pattern that is looked up by the texture coordinates `(u, v)` of a surface rather
+    /// than by position. The pattern transformation applies to the point `(u, v, 0)`.
+    pub fn new_uv(f: impl 'static + Sync + Send + Fn(f64, f64) -> Color) -> Self {
+        Pattern {
+            uv_mapped: true,
+            ..Pattern::new(move |p| f(p.x(), p.y()))
         }
     }
 
@@ -52,6 +83,16 @@ impl Pattern {
         (self.func)(self.inv_transform * obj_point)
     }
 
+    /// The color at texture coordinates `(u, v)`.
+    pub fn at_uv(&self, u: f64, v: f64) -> Color {
+        self.at(point(u, v, 0))
+    }
+
+    /// Whether the pattern should be looked up by texture coordinates, where available.
+    pub fn is_uv_mapped(&self) -> bool {
+        self.uv_mapped
+    }
+
     pub fn set_transform(&mut self, t: Matrix) {
         self.inv_transform = t.inverse();
     }
@@ -179,7 +220,7 @@ mod tests {
         let pos2 = point(1.1, 0, 0);
         let c1 = m.lighting(
             &sphere(),
-            light.incoming_at(pos1),
+            light.incoming_at(pos1, &mut Prng::new(0)),
             pos1,
             eyev,
             normalv,
@@ -187,7 +228,7 @@ mod tests {
         );
         let c2 = m.lighting(
             &sphere(),
-            light.incoming_at(pos2),
+            light.incoming_at(pos2, &mut Prng::new(0)),
             pos2,
             eyev,
             normalv,
@@ -242,4 +283,27 @@ mod tests {
         assert_almost_eq!(pattern.at(point(0, 0, 0.99)), WHITE);
         assert_almost_eq!(pattern.at(point(0, 0, 1.01)), BLACK);
     }
+
+    /// Image patterns wrap around by texture coordinates and interpolate between pixels
+    #[test]
+    fn image_texture() {
+        let mut image = Canvas::new(2, 2);
+        image.set_pixel(0, 0, color(1, 0, 0));
+        image.set_pixel(1, 0, color(0, 1, 0));
+        image.set_pixel(0, 1, color(0, 0, 1));
+        image.set_pixel(1, 1, color(1, 1, 1));
+        let pattern = image_pattern(image);
+        assert!(pattern.is_uv_mapped());
+        assert!(!test_pattern().is_uv_mapped());
+
+        // pixel centers; v points up, so the bottom row is at v = 0.25
+        assert_almost_eq!(pattern.at_uv(0.25, 0.75), color(1, 0, 0));
+        assert_almost_eq!(pattern.at_uv(0.75, 0.75), color(0, 1, 0));
+        assert_almost_eq!(pattern.at_uv(0.25, 0.25), color(0, 0, 1));
+        assert_almost_eq!(pattern.at_uv(1.25, -0.75), color(0, 0, 1));
+        assert_almost_eq!(pattern.at_uv(0.5, 0.75), color(0.5, 0.5, 0));
+        assert_almost_eq!(pattern.at_uv(0.5, 0.5), color(0.5, 0.5, 0.5));
+        // halfway between the last and the first column, which wraps around
+        assert_almost_eq!(pattern.at_uv(0.0, 0.25), color(0.5, 0.5, 1));
+    }
 }
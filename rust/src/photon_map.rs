@@ -4,10 +4,10 @@ use crate::color::{color, Color};
 use crate::cosine_distribution::CosineDistribution;
 use crate::lights::LightRay;
 use crate::partial_sort::partition_by_key;
+use crate::random::Prng;
 use crate::ray::Ray;
 use crate::tuple::{point, vector, Point, Vector};
 use rand::distributions::Distribution;
-use rand::thread_rng;
 use std::collections::BinaryHeap;
 use std::f64::INFINITY;
 
@@ -141,10 +141,16 @@ impl TravellingPhoton {
         StoredPhoton::new(position, -self.ray.direction(), self.power)
     }
 
-    pub fn scatter(self, p: Point, normv: Vector, diffuse_reflectance: Color) -> Self {
+    pub fn scatter(
+        self,
+        p: Point,
+        normv: Vector,
+        diffuse_reflectance: Color,
+        rng: &mut Prng,
+    ) -> Self {
         let pd_avg = diffuse_reflectance.sum() / 3.0;
         TravellingPhoton {
-            ray: Ray::new(p, CosineDistribution::new(normv).sample(&mut thread_rng())),
+            ray: Ray::new(p, CosineDistribution::new(normv).sample(rng)),
             power: self.power * diffuse_reflectance / pd_avg,
             kind: self.kind.scatter(),
         }
@@ -0,0 +1,280 @@
+//! Finding out what is seen at a pixel.
+//!
+//! When a scene looks wrong, `Pick::new` casts the ray through the center of a pixel and
+//! collects everything the renderer knows about the surface it hits: the shape, the geometry,
+//! the point and normal, the material, the refractive indices on both sides and whether each
+//! light reaches the point. Its `Display` implementation prints a short report; clicking into
+//! the live preview window prints it for the clicked pixel.
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::lights::IncomingLight;
+use crate::materials::{Material, Phong, SurfaceColor};
+use crate::random::Prng;
+use crate::ray::{hit, Ray};
+use crate::shapes::Shape;
+use crate::tuple::{Point, Vector};
+use crate::world::World;
+use std::fmt;
+
+/// What is seen at a pixel
+pub struct Pick<'a> {
+    pub x: u32,
+    pub y: u32,
+    pub ray: Ray,
+    /// `None` if the ray hits nothing
+    pub surface: Option<PickedSurface<'a>>,
+}
+
+/// A surface hit by a ray
+pub struct PickedSurface<'a> {
+    pub shape: &'a Shape,
+    /// The position of the object containing the shape among the objects of the world,
+    /// counted from 1 like the object ids of the `ObjectId` AOV
+    pub object_id: Option<usize>,
+    pub t: f64,
+    pub point: Point,
+    /// The normal facing the ray
+    pub normal: Vector,
+    /// Whether the ray hits the surface from inside the shape
+    pub inside: bool,
+    /// The color of the surface at the point, including patterns, textures and vertex colors
+    pub color: Color,
+    pub texture_coords: Option<(f64, f64)>,
+    /// The refractive index on the side of the ray
+    pub n1: f64,
+    /// The refractive index on the other side
+    pub n2: f64,
+    /// The status of every light of the world, in order
+    pub lights: Vec<LightStatus>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LightStatus {
+    Lit,
+    InShadow,
+    /// Partly blocked by transparent shadow casters, which let this fraction of the light pass
+    Attenuated(Color),
+    /// The light does not shine at the point, e.g. because it is outside of a spot light's cone
+    NotReached,
+}
+
+impl<'a> Pick<'a> {
+    /// Look at the center of the pixel `(x, y)` of `camera`
+    pub fn new(camera: &Camera, world: &'a World, x: u32, y: u32) -> Self {
+        let mut rng = Prng::new(camera.seed());
+        let ray = camera.ray_for_pixel(x, y, false, &mut rng);
+        let surface = PickedSurface::hit_by(world, &ray, &mut rng);
+        Pick { x, y, ray, surface }
+    }
+}
+
+impl<'a> PickedSurface<'a> {
+    /// The first surface that `ray` hits, if any. `rng` samples area lights.
+    pub fn hit_by(world: &'a World, ray: &Ray, rng: &mut Prng) -> Option<Self> {
+        let xs = world.intersect(ray);
+        let hit = hit(&xs)?;
+        let comps = hit.prepare_computations(ray, &xs);
+        let shape = hit.obj;
+
+        let lights = world
+            .lights()
+            .iter()
+            .map(|light| {
+                let incoming = light.incoming_at(comps.over_point, rng);
+                match incoming {
+                    IncomingLight::NoLight => LightStatus::NotReached,
+                    IncomingLight::Omni(_) => LightStatus::Lit,
+                    IncomingLight::Ray(_) => {
+                        let visibility = world.light_visibility(&incoming, comps.over_point);
+                        if visibility.is_black() {
+                            LightStatus::InShadow
+                        } else if visibility.sum() < 3.0 {
+                            LightStatus::Attenuated(visibility)
+                        } else {
+                            LightStatus::Lit
+                        }
+                    }
+                }
+            })
+            .collect();
+
+        Some(PickedSurface {
+            shape,
+            object_id: world
+                .objects()
+                .iter()
+                .position(|object| object.contains(shape))
+                .map(|i| i + 1),
+            t: comps.t,
+            point: comps.point,
+            normal: comps.normalv,
+            inside: comps.inside,
+            color: shape.material().color_at(&comps),
+            texture_coords: comps.texture_coords,
+            n1: comps.n1,
+            n2: comps.n2,
+            lights,
+        })
+    }
+}
+
+impl fmt::Display for Pick<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let surface = match &self.surface {
+            Some(surface) => surface,
+            None => return writeln!(f, "pixel ({}, {}): nothing", self.x, self.y),
+        };
+        let object = match surface.object_id {
+            Some(id) => format!("object {}", id),
+            None => "an unknown object".to_string(),
+        };
+        writeln!(
+            f,
+            "pixel ({}, {}): {} of {} at distance {:.4}",
+            self.x,
+            self.y,
+            surface.shape.geometry().type_name(),
+            object,
+            surface.t
+        )?;
+        writeln!(
+            f,
+            "  point:    {}",
+            triple(surface.point.x(), surface.point.y(), surface.point.z())
+        )?;
+        writeln!(
+            f,
+            "  normal:   {}{}",
+            triple(surface.normal.x(), surface.normal.y(), surface.normal.z()),
+            if surface.inside { " (inside)" } else { "" }
+        )?;
+        if let Some((u, v)) = surface.texture_coords {
+            writeln!(f, "  uv:       ({:.4}, {:.4})", u, v)?;
+        }
+        writeln!(f, "  color:    {}", rgb(surface.color))?;
+        write_material(f, surface.shape.material())?;
+        writeln!(f, "  n1, n2:   {}, {}", surface.n1, surface.n2)?;
+        writeln!(
+            f,
+            "  shadows:  {}",
+            if surface.shape.cast_shadow() {
+                "cast"
+            } else {
+                "not cast"
+            }
+        )?;
+        for (i, status) in surface.lights.iter().enumerate() {
+            let status = match status {
+                LightStatus::Lit => "lit".to_string(),
+                LightStatus::InShadow => "in shadow".to_string(),
+                LightStatus::Attenuated(c) => format!("attenuated to {}", rgb(*c)),
+                LightStatus::NotReached => "not reached".to_string(),
+            };
+            writeln!(f, "  light {}:  {}", i + 1, status)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_material(f: &mut fmt::Formatter, material: &dyn Material) -> fmt::Result {
+    let phong = match material.as_any().downcast_ref::<Phong>() {
+        Some(phong) => phong,
+        None => return writeln!(f, "  material: {:?}", material),
+    };
+    let color = match phong.color() {
+        SurfaceColor::Flat(c) => rgb(*c),
+        SurfaceColor::Pattern(_) => "pattern".to_string(),
+    };
+    writeln!(
+        f,
+        "  material: Phong color {}, emissive {}, diffuse {}, specular {}, shininess {}, \
+         reflective {}, transparency {}, refractive index {}",
+        color,
+        phong.emissive(),
+        phong.diffuse(),
+        phong.specular(),
+        phong.shininess(),
+        phong.reflective(),
+        phong.transparency(),
+        phong.refractive_index()
+    )
+}
+
+fn triple(x: f64, y: f64, z: f64) -> String {
+    format!("({:.4}, {:.4}, {:.4})", x, y, z)
+}
+
+fn rgb(c: Color) -> String {
+    triple(c.red(), c.green(), c.blue())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::color::color;
+    use crate::lights::PointLight;
+    use crate::matrix::translation;
+    use crate::shapes::{plane, sphere};
+    use crate::tuple::{point, vector};
+    use std::f64::consts::PI;
+
+    /// The pixel in the center of the image shows the outer sphere of the default world
+    #[test]
+    fn pick_pixel() {
+        let camera = Camera::new(11, 11, PI / 2.0).with_view_transform(
+            point(0, 0, -5),
+            point(0, 0, 0),
+            vector(0, 1, 0),
+        );
+        let world = World::default();
+        let pick = Pick::new(&camera, &world, 5, 5);
+        let surface = pick.surface.as_ref().unwrap();
+        assert_eq!(surface.object_id, Some(1));
+        assert_almost_eq!(surface.point, point(0, 0, -1));
+        assert_almost_eq!(surface.normal, vector(0, 0, -1));
+        assert_eq!((surface.n1, surface.n2), (1.0, 1.0));
+        assert!(matches!(surface.lights[..], [LightStatus::Lit]));
+
+        let report = pick.to_string();
+        assert!(report.starts_with("pixel (5, 5): Sphere of object 1 at distance 4.0000"));
+        assert!(report.contains("diffuse 0.7"));
+
+        let corner = Pick::new(&camera, &world, 0, 0);
+        assert!(corner.surface.is_none());
+        assert_eq!(corner.to_string(), "pixel (0, 0): nothing\n");
+    }
+
+    /// Refractive indices and shadows of a glass sphere above a floor
+    #[test]
+    fn glass_and_shadows() {
+        let glass = Phong::default().with_refractive_index(1.5);
+        let mut world = World::new(
+            vec![Box::new(PointLight::new(point(0, 10, 0), color(1, 1, 1)))],
+            vec![
+                plane().into(),
+                sphere()
+                    .with_material(glass.with_transparency(0.8))
+                    .with_transform(translation(0, 1, 0))
+                    .into(),
+            ],
+        );
+        let rng = &mut Prng::new(0);
+
+        let ray = Ray::new(point(0, 1, -5), vector(0, 0, 1));
+        let glass = PickedSurface::hit_by(&world, &ray, rng).unwrap();
+        assert_eq!(glass.object_id, Some(2));
+        assert_eq!((glass.n1, glass.n2), (1.0, 1.5));
+
+        let ray = Ray::new(point(0, 3, -3), vector(0, -3, 2.5));
+        let floor = PickedSurface::hit_by(&world, &ray, rng).unwrap();
+        assert_eq!(floor.object_id, Some(1));
+        assert_almost_eq!(floor.point, point(0, 0, -0.5));
+        assert!(matches!(floor.lights[..], [LightStatus::InShadow]));
+
+        world.set_transparent_shadows(true);
+        let floor = PickedSurface::hit_by(&world, &ray, rng).unwrap();
+        assert!(matches!(floor.lights[..], [LightStatus::Attenuated(_)]));
+    }
+}
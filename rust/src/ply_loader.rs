@@ -0,0 +1,642 @@
+//! Reading polygon meshes from PLY (Stanford triangle format) files.
+//!
+//! The header lists the elements of the file with their properties; the body holds the values,
+//! either as ASCII text or as binary little-endian numbers. Vertices are read from the `vertex`
+//! element: the position `x`, `y`, `z`, and if present the normal `nx`, `ny`, `nz` and the color
+//! `red`, `green`, `blue`. Colors stored as integers are scaled to `[0, 1]`. Polygons are read
+//! from the `vertex_indices` (or `vertex_index`) list of the `face` element and split into
+//! triangle fans. Other elements and properties are skipped.
+
+use crate::color::{color, Color};
+use crate::shapes::{Group, Mesh, SceneItem, Shape};
+use crate::tuple::{point, vector, Point, Vector};
+use std::fmt;
+
+pub struct PlyParser {
+    vertices: Vec<Point>,
+    normals: Vec<Vector>,
+    colors: Vec<Color>,
+    triangles: Vec<[u32; 3]>,
+    comments: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlyError {
+    /// A header line (counted from 1) that is malformed or describes an unsupported file
+    InvalidHeader { line: usize, message: String },
+    /// The file ended before all rows of an element were read
+    Truncated {
+        element: String,
+        read: usize,
+        count: usize,
+    },
+    /// A value in an ASCII body that is not a number
+    InvalidNumber(String),
+    /// A row of an element with a value that is infinite or not a number
+    NonFiniteNumber { element: String, row: usize },
+    /// A face that refers to a vertex that does not exist
+    IndexOutOfRange { index: i64, count: usize },
+}
+
+impl fmt::Display for PlyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PlyError::InvalidHeader { line, message } => {
+                write!(f, "header line {}: {}", line, message)
+            }
+            PlyError::Truncated {
+                element,
+                read,
+                count,
+            } => write!(
+                f,
+                "the file ends after {} of {} `{}` elements",
+                read, count, element
+            ),
+            PlyError::InvalidNumber(s) => write!(f, "expected a number, found `{}`", s),
+            PlyError::NonFiniteNumber { element, row } => write!(
+                f,
+                "`{}` element {} has a value that is not a finite number",
+                element, row
+            ),
+            PlyError::IndexOutOfRange { index, count } => write!(
+                f,
+                "vertex index {} is out of range, the file has {} vertices",
+                index, count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlyError {}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+}
+
+/// The type of a scalar property, or of the count or items of a list
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        use Scalar::*;
+        Some(match name {
+            "char" | "int8" => I8,
+            "uchar" | "uint8" => U8,
+            "short" | "int16" => I16,
+            "ushort" | "uint16" => U16,
+            "int" | "int32" => I32,
+            "uint" | "uint32" => U32,
+            "float" | "float32" => F32,
+            "double" | "float64" => F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        use Scalar::*;
+        match self {
+            I8 | U8 => 1,
+            I16 | U16 => 2,
+            I32 | U32 | F32 => 4,
+            F64 => 8,
+        }
+    }
+
+    /// The value that stands for full intensity in a color channel of this type
+    fn color_scale(self) -> f64 {
+        use Scalar::*;
+        match self {
+            I8 | U8 => 255.0,
+            I16 | U16 => 65535.0,
+            I32 | U32 => 4294967295.0,
+            F32 | F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum PropertyType {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    ty: PropertyType,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn scalar(&self, name: &str) -> Option<(usize, Scalar)> {
+        self.properties
+            .iter()
+            .enumerate()
+            .find_map(|(k, p)| match p.ty {
+                PropertyType::Scalar(ty) if p.name == name => Some((k, ty)),
+                _ => None,
+            })
+    }
+
+    fn scalars(&self, names: [&str; 3]) -> Option<[(usize, Scalar); 3]> {
+        Some([
+            self.scalar(names[0])?,
+            self.scalar(names[1])?,
+            self.scalar(names[2])?,
+        ])
+    }
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    comments: Vec<String>,
+}
+
+/// The values of the body, in the order they are stored
+trait Source {
+    /// The next value, or `None` at the end of the data
+    fn read(&mut self, ty: Scalar) -> Result<Option<f64>, PlyError>;
+}
+
+struct AsciiSource<'a> {
+    tokens: std::str::SplitWhitespace<'a>,
+}
+
+impl Source for AsciiSource<'_> {
+    fn read(&mut self, _: Scalar) -> Result<Option<f64>, PlyError> {
+        match self.tokens.next() {
+            Some(s) => s
+                .parse()
+                .map(Some)
+                .map_err(|_| PlyError::InvalidNumber(s.to_string())),
+            None => Ok(None),
+        }
+    }
+}
+
+struct BinarySource<'a> {
+    data: &'a [u8],
+}
+
+impl Source for BinarySource<'_> {
+    fn read(&mut self, ty: Scalar) -> Result<Option<f64>, PlyError> {
+        use Scalar::*;
+        let size = ty.size();
+        if self.data.len() < size {
+            return Ok(None);
+        }
+        let (bytes, rest) = self.data.split_at(size);
+        self.data = rest;
+        let mut b = [0; 8];
+        b[..size].copy_from_slice(bytes);
+        let [b0, b1, b2, b3, ..] = b;
+        Ok(Some(match ty {
+            I8 => f64::from(b0 as i8),
+            U8 => f64::from(b0),
+            I16 => f64::from(i16::from_le_bytes([b0, b1])),
+            U16 => f64::from(u16::from_le_bytes([b0, b1])),
+            I32 => f64::from(i32::from_le_bytes([b0, b1, b2, b3])),
+            U32 => f64::from(u32::from_le_bytes([b0, b1, b2, b3])),
+            F32 => f64::from(f32::from_le_bytes([b0, b1, b2, b3])),
+            F64 => f64::from_le_bytes(b),
+        }))
+    }
+}
+
+impl PlyParser {
+    pub fn parse_bytes(data: &[u8]) -> Result<PlyParser, PlyError> {
+        let (header, body) = parse_header(data)?;
+        let mut parser = PlyParser {
+            vertices: vec![],
+            normals: vec![],
+            colors: vec![],
+            triangles: vec![],
+            comments: header.comments,
+        };
+
+        let mut source: Box<dyn Source> = match header.format {
+            Format::Ascii => Box::new(AsciiSource {
+                tokens: std::str::from_utf8(body)
+                    .map_err(|_| PlyError::InvalidNumber("non-ASCII data".to_string()))?
+                    .split_whitespace(),
+            }),
+            Format::BinaryLittleEndian => Box::new(BinarySource { data: body }),
+        };
+        for element in &header.elements {
+            parser.read_element(element, &mut *source)?;
+        }
+
+        let count = parser.vertices.len();
+        if let Some(&index) = parser
+            .triangles
+            .iter()
+            .flatten()
+            .find(|&&i| i as usize >= count)
+        {
+            return Err(PlyError::IndexOutOfRange {
+                index: i64::from(index),
+                count,
+            });
+        }
+        Ok(parser)
+    }
+
+    /// The `comment` lines of the header
+    pub fn comments(&self) -> &[String] {
+        &self.comments
+    }
+
+    /// The faces as a mesh, with the normals and colors of the vertices if the file has them
+    pub fn mesh(&self) -> Mesh {
+        let mut mesh = Mesh::new(self.vertices.clone(), self.triangles.clone());
+        if !self.normals.is_empty() {
+            mesh = mesh.with_normals(self.normals.clone());
+        }
+        if !self.colors.is_empty() {
+            mesh = mesh.with_colors(self.colors.clone());
+        }
+        mesh
+    }
+
+    /// A group holding the mesh, like `ObjParser::meshes`
+    pub fn meshes(&self) -> Group {
+        let mut group = Group::default();
+        group.add_child(Shape::new(self.mesh()));
+        group
+    }
+
+    fn read_element(&mut self, element: &Element, source: &mut dyn Source) -> Result<(), PlyError> {
+        let position = element.scalars(["x", "y", "z"]);
+        let normal = element.scalars(["nx", "ny", "nz"]);
+        let rgb = element.scalars(["red", "green", "blue"]);
+        let indices = element.properties.iter().position(|p| {
+            matches!(p.ty, PropertyType::List { .. })
+                && (p.name == "vertex_indices" || p.name == "vertex_index")
+        });
+        let (is_vertex, is_face) = (element.name == "vertex", element.name == "face");
+
+        let mut scalars = vec![0.0; element.properties.len()];
+        let mut polygon = vec![];
+        for row in 0..element.count {
+            let mut read = |ty| match source.read(ty)? {
+                Some(value) if value.is_finite() => Ok(value),
+                Some(_) => Err(PlyError::NonFiniteNumber {
+                    element: element.name.clone(),
+                    row,
+                }),
+                None => Err(PlyError::Truncated {
+                    element: element.name.clone(),
+                    read: row,
+                    count: element.count,
+                }),
+            };
+            for (k, property) in element.properties.iter().enumerate() {
+                match property.ty {
+                    PropertyType::Scalar(ty) => scalars[k] = read(ty)?,
+                    PropertyType::List { count, item } => {
+                        let n = read(count)?;
+                        if n < 0.0 {
+                            return Err(PlyError::InvalidNumber(n.to_string()));
+                        }
+                        polygon.clear();
+                        for _ in 0..n as usize {
+                            polygon.push(read(item)?);
+                        }
+                        if is_face && Some(k) == indices {
+                            self.add_polygon(&polygon)?;
+                        }
+                    }
+                }
+            }
+
+            if !is_vertex {
+                continue;
+            }
+            let get = |props: [(usize, Scalar); 3]| props.map(|(k, _)| scalars[k]);
+            if let Some(p) = position {
+                let [x, y, z] = get(p);
+                self.vertices.push(point(x, y, z));
+            }
+            if let Some(n) = normal {
+                let [x, y, z] = get(n);
+                self.normals.push(vector(x, y, z));
+            }
+            if let Some(c) = rgb {
+                let [r, g, b] = get(c);
+                let scale = c[0].1.color_scale();
+                self.colors.push(color(r / scale, g / scale, b / scale));
+            }
+        }
+        Ok(())
+    }
+
+    fn add_polygon(&mut self, polygon: &[f64]) -> Result<(), PlyError> {
+        let indices = polygon
+            .iter()
+            .map(|&i| {
+                if i >= 0.0 && i <= f64::from(u32::MAX) {
+                    Ok(i as u32)
+                } else {
+                    Err(PlyError::IndexOutOfRange {
+                        index: i as i64,
+                        count: self.vertices.len(),
+                    })
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for i in 1..indices.len().saturating_sub(1) {
+            self.triangles
+                .push([indices[0], indices[i], indices[i + 1]]);
+        }
+        Ok(())
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<(Header, &[u8]), PlyError> {
+    let mut header = Header {
+        format: Format::Ascii,
+        elements: vec![],
+        comments: vec![],
+    };
+    let mut format = None;
+    let mut pos = 0;
+    let mut line_number = 0;
+
+    loop {
+        line_number += 1;
+        let error = |message: &str| PlyError::InvalidHeader {
+            line: line_number,
+            message: message.to_string(),
+        };
+        if pos >= data.len() {
+            return Err(error("the header has no `end_header` line"));
+        }
+        let end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| pos + i);
+        let line = std::str::from_utf8(&data[pos..end]).map_err(|_| error("not a PLY file"))?;
+        pos = (end + 1).min(data.len());
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if line_number == 1 {
+            if fields != ["ply"] {
+                return Err(error("not a PLY file"));
+            }
+            continue;
+        }
+        match fields.as_slice() {
+            ["end_header"] => break,
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", other, ..] => {
+                return Err(error(&format!("unsupported format `{}`", other)));
+            }
+            ["comment", ..] => header
+                .comments
+                .push(line.trim_start()["comment".len()..].trim().to_string()),
+            ["obj_info", ..] | [] => {}
+            ["element", name, count] => header.elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| error(&format!("invalid element count `{}`", count)))?,
+                properties: vec![],
+            }),
+            ["property", rest @ ..] => {
+                let element = header
+                    .elements
+                    .last_mut()
+                    .ok_or_else(|| error("a property before the first element"))?;
+                let scalar = |name: &str| {
+                    Scalar::parse(name).ok_or_else(|| error(&format!("unknown type `{}`", name)))
+                };
+                let (name, ty) = match rest {
+                    ["list", count, item, name] => (
+                        name,
+                        PropertyType::List {
+                            count: scalar(count)?,
+                            item: scalar(item)?,
+                        },
+                    ),
+                    [ty, name] => (name, PropertyType::Scalar(scalar(ty)?)),
+                    _ => return Err(error("invalid property")),
+                };
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    ty,
+                });
+            }
+            _ => return Err(error(&format!("unexpected `{}`", line.trim()))),
+        }
+    }
+
+    let error = |message: &str| PlyError::InvalidHeader {
+        line: line_number,
+        message: message.to_string(),
+    };
+    header.format = format.ok_or_else(|| error("the header has no `format` line"))?;
+    let vertex = header.elements.iter().find(|e| e.name == "vertex");
+    if vertex.is_some_and(|e| e.scalars(["x", "y", "z"]).is_none()) {
+        return Err(error("the vertex element has no x, y and z properties"));
+    }
+    Ok((header, &data[pos..]))
+}
+
+impl From<PlyParser> for Group {
+    fn from(p: PlyParser) -> Group {
+        p.mesh().to_triangles()
+    }
+}
+
+impl From<PlyParser> for SceneItem {
+    fn from(p: PlyParser) -> SceneItem {
+        SceneItem::Compound(p.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::ray::{Intersection, Ray};
+
+    const ASCII: &str = "\
+ply
+format ascii 1.0
+comment a quad and a triangle
+element vertex 5
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element face 2
+property uchar intensity
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+0 0 0 0 0 -1 255 0 0
+1 0 0 0 0 -1 0 255 0
+1 1 0 0 0 -1 0 0 255
+0 1 0 0 0 -1 255 255 255
+0 0 1 1 0 0 0 0 0
+7 4 0 1 2 3
+7 3 0 1 4
+0 1
+";
+
+    /// Vertices with normals and colors, polygons split into triangles
+    #[test]
+    fn ascii() {
+        let parser = PlyParser::parse_bytes(ASCII.as_bytes()).unwrap();
+        assert_eq!(parser.comments(), &["a quad and a triangle"]);
+        assert_almost_eq!(parser.vertices[2], point(1, 1, 0));
+        assert_almost_eq!(parser.normals[4], vector(1, 0, 0));
+        assert_almost_eq!(parser.colors[1], color(0, 1, 0));
+        assert_eq!(parser.triangles, vec![[0, 1, 2], [0, 2, 3], [0, 1, 4]]);
+    }
+
+    /// A binary file holds the same data as the ASCII one
+    #[test]
+    fn binary() {
+        let mut data = b"ply\nformat binary_little_endian 1.0\n\
+            element vertex 3\nproperty double x\nproperty float y\nproperty short z\n\
+            element face 1\nproperty list uchar uint vertex_indices\nend_header\n"
+            .to_vec();
+        for &(x, y, z) in &[(0.0, 0.0, 0), (1.0, 0.0, -2), (1.0, 1.5, 0)] {
+            data.extend_from_slice(&f64::to_le_bytes(x));
+            data.extend_from_slice(&f32::to_le_bytes(y));
+            data.extend_from_slice(&i16::to_le_bytes(z));
+        }
+        data.push(3);
+        for i in 0u32..3 {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+
+        let parser = PlyParser::parse_bytes(&data).unwrap();
+        assert_almost_eq!(
+            parser.vertices,
+            vec![point(0, 0, 0), point(1, 0, -2), point(1, 1.5, 0)]
+        );
+        assert!(parser.normals.is_empty());
+        assert!(parser.colors.is_empty());
+        assert_eq!(parser.triangles, vec![[0, 1, 2]]);
+
+        let err = PlyParser::parse_bytes(&data[..data.len() - 2]).err();
+        assert_eq!(
+            err,
+            Some(PlyError::Truncated {
+                element: "face".to_string(),
+                read: 0,
+                count: 1
+            })
+        );
+
+        let start = data.len() - 13 - 3 * 14;
+        data[start..start + 8].copy_from_slice(&f64::NAN.to_le_bytes());
+        let err = PlyParser::parse_bytes(&data).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "`vertex` element 0 has a value that is not a finite number"
+        );
+    }
+
+    /// The mesh interpolates the vertex colors, which tint the material
+    #[test]
+    fn vertex_colors() {
+        let parser = PlyParser::parse_bytes(ASCII.as_bytes()).unwrap();
+        let group = parser.meshes();
+        let shape = group.get_child(0).as_shape().unwrap();
+        let r = Ray::new(point(0.5, 0.25, -1), vector(0, 0, 1));
+        let xs = shape.intersect(&r);
+        assert_eq!(xs.len(), 1);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert_almost_eq!(comps.vertex_color.unwrap(), color(0.5, 0.25, 0.25));
+
+        let triangles: Group = parser.into();
+        assert_eq!(triangles.len(), 3);
+        let tri = triangles.get_child(0).as_shape().unwrap();
+        assert_eq!(tri.vertex_color(&Intersection::new(0.0, tri)), None);
+    }
+
+    /// Malformed and truncated files are reported
+    #[test]
+    fn errors() {
+        let header_error = |data: &str| match PlyParser::parse_bytes(data.as_bytes()) {
+            Err(PlyError::InvalidHeader { line, .. }) => line,
+            other => panic!("unexpected result {:?}", other.map(|_| ())),
+        };
+        assert_eq!(header_error("solid cube\n"), 1);
+        assert_eq!(header_error("ply\nformat ascii 1.0\n"), 3);
+        assert_eq!(header_error("ply\nformat binary_big_endian 1.0\n"), 2);
+        assert_eq!(header_error("ply\nformat ascii 1.0\nproperty float x\n"), 3);
+        assert_eq!(
+            header_error("ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\n"),
+            4
+        );
+        assert_eq!(header_error("ply\nelement vertex 0\nend_header\n"), 3);
+        assert_eq!(
+            header_error("ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\nend_header\n"),
+            5
+        );
+
+        let truncated = &ASCII[..ASCII.len() - 15];
+        assert_eq!(
+            PlyParser::parse_bytes(truncated.as_bytes()).err(),
+            Some(PlyError::Truncated {
+                element: "face".to_string(),
+                read: 1,
+                count: 2
+            })
+        );
+
+        let data = ASCII.replace("7 3 0 1 4", "7 3 0 1 5");
+        let err = PlyParser::parse_bytes(data.as_bytes()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "vertex index 5 is out of range, the file has 5 vertices"
+        );
+
+        let data = ASCII.replace("7 3 0 1 4", "7 3 0 one 4");
+        assert_eq!(
+            PlyParser::parse_bytes(data.as_bytes()).err(),
+            Some(PlyError::InvalidNumber("one".to_string()))
+        );
+
+        let data = ASCII.replace("1 1 0 0 0 -1", "1 1e309 0 0 0 -1");
+        assert_eq!(
+            PlyParser::parse_bytes(data.as_bytes()).err(),
+            Some(PlyError::NonFiniteNumber {
+                element: "vertex".to_string(),
+                row: 2
+            })
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Showing an image while it is rendered.
+//!
+//! `Camera::render_with_preview` passes every finished pixel to a `Preview`. There are two
+//! backends: a window (`live_preview::WindowPreview`, which needs the `live-preview` feature and
+//! the SDL2 libraries) and `PngPreview`, which writes the partially rendered image to a PNG
+//! file from time to time and works on machines without a display.
+
+use crate::canvas::Canvas;
+use crate::color::Color;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Receives the pixels of a render as they are done. It is called from the rendering threads.
+pub trait Preview: Sync {
+    fn set_pixel(&self, x: u32, y: u32, c: Color);
+
+    /// Whether the preview was closed, which cancels the render
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Called once the render is done
+    fn finish(&self) {}
+}
+
+/// Writes the image to a PNG file every `interval` during the render, and when it is done.
+/// The file is written from a copy of the image, so that the other rendering threads don't wait
+/// for the encoding.
+pub struct PngPreview {
+    path: PathBuf,
+    interval: Duration,
+    state: Mutex<(Canvas, Instant)>,
+    /// Held while the file is written; a thread finding it taken skips its write
+    writing: Mutex<()>,
+}
+
+impl PngPreview {
+    pub fn new(path: impl Into<PathBuf>, width: u32, height: u32, interval: Duration) -> Self {
+        PngPreview {
+            path: path.into(),
+            interval,
+            state: Mutex::new((Canvas::new(width, height), Instant::now())),
+            writing: Mutex::new(()),
+        }
+    }
+
+    /// Write the image to a temporary file and move it over the preview, so that image viewers
+    /// never see a half written file.
+    fn write(&self, canvas: &Canvas) {
+        let mut temporary = self.path.as_os_str().to_owned();
+        temporary.push(".tmp.png");
+        let result = canvas
+            .write_image(&temporary)
+            .and_then(|_| std::fs::rename(&temporary, &self.path));
+        if let Err(e) = result {
+            log::warn!("could not write preview {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl Preview for PngPreview {
+    fn set_pixel(&self, x: u32, y: u32, c: Color) {
+        let mut state = self.state.lock().unwrap();
+        let (canvas, last_write) = &mut *state;
+        canvas.set_pixel(x, y, c);
+        if last_write.elapsed() < self.interval {
+            return;
+        }
+        if let Ok(_writing) = self.writing.try_lock() {
+            let copy = canvas.clone();
+            *last_write = Instant::now();
+            drop(state);
+            self.write(&copy);
+        }
+    }
+
+    fn finish(&self) {
+        let _writing = self.writing.lock().unwrap();
+        let copy = self.state.lock().unwrap().0.clone();
+        self.write(&copy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::progress::RenderControl;
+    use crate::tuple::{point, vector};
+    use crate::world::World;
+    use std::f64::consts::PI;
+
+    /// The PNG preview holds the finished image at the end of the render
+    #[test]
+    fn png_preview() {
+        let mut camera = Camera::new(6, 4, PI / 2.0).with_view_transform(
+            point(0, 0, -5),
+            point(0, 0, 0),
+            vector(0, 1, 0),
+        );
+        camera.set_min_samples(1);
+        let world = World::default();
+
+        let dir = std::env::temp_dir().join(format!("raytracing-preview-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("preview.png");
+        let preview = PngPreview::new(&path, 6, 4, Duration::from_secs(0));
+        let (image, _) = camera.render_with_preview(&world, &preview, &RenderControl::default());
+        let written = Canvas::read_image(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let written = written.unwrap();
+        let expected = image.get_pixel(3, 2).to_u8();
+        assert_eq!(written.get_pixel(3, 2).to_u8(), expected);
+        assert!(!preview.is_closed());
+    }
+}
@@ -0,0 +1,356 @@
+//! Watching and stopping renders.
+//!
+//! A `RenderControl` is passed to a render to receive progress reports, to cancel it from
+//! another thread, or to limit the time it may take:
+//!
+//! ```no_run
+//! # use raytracing::{camera::Camera, world::World};
+//! use raytracing::progress::{CancellationToken, Progress, RenderControl};
+//! use std::time::Duration;
+//!
+//! # let (camera, world) = (Camera::new(1, 1, 1.0), World::default());
+//! let report = |progress: &Progress| eprintln!("{:.0}% done", 100.0 * progress.fraction());
+//! let cancel = CancellationToken::new();
+//! let control = RenderControl::new()
+//!     .with_observer(&report)
+//!     .with_cancellation(cancel.clone())
+//!     .with_time_budget(Duration::from_secs(600));
+//! let (image, progress) = camera.render_with_control(&world, &control);
+//! ```
+//!
+//! When the time budget is used up, pixels are no longer refined: every pixel that is still to
+//! be rendered gets a single sample, so the render finishes soon with a noisy but complete
+//! image. A cancelled render stops as soon as possible and leaves the remaining pixels black.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A snapshot of the state of a render
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub pixels_done: u64,
+    pub pixels_total: u64,
+    /// Zero if the render does not use tiles
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    /// The number of primary rays traced so far
+    pub samples: u64,
+    pub elapsed: Duration,
+    /// The estimated time until the render is done
+    pub eta: Option<Duration>,
+    pub cancelled: bool,
+    pub out_of_time: bool,
+}
+
+impl Progress {
+    /// The part of all pixels that is done, from 0 to 1
+    pub fn fraction(&self) -> f64 {
+        if self.pixels_total == 0 {
+            1.0
+        } else {
+            self.pixels_done as f64 / self.pixels_total as f64
+        }
+    }
+}
+
+/// Receives the progress of a render. It is called from the rendering threads.
+pub trait ProgressObserver: Sync {
+    fn update(&self, progress: &Progress);
+}
+
+impl<F: Fn(&Progress) + Sync> ProgressObserver for F {
+    fn update(&self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+/// A flag to stop a render from another thread. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// The observer, cancellation token and time budget of a render. The default has none of them.
+#[derive(Clone)]
+pub struct RenderControl<'a> {
+    observer: Option<&'a dyn ProgressObserver>,
+    report_interval: Duration,
+    cancellation: Option<CancellationToken>,
+    time_budget: Option<Duration>,
+}
+
+impl Default for RenderControl<'_> {
+    fn default() -> Self {
+        RenderControl {
+            observer: None,
+            report_interval: Duration::from_millis(500),
+            cancellation: None,
+            time_budget: None,
+        }
+    }
+}
+
+impl<'a> RenderControl<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_observer(mut self, observer: &'a dyn ProgressObserver) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// The minimum time between two reports to the observer. The end of the render is always
+    /// reported.
+    pub fn with_report_interval(mut self, interval: Duration) -> Self {
+        self.report_interval = interval;
+        self
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Stop refining pixels after this time. A budget too long to be represented is no limit.
+    pub fn with_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Begin tracking a render of `pixels_total` pixels, of which `pixels_done` are already
+    /// done (when resuming a render).
+    pub(crate) fn start(
+        &self,
+        pixels_total: u64,
+        pixels_done: u64,
+        tiles_total: usize,
+        tiles_done: usize,
+    ) -> Tracker<'_> {
+        let start = Instant::now();
+        Tracker {
+            control: self,
+            start,
+            deadline: self
+                .time_budget
+                .and_then(|budget| start.checked_add(budget)),
+            pixels_total,
+            pixels_resumed: pixels_done,
+            pixels_done: AtomicU64::new(pixels_done),
+            tiles_total,
+            tiles_done: AtomicUsize::new(tiles_done),
+            samples: AtomicU64::new(0),
+            last_report: Mutex::new(start),
+            stopped: AtomicBool::new(false),
+        }
+    }
+}
+
+/// The state of a running render
+pub(crate) struct Tracker<'a> {
+    control: &'a RenderControl<'a>,
+    start: Instant,
+    deadline: Option<Instant>,
+    pixels_total: u64,
+    pixels_resumed: u64,
+    pixels_done: AtomicU64,
+    tiles_total: usize,
+    tiles_done: AtomicUsize,
+    samples: AtomicU64,
+    last_report: Mutex<Instant>,
+    /// Set by `cancel`, besides the token of the control
+    stopped: AtomicBool,
+}
+
+impl Tracker<'_> {
+    pub fn cancel(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        let token_cancelled = match &self.control.cancellation {
+            Some(token) => token.is_cancelled(),
+            None => false,
+        };
+        token_cancelled || self.stopped.load(Ordering::Relaxed)
+    }
+
+    pub fn is_out_of_time(&self) -> bool {
+        match self.deadline {
+            Some(deadline) => Instant::now() >= deadline,
+            None => false,
+        }
+    }
+
+    /// Whether pixels should take more samples than the first one
+    pub fn may_refine(&self) -> bool {
+        !self.is_cancelled() && !self.is_out_of_time()
+    }
+
+    pub fn pixel_done(&self, samples: u32) {
+        self.pixels_done.fetch_add(1, Ordering::Relaxed);
+        self.samples.fetch_add(samples as u64, Ordering::Relaxed);
+        self.report();
+    }
+
+    /// Count samples of pixels that are not done yet
+    pub fn samples_done(&self, samples: u64) {
+        self.samples.fetch_add(samples, Ordering::Relaxed);
+        self.report();
+    }
+
+    pub fn tile_done(&self) {
+        self.tiles_done.fetch_add(1, Ordering::Relaxed);
+        self.report();
+    }
+
+    /// Report the final state to the observer and return it
+    pub fn finish(&self) -> Progress {
+        let progress = self.progress();
+        if let Some(observer) = self.control.observer {
+            observer.update(&progress);
+        }
+        progress
+    }
+
+    fn report(&self) {
+        let observer = match self.control.observer {
+            Some(observer) => observer,
+            None => return,
+        };
+        // skip the report if another thread is reporting right now
+        if let Ok(mut last_report) = self.last_report.try_lock() {
+            if last_report.elapsed() >= self.control.report_interval {
+                *last_report = Instant::now();
+                observer.update(&self.progress());
+            }
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let elapsed = self.start.elapsed();
+        let pixels_done = self.pixels_done.load(Ordering::Relaxed);
+        let rendered = pixels_done - self.pixels_resumed;
+        let remaining = self.pixels_total.saturating_sub(pixels_done);
+        let eta = if rendered > 0 {
+            Some(elapsed.mul_f64(remaining as f64 / rendered as f64))
+        } else {
+            None
+        };
+        Progress {
+            pixels_done,
+            pixels_total: self.pixels_total,
+            tiles_done: self.tiles_done.load(Ordering::Relaxed),
+            tiles_total: self.tiles_total,
+            samples: self.samples.load(Ordering::Relaxed),
+            elapsed,
+            eta,
+            cancelled: self.is_cancelled(),
+            out_of_time: self.is_out_of_time(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+    use crate::color::BLACK;
+    use crate::tiles::TiledRender;
+    use crate::tuple::{point, vector};
+    use crate::world::World;
+    use std::f64::consts::PI;
+
+    fn camera(width: u32, height: u32) -> Camera {
+        let mut camera = Camera::new(width, height, PI / 2.0).with_view_transform(
+            point(0, 0, -5),
+            point(0, 0, 0),
+            vector(0, 1, 0),
+        );
+        camera.set_min_samples(4);
+        camera
+    }
+
+    /// The observer sees the render progress, and the final report counts all pixels
+    #[test]
+    fn observer() {
+        let reports = Mutex::new(vec![]);
+        let observer = |progress: &Progress| reports.lock().unwrap().push(progress.clone());
+        let control = RenderControl::new()
+            .with_observer(&observer)
+            .with_report_interval(Duration::from_secs(0));
+        let (_, progress) = camera(8, 6).render_with_control(&World::default(), &control);
+
+        let reports = reports.into_inner().unwrap();
+        assert!(reports.len() > 1);
+        assert_eq!(reports.last(), Some(&progress));
+        assert_eq!((progress.pixels_done, progress.pixels_total), (48, 48));
+        assert!(progress.samples >= 4 * 48);
+        assert_eq!(progress.fraction(), 1.0);
+        assert_eq!(progress.eta, Some(Duration::from_secs(0)));
+        assert!(!progress.cancelled && !progress.out_of_time);
+    }
+
+    /// Without time, every pixel gets only a single sample
+    #[test]
+    fn time_budget() {
+        let control = RenderControl::new().with_time_budget(Duration::from_secs(0));
+        let (image, progress) = camera(8, 6).render_with_control(&World::default(), &control);
+        assert_eq!(progress.samples, 48);
+        assert!(progress.out_of_time);
+        assert!(!image.get_pixel(4, 3).is_black());
+    }
+
+    /// A budget beyond the range of `Instant` doesn't limit the render
+    #[test]
+    fn unlimited_time_budget() {
+        let control = RenderControl::new().with_time_budget(Duration::MAX);
+        let (_, progress) = camera(8, 6).render_with_control(&World::default(), &control);
+        assert!(progress.samples > 48);
+        assert!(!progress.out_of_time);
+    }
+
+    /// A cancelled render leaves the remaining pixels and tiles undone
+    #[test]
+    fn cancellation() {
+        let (camera, world) = (camera(40, 30), World::default());
+        let token = CancellationToken::new();
+        let cancel_after_one = |progress: &Progress| {
+            if progress.pixels_done > 0 {
+                token.cancel()
+            }
+        };
+        let control = RenderControl::new()
+            .with_observer(&cancel_after_one)
+            .with_report_interval(Duration::from_secs(0))
+            .with_cancellation(token.clone());
+        let (image, progress) = camera.render_with_control(&world, &control);
+        assert!(progress.cancelled);
+        assert!(progress.pixels_done < 1200);
+        assert!(image.flat().filter(|c| c.is_black()).count() > 0);
+
+        let control = RenderControl::new().with_cancellation(token.clone());
+        let checkpoint = TiledRender::new(&camera, &world)
+            .with_tile_size(2)
+            .with_control(control)
+            .render()
+            .unwrap();
+        assert_eq!(checkpoint.completed_tiles(), 0);
+        assert_eq!(checkpoint.image().unwrap().get_pixel(20, 15), BLACK);
+    }
+}
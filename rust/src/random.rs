@@ -0,0 +1,111 @@
+//! Deterministic random numbers.
+//!
+//! Rendering and photon tracing draw random numbers on many threads. To make the results
+//! reproducible, every unit of work (a pixel, a photon) gets its own generator, derived from a
+//! seed and the index of the work item. That way, the numbers a pixel sees do not depend on
+//! which thread renders it, or in which order.
+
+use rand::{Error, RngCore, SeedableRng};
+
+/// A small and fast pseudo random number generator (SplitMix64).
+#[derive(Debug, Clone)]
+pub struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    pub fn new(seed: u64) -> Self {
+        Prng { state: seed }
+    }
+
+    /// A generator for the random stream identified by `keys`, such as the coordinates of a
+    /// pixel. Different keys give statistically independent streams.
+    pub fn stream(seed: u64, keys: &[u64]) -> Self {
+        let state = keys
+            .iter()
+            .fold(mix(seed), |state, &key| mix(state ^ mix(key)));
+        Prng::new(state)
+    }
+}
+
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl RngCore for Prng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let z = mix(self.state);
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for Prng {
+    type Seed = [u8; 8];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        Prng::new(u64::from_le_bytes(seed))
+    }
+
+    fn seed_from_u64(seed: u64) -> Self {
+        Prng::new(seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    /// The same seed gives the same numbers
+    #[test]
+    fn reproducible() {
+        let mut a = Prng::new(42);
+        let mut b = Prng::new(42);
+        for _ in 0..10 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+        assert_ne!(Prng::new(42).next_u64(), Prng::new(43).next_u64());
+    }
+
+    /// Streams depend on the seed and on all keys
+    #[test]
+    fn streams() {
+        let first = |seed, keys: &[u64]| Prng::stream(seed, keys).next_u64();
+        assert_eq!(first(1, &[2, 3]), first(1, &[2, 3]));
+        assert_ne!(first(1, &[2, 3]), first(1, &[3, 2]));
+        assert_ne!(first(1, &[2, 3]), first(2, &[2, 3]));
+        assert_ne!(first(1, &[0]), first(1, &[0, 0]));
+    }
+
+    /// Uniform numbers cover the unit interval evenly
+    #[test]
+    fn uniform() {
+        let mut rng = Prng::new(0);
+        let mut bins = [0; 10];
+        for _ in 0..10000 {
+            let x: f64 = rng.gen();
+            bins[(x * 10.0) as usize] += 1;
+        }
+        assert!(bins.iter().all(|&n| n > 900 && n < 1100), "{:?}", bins);
+    }
+}
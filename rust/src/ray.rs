@@ -1,4 +1,5 @@
 use crate::approx_eq::EPSILON;
+use crate::color::Color;
 use crate::materials::Material;
 use crate::matrix::Matrix;
 use crate::shapes::Shape;
@@ -84,6 +85,8 @@ impl<'a> Intersection<'a> {
         let reflectv = ray.direction().reflect(&normalv);
         let (mat1, mat2) = self.compute_materials(xs);
         let (n1, n2) = self.compute_refractive_indices(xs);
+        let texture_coords = self.obj.texture_coords(self);
+        let vertex_color = self.obj.vertex_color(self);
         IntersectionState {
             t: self.t,
             obj: self.obj,
@@ -98,6 +101,8 @@ impl<'a> Intersection<'a> {
             n2,
             mat1,
             mat2,
+            texture_coords,
+            vertex_color,
         }
     }
 
@@ -188,12 +193,29 @@ pub struct IntersectionState<'a> {
     pub n2: f64,
     pub mat1: Option<&'a dyn Material>,
     pub mat2: Option<&'a dyn Material>,
+    /// Texture coordinates of the surface at the hit, if it has any
+    pub texture_coords: Option<(f64, f64)>,
+    /// Color interpolated from the vertex colors of a mesh, if it has any
+    pub vertex_color: Option<Color>,
 }
 
 impl IntersectionState<'_> {
     pub fn schlick(&self) -> f64 {
         schlick(self.eyev, self.normalv, self.n1, self.n2)
     }
+
+    /// The direction of the refracted ray, or `None` under total internal reflection.
+    pub fn refraction_direction(&self) -> Option<Vector> {
+        let n_ratio = self.n1 / self.n2;
+        let cos_i = self.eyev.dot(&self.normalv);
+        let sin2_t = n_ratio * n_ratio * (1.0 - cos_i * cos_i);
+        if sin2_t > 1.0 {
+            None
+        } else {
+            let cos_t = (1.0 - sin2_t).sqrt();
+            Some(self.normalv * (n_ratio * cos_i - cos_t) - self.eyev * n_ratio)
+        }
+    }
 }
 
 pub fn schlick(eyev: Vector, normalv: Vector, n1: f64, n2: f64) -> f64 {
@@ -0,0 +1,1058 @@
+//! Scenes in the glTF 2.0 format.
+//!
+//! Both JSON files (`.gltf`, with their buffers embedded as base64 data URIs or stored in
+//! adjacent files) and binary files (`.glb`) are read. The node hierarchy of the default scene
+//! becomes nested groups with the node transforms, and every triangle primitive becomes an
+//! indexed mesh with its normals, texture coordinates and vertex colors.
+//!
+//! The first camera found in the hierarchy is used; without one, a camera looks at the whole scene
+//! along the negative z axis. Lights of the `KHR_lights_punctual` extension become point and spot
+//! lights; directional lights are approximated by distant point lights. If there are no lights,
+//! a light is placed at the camera. Intensities are used as they are, without photometric units.
+//!
+//! The metallic-roughness materials are approximated with `Phong`:
+//! - the base color (or the base color texture) becomes the surface color
+//! - metals have a weaker diffuse and a stronger specular component, and reflect according to
+//!   their smoothness; the roughness determines the shininess
+//! - emissive factors, blended alpha and `KHR_materials_transmission` map onto the emissive and
+//!   transparency properties
+//!
+//! Other texture maps, sparse accessors, animations and skins are ignored.
+
+use crate::camera::Camera;
+use crate::canvas::Canvas;
+use crate::color::{color, Color};
+use crate::lights::{PointLight, SpotLight};
+use crate::materials::Phong;
+use crate::matrix::{scaling, translation, Matrix};
+use crate::pattern::image_pattern;
+use crate::scene::json;
+use crate::scene::yaml::Node;
+use crate::scene::{Scene, SceneError};
+use crate::shapes::{Group, Mesh, Shape};
+use crate::tuple::{point, vector, Point, Vector};
+use crate::world::World;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+const GLB_MAGIC: &[u8] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+/// The size of the image when the camera does not define an aspect ratio
+const DEFAULT_WIDTH: u32 = 640;
+const DEFAULT_ASPECT_RATIO: f64 = 4.0 / 3.0;
+
+pub fn load_gltf_scene(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let data = std::fs::read(path)?;
+    parse_gltf_scene(&data, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Build a scene from the contents of a `.gltf` or `.glb` file. External buffers and images are
+/// resolved against `base_dir`.
+pub fn parse_gltf_scene(data: &[u8], base_dir: &Path) -> Result<Scene, SceneError> {
+    let (text, binary_chunk) = if data.starts_with(GLB_MAGIC) {
+        split_glb(data)?
+    } else {
+        (data, None)
+    };
+    let text = std::str::from_utf8(text).map_err(|_| invalid_data("the JSON is not UTF-8"))?;
+    let document = json::parse(text)?;
+    Importer::new(&document, binary_chunk, base_dir)?.import()
+}
+
+fn invalid_data(message: &str) -> SceneError {
+    SceneError::Io(Error::new(ErrorKind::InvalidData, message))
+}
+
+/// The JSON and the binary chunk of a `.glb` file.
+fn split_glb(data: &[u8]) -> Result<(&[u8], Option<&[u8]>), SceneError> {
+    let u32_at = |pos: usize| {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if u32_at(4) != Some(2) {
+        return Err(invalid_data("unsupported GLB version"));
+    }
+    let length = u32_at(8).ok_or_else(|| invalid_data("truncated GLB header"))? as usize;
+    if length > data.len() {
+        return Err(invalid_data("truncated GLB file"));
+    }
+
+    let (mut text, mut binary) = (None, None);
+    let mut pos = 12;
+    while pos + 8 <= length {
+        let chunk_length = u32_at(pos).unwrap() as usize;
+        let chunk = data
+            .get(pos + 8..pos + 8 + chunk_length)
+            .ok_or_else(|| invalid_data("truncated GLB chunk"))?;
+        match u32_at(pos + 4).unwrap() {
+            GLB_JSON_CHUNK if text.is_none() => text = Some(chunk),
+            GLB_BIN_CHUNK if binary.is_none() => binary = Some(chunk),
+            _ => {}
+        }
+        pos += 8 + chunk_length;
+    }
+    let text = text.ok_or_else(|| invalid_data("the GLB file has no JSON chunk"))?;
+    Ok((text, binary))
+}
+
+struct Importer<'a> {
+    document: &'a Node,
+    base_dir: &'a Path,
+    buffers: Vec<Vec<u8>>,
+    materials: HashMap<u32, Phong>,
+    meshes: HashMap<u32, Group>,
+    world: World,
+    camera: Option<(Camera, Point)>,
+    directional_lights: Vec<(Vector, Color)>,
+    /// The nodes from the root to the node being imported, to detect cycles
+    path: Vec<u32>,
+}
+
+impl<'a> Importer<'a> {
+    fn new(
+        document: &'a Node,
+        binary_chunk: Option<&[u8]>,
+        base_dir: &'a Path,
+    ) -> Result<Self, SceneError> {
+        let version = required(required(document, "asset")?, "version")?;
+        if !version.as_str()?.starts_with("2.") {
+            return Err(version.error("only glTF 2.0 is supported"));
+        }
+
+        let mut importer = Importer {
+            document,
+            base_dir,
+            buffers: vec![],
+            materials: HashMap::new(),
+            meshes: HashMap::new(),
+            world: World::empty(),
+            camera: None,
+            directional_lights: vec![],
+            path: vec![],
+        };
+        for (i, buffer) in importer.items("buffers")?.iter().enumerate() {
+            let data = match buffer.get("uri") {
+                Some(uri) => importer.read_uri(uri)?,
+                None if i == 0 && binary_chunk.is_some() => binary_chunk.unwrap().to_vec(),
+                None => return Err(buffer.error("the buffer has no data")),
+            };
+            if data.len() < required(buffer, "byteLength")?.as_u32()? as usize {
+                return Err(buffer.error("the buffer is shorter than its `byteLength`"));
+            }
+            importer.buffers.push(data);
+        }
+        Ok(importer)
+    }
+
+    fn import(mut self) -> Result<Scene, SceneError> {
+        let mut root = Group::default();
+        for (i, reference) in self.root_nodes()? {
+            let child = self.node(i, reference, Matrix::identity())?;
+            root.add_child(child);
+        }
+        root.update_transform(Matrix::identity());
+        let bounds = root.aabb();
+        let (center, radius) = if bounds.is_finite() {
+            (bounds.center(), bounds.size().len() / 2.0)
+        } else {
+            (point(0.0, 0.0, 0.0), 1.0)
+        };
+        self.world.add_item(root);
+
+        let (camera, eye) = match self.camera.take() {
+            Some(camera) => camera,
+            None => default_camera(center, radius),
+        };
+        for &(direction, intensity) in &self.directional_lights {
+            let position = center - direction * (1000.0 * radius.max(1.0));
+            self.world.add_light(PointLight::new(position, intensity));
+        }
+        if self.world.lights().is_empty() {
+            self.world
+                .add_light(PointLight::new(eye, color(1.0, 1.0, 1.0)));
+        }
+
+        self.world.finalize_scene();
+        Ok(Scene {
+            world: self.world,
+            camera,
+        })
+    }
+
+    /// The top-level array `name`, which may be absent
+    fn items(&self, name: &str) -> Result<&'a [Node], SceneError> {
+        match self.document.get(name) {
+            Some(items) => items.as_sequence(),
+            None => Ok(&[]),
+        }
+    }
+
+    /// The entry of the top-level array `name` that `index` refers to
+    fn lookup(&self, name: &str, index: &Node) -> Result<&'a Node, SceneError> {
+        let i = index.as_u32()?;
+        self.items(name)?
+            .get(i as usize)
+            .ok_or_else(|| index.error(format!("`{}` has no entry {}", name, i)))
+    }
+
+    /// The indices of the nodes of the default scene, or of all nodes that are not children of
+    /// other nodes if there are no scenes, together with a node to report errors at
+    fn root_nodes(&self) -> Result<Vec<(u32, &'a Node)>, SceneError> {
+        let scenes = self.items("scenes")?;
+        let scene = match self.document.get("scene") {
+            Some(index) => Some(self.lookup("scenes", index)?),
+            None => scenes.first(),
+        };
+        if let Some(scene) = scene {
+            return Ok(match scene.get("nodes") {
+                Some(nodes) => nodes
+                    .as_sequence()?
+                    .iter()
+                    .map(|index| Ok((index.as_u32()?, index)))
+                    .collect::<Result<_, SceneError>>()?,
+                None => vec![],
+            });
+        }
+
+        let mut is_child = vec![false; self.items("nodes")?.len()];
+        for node in self.items("nodes")? {
+            for child in sequence(node, "children")? {
+                if let Some(flag) = is_child.get_mut(child.as_u32()? as usize) {
+                    *flag = true;
+                }
+            }
+        }
+        Ok(self
+            .items("nodes")?
+            .iter()
+            .zip(0..)
+            .zip(is_child)
+            .filter(|(_, is_child)| !is_child)
+            .map(|((node, i), _)| (i, node))
+            .collect())
+    }
+
+    /// Read the data of a buffer or an image from a data URI or a file
+    fn read_uri(&self, uri: &Node) -> Result<Vec<u8>, SceneError> {
+        let text = uri.as_str()?;
+        if let Some(data) = text.strip_prefix("data:") {
+            let (_, encoded) = data
+                .split_once(";base64,")
+                .ok_or_else(|| uri.error("only base64 data URIs are supported"))?;
+            decode_base64(encoded).ok_or_else(|| uri.error("invalid base64 data"))
+        } else {
+            let path = self.base_dir.join(percent_decode(text));
+            std::fs::read(&path)
+                .map_err(|e| uri.error(format!("unable to read `{}`: {}", path.display(), e)))
+        }
+    }
+
+    /// The bytes of a buffer view
+    fn buffer_view(&self, index: &Node) -> Result<(&[u8], Option<usize>), SceneError> {
+        let view = self.lookup("bufferViews", index)?;
+        let buffer_index = required(view, "buffer")?;
+        let buffer = self
+            .buffers
+            .get(buffer_index.as_u32()? as usize)
+            .ok_or_else(|| buffer_index.error("there is no such buffer"))?;
+        let offset = optional_u32(view, "byteOffset", 0)? as usize;
+        let length = required(view, "byteLength")?.as_u32()? as usize;
+        let stride = match optional_u32(view, "byteStride", 0)? {
+            0 => None,
+            stride => Some(stride as usize),
+        };
+        let data = buffer
+            .get(offset..offset + length)
+            .ok_or_else(|| view.error("the buffer view exceeds its buffer"))?;
+        Ok((data, stride))
+    }
+
+    /// The elements of an accessor, which must have `size` components each. Normalized integers
+    /// are converted to the range [0, 1] (or [-1, 1] if they are signed).
+    fn accessor(&self, index: &Node, sizes: &[usize]) -> Result<Vec<Vec<f64>>, SceneError> {
+        let accessor = self.lookup("accessors", index)?;
+        if accessor.get("sparse").is_some() {
+            return Err(accessor.error("sparse accessors are not supported"));
+        }
+        let count = required(accessor, "count")?.as_u32()? as usize;
+        let kind = required(accessor, "type")?;
+        let size = match kind.as_str()? {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            other => return Err(kind.error(format!("unsupported accessor type `{}`", other))),
+        };
+        if !sizes.contains(&size) {
+            return Err(kind.error(format!("unexpected accessor type `{}`", kind.as_str()?)));
+        }
+
+        let component_type = required(accessor, "componentType")?;
+        let (component_size, max): (usize, f64) = match component_type.as_u32()? {
+            5120 => (1, i8::MAX as f64),
+            5121 => (1, u8::MAX as f64),
+            5122 => (2, i16::MAX as f64),
+            5123 => (2, u16::MAX as f64),
+            5125 => (4, u32::MAX as f64),
+            5126 => (4, 1.0),
+            other => return Err(component_type.error(format!("unknown component type {}", other))),
+        };
+        let read = |b: &[u8]| match (component_type.as_u32().unwrap(), b) {
+            (5120, [b0, ..]) => *b0 as i8 as f64,
+            (5121, [b0, ..]) => *b0 as f64,
+            (5122, [b0, b1, ..]) => i16::from_le_bytes([*b0, *b1]) as f64,
+            (5123, [b0, b1, ..]) => u16::from_le_bytes([*b0, *b1]) as f64,
+            (5125, [b0, b1, b2, b3, ..]) => u32::from_le_bytes([*b0, *b1, *b2, *b3]) as f64,
+            (_, [b0, b1, b2, b3, ..]) => f32::from_le_bytes([*b0, *b1, *b2, *b3]) as f64,
+            _ => unreachable!(),
+        };
+        let normalized = match accessor.get("normalized") {
+            Some(flag) => flag.as_bool()?,
+            None => false,
+        };
+        let scale = |x: f64| {
+            if normalized {
+                (x / max).max(-1.0)
+            } else {
+                x
+            }
+        };
+
+        let view = match accessor.get("bufferView") {
+            Some(view) => view,
+            None => return Ok(vec![vec![0.0; size]; count]),
+        };
+        let (data, stride) = self.buffer_view(view)?;
+        let element_size = size * component_size;
+        let stride = stride.unwrap_or(element_size);
+        let offset = optional_u32(accessor, "byteOffset", 0)? as usize;
+        if count > 0 && offset + (count - 1) * stride + element_size > data.len() {
+            return Err(accessor.error("the accessor exceeds its buffer view"));
+        }
+        let values: Vec<Vec<f64>> = (0..count)
+            .map(|i| {
+                let element = offset + i * stride;
+                (0..size)
+                    .map(|c| scale(read(&data[element + c * component_size..])))
+                    .collect()
+            })
+            .collect();
+        if values.iter().flatten().any(|x| !x.is_finite()) {
+            return Err(accessor.error("the accessor holds a number that is not finite"));
+        }
+        Ok(values)
+    }
+
+    /// Import node `i` and its children. Errors about the node itself are reported at `reference`.
+    fn node(&mut self, i: u32, reference: &Node, parent: Matrix) -> Result<Group, SceneError> {
+        let node = self
+            .items("nodes")?
+            .get(i as usize)
+            .ok_or_else(|| reference.error(format!("`nodes` has no entry {}", i)))?;
+        if self.path.contains(&i) {
+            return Err(reference.error("the node hierarchy contains a cycle"));
+        }
+        self.path.push(i);
+
+        let local = local_transform(node)?;
+        let global = parent * local;
+        let mut group = Group::default().with_transform(local);
+        if let Some(mesh) = node.get("mesh") {
+            group.add_child(self.mesh(mesh)?);
+        }
+        if let Some(camera) = node.get("camera") {
+            if self.camera.is_none() {
+                self.camera = self.camera(camera, global)?;
+            }
+        }
+        if let Some(light) = node
+            .get("extensions")
+            .and_then(|extensions| extensions.get("KHR_lights_punctual"))
+        {
+            self.light(required(light, "light")?, global)?;
+        }
+        for child in sequence(node, "children")? {
+            let child = self.node(child.as_u32()?, child, global)?;
+            group.add_child(child);
+        }
+
+        self.path.pop();
+        Ok(group)
+    }
+
+    fn mesh(&mut self, index: &Node) -> Result<Group, SceneError> {
+        let i = index.as_u32()?;
+        if let Some(group) = self.meshes.get(&i) {
+            return Ok(group.clone());
+        }
+
+        let mesh = self.lookup("meshes", index)?;
+        let mut group = Group::default();
+        for primitive in required(mesh, "primitives")?.as_sequence()? {
+            let mode = optional_u32(primitive, "mode", 4)?;
+            if mode != 4 {
+                log::warn!(
+                    "skipping a primitive with mode {}, only triangles are supported",
+                    mode
+                );
+                continue;
+            }
+            let mut shape = Shape::new(self.primitive(primitive)?);
+            if let Some(material) = primitive.get("material") {
+                shape.set_material(self.material(material)?);
+            }
+            group.add_child(shape);
+        }
+
+        self.meshes.insert(i, group.clone());
+        Ok(group)
+    }
+
+    fn primitive(&self, primitive: &Node) -> Result<Mesh, SceneError> {
+        let attributes = required(primitive, "attributes")?;
+        let vertices: Vec<Point> = self
+            .accessor(required(attributes, "POSITION")?, &[3])?
+            .iter()
+            .map(|p| point(p[0], p[1], p[2]))
+            .collect();
+        let n = vertices.len();
+
+        let triangles = match primitive.get("indices") {
+            Some(indices) => {
+                let indices = self.accessor(indices, &[1])?;
+                if indices.iter().any(|i| i[0] as usize >= n) {
+                    return Err(primitive.error("a vertex index is out of range"));
+                }
+                indices
+                    .chunks_exact(3)
+                    .map(|t| [t[0][0] as u32, t[1][0] as u32, t[2][0] as u32])
+                    .collect()
+            }
+            None => (0..n as u32 / 3)
+                .map(|t| [3 * t, 3 * t + 1, 3 * t + 2])
+                .collect(),
+        };
+
+        let attribute = |name: &str, sizes: &[usize]| -> Result<_, SceneError> {
+            match attributes.get(name) {
+                Some(index) => {
+                    let values = self.accessor(index, sizes)?;
+                    if values.len() != n {
+                        return Err(index
+                            .error(format!("`{}` does not have a value for every vertex", name)));
+                    }
+                    Ok(Some(values))
+                }
+                None => Ok(None),
+            }
+        };
+
+        let mut mesh = Mesh::new(vertices, triangles);
+        if let Some(normals) = attribute("NORMAL", &[3])? {
+            mesh = mesh.with_normals(normals.iter().map(|v| vector(v[0], v[1], v[2])).collect());
+        }
+        if let Some(uvs) = attribute("TEXCOORD_0", &[2])? {
+            // glTF puts the origin of texture coordinates at the top left corner
+            mesh = mesh.with_texture_coords(uvs.iter().map(|uv| (uv[0], 1.0 - uv[1])).collect());
+        }
+        if let Some(colors) = attribute("COLOR_0", &[3, 4])? {
+            mesh = mesh.with_colors(colors.iter().map(|c| color(c[0], c[1], c[2])).collect());
+        }
+        Ok(mesh)
+    }
+
+    fn material(&mut self, index: &Node) -> Result<Phong, SceneError> {
+        let i = index.as_u32()?;
+        if let Some(material) = self.materials.get(&i) {
+            return Ok(material.clone());
+        }
+
+        let material = self.lookup("materials", index)?;
+        let pbr = material.get("pbrMetallicRoughness");
+        let pbr_f64 = |key, default| match pbr {
+            Some(pbr) => optional_f64(pbr, key, default),
+            None => Ok(default),
+        };
+        let base_color = match pbr.and_then(|pbr| pbr.get("baseColorFactor")) {
+            Some(factor) => floats(factor, 4)?,
+            None => vec![1.0; 4],
+        };
+        let metallic = pbr_f64("metallicFactor", 1.0)?.clamp(0.0, 1.0);
+        let roughness = pbr_f64("roughnessFactor", 1.0)?.clamp(0.0, 1.0);
+        let smoothness = 1.0 - roughness;
+        // the exponent of the Blinn-Phong lobe that matches the GGX roughness
+        let alpha = roughness * roughness;
+        let shininess = (2.0 / (alpha * alpha).max(1e-6) - 2.0).clamp(1.0, 1000.0);
+
+        let surface = color(base_color[0], base_color[1], base_color[2]);
+        let diffuse = 0.9 * (1.0 - 0.5 * metallic);
+        let mut phong = Phong::default()
+            .with_color(surface)
+            .with_diffuse(diffuse)
+            .with_specular(smoothness * (0.1 + 0.8 * metallic))
+            .with_shininess(shininess)
+            .with_reflective(metallic * smoothness);
+
+        let emissive = match material.get("emissiveFactor") {
+            Some(factor) => floats(factor, 3)?,
+            None => vec![0.0; 3],
+        };
+        let strength = match material
+            .get("extensions")
+            .and_then(|extensions| extensions.get("KHR_materials_emissive_strength"))
+        {
+            Some(extension) => optional_f64(extension, "emissiveStrength", 1.0)?,
+            None => 1.0,
+        };
+        let emissive = color(emissive[0], emissive[1], emissive[2]) * strength;
+        if !emissive.is_black() {
+            if surface.sum() > 0.0 {
+                phong = phong.with_emissive(emissive.sum() / surface.sum());
+            } else {
+                phong = phong
+                    .with_color(emissive)
+                    .with_emissive(1.0)
+                    .with_diffuse(0.0);
+            }
+        }
+
+        if let Some(mode) = material.get("alphaMode") {
+            if mode.as_str()? == "BLEND" {
+                phong = phong.with_transparency(1.0 - base_color[3].clamp(0.0, 1.0));
+            }
+        }
+        let extensions = material.get("extensions");
+        if let Some(transmission) =
+            extensions.and_then(|extensions| extensions.get("KHR_materials_transmission"))
+        {
+            let factor = optional_f64(transmission, "transmissionFactor", 0.0)?.clamp(0.0, 1.0);
+            let ior = match extensions.and_then(|extensions| extensions.get("KHR_materials_ior")) {
+                Some(ior) => optional_f64(ior, "ior", 1.5)?,
+                None => 1.5,
+            };
+            phong = phong
+                .with_diffuse(diffuse * (1.0 - factor))
+                .with_transparency(factor)
+                .with_refractive_index(ior);
+        }
+
+        if let Some(texture) = pbr.and_then(|pbr| pbr.get("baseColorTexture")) {
+            if optional_u32(texture, "texCoord", 0)? != 0 {
+                log::warn!("only the first set of texture coordinates is supported");
+            }
+            if let Some(image) = self.texture(required(texture, "index")?)? {
+                phong = phong.with_pattern(image_pattern(image));
+            }
+        }
+
+        self.materials.insert(i, phong.clone());
+        Ok(phong)
+    }
+
+    /// The image of a texture, or `None` if it is not a PNG image
+    fn texture(&self, index: &Node) -> Result<Option<Canvas>, SceneError> {
+        let texture = self.lookup("textures", index)?;
+        let image = self.lookup("images", required(texture, "source")?)?;
+        let data = match (image.get("uri"), image.get("bufferView")) {
+            (Some(uri), _) => self.read_uri(uri)?,
+            (None, Some(view)) => self.buffer_view(view)?.0.to_vec(),
+            (None, None) => return Err(image.error("the image has no data")),
+        };
+        if !data.starts_with(b"\x89PNG") {
+            log::warn!("ignoring a texture that is not a PNG image");
+            return Ok(None);
+        }
+        Canvas::read_png(&mut &data[..])
+            .map(Some)
+            .map_err(|e| image.error(format!("unable to read the image: {}", e)))
+    }
+
+    /// A camera with the transform of its node, together with its position
+    fn camera(&self, index: &Node, global: Matrix) -> Result<Option<(Camera, Point)>, SceneError> {
+        let camera = self.lookup("cameras", index)?;
+        let kind = required(camera, "type")?;
+        match kind.as_str()? {
+            "perspective" => {
+                let perspective = required(camera, "perspective")?;
+                let yfov = required(perspective, "yfov")?.as_f64()?;
+                let aspect = optional_f64(perspective, "aspectRatio", DEFAULT_ASPECT_RATIO)?;
+                Ok(Some(perspective_camera(yfov, aspect, global)))
+            }
+            "orthographic" => {
+                let orthographic = required(camera, "orthographic")?;
+                let xmag = required(orthographic, "xmag")?.as_f64()?;
+                let ymag = required(orthographic, "ymag")?.as_f64()?;
+                if xmag <= 0.0 || ymag <= 0.0 {
+                    return Err(orthographic.error("`xmag` and `ymag` must be positive"));
+                }
+                Ok(Some(orthographic_camera(xmag, ymag, global)))
+            }
+            other => {
+                log::warn!("ignoring a camera of type `{}`", other);
+                Ok(None)
+            }
+        }
+    }
+
+    fn light(&mut self, index: &Node, global: Matrix) -> Result<(), SceneError> {
+        let lights = self
+            .document
+            .get("extensions")
+            .and_then(|extensions| extensions.get("KHR_lights_punctual"))
+            .ok_or_else(|| index.error("the lights are not defined"))?;
+        let i = index.as_u32()?;
+        let light = required(lights, "lights")?
+            .as_sequence()?
+            .get(i as usize)
+            .ok_or_else(|| index.error(format!("there is no light {}", i)))?;
+
+        let rgb = match light.get("color") {
+            Some(c) => c.as_triple()?,
+            None => [1.0, 1.0, 1.0],
+        };
+        let intensity = color(rgb[0], rgb[1], rgb[2]) * optional_f64(light, "intensity", 1.0)?;
+        let position = global * point(0.0, 0.0, 0.0);
+        let direction = (global * vector(0.0, 0.0, -1.0)).normalized();
+
+        let kind = required(light, "type")?;
+        match kind.as_str()? {
+            "point" => self.world.add_light(PointLight::new(position, intensity)),
+            "spot" => {
+                let spot = required(light, "spot")?;
+                self.world.add_light(SpotLight::new(
+                    position,
+                    direction,
+                    optional_f64(spot, "innerConeAngle", 0.0)?,
+                    optional_f64(spot, "outerConeAngle", PI / 4.0)?,
+                    intensity,
+                ))
+            }
+            "directional" => self.directional_lights.push((direction, intensity)),
+            other => return Err(kind.error(format!("unknown light type `{}`", other))),
+        }
+        Ok(())
+    }
+}
+
+/// A camera for a vertical field of view in radians, looking along the negative z axis of
+/// `global`
+fn perspective_camera(yfov: f64, aspect: f64, global: Matrix) -> (Camera, Point) {
+    let vsize = (DEFAULT_WIDTH as f64 / aspect).round().max(1.0) as u32;
+    // the field of view of `Camera` spans the longer side of the image
+    let fov = if aspect >= 1.0 {
+        2.0 * ((yfov / 2.0).tan() * aspect).atan()
+    } else {
+        yfov
+    };
+    // the camera space of `Camera` has the x axis pointing to the left
+    let view = scaling(-1, 1, 1) * global.inverse();
+    let camera = Camera::new(DEFAULT_WIDTH, vsize, fov).with_transform(view);
+    (camera, global * point(0.0, 0.0, 0.0))
+}
+
+/// A camera with a view plane of `2 * xmag` by `2 * ymag`, looking along the negative z axis
+/// of `global`
+fn orthographic_camera(xmag: f64, ymag: f64, global: Matrix) -> (Camera, Point) {
+    let vsize = (DEFAULT_WIDTH as f64 * ymag / xmag).round().max(1.0) as u32;
+    let view = scaling(-1, 1, 1) * global.inverse();
+    let camera = Camera::orthographic(DEFAULT_WIDTH, vsize, 2.0 * xmag).with_transform(view);
+    (camera, global * point(0.0, 0.0, 0.0))
+}
+
+/// A camera that looks at a sphere around the scene along the negative z axis
+fn default_camera(center: Point, radius: f64) -> (Camera, Point) {
+    let yfov = PI / 4.0;
+    let distance = 1.1 * radius / (yfov / 2.0).sin();
+    let eye = center + vector(0.0, 0.0, distance);
+    perspective_camera(
+        yfov,
+        DEFAULT_ASPECT_RATIO,
+        translation(eye.x(), eye.y(), eye.z()),
+    )
+}
+
+/// The transform of a node, either a column-major matrix or a translation, rotation
+/// (a quaternion) and scale
+fn local_transform(node: &Node) -> Result<Matrix, SceneError> {
+    if let Some(m) = node.get("matrix") {
+        let m = floats(m, 16)?;
+        return Ok(Matrix::Full([
+            [m[0], m[4], m[8], m[12]],
+            [m[1], m[5], m[9], m[13]],
+            [m[2], m[6], m[10], m[14]],
+            [m[3], m[7], m[11], m[15]],
+        ]));
+    }
+
+    let mut transform = Matrix::identity();
+    if let Some(t) = node.get("translation") {
+        let [x, y, z] = t.as_triple()?;
+        transform = transform * translation(x, y, z);
+    }
+    if let Some(r) = node.get("rotation") {
+        let q = floats(r, 4)?;
+        let (x, y, z, w) = (q[0], q[1], q[2], q[3]);
+        transform = transform
+            * Matrix::Full([
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - z * w),
+                    2.0 * (x * z + y * w),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + z * w),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - x * w),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - y * w),
+                    2.0 * (y * z + x * w),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ]);
+    }
+    if let Some(s) = node.get("scale") {
+        let [x, y, z] = s.as_triple()?;
+        transform = transform * scaling(x, y, z);
+    }
+    Ok(transform)
+}
+
+fn required<'n>(node: &'n Node, key: &str) -> Result<&'n Node, SceneError> {
+    node.get(key)
+        .ok_or_else(|| node.error(format!("missing attribute `{}`", key)))
+}
+
+fn optional_f64(node: &Node, key: &str, default: f64) -> Result<f64, SceneError> {
+    node.get(key).map(Node::as_f64).unwrap_or(Ok(default))
+}
+
+fn optional_u32(node: &Node, key: &str, default: u32) -> Result<u32, SceneError> {
+    node.get(key).map(Node::as_u32).unwrap_or(Ok(default))
+}
+
+/// The items of an array that may be absent
+fn sequence<'n>(node: &'n Node, key: &str) -> Result<&'n [Node], SceneError> {
+    match node.get(key) {
+        Some(items) => items.as_sequence(),
+        None => Ok(&[]),
+    }
+}
+
+/// An array of exactly `n` numbers
+fn floats(node: &Node, n: usize) -> Result<Vec<f64>, SceneError> {
+    let values = node
+        .as_sequence()?
+        .iter()
+        .map(Node::as_f64)
+        .collect::<Result<Vec<_>, _>>()?;
+    if values.len() != n {
+        return Err(node.error(format!("expected {} numbers", n)));
+    }
+    Ok(values)
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut n) = (0u32, 0);
+    for c in text.bytes().take_while(|&c| c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        bits = bits << 6 | value as u32;
+        n += 6;
+        if n >= 8 {
+            n -= 8;
+            data.push((bits >> n) as u8);
+        }
+    }
+    Some(data)
+}
+
+/// Decode the `%XX` escapes of a relative URI
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::matrix::rotation_y;
+    use crate::random::Prng;
+    use crate::ray::Ray;
+    use crate::scene::Position;
+
+    /// A scene with a red triangle, scaled and moved away from a camera that carries a light.
+    /// `BUFFER` is replaced by the location of the buffer.
+    const TRIANGLE: &str = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0, 2]}],
+        "nodes": [
+            {"translation": [0, 0, -2], "children": [1]},
+            {"mesh": 0, "scale": [2, 2, 2]},
+            {"camera": 0, "translation": [0, 0, 5],
+             "extensions": {"KHR_lights_punctual": {"light": 0}}}
+        ],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "indices": 1, "material": 0}]}],
+        "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0}}],
+        "cameras": [{"type": "perspective", "perspective": {"yfov": 0.8, "aspectRatio": 2.0}}],
+        "extensions": {"KHR_lights_punctual": {"lights": [{"type": "point", "intensity": 2}]}},
+        "buffers": [{BUFFER "byteLength": 44}],
+        "bufferViews": [
+            {"buffer": 0, "byteLength": 36},
+            {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"},
+            {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}
+        ]
+    }"#;
+
+    fn triangle_buffer() -> Vec<u8> {
+        let mut data = vec![];
+        for x in &[-1.0f32, -1.0, 0.0, 1.0, -1.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend_from_slice(&x.to_le_bytes());
+        }
+        for i in &[0u16, 1, 2, 0] {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        data
+    }
+
+    fn encode_base64(data: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for chunk in data.chunks(3) {
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    text.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    fn glb(json: &str, binary: &[u8]) -> Vec<u8> {
+        let mut json = json.as_bytes().to_vec();
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let mut data = b"glTF".to_vec();
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&((28 + json.len() + binary.len()) as u32).to_le_bytes());
+        data.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        data.extend_from_slice(&GLB_JSON_CHUNK.to_le_bytes());
+        data.extend_from_slice(&json);
+        data.extend_from_slice(&(binary.len() as u32).to_le_bytes());
+        data.extend_from_slice(&GLB_BIN_CHUNK.to_le_bytes());
+        data.extend_from_slice(binary);
+        data
+    }
+
+    fn parse(json: &str) -> Result<Scene, SceneError> {
+        parse_gltf_scene(json.as_bytes(), Path::new("."))
+    }
+
+    /// The ray hits the triangle only if the transforms of both nodes are applied
+    fn assert_triangle(scene: &Scene) {
+        let r = Ray::new(point(1.5, -1.5, 5.0), vector(0, 0, -1));
+        let xs = scene.world.intersect(&r);
+        assert_eq!(xs.len(), 1);
+        assert_almost_eq!(xs[0].t, 7.0);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert_almost_eq!(comps.obj.material().color_at(&comps), color(1, 0, 0));
+    }
+
+    /// Buffers can be embedded, adjacent files or the binary chunk of a GLB file
+    #[test]
+    fn buffers() {
+        let data = triangle_buffer();
+        let embedded = TRIANGLE.replace(
+            "BUFFER",
+            &format!(
+                r#""uri": "data:application/octet-stream;base64,{}","#,
+                encode_base64(&data)
+            ),
+        );
+        assert_triangle(&parse(&embedded).unwrap());
+
+        let glb = glb(&TRIANGLE.replace("BUFFER", ""), &data);
+        assert_triangle(&parse_gltf_scene(&glb, Path::new(".")).unwrap());
+
+        let dir = std::env::temp_dir().join(format!("raytracing-gltf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("tri data.bin"), &data).unwrap();
+        let adjacent = TRIANGLE.replace("BUFFER", r#""uri": "tri%20data.bin","#);
+        let scene = parse_gltf_scene(adjacent.as_bytes(), &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_triangle(&scene.unwrap());
+    }
+
+    /// The camera and the light are placed by their node
+    #[test]
+    fn camera_and_lights() {
+        let embedded = TRIANGLE.replace(
+            "BUFFER",
+            &format!(
+                r#""uri": "data:application/octet-stream;base64,{}","#,
+                encode_base64(&triangle_buffer())
+            ),
+        );
+        let scene = parse(&embedded).unwrap();
+        assert_eq!((scene.camera.hsize(), scene.camera.vsize()), (640, 320));
+        assert_eq!(scene.world.lights().len(), 1);
+
+        let mut rng = Prng::new(0);
+        let center = scene.camera.ray_for_pixel(320, 160, false, &mut rng);
+        assert_almost_eq!(center.origin(), point(0, 0, 5));
+        assert!(center.direction().z() < -0.9999);
+        // the image is not mirrored, and the vertical field of view is `yfov`
+        let top_left = scene.camera.ray_for_pixel(0, 0, false, &mut rng);
+        assert!(top_left.direction().x() < 0.0 && top_left.direction().y() > 0.0);
+        let top = scene
+            .camera
+            .ray_for_pixel(320, 0, false, &mut rng)
+            .direction();
+        assert!((top.y().atan2(-top.z()) - 0.4).abs() < 0.01);
+    }
+
+    /// Orthographic cameras show the area given by `xmag` and `ymag`
+    #[test]
+    fn orthographic_camera() {
+        let embedded = TRIANGLE
+            .replace(
+                "BUFFER",
+                &format!(
+                    r#""uri": "data:application/octet-stream;base64,{}","#,
+                    encode_base64(&triangle_buffer())
+                ),
+            )
+            .replace(
+                r#"{"type": "perspective", "perspective": {"yfov": 0.8, "aspectRatio": 2.0}}"#,
+                r#"{"type": "orthographic", "orthographic": {"xmag": 2, "ymag": 1, "znear": 0.1, "zfar": 100}}"#,
+            );
+        let scene = parse(&embedded).unwrap();
+        assert_eq!((scene.camera.hsize(), scene.camera.vsize()), (640, 320));
+
+        let mut rng = Prng::new(0);
+        let top_left = scene.camera.ray_for_pixel(0, 0, false, &mut rng);
+        assert_almost_eq!(top_left.origin(), point(-1.99688, 0.99688, 5));
+        assert_almost_eq!(top_left.direction(), vector(0, 0, -1));
+    }
+
+    /// Nodes have a column-major matrix or a translation, a rotation and a scale
+    #[test]
+    fn node_transforms() {
+        let node = json::parse(r#"{"matrix": [1,0,0,0, 0,1,0,0, 0,0,1,0, 1,2,3,1]}"#).unwrap();
+        assert_almost_eq!(local_transform(&node).unwrap(), translation(1, 2, 3));
+
+        let half = (PI / 4.0).sin();
+        let node = json::parse(&format!(
+            r#"{{"translation": [1, 0, 0], "rotation": [0, {}, 0, {}], "scale": [2, 2, 2]}}"#,
+            half, half
+        ))
+        .unwrap();
+        assert_almost_eq!(
+            local_transform(&node).unwrap(),
+            translation(1, 0, 0) * rotation_y(PI / 2.0) * scaling(2, 2, 2)
+        );
+    }
+
+    /// Without cameras and lights, the scene is lit from the viewpoint of a default camera
+    #[test]
+    fn defaults() {
+        let scene = parse(r#"{"asset": {"version": "2.0"}, "nodes": [{}]}"#).unwrap();
+        assert_eq!(scene.world.lights().len(), 1);
+        assert_eq!(scene.camera.hsize(), 640);
+        let mut rng = Prng::new(0);
+        let r = scene.camera.ray_for_pixel(320, 240, false, &mut rng);
+        assert!(r.direction().z() < -0.9999);
+    }
+
+    /// Invalid files are rejected with the location of the problem
+    #[test]
+    fn errors() {
+        let error_at = |json: &str| match parse(json) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.position().unwrap(),
+        };
+        assert_eq!(
+            error_at(r#"{"asset": {"version": "1.0"}}"#),
+            Position::new(1, 23)
+        );
+        assert_eq!(
+            error_at(
+                r#"{"asset": {"version": "2.0"}, "scenes": [{"nodes": [0]}], "nodes": [{"children": [0]}]}"#
+            ),
+            Position::new(1, 83)
+        );
+        let embedded = TRIANGLE.replace(
+            "BUFFER",
+            &format!(
+                r#""uri": "data:application/octet-stream;base64,{}","#,
+                encode_base64(&triangle_buffer()[..40])
+            ),
+        );
+        assert!(parse(&embedded).is_err());
+        let mut nan = triangle_buffer();
+        nan[4..8].copy_from_slice(&f32::NAN.to_le_bytes());
+        let line = TRIANGLE
+            .lines()
+            .position(|l| l.contains(r#"{"bufferView": 0"#));
+        let column = TRIANGLE.lines().nth(line.unwrap()).unwrap().find('{');
+        assert_eq!(
+            error_at(&TRIANGLE.replace(
+                "BUFFER",
+                &format!(
+                    r#""uri": "data:application/octet-stream;base64,{}","#,
+                    encode_base64(&nan)
+                ),
+            )),
+            Position::new(line.unwrap() + 1, column.unwrap() + 1)
+        );
+        let truncated = glb(&TRIANGLE.replace("BUFFER", ""), &triangle_buffer());
+        assert!(parse_gltf_scene(&truncated[..truncated.len() - 4], Path::new(".")).is_err());
+        assert!(decode_base64("QUJD!").is_none());
+    }
+}
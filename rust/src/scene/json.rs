@@ -0,0 +1,250 @@
+//! A small JSON parser, used to read glTF files.
+//!
+//! It produces the same `Node` tree as the YAML parser, so that scene builders can use the same
+//! accessors and report errors at the right line and column. Numbers and booleans become
+//! scalars holding their text, `null` becomes `Value::Null`.
+
+use crate::scene::yaml::{Node, Value};
+use crate::scene::{Position, SceneError};
+use std::iter::Peekable;
+use std::str::Chars;
+
+pub fn parse(input: &str) -> Result<Node, SceneError> {
+    let mut parser = Parser {
+        chars: input.chars().peekable(),
+        line: 1,
+        column: 1,
+    };
+    let node = parser.parse_value()?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+        None => Ok(node),
+        Some(_) => Err(parser.error("unexpected content after the end of the document")),
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl Parser<'_> {
+    fn position(&self) -> Position {
+        Position::new(self.line, self.column)
+    }
+
+    fn error(&self, message: impl Into<String>) -> SceneError {
+        SceneError::parse(self.position(), message)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.chars.peek() {
+            self.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SceneError> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some(&c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            _ => Err(self.error(format!("expected `{}`", expected))),
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Node, SceneError> {
+        self.skip_whitespace();
+        let pos = self.position();
+        let value = match self.chars.peek() {
+            Some('{') => self.parse_object()?,
+            Some('[') => self.parse_array()?,
+            Some('"') => Value::Scalar(self.parse_string()?),
+            Some(c) if c.is_ascii_alphanumeric() || *c == '-' => {
+                let mut word = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+                        break;
+                    }
+                    word.push(c);
+                    self.next();
+                }
+                match word.as_str() {
+                    "null" => Value::Null,
+                    "true" | "false" => Value::Scalar(word),
+                    _ => match word.parse::<f64>() {
+                        Ok(x) if x.is_finite() => Value::Scalar(word),
+                        Ok(_) => {
+                            let message = format!("`{}` is not a finite number", word);
+                            return Err(SceneError::parse(pos, message));
+                        }
+                        Err(_) => {
+                            return Err(SceneError::parse(pos, format!("invalid value `{}`", word)))
+                        }
+                    },
+                }
+            }
+            _ => return Err(self.error("expected a value")),
+        };
+        Ok(Node::new(pos, value))
+    }
+
+    fn parse_object(&mut self) -> Result<Value, SceneError> {
+        self.expect('{')?;
+        let mut entries = vec![];
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.next();
+            return Ok(Value::Mapping(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            let pos = self.position();
+            if self.chars.peek() != Some(&'"') {
+                return Err(self.error("expected a string as key"));
+            }
+            let key = Node::new(pos, Value::Scalar(self.parse_string()?));
+            self.expect(':')?;
+            let value = self.parse_value()?;
+            entries.push((key, value));
+
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Mapping(entries)),
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, SceneError> {
+        self.expect('[')?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.next();
+            return Ok(Value::Sequence(items));
+        }
+        loop {
+            items.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Sequence(items)),
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, SceneError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None => return Err(self.error("unterminated string")),
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.parse_unicode_escape()?,
+                        _ => return Err(self.error("invalid escape sequence")),
+                    };
+                    s.push(c);
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// The character of a `\uXXXX` escape, which may be followed by the low half of a
+    /// surrogate pair
+    fn parse_unicode_escape(&mut self) -> Result<char, SceneError> {
+        let high = self.parse_hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if self.next() != Some('\\') || self.next() != Some('u') {
+                return Err(self.error("expected the low half of a surrogate pair"));
+            }
+            let low = self.parse_hex4()?;
+            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, SceneError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("invalid unicode escape"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Objects, arrays, strings with escapes, numbers and literals
+    #[test]
+    fn values() {
+        let doc = parse(
+            r#"{
+                "name": "café \"one\"\n",
+                "list": [1, -2.5e3, true, null, {}],
+                "empty": []
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(doc.get("name").unwrap().as_str().unwrap(), "café \"one\"\n");
+        let list = doc.get("list").unwrap().as_sequence().unwrap();
+        assert_eq!(list[0].as_f64().unwrap(), 1.0);
+        assert_eq!(list[1].as_f64().unwrap(), -2500.0);
+        assert!(list[2].as_bool().unwrap());
+        assert_eq!(list[3].value, Value::Null);
+        assert_eq!(list[4].value, Value::Mapping(vec![]));
+        assert_eq!(list[1].pos, Position::new(3, 29));
+        assert!(doc.get("empty").unwrap().as_sequence().unwrap().is_empty());
+        assert_eq!(
+            parse(r#""\ud83d\ude00""#).unwrap().as_str().unwrap(),
+            "\u{1F600}"
+        );
+    }
+
+    /// Syntax errors are reported where they occur
+    #[test]
+    fn errors() {
+        let error_at = |input| parse(input).unwrap_err().position().unwrap();
+        assert_eq!(error_at("{\"a\": 1,\n \"b\" 2}"), Position::new(2, 6));
+        assert_eq!(error_at("[1, 2"), Position::new(1, 6));
+        assert_eq!(error_at("[1, nope]"), Position::new(1, 5));
+        assert_eq!(error_at("[1, 1e999]"), Position::new(1, 5));
+        assert_eq!(error_at("{\"a\": \"open"), Position::new(1, 12));
+        assert_eq!(error_at("[1] 2"), Position::new(1, 5));
+        assert_eq!(error_at("{a: 1}"), Position::new(1, 2));
+    }
+}
@@ -0,0 +1,110 @@
+//! Declarative scene descriptions.
+//!
+//! Instead of writing a Rust program for every scene, a scene can be described in a text file
+//! and loaded at runtime. Three formats are supported: YAML files in the style of the book (see
+//! `yaml_scene`), the scene programs of the Scheme implementation (see `sexpr_scene`) and glTF 2.0
+//! files exported by modelling tools (see `gltf_scene`).
+
+mod gltf_scene;
+pub mod json;
+pub mod sexpr;
+mod sexpr_scene;
+pub mod yaml;
+mod yaml_scene;
+
+pub use gltf_scene::{load_gltf_scene, parse_gltf_scene};
+pub use sexpr_scene::{load_sexpr_scene, parse_sexpr_scene};
+pub use yaml_scene::{load_yaml_scene, parse_yaml_scene};
+
+use crate::camera::Camera;
+use crate::world::World;
+use std::fmt;
+use std::path::Path;
+
+/// Load a scene file, choosing the format by the file extension: `.scm` and `.ss` files are
+/// Scheme programs, `.gltf` and `.glb` files are glTF scenes, everything else is YAML.
+pub fn load_scene(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("scm") | Some("ss") => load_sexpr_scene(path),
+        Some("gltf") | Some("glb") => load_gltf_scene(path),
+        _ => load_yaml_scene(path),
+    }
+}
+
+/// Everything that is needed to render an image.
+pub struct Scene {
+    pub world: World,
+    pub camera: Camera,
+}
+
+impl Scene {
+    pub fn render(&self) -> crate::canvas::Canvas {
+        self.camera.render(&self.world)
+    }
+}
+
+/// A location in a scene file. Lines and columns are counted from 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    pub fn new(line: usize, column: usize) -> Self {
+        Position { line, column }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse { pos: Position, message: String },
+}
+
+impl SceneError {
+    pub fn parse(pos: Position, message: impl Into<String>) -> Self {
+        SceneError::Parse {
+            pos,
+            message: message.into(),
+        }
+    }
+
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            SceneError::Io(_) => None,
+            SceneError::Parse { pos, .. } => Some(*pos),
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Parse { pos, message } => write!(f, "{}: {}", pos, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io(e) => Some(e),
+            SceneError::Parse { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneError {
+    fn from(e: std::io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
@@ -0,0 +1,260 @@
+//! A reader for S-expressions as they appear in the scenes of the Scheme ray tracer.
+//!
+//! Supported are lists (with `(` `)` or `[` `]`), numbers, booleans (`#t`, `#f`), strings,
+//! symbols, the quote shorthand `'x`, and `;` line comments. Like the YAML parser, every
+//! expression remembers where it started.
+
+use crate::scene::{Position, SceneError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub pos: Position,
+    pub datum: Datum,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Number(f64),
+    Bool(bool),
+    Str(String),
+    Symbol(String),
+    List(Vec<Expr>),
+}
+
+impl Expr {
+    pub fn new(pos: Position, datum: Datum) -> Self {
+        Expr { pos, datum }
+    }
+
+    pub fn as_symbol(&self) -> Option<&str> {
+        match &self.datum {
+            Datum::Symbol(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Expr]> {
+        match &self.datum {
+            Datum::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self, message: impl Into<String>) -> SceneError {
+        SceneError::parse(self.pos, message)
+    }
+}
+
+/// Read all expressions of a source file.
+pub fn parse(input: &str) -> Result<Vec<Expr>, SceneError> {
+    let mut reader = Reader::new(input);
+    let mut exprs = vec![];
+    while let Some(expr) = reader.read()? {
+        exprs.push(expr);
+    }
+    Ok(exprs)
+}
+
+struct Reader<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(input: &'a str) -> Self {
+        Reader {
+            chars: input.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn pos(&self) -> Position {
+        Position::new(self.line, self.column)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            if c == ';' {
+                while self.chars.peek().is_some_and(|&c| c != '\n') {
+                    self.next_char();
+                }
+            } else if c.is_whitespace() {
+                self.next_char();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Read the next expression, or `None` at the end of the input.
+    fn read(&mut self) -> Result<Option<Expr>, SceneError> {
+        self.skip_whitespace();
+        let pos = self.pos();
+        let c = match self.chars.peek() {
+            Some(&c) => c,
+            None => return Ok(None),
+        };
+        let datum = match c {
+            '(' | '[' => {
+                self.next_char();
+                let close = if c == '(' { ')' } else { ']' };
+                let mut items = vec![];
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.peek() {
+                        Some(&c) if c == close => {
+                            self.next_char();
+                            break;
+                        }
+                        Some(')') | Some(']') => {
+                            return Err(SceneError::parse(self.pos(), "mismatched parenthesis"))
+                        }
+                        Some(_) => items.push(self.read()?.unwrap()),
+                        None => return Err(SceneError::parse(pos, "unclosed parenthesis")),
+                    }
+                }
+                Datum::List(items)
+            }
+            ')' | ']' => return Err(SceneError::parse(pos, "unexpected closing parenthesis")),
+            '\'' => {
+                self.next_char();
+                let quoted = self
+                    .read()?
+                    .ok_or_else(|| SceneError::parse(pos, "expected an expression after '"))?;
+                Datum::List(vec![
+                    Expr::new(pos, Datum::Symbol("quote".to_string())),
+                    quoted,
+                ])
+            }
+            '"' => {
+                self.next_char();
+                let mut s = String::new();
+                loop {
+                    match self.next_char() {
+                        Some('"') => break,
+                        Some('\\') => match self.next_char() {
+                            Some('n') => s.push('\n'),
+                            Some('t') => s.push('\t'),
+                            Some(c) => s.push(c),
+                            None => return Err(SceneError::parse(pos, "unterminated string")),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err(SceneError::parse(pos, "unterminated string")),
+                    }
+                }
+                Datum::Str(s)
+            }
+            _ => {
+                let mut token = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c.is_whitespace() || "()[]'\";".contains(c) {
+                        break;
+                    }
+                    token.push(c);
+                    self.next_char();
+                }
+                atom(&token)
+                    .ok_or_else(|| SceneError::parse(pos, format!("invalid token `{}`", token)))?
+            }
+        };
+        Ok(Some(Expr::new(pos, datum)))
+    }
+}
+
+fn atom(token: &str) -> Option<Datum> {
+    match token {
+        "#t" | "#true" => return Some(Datum::Bool(true)),
+        "#f" | "#false" => return Some(Datum::Bool(false)),
+        "+inf.0" => return Some(Datum::Number(f64::INFINITY)),
+        "-inf.0" => return Some(Datum::Number(f64::NEG_INFINITY)),
+        _ => {}
+    }
+    if token.starts_with('#') {
+        return None;
+    }
+    let looks_numeric = token
+        .trim_start_matches(['+', '-'])
+        .starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    match token.parse() {
+        Ok(x) if looks_numeric => Some(Datum::Number(x)),
+        _ if looks_numeric && token != "." && token != "..." => None,
+        _ => Some(Datum::Symbol(token.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sym(s: &str) -> Datum {
+        Datum::Symbol(s.to_string())
+    }
+
+    fn data(exprs: &[Expr]) -> Vec<Datum> {
+        exprs.iter().map(|e| e.datum.clone()).collect()
+    }
+
+    /// Atoms are read as numbers, booleans, strings or symbols
+    #[test]
+    fn atoms() {
+        let exprs = parse("1 -2.5 .5 #t #f \"a \\\"b\\\"\" m4* - set-transform! +inf.0").unwrap();
+        assert_eq!(
+            data(&exprs),
+            vec![
+                Datum::Number(1.0),
+                Datum::Number(-2.5),
+                Datum::Number(0.5),
+                Datum::Bool(true),
+                Datum::Bool(false),
+                Datum::Str("a \"b\"".to_string()),
+                sym("m4*"),
+                sym("-"),
+                sym("set-transform!"),
+                Datum::Number(f64::INFINITY),
+            ]
+        );
+    }
+
+    /// Lists nest, quotes expand and comments are skipped
+    #[test]
+    fn lists() {
+        let exprs = parse("; a comment\n(a 'b ; another\n  [c (d)])").unwrap();
+        assert_eq!(exprs.len(), 1);
+        let quote = Expr::new(Position::new(2, 4), sym("quote"));
+        match &exprs[0].datum {
+            Datum::List(items) => {
+                assert_eq!(items[0].datum, sym("a"));
+                assert_eq!(
+                    items[1].datum,
+                    Datum::List(vec![quote, Expr::new(Position::new(2, 5), sym("b"))])
+                );
+                assert_eq!(items[2].pos, Position::new(3, 3));
+                assert_eq!(items[2].as_list().unwrap().len(), 2);
+            }
+            _ => panic!("expected a list"),
+        }
+    }
+
+    /// Errors report where they happened
+    #[test]
+    fn errors() {
+        let error_at = |input| parse(input).unwrap_err().position().unwrap();
+        assert_eq!(error_at("(a\n  (b c)"), Position::new(1, 1));
+        assert_eq!(error_at("(a b))"), Position::new(1, 6));
+        assert_eq!(error_at("(a b]"), Position::new(1, 5));
+        assert_eq!(error_at("\n  1.2.3"), Position::new(2, 3));
+    }
+}
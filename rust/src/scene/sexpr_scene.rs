@@ -0,0 +1,1003 @@
+//! Scenes written for the Scheme ray tracer in `scheme/`.
+//!
+//! The chapter scenes of the Scheme implementation are short programs that build shapes, mutate
+//! them by sending messages, and finally render a world with a camera:
+//!
+//! ```scheme
+//! (define floor (plane))
+//! (floor 'set-material! (material (color 1 0.9 0.9) 0.1 0.9 0.0 100.0))
+//!
+//! (define ball (sphere))
+//! (ball 'set-transform! (m4* (translation 0 1 0) (scaling 0.5 0.5 0.5)))
+//!
+//! (define world (make-world (list floor ball)
+//!                           (list (point-light (point -10 10 -10) (color 1 1 1)))))
+//!
+//! (define camera (make-camera 320 160 (/ PI 3)))
+//! (camera 'set-transform! (view-transform (point 0 1.5 -5) (point 0 1 0) (vec 0 1 0)))
+//!
+//! (define image (camera 'render world))
+//! ```
+//!
+//! Only the declarative part of the language is understood: top-level `define`s of values,
+//! arithmetic, the constructors of the `raytrace` libraries (`sphere`, `cube`, `csg`,
+//! `material`, `checkers-pattern`, `view-transform`, ...) and the messages that configure them
+//! (`'set-transform!`, `'set-material!`, `'set-minimum!`, `'add-children!`, ...). Procedure
+//! definitions are rejected; `import` and `call-with-output-file` are skipped, because writing
+//! the image is left to the caller.
+//!
+//! The scene consists of the world and camera of the last `(camera 'render world)`, or of the
+//! last world and camera that were made if nothing is rendered. The `ambient` term of Scheme
+//! materials becomes the `emissive` term of `Phong`; for a single white light, as in all
+//! chapter scenes, the two agree.
+
+use crate::camera::Camera;
+use crate::color::{color, Color};
+use crate::lights::PointLight;
+use crate::materials::Phong;
+use crate::matrix::{
+    rotation_x, rotation_y, rotation_z, scaling, shearing, translation, view_transform, Matrix,
+};
+use crate::pattern::{checkers_pattern, gradient_pattern, ring_pattern, stripe_pattern, Pattern};
+use crate::scene::sexpr::{self, Datum, Expr};
+use crate::scene::{Position, Scene, SceneError};
+use crate::shapes::{cube, plane, sphere, Cone, CsgOp, CsgPair, Cylinder, Group, SceneItem, Shape};
+use crate::tuple::{point, vector, Point, Vector};
+use crate::world::World;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::path::Path;
+use std::rc::Rc;
+
+pub fn load_sexpr_scene(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let source = std::fs::read_to_string(path)?;
+    parse_sexpr_scene(&source)
+}
+
+/// Build a scene from the source of a Scheme scene program.
+pub fn parse_sexpr_scene(input: &str) -> Result<Scene, SceneError> {
+    let program = sexpr::parse(input)?;
+    let mut interpreter = Interpreter::default();
+    for expr in &program {
+        interpreter.toplevel(expr)?;
+    }
+    interpreter.finish()
+}
+
+type Ref<T> = Rc<RefCell<T>>;
+
+fn new_ref<T>(x: T) -> Ref<T> {
+    Rc::new(RefCell::new(x))
+}
+
+/// Scheme objects have identity: a material or pattern that is shared by several shapes, or a
+/// shape that is configured after it was put into a world, is the same object everywhere. So
+/// the interpreter builds a description of the scene and only turns it into `SceneItem`s at the
+/// end.
+#[derive(Clone)]
+enum Val {
+    Unspecified,
+    Number(f64),
+    Bool(bool),
+    Symbol(String),
+    Point(Point),
+    Vector(Vector),
+    Color(Color),
+    Matrix(Matrix),
+    List(Vec<Val>),
+    Pattern(Ref<Pattern>),
+    Material(Ref<MaterialSpec>),
+    Object(Ref<ObjectSpec>),
+    Light(Point, Color),
+    World(Ref<WorldSpec>),
+    Camera(Ref<Camera>),
+    CsgOp(CsgOp),
+}
+
+#[derive(Clone)]
+enum Paint {
+    Color(Color),
+    Pattern(Ref<Pattern>),
+}
+
+#[derive(Clone)]
+struct MaterialSpec {
+    color: Paint,
+    ambient: f64,
+    diffuse: f64,
+    specular: f64,
+    shininess: f64,
+    reflective: f64,
+    transparency: f64,
+    refractive_index: f64,
+}
+
+impl Default for MaterialSpec {
+    /// The `default-material` of the Scheme implementation.
+    fn default() -> Self {
+        MaterialSpec {
+            color: Paint::Color(color(1, 1, 1)),
+            ambient: 0.1,
+            diffuse: 0.9,
+            specular: 0.9,
+            shininess: 200.0,
+            reflective: 0.0,
+            transparency: 0.0,
+            refractive_index: 1.0,
+        }
+    }
+}
+
+impl MaterialSpec {
+    fn to_phong(&self) -> Phong {
+        let mut material = Phong::new(
+            color(1, 1, 1),
+            self.ambient,
+            self.diffuse,
+            self.specular,
+            self.shininess,
+            self.reflective,
+            self.transparency,
+            self.refractive_index,
+        );
+        match &self.color {
+            Paint::Color(c) => material.set_color(*c),
+            Paint::Pattern(p) => material.set_pattern(p.borrow().clone()),
+        }
+        material
+    }
+}
+
+struct ObjectSpec {
+    kind: ObjectKind,
+    transform: Matrix,
+    material: Ref<MaterialSpec>,
+}
+
+enum ObjectKind {
+    Sphere,
+    Plane,
+    Cube,
+    Cylinder(Limits),
+    Cone(Limits),
+    Group(Vec<Ref<ObjectSpec>>),
+    Csg(CsgOp, Ref<ObjectSpec>, Ref<ObjectSpec>),
+}
+
+struct Limits {
+    min: f64,
+    max: f64,
+    closed: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            min: f64::NEG_INFINITY,
+            max: f64::INFINITY,
+            closed: false,
+        }
+    }
+}
+
+impl ObjectSpec {
+    fn new(kind: ObjectKind) -> Self {
+        ObjectSpec {
+            kind,
+            transform: Matrix::identity(),
+            material: new_ref(MaterialSpec::default()),
+        }
+    }
+
+    /// Whether `object` is this object or one of its descendants
+    fn contains(&self, object: &Ref<ObjectSpec>) -> bool {
+        let children = match &self.kind {
+            ObjectKind::Group(children) => children.iter().collect(),
+            ObjectKind::Csg(_, left, right) => vec![left, right],
+            _ => vec![],
+        };
+        std::ptr::eq(self, object.as_ptr())
+            || children.into_iter().any(|c| c.borrow().contains(object))
+    }
+
+    fn build(&self) -> SceneItem {
+        let mut item: SceneItem = match &self.kind {
+            ObjectKind::Sphere => sphere().into(),
+            ObjectKind::Plane => plane().into(),
+            ObjectKind::Cube => cube().into(),
+            ObjectKind::Cylinder(l) => Shape::new(Cylinder::new(l.min, l.max, l.closed)).into(),
+            ObjectKind::Cone(l) => Shape::new(Cone::new(l.min, l.max, l.closed)).into(),
+            ObjectKind::Group(children) => {
+                let mut group = Group::default();
+                for child in children {
+                    group.add_child(child.borrow().build());
+                }
+                group.into()
+            }
+            ObjectKind::Csg(op, left, right) => {
+                CsgPair::new(*op, left.borrow().build(), right.borrow().build()).into()
+            }
+        };
+        if let SceneItem::Primitive(shape) = &mut item {
+            shape.set_material(self.material.borrow().to_phong());
+        }
+        item.with_transform(self.transform)
+    }
+}
+
+#[derive(Default)]
+struct WorldSpec {
+    objects: Vec<Ref<ObjectSpec>>,
+    lights: Vec<(Point, Color)>,
+}
+
+impl WorldSpec {
+    fn build(&self) -> World {
+        let mut world = World::empty();
+        for &(position, intensity) in &self.lights {
+            world.add_light(PointLight::new(position, intensity));
+        }
+        for object in &self.objects {
+            world.add_item(object.borrow().build());
+        }
+        world.finalize_scene();
+        world
+    }
+}
+
+/// An evaluated argument, together with the position of the expression it came from.
+struct Arg {
+    pos: Position,
+    val: Val,
+}
+
+impl Arg {
+    fn error(&self, message: impl Into<String>) -> SceneError {
+        SceneError::parse(self.pos, message)
+    }
+
+    fn number(&self) -> Result<f64, SceneError> {
+        match self.val {
+            Val::Number(x) => Ok(x),
+            _ => Err(self.error("expected a number")),
+        }
+    }
+
+    fn size(&self) -> Result<u32, SceneError> {
+        let x = self.number()?;
+        if x >= 0.0 && x.fract() == 0.0 && x <= u32::MAX as f64 {
+            Ok(x as u32)
+        } else {
+            Err(self.error("expected a non-negative integer"))
+        }
+    }
+
+    fn boolean(&self) -> Result<bool, SceneError> {
+        match self.val {
+            Val::Bool(b) => Ok(b),
+            _ => Err(self.error("expected #t or #f")),
+        }
+    }
+
+    fn symbol(&self) -> Result<&str, SceneError> {
+        match &self.val {
+            Val::Symbol(s) => Ok(s),
+            _ => Err(self.error("expected a quoted symbol")),
+        }
+    }
+
+    fn point(&self) -> Result<Point, SceneError> {
+        match self.val {
+            Val::Point(p) => Ok(p),
+            _ => Err(self.error("expected a point")),
+        }
+    }
+
+    fn vector(&self) -> Result<Vector, SceneError> {
+        match self.val {
+            Val::Vector(v) => Ok(v),
+            _ => Err(self.error("expected a vector")),
+        }
+    }
+
+    fn color(&self) -> Result<Color, SceneError> {
+        match self.val {
+            Val::Color(c) => Ok(c),
+            _ => Err(self.error("expected a color")),
+        }
+    }
+
+    fn paint(&self) -> Result<Paint, SceneError> {
+        match &self.val {
+            Val::Color(c) => Ok(Paint::Color(*c)),
+            Val::Pattern(p) => Ok(Paint::Pattern(p.clone())),
+            _ => Err(self.error("expected a color or a pattern")),
+        }
+    }
+
+    fn matrix(&self) -> Result<Matrix, SceneError> {
+        match self.val {
+            Val::Matrix(m) => Ok(m),
+            _ => Err(self.error("expected a transformation matrix")),
+        }
+    }
+
+    fn material(&self) -> Result<Ref<MaterialSpec>, SceneError> {
+        match &self.val {
+            Val::Material(m) => Ok(m.clone()),
+            _ => Err(self.error("expected a material")),
+        }
+    }
+
+    fn object(&self) -> Result<Ref<ObjectSpec>, SceneError> {
+        match &self.val {
+            Val::Object(o) => Ok(o.clone()),
+            _ => Err(self.error("expected a shape")),
+        }
+    }
+
+    fn world(&self) -> Result<Ref<WorldSpec>, SceneError> {
+        match &self.val {
+            Val::World(w) => Ok(w.clone()),
+            _ => Err(self.error("expected a world")),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<Arg>, SceneError> {
+        match &self.val {
+            Val::List(items) => Ok(items
+                .iter()
+                .map(|val| Arg {
+                    pos: self.pos,
+                    val: val.clone(),
+                })
+                .collect()),
+            _ => Err(self.error("expected a list")),
+        }
+    }
+
+    fn light(&self) -> Result<(Point, Color), SceneError> {
+        match self.val {
+            Val::Light(p, c) => Ok((p, c)),
+            _ => Err(self.error("expected a light")),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Interpreter {
+    env: HashMap<String, Val>,
+    rendered: Option<(Ref<Camera>, Ref<WorldSpec>)>,
+    last_camera: Option<Ref<Camera>>,
+    last_world: Option<Ref<WorldSpec>>,
+}
+
+impl Interpreter {
+    fn finish(self) -> Result<Scene, SceneError> {
+        let start = Position::new(1, 1);
+        let (camera, world) = match self.rendered {
+            Some(rendered) => rendered,
+            None => (
+                self.last_camera
+                    .ok_or_else(|| SceneError::parse(start, "the scene does not make a camera"))?,
+                self.last_world
+                    .ok_or_else(|| SceneError::parse(start, "the scene does not make a world"))?,
+            ),
+        };
+        let camera = camera.borrow().clone();
+        let world = world.borrow().build();
+        Ok(Scene { world, camera })
+    }
+
+    fn toplevel(&mut self, expr: &Expr) -> Result<(), SceneError> {
+        if let Some(items) = expr.as_list() {
+            match items.first().and_then(Expr::as_symbol) {
+                Some("import") | Some("call-with-output-file") => return Ok(()),
+                Some("define") => return self.define(expr, &items[1..]),
+                _ => {}
+            }
+        }
+        self.eval(expr).map(|_| ())
+    }
+
+    fn define(&mut self, expr: &Expr, args: &[Expr]) -> Result<(), SceneError> {
+        match args {
+            [name, value] if name.as_symbol().is_some() => {
+                let value = self.eval(value)?;
+                self.env
+                    .insert(name.as_symbol().unwrap().to_string(), value);
+                Ok(())
+            }
+            [name, ..] if name.as_list().is_some() => {
+                Err(name.error("procedure definitions are not supported"))
+            }
+            _ => Err(expr.error("expected (define name value)")),
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<Val, SceneError> {
+        match &expr.datum {
+            Datum::Number(x) => Ok(Val::Number(*x)),
+            Datum::Bool(b) => Ok(Val::Bool(*b)),
+            Datum::Str(_) => Err(expr.error("strings are not supported here")),
+            Datum::Symbol(name) => self.lookup(expr, name),
+            Datum::List(items) => {
+                let (head, rest) = items
+                    .split_first()
+                    .ok_or_else(|| expr.error("cannot evaluate ()"))?;
+                match head.as_symbol() {
+                    Some("quote") => match rest {
+                        [quoted] => quote(quoted),
+                        _ => Err(expr.error("`quote` takes 1 argument")),
+                    },
+                    Some("begin") => {
+                        let mut result = Val::Unspecified;
+                        for e in rest {
+                            result = self.eval(e)?;
+                        }
+                        Ok(result)
+                    }
+                    Some("define") => Err(expr.error("`define` is only allowed at the top level")),
+                    Some(name) if !self.env.contains_key(name) => {
+                        let args = self.eval_args(rest)?;
+                        self.call(expr, name, args)
+                    }
+                    _ => {
+                        let target = Arg {
+                            pos: head.pos,
+                            val: self.eval(head)?,
+                        };
+                        let args = self.eval_args(rest)?;
+                        self.send(expr, target, args)
+                    }
+                }
+            }
+        }
+    }
+
+    fn eval_args(&mut self, exprs: &[Expr]) -> Result<Vec<Arg>, SceneError> {
+        exprs
+            .iter()
+            .map(|e| {
+                Ok(Arg {
+                    pos: e.pos,
+                    val: self.eval(e)?,
+                })
+            })
+            .collect()
+    }
+
+    fn lookup(&self, expr: &Expr, name: &str) -> Result<Val, SceneError> {
+        if let Some(val) = self.env.get(name) {
+            return Ok(val.clone());
+        }
+        match name {
+            "PI" => Ok(Val::Number(PI)),
+            "csg-union" => Ok(Val::CsgOp(CsgOp::Union)),
+            "csg-intersection" => Ok(Val::CsgOp(CsgOp::Intersection)),
+            "csg-difference" => Ok(Val::CsgOp(CsgOp::Difference)),
+            _ => Err(expr.error(format!("unbound variable `{}`", name))),
+        }
+    }
+
+    /// Call one of the procedures of the `raytrace` libraries.
+    fn call(&mut self, expr: &Expr, name: &str, args: Vec<Arg>) -> Result<Val, SceneError> {
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(expr.error(format!("`{}` takes {} arguments", name, n)))
+            }
+        };
+
+        let val = match name {
+            "+" => Val::Number(sum(&args)?),
+            "*" => Val::Number(product(&args)?),
+            "-" | "/" => {
+                let (first, rest) = args
+                    .split_first()
+                    .ok_or_else(|| expr.error(format!("`{}` takes at least 1 argument", name)))?;
+                let x = first.number()?;
+                Val::Number(match (name, rest.is_empty()) {
+                    ("-", true) => -x,
+                    ("-", false) => x - sum(rest)?,
+                    (_, true) => 1.0 / x,
+                    (_, false) => x / product(rest)?,
+                })
+            }
+            "sqrt" => {
+                arity(1)?;
+                Val::Number(args[0].number()?.sqrt())
+            }
+            "list" => Val::List(args.into_iter().map(|a| a.val).collect()),
+
+            "point" | "vec" | "color" => {
+                arity(3)?;
+                let (x, y, z) = (args[0].number()?, args[1].number()?, args[2].number()?);
+                match name {
+                    "point" => Val::Point(point(x, y, z)),
+                    "vec" => Val::Vector(vector(x, y, z)),
+                    _ => Val::Color(color(x, y, z)),
+                }
+            }
+
+            "identity-transform" => {
+                arity(0)?;
+                Val::Matrix(Matrix::identity())
+            }
+            "translation" | "scaling" => {
+                arity(3)?;
+                let (x, y, z) = (args[0].number()?, args[1].number()?, args[2].number()?);
+                Val::Matrix(if name == "translation" {
+                    translation(x, y, z)
+                } else {
+                    scaling(x, y, z)
+                })
+            }
+            "rotation-x" | "rotation-y" | "rotation-z" => {
+                arity(1)?;
+                let angle = args[0].number()?;
+                Val::Matrix(match name {
+                    "rotation-x" => rotation_x(angle),
+                    "rotation-y" => rotation_y(angle),
+                    _ => rotation_z(angle),
+                })
+            }
+            "shearing" => {
+                arity(6)?;
+                let a = args
+                    .iter()
+                    .map(Arg::number)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Val::Matrix(shearing(a[0], a[1], a[2], a[3], a[4], a[5]))
+            }
+            "view-transform" => {
+                arity(3)?;
+                Val::Matrix(view_transform(
+                    args[0].point()?,
+                    args[1].point()?,
+                    args[2].vector()?,
+                ))
+            }
+            "m4*" => {
+                let mut m = Matrix::identity();
+                for a in &args {
+                    m = m * a.matrix()?;
+                }
+                Val::Matrix(m)
+            }
+
+            "sphere" | "plane" | "cube" | "cylinder" | "cone" | "group" | "glass-sphere" => {
+                arity(0)?;
+                let object = ObjectSpec::new(match name {
+                    "sphere" | "glass-sphere" => ObjectKind::Sphere,
+                    "plane" => ObjectKind::Plane,
+                    "cube" => ObjectKind::Cube,
+                    "cylinder" => ObjectKind::Cylinder(Limits::default()),
+                    "cone" => ObjectKind::Cone(Limits::default()),
+                    _ => ObjectKind::Group(vec![]),
+                });
+                if name == "glass-sphere" {
+                    let mut m = object.material.borrow_mut();
+                    m.transparency = 1.0;
+                    m.refractive_index = 1.5;
+                }
+                Val::Object(new_ref(object))
+            }
+            "csg" => {
+                arity(3)?;
+                let op = match args[0].val {
+                    Val::CsgOp(op) => op,
+                    _ => return Err(args[0].error("expected a CSG operation")),
+                };
+                let kind = ObjectKind::Csg(op, args[1].object()?, args[2].object()?);
+                Val::Object(new_ref(ObjectSpec::new(kind)))
+            }
+
+            "default-material" => {
+                arity(0)?;
+                Val::Material(new_ref(MaterialSpec::default()))
+            }
+            "material" | "make-material" => {
+                arity(if name == "material" { 5 } else { 8 })?;
+                let x = args[1..]
+                    .iter()
+                    .map(Arg::number)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut m = MaterialSpec {
+                    color: args[0].paint()?,
+                    ambient: x[0],
+                    diffuse: x[1],
+                    specular: x[2],
+                    shininess: x[3],
+                    ..MaterialSpec::default()
+                };
+                if name == "make-material" {
+                    m.reflective = x[4];
+                    m.transparency = x[5];
+                    m.refractive_index = x[6];
+                }
+                Val::Material(new_ref(m))
+            }
+            "material-color" => {
+                arity(1)?;
+                match &args[0].material()?.borrow().color {
+                    Paint::Color(c) => Val::Color(*c),
+                    Paint::Pattern(p) => Val::Pattern(p.clone()),
+                }
+            }
+            "material-set-color!" => {
+                arity(2)?;
+                args[0].material()?.borrow_mut().color = args[1].paint()?;
+                Val::Unspecified
+            }
+            "material-set-ambient!"
+            | "material-set-diffuse!"
+            | "material-set-specular!"
+            | "material-set-shininess!"
+            | "material-set-reflective!"
+            | "material-set-transparency!"
+            | "material-set-refractive-index!" => {
+                arity(2)?;
+                let material = args[0].material()?;
+                let mut m = material.borrow_mut();
+                let field = match name {
+                    "material-set-ambient!" => &mut m.ambient,
+                    "material-set-diffuse!" => &mut m.diffuse,
+                    "material-set-specular!" => &mut m.specular,
+                    "material-set-shininess!" => &mut m.shininess,
+                    "material-set-reflective!" => &mut m.reflective,
+                    "material-set-transparency!" => &mut m.transparency,
+                    _ => &mut m.refractive_index,
+                };
+                *field = args[1].number()?;
+                Val::Unspecified
+            }
+            "refractive-index" => {
+                arity(1)?;
+                Val::Number(match args[0].symbol()? {
+                    "vacuum" => 1.0,
+                    "air" => 1.00029,
+                    "ice" => 1.31,
+                    "water" => 1.333,
+                    "olive-oil" => 1.47,
+                    "glass" => 1.52,
+                    "sapphire" => 1.77,
+                    "diamond" => 2.417,
+                    other => return Err(args[0].error(format!("unknown medium `{}`", other))),
+                })
+            }
+
+            "stripe-pattern" | "gradient-pattern" | "ring-pattern" | "checkers-pattern" => {
+                arity(2)?;
+                let (a, b) = (args[0].color()?, args[1].color()?);
+                Val::Pattern(new_ref(match name {
+                    "stripe-pattern" => stripe_pattern(a, b),
+                    "gradient-pattern" => gradient_pattern(a, b),
+                    "ring-pattern" => ring_pattern(a, b),
+                    _ => checkers_pattern(a, b),
+                }))
+            }
+
+            "point-light" => {
+                arity(2)?;
+                Val::Light(args[0].point()?, args[1].color()?)
+            }
+            "make-world" => {
+                arity(2)?;
+                let objects = args[0]
+                    .list()?
+                    .iter()
+                    .map(Arg::object)
+                    .collect::<Result<_, _>>()?;
+                let lights = args[1]
+                    .list()?
+                    .iter()
+                    .map(Arg::light)
+                    .collect::<Result<_, _>>()?;
+                let world = new_ref(WorldSpec { objects, lights });
+                self.last_world = Some(world.clone());
+                Val::World(world)
+            }
+            "make-camera" => {
+                arity(3)?;
+                let camera = new_ref(Camera::new(
+                    args[0].size()?,
+                    args[1].size()?,
+                    args[2].number()?,
+                ));
+                self.last_camera = Some(camera.clone());
+                Val::Camera(camera)
+            }
+
+            _ => return Err(expr.error(format!("unknown procedure `{}`", name))),
+        };
+        Ok(val)
+    }
+
+    /// Send a message such as `'set-transform!` to a shape, pattern, camera or world.
+    fn send(&mut self, expr: &Expr, target: Arg, args: Vec<Arg>) -> Result<Val, SceneError> {
+        let (message, args) = args
+            .split_first()
+            .ok_or_else(|| expr.error("expected a message"))?;
+        let name = message.symbol()?;
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(message.error(format!("`{}` takes {} arguments", name, n)))
+            }
+        };
+        let unknown = || message.error(format!("unknown message `{}`", name));
+
+        match &target.val {
+            Val::Object(object) => match name {
+                "set-transform!" => {
+                    arity(1)?;
+                    object.borrow_mut().transform = args[0].matrix()?;
+                }
+                "transform" => {
+                    arity(0)?;
+                    return Ok(Val::Matrix(object.borrow().transform));
+                }
+                "set-material!" => {
+                    arity(1)?;
+                    object.borrow_mut().material = args[0].material()?;
+                }
+                "material" => {
+                    arity(0)?;
+                    return Ok(Val::Material(object.borrow().material.clone()));
+                }
+                "set-minimum!" | "set-maximum!" | "set-closed!" => {
+                    arity(1)?;
+                    let mut object = object.borrow_mut();
+                    let limits = match &mut object.kind {
+                        ObjectKind::Cylinder(l) | ObjectKind::Cone(l) => l,
+                        _ => return Err(unknown()),
+                    };
+                    match name {
+                        "set-minimum!" => limits.min = args[0].number()?,
+                        "set-maximum!" => limits.max = args[0].number()?,
+                        _ => limits.closed = args[0].boolean()?,
+                    }
+                }
+                "add-children!" => {
+                    let mut children = args
+                        .iter()
+                        .map(Arg::object)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if children.iter().any(|c| c.borrow().contains(object)) {
+                        return Err(message.error("a group cannot contain itself"));
+                    }
+                    match &mut object.borrow_mut().kind {
+                        ObjectKind::Group(items) => items.append(&mut children),
+                        _ => return Err(unknown()),
+                    }
+                }
+                _ => return Err(unknown()),
+            },
+            Val::Pattern(pattern) => match name {
+                "set-transform!" => {
+                    arity(1)?;
+                    pattern.borrow_mut().set_transform(args[0].matrix()?);
+                }
+                _ => return Err(unknown()),
+            },
+            Val::Camera(camera) => match name {
+                "set-transform!" => {
+                    arity(1)?;
+                    camera.borrow_mut().set_transform(args[0].matrix()?);
+                }
+                "render" => {
+                    arity(1)?;
+                    self.rendered = Some((camera.clone(), args[0].world()?));
+                }
+                _ => return Err(unknown()),
+            },
+            Val::World(world) => match name {
+                "add-object!" => {
+                    arity(1)?;
+                    world.borrow_mut().objects.push(args[0].object()?);
+                }
+                "add-light!" => {
+                    arity(1)?;
+                    world.borrow_mut().lights.push(args[0].light()?);
+                }
+                _ => return Err(unknown()),
+            },
+            _ => return Err(target.error("cannot send messages to this value")),
+        }
+        Ok(Val::Unspecified)
+    }
+}
+
+fn quote(expr: &Expr) -> Result<Val, SceneError> {
+    match &expr.datum {
+        Datum::Number(x) => Ok(Val::Number(*x)),
+        Datum::Bool(b) => Ok(Val::Bool(*b)),
+        Datum::Str(_) => Err(expr.error("strings are not supported here")),
+        Datum::Symbol(s) => Ok(Val::Symbol(s.clone())),
+        Datum::List(items) => Ok(Val::List(
+            items.iter().map(quote).collect::<Result<_, _>>()?,
+        )),
+    }
+}
+
+fn sum(args: &[Arg]) -> Result<f64, SceneError> {
+    args.iter().map(Arg::number).sum()
+}
+
+fn product(args: &[Arg]) -> Result<f64, SceneError> {
+    args.iter().map(Arg::number).product()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::ray::Ray;
+
+    const CAMERA: &str = "
+(define camera (make-camera 11 11 (/ PI 2)))
+(camera 'set-transform! (view-transform (point 0 0 -5) (point 0 0 0) (vec 0 1 0)))
+";
+
+    fn error_at(input: &str) -> Position {
+        match parse_sexpr_scene(input) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.position().unwrap(),
+        }
+    }
+
+    /// The camera is configured from the scene program
+    #[test]
+    fn camera() {
+        let input = format!("{}(define world (make-world (list) (list)))", CAMERA);
+        let scene = parse_sexpr_scene(&input).unwrap();
+        assert_eq!(scene.camera.hsize(), 11);
+        assert_eq!(scene.camera.vsize(), 11);
+        assert_almost_eq!(scene.camera.field_of_view(), PI / 2.0);
+        assert_almost_eq!(
+            scene.camera.transform(),
+            Matrix::view(point(0, 0, -5), point(0, 0, 0), vector(0, 1, 0))
+        );
+    }
+
+    /// The default world of the Scheme tests renders like the default world
+    #[test]
+    fn default_world() {
+        let input = format!(
+            "{}
+(define s1 (sphere))
+(s1 'set-material! (material (color 0.8 1.0 0.6) 0.1 0.7 0.2 200))
+(define s2 (sphere))
+(s2 'set-transform! (scaling 0.5 0.5 0.5))
+(define world (make-world (list s1 s2)
+                          (list (point-light (point -10 10 -10) (color 1 1 1)))))
+(define image (camera 'render world))
+",
+            CAMERA
+        );
+        let mut scene = parse_sexpr_scene(&input).unwrap();
+        scene.camera.set_min_samples(1);
+        scene.camera.set_allowed_standard_error(1.0);
+        let image = scene.render();
+        assert_almost_eq!(image.get_pixel(5, 5), color(0.38066, 0.47583, 0.2855));
+    }
+
+    /// Messages sent after a shape was put into the world still affect it, and materials are
+    /// shared between the shapes they were given to
+    #[test]
+    fn shapes_have_identity() {
+        let input = format!(
+            "{}
+(define m (default-material))
+(define a (cube))
+(define b (sphere))
+(a 'set-material! m)
+(b 'set-material! m)
+(define c (csg csg-difference a b))
+(define world (make-world (list c) (list)))
+(b 'set-transform! (translation 0 0 -1))
+(c 'set-transform! (translation 0 1 0))
+(material-set-color! m (color 0 0 1))
+",
+            CAMERA
+        );
+        let scene = parse_sexpr_scene(&input).unwrap();
+        let r = Ray::new(point(0, 1, -5), vector(0, 0, 1));
+        let xs = scene.world.intersect(&r);
+        assert_eq!(xs.len(), 2);
+        assert_almost_eq!(xs[0].t, 5.0);
+        assert_almost_eq!(xs[1].t, 6.0);
+        assert_almost_eq!(
+            xs[0]
+                .obj
+                .material()
+                .color_at(&xs[0].prepare_computations(&r, &xs)),
+            color(0, 0, 1)
+        );
+    }
+
+    /// Cylinders, cones and groups are configured with messages
+    #[test]
+    fn groups_and_limits() {
+        let input = format!(
+            "{}
+(define cyl (cylinder))
+(cyl 'set-minimum! 0)
+(cyl 'set-maximum! 1)
+(cyl 'set-closed! #t)
+(define g (group))
+(g 'add-children! cyl)
+(g 'set-transform! (translation 0 -0.5 0))
+(define world (make-world (list g) (list)))
+",
+            CAMERA
+        );
+        let scene = parse_sexpr_scene(&input).unwrap();
+        let xs = scene
+            .world
+            .intersect(&Ray::new(point(0, 0, -5), vector(0, 0, 1)));
+        assert_eq!(xs.len(), 2);
+        assert_almost_eq!(xs[0].t, 4.0);
+        let xs = scene
+            .world
+            .intersect(&Ray::new(point(0, 0.6, -5), vector(0, 0, 1)));
+        assert_eq!(xs.len(), 0);
+    }
+
+    /// The chapter scenes of the Scheme implementation can be loaded
+    #[test]
+    fn scheme_chapters() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../scheme");
+        for chapter in &["07", "08", "09", "10", "11a", "11b", "13", "16"] {
+            let path = dir.join(format!("chapter{}.scm", chapter));
+            let scene = load_sexpr_scene(&path).unwrap();
+            assert_eq!(scene.camera.hsize(), 320);
+            assert_eq!(scene.camera.vsize(), 160);
+        }
+    }
+
+    /// Errors point at the offending expression
+    #[test]
+    fn error_positions() {
+        assert_eq!(
+            error_at("(define s (sphere))\n(s 'set-transform! 1)"),
+            Position::new(2, 20)
+        );
+        assert_eq!(
+            error_at("(define s (sphere))\n(s 'frobnicate!)"),
+            Position::new(2, 4)
+        );
+        assert_eq!(error_at("(define (f x) x)"), Position::new(1, 9));
+        assert_eq!(error_at("(define s (spheer))"), Position::new(1, 11));
+        assert_eq!(error_at("(display undefined)"), Position::new(1, 10));
+        assert_eq!(error_at("(define s (sphere))"), Position::new(1, 1));
+    }
+
+    /// Groups cannot contain themselves, directly or through other groups and CSG pairs
+    #[test]
+    fn group_cycles() {
+        let groups = "(define a (group))\n(define b (group))\n";
+        assert_eq!(
+            error_at(&format!("{}(a 'add-children! a)", groups)),
+            Position::new(3, 4)
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}(a 'add-children! b)\n(b 'add-children! a)",
+                groups
+            )),
+            Position::new(4, 4)
+        );
+        assert_eq!(
+            error_at(&format!(
+                "{}(define c (csg csg-union a (sphere)))\n(b 'add-children! c)\n(a 'add-children! b)",
+                groups
+            )),
+            Position::new(5, 4)
+        );
+    }
+}
@@ -0,0 +1,611 @@
+//! A small parser for the subset of YAML used by scene description files.
+//!
+//! Supported are block mappings and sequences (including the compact `- key: value` form),
+//! single-line flow sequences and mappings (`[1, 2, 3]`, `{ a: 1 }`), plain and quoted scalars,
+//! and comments. Every node remembers where it started, so that errors found while building a
+//! scene can point at the offending line and column.
+
+use crate::scene::{Position, SceneError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub pos: Position,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Scalar(String),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>),
+}
+
+impl Node {
+    pub fn new(pos: Position, value: Value) -> Self {
+        Node { pos, value }
+    }
+
+    pub fn as_str(&self) -> Result<&str, SceneError> {
+        match &self.value {
+            Value::Scalar(s) => Ok(s),
+            _ => Err(self.error("expected a string")),
+        }
+    }
+
+    pub fn as_f64(&self) -> Result<f64, SceneError> {
+        self.as_str()?
+            .parse()
+            .map_err(|_| self.error("expected a number"))
+    }
+
+    pub fn as_u32(&self) -> Result<u32, SceneError> {
+        self.as_str()?
+            .parse()
+            .map_err(|_| self.error("expected a non-negative integer"))
+    }
+
+    pub fn as_bool(&self) -> Result<bool, SceneError> {
+        match self.as_str()? {
+            "true" | "yes" | "on" => Ok(true),
+            "false" | "no" | "off" => Ok(false),
+            _ => Err(self.error("expected true or false")),
+        }
+    }
+
+    pub fn as_sequence(&self) -> Result<&[Node], SceneError> {
+        match &self.value {
+            Value::Sequence(items) => Ok(items),
+            _ => Err(self.error("expected a list")),
+        }
+    }
+
+    pub fn as_mapping(&self) -> Result<&[(Node, Node)], SceneError> {
+        match &self.value {
+            Value::Mapping(entries) => Ok(entries),
+            _ => Err(self.error("expected a mapping")),
+        }
+    }
+
+    pub fn as_triple(&self) -> Result<[f64; 3], SceneError> {
+        match self.as_sequence()? {
+            [x, y, z] => Ok([x.as_f64()?, y.as_f64()?, z.as_f64()?]),
+            _ => Err(self.error("expected a list of three numbers")),
+        }
+    }
+
+    pub fn is_mapping(&self) -> bool {
+        matches!(self.value, Value::Mapping(_))
+    }
+
+    /// Look up the value of `key` if this node is a mapping.
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        match &self.value {
+            Value::Mapping(entries) => entries
+                .iter()
+                .find(|(k, _)| k.value == Value::Scalar(key.to_string()))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self, message: impl Into<String>) -> SceneError {
+        SceneError::parse(self.pos, message)
+    }
+}
+
+pub fn parse(input: &str) -> Result<Node, SceneError> {
+    let lines = split_lines(input)?;
+    let mut parser = Parser { lines, current: 0 };
+
+    let node = match parser.peek() {
+        None => Node::new(Position::new(1, 1), Value::Null),
+        Some(line) => {
+            let indent = line.indent;
+            parser.parse_block(indent)?
+        }
+    };
+
+    if let Some(line) = parser.peek() {
+        return Err(SceneError::parse(
+            line.position(0),
+            "unexpected content after the end of the document",
+        ));
+    }
+
+    Ok(node)
+}
+
+#[derive(Debug, Clone)]
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+impl Line<'_> {
+    fn position(&self, offset: usize) -> Position {
+        Position::new(self.number, self.indent + offset + 1)
+    }
+
+    fn is_sequence_item(&self) -> bool {
+        self.text == "-" || self.text.starts_with("- ")
+    }
+}
+
+fn split_lines(input: &str) -> Result<Vec<Line<'_>>, SceneError> {
+    let mut lines = vec![];
+    for (i, raw) in input.lines().enumerate() {
+        let number = i + 1;
+        let text = strip_comment(raw).trim_end();
+        let content = text.trim_start_matches(' ');
+        if content.is_empty() || content == "---" || content == "..." {
+            continue;
+        }
+        let indent = text.len() - content.len();
+        if content.starts_with('\t') {
+            return Err(SceneError::parse(
+                Position::new(number, indent + 1),
+                "tabs are not allowed for indentation",
+            ));
+        }
+        lines.push(Line {
+            number,
+            indent,
+            text: content,
+        });
+    }
+    Ok(lines)
+}
+
+/// Remove a trailing comment, taking care not to cut quoted strings.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '#' && previous.is_whitespace() => return &line[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    line
+}
+
+struct Parser<'a> {
+    lines: Vec<Line<'a>>,
+    current: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Line<'a>> {
+        self.lines.get(self.current)
+    }
+
+    fn parse_block(&mut self, indent: usize) -> Result<Node, SceneError> {
+        let line = self.lines[self.current].clone();
+        if line.is_sequence_item() {
+            self.parse_sequence(indent)
+        } else if find_mapping_colon(line.text).is_some() {
+            self.parse_mapping(indent)
+        } else {
+            self.current += 1;
+            let node = parse_inline(&line, 0)?;
+            self.expect_dedent(indent)?;
+            Ok(node)
+        }
+    }
+
+    fn parse_sequence(&mut self, indent: usize) -> Result<Node, SceneError> {
+        let pos = self.lines[self.current].position(0);
+        let mut items = vec![];
+
+        while let Some(line) = self.peek() {
+            if line.indent < indent {
+                break;
+            }
+            if line.indent > indent {
+                return Err(SceneError::parse(
+                    line.position(0),
+                    "unexpected indentation",
+                ));
+            }
+            if !line.is_sequence_item() {
+                break;
+            }
+
+            let rest = line.text[1..].trim_start_matches(' ');
+            if rest.is_empty() {
+                let item_pos = line.position(0);
+                self.current += 1;
+                items.push(self.parse_nested(indent, item_pos)?);
+            } else {
+                // Treat the content after the dash as if it started a new, deeper indented line.
+                let offset = line.text.len() - rest.len();
+                let line = &mut self.lines[self.current];
+                line.indent += offset;
+                line.text = rest;
+                let item_indent = line.indent;
+                items.push(self.parse_block(item_indent)?);
+            }
+        }
+
+        Ok(Node::new(pos, Value::Sequence(items)))
+    }
+
+    fn parse_mapping(&mut self, indent: usize) -> Result<Node, SceneError> {
+        let pos = self.lines[self.current].position(0);
+        let mut entries: Vec<(Node, Node)> = vec![];
+
+        while let Some(line) = self.peek().cloned() {
+            if line.indent < indent {
+                break;
+            }
+            if line.indent > indent {
+                return Err(SceneError::parse(
+                    line.position(0),
+                    "unexpected indentation",
+                ));
+            }
+            let colon = match find_mapping_colon(line.text) {
+                Some(colon) => colon,
+                None => return Err(SceneError::parse(line.position(0), "expected `key: value`")),
+            };
+
+            let key = parse_scalar(line.text[..colon].trim_end(), line.position(0))?;
+            if let Some((existing, _)) = entries.iter().find(|(k, _)| k.value == key.value) {
+                return Err(key.error(format!(
+                    "duplicate key (first defined at line {})",
+                    existing.pos.line
+                )));
+            }
+
+            let rest = &line.text[colon + 1..];
+            let value_offset = colon + 1 + rest.len() - rest.trim_start_matches(' ').len();
+            let value;
+            if rest.trim().is_empty() {
+                self.current += 1;
+                value = match self.peek() {
+                    // YAML allows the items of a list to be at the same level as their key
+                    Some(next) if next.indent == indent && next.is_sequence_item() => {
+                        self.parse_sequence(indent)?
+                    }
+                    _ => self.parse_nested(indent, line.position(colon + 1))?,
+                };
+            } else {
+                self.current += 1;
+                value = parse_inline(&line, value_offset)?;
+                self.expect_dedent(indent)?;
+            }
+
+            entries.push((key, value));
+        }
+
+        Ok(Node::new(pos, Value::Mapping(entries)))
+    }
+
+    /// Parse a block that is indented deeper than `parent_indent`, or return null if there is none.
+    fn parse_nested(&mut self, parent_indent: usize, pos: Position) -> Result<Node, SceneError> {
+        match self.peek() {
+            Some(next) if next.indent > parent_indent => {
+                let indent = next.indent;
+                self.parse_block(indent)
+            }
+            _ => Ok(Node::new(pos, Value::Null)),
+        }
+    }
+
+    /// A value that fits on a single line must not be followed by deeper indented lines.
+    fn expect_dedent(&self, indent: usize) -> Result<(), SceneError> {
+        match self.peek() {
+            Some(next) if next.indent > indent => Err(SceneError::parse(
+                next.position(0),
+                "unexpected indentation",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Find the colon that separates a mapping key from its value.
+fn find_mapping_colon(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    match bytes.first() {
+        Some(b'[') | Some(b'{') => return None,
+        Some(&q) if q == b'"' || q == b'\'' => {
+            let end = text[1..].find(q as char)? + 1;
+            return match bytes.get(end + 1) {
+                Some(b':') if is_value_separator(bytes.get(end + 2)) => Some(end + 1),
+                _ => None,
+            };
+        }
+        _ => {}
+    }
+    (0..bytes.len()).find(|&i| bytes[i] == b':' && is_value_separator(bytes.get(i + 1)))
+}
+
+fn is_value_separator(next: Option<&u8>) -> bool {
+    match next {
+        None => true,
+        Some(c) => c.is_ascii_whitespace(),
+    }
+}
+
+fn parse_inline(line: &Line, offset: usize) -> Result<Node, SceneError> {
+    let mut cursor = Cursor {
+        line,
+        text: line.text,
+        offset,
+    };
+    let node = cursor.parse_value(false)?;
+    cursor.skip_spaces();
+    if cursor.offset < cursor.text.len() {
+        return Err(SceneError::parse(
+            cursor.position(),
+            "unexpected characters after value",
+        ));
+    }
+    Ok(node)
+}
+
+fn parse_scalar(text: &str, pos: Position) -> Result<Node, SceneError> {
+    let line = Line {
+        number: pos.line,
+        indent: pos.column - 1,
+        text,
+    };
+    parse_inline(&line, 0)
+}
+
+struct Cursor<'a> {
+    line: &'a Line<'a>,
+    text: &'a str,
+    offset: usize,
+}
+
+impl Cursor<'_> {
+    fn position(&self) -> Position {
+        self.line.position(self.offset)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.offset..].chars().next()
+    }
+
+    fn skip_spaces(&mut self) {
+        while let Some(' ') = self.peek() {
+            self.offset += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), SceneError> {
+        self.skip_spaces();
+        if self.peek() == Some(c) {
+            self.offset += 1;
+            Ok(())
+        } else {
+            Err(SceneError::parse(
+                self.position(),
+                format!("expected `{}`", c),
+            ))
+        }
+    }
+
+    fn parse_value(&mut self, in_flow: bool) -> Result<Node, SceneError> {
+        self.skip_spaces();
+        let pos = self.position();
+        match self.peek() {
+            Some('[') => self.parse_flow_sequence(),
+            Some('{') => self.parse_flow_mapping(),
+            Some(q) if q == '"' || q == '\'' => self.parse_quoted(q),
+            Some('|') | Some('>') => Err(SceneError::parse(pos, "block scalars are not supported")),
+            _ => {
+                let rest = &self.text[self.offset..];
+                let len = if in_flow {
+                    rest.find([',', ']', '}', ':']).unwrap_or(rest.len())
+                } else {
+                    rest.len()
+                };
+                let s = rest[..len].trim_end();
+                self.offset += len;
+                if s.is_empty() || s == "~" || s == "null" {
+                    Ok(Node::new(pos, Value::Null))
+                } else {
+                    Ok(Node::new(pos, Value::Scalar(s.to_string())))
+                }
+            }
+        }
+    }
+
+    fn parse_quoted(&mut self, quote: char) -> Result<Node, SceneError> {
+        let pos = self.position();
+        self.offset += 1;
+        let mut s = String::new();
+        let mut chars = self.text[self.offset..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                _ if c == quote => {
+                    self.offset += i + 1;
+                    return Ok(Node::new(pos, Value::Scalar(s)));
+                }
+                '\\' if quote == '"' => match chars.next() {
+                    Some((_, 'n')) => s.push('\n'),
+                    Some((_, 't')) => s.push('\t'),
+                    Some((_, c)) => s.push(c),
+                    None => break,
+                },
+                _ => s.push(c),
+            }
+        }
+        Err(SceneError::parse(pos, "unterminated string"))
+    }
+
+    fn parse_flow_sequence(&mut self) -> Result<Node, SceneError> {
+        let pos = self.position();
+        self.offset += 1;
+        let mut items = vec![];
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(']') => {
+                    self.offset += 1;
+                    return Ok(Node::new(pos, Value::Sequence(items)));
+                }
+                None => return Err(SceneError::parse(pos, "unterminated list")),
+                _ => {}
+            }
+            items.push(self.parse_value(true)?);
+            self.skip_spaces();
+            match self.peek() {
+                Some(',') => self.offset += 1,
+                Some(']') => {}
+                None => return Err(SceneError::parse(pos, "unterminated list")),
+                _ => return Err(SceneError::parse(self.position(), "expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn parse_flow_mapping(&mut self) -> Result<Node, SceneError> {
+        let pos = self.position();
+        self.offset += 1;
+        let mut entries = vec![];
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some('}') => {
+                    self.offset += 1;
+                    return Ok(Node::new(pos, Value::Mapping(entries)));
+                }
+                None => return Err(SceneError::parse(pos, "unterminated mapping")),
+                _ => {}
+            }
+            let key = self.parse_value(true)?;
+            self.expect(':')?;
+            let value = self.parse_value(true)?;
+            entries.push((key, value));
+            self.skip_spaces();
+            match self.peek() {
+                Some(',') => self.offset += 1,
+                Some('}') => {}
+                None => return Err(SceneError::parse(pos, "unterminated mapping")),
+                _ => return Err(SceneError::parse(self.position(), "expected `,` or `}`")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalar(s: &str) -> Value {
+        Value::Scalar(s.to_string())
+    }
+
+    fn values(node: &Node) -> Vec<&Value> {
+        node.as_sequence()
+            .unwrap()
+            .iter()
+            .map(|n| &n.value)
+            .collect()
+    }
+
+    /// Parsing a plain scalar
+    #[test]
+    fn plain_scalar() {
+        let node = parse("hello world").unwrap();
+        assert_eq!(node.value, scalar("hello world"));
+    }
+
+    /// Parsing a block sequence
+    #[test]
+    fn block_sequence() {
+        let node = parse("- 1\n- two\n-\n- 'three'").unwrap();
+        assert_eq!(
+            values(&node),
+            vec![&scalar("1"), &scalar("two"), &Value::Null, &scalar("three")]
+        );
+    }
+
+    /// Parsing a flow sequence
+    #[test]
+    fn flow_sequence() {
+        let node = parse("[1, -2.5, [a, b], []]").unwrap();
+        let items = node.as_sequence().unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[1].as_f64().unwrap(), -2.5);
+        assert_eq!(values(&items[2]), vec![&scalar("a"), &scalar("b")]);
+        assert!(items[3].as_sequence().unwrap().is_empty());
+    }
+
+    /// Parsing a block mapping with nested values
+    #[test]
+    fn block_mapping() {
+        let data = "\
+add: sphere   # a comment
+material:
+  color: [1, 0.5, 0]
+  diffuse: 0.7
+transform:
+- [translate, 1, 2, 3]
+- [scale, 2, 2, 2]
+shadow: false";
+        let node = parse(data).unwrap();
+        assert_eq!(node.get("add").unwrap().value, scalar("sphere"));
+        let material = node.get("material").unwrap();
+        assert_eq!(
+            material.get("color").unwrap().as_triple().unwrap(),
+            [1.0, 0.5, 0.0]
+        );
+        assert_eq!(material.get("diffuse").unwrap().as_f64().unwrap(), 0.7);
+        assert_eq!(
+            node.get("transform").unwrap().as_sequence().unwrap().len(),
+            2
+        );
+        assert!(!node.get("shadow").unwrap().as_bool().unwrap());
+    }
+
+    /// Mappings can start on the line of a sequence item
+    #[test]
+    fn compact_mapping_in_sequence() {
+        let data = "\
+- add: camera
+  width: 100
+  from: [0, 1, 2]
+
+- define: blue
+  value:
+    color: [0, 0, 1]";
+        let node = parse(data).unwrap();
+        let items = node.as_sequence().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].get("width").unwrap().as_u32().unwrap(), 100);
+        assert_eq!(items[0].pos, Position::new(1, 3));
+        let value = items[1].get("value").unwrap();
+        assert_eq!(value.get("color").unwrap().pos, Position::new(7, 12));
+    }
+
+    /// Parsing a flow mapping and quoted strings
+    #[test]
+    fn flow_mapping() {
+        let node = parse("{ file: \"my teapot.obj\", 'size': 2 }").unwrap();
+        assert_eq!(node.get("file").unwrap().value, scalar("my teapot.obj"));
+        assert_eq!(node.get("size").unwrap().as_f64().unwrap(), 2.0);
+    }
+
+    /// Errors report the line and column
+    #[test]
+    fn error_position() {
+        let err = parse("- add: sphere\n  transform: [1, 2\n").unwrap_err();
+        assert_eq!(err.position(), Some(Position::new(2, 14)));
+
+        let err = parse("a: 1\n   b: 2").unwrap_err();
+        assert_eq!(err.position(), Some(Position::new(2, 4)));
+
+        let err = parse("a: 1\na: 2").unwrap_err();
+        assert_eq!(err.position(), Some(Position::new(2, 1)));
+    }
+}
@@ -0,0 +1,953 @@
+//! Scene files in the style of the YAML scenes that accompany "The Ray Tracer Challenge".
+//!
+//! A scene file is a list of commands. `add` puts a camera, a light or an object into the scene;
+//! `define` gives a name to a material, a transform or an object so that it can be reused
+//! (optionally `extend`ing an earlier definition):
+//!
+//! ```yaml
+//! - add: camera
+//!   width: 400
+//!   height: 200
+//!   field-of-view: 1.047
+//!   from: [0, 1.5, -5]
+//!   to: [0, 1, 0]
+//!   up: [0, 1, 0]
+//!
+//! - add: light
+//!   at: [-10, 10, -10]
+//!   intensity: [1, 1, 1]
+//!
+//! - define: shiny
+//!   value:
+//!     color: [0.1, 1, 0.5]
+//!     diffuse: 0.7
+//!     specular: 0.3
+//!
+//! - add: sphere
+//!   material: shiny
+//!   transform:
+//!     - [scale, 0.5, 0.5, 0.5]
+//!     - [translate, 1.5, 0.5, -0.5]
+//! ```
+//!
+//! Transforms are applied in the order they are listed. Materials map onto `Phong`; note that
+//! `ambient` is an alias for `emissive` (a material sets one of them) and that the defaults are
+//! those of `Phong::default()`.
+//!
+//! Objects of type `obj` are read from Wavefront OBJ files into indexed meshes, one per group
+//! and material, together with the material libraries (MTL files) they refer to. A `material`
+//! given in the scene file replaces the materials from the libraries. Objects of type `ply` and
+//! `stl` are read from PLY and STL files into a single mesh; PLY vertex colors tint the material.
+
+use crate::camera::Camera;
+use crate::canvas::Canvas;
+use crate::color::{color, Color};
+use crate::lights::{AmbientLight, Beam, DiscLight, PointLight, RealisticPointLight, SphereLight};
+use crate::materials::Phong;
+use crate::matrix::{rotation_x, rotation_y, rotation_z, scaling, shearing, translation, Matrix};
+use crate::mtl_loader::MaterialLibrary;
+use crate::obj_loader::ObjParser;
+use crate::pattern::{checkers_pattern, gradient_pattern, ring_pattern, stripe_pattern, Pattern};
+use crate::ply_loader::PlyParser;
+use crate::scene::yaml::{self, Node, Value};
+use crate::scene::{Scene, SceneError};
+use crate::shapes::{
+    csg_difference, csg_intersection, csg_union, cube, plane, sphere, triangle, Cone, Cylinder,
+    Group, SceneItem, Shape,
+};
+use crate::stl_loader::StlParser;
+use crate::tuple::{point, vector, Point, Vector};
+use crate::world::World;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub fn load_yaml_scene(path: impl AsRef<Path>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let source = std::fs::read_to_string(path)?;
+    parse_yaml_scene(&source, path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+/// Build a scene from the contents of a scene file. Relative paths of external files (such as
+/// OBJ meshes) are resolved against `base_dir`.
+pub fn parse_yaml_scene(input: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let document = yaml::parse(input)?;
+    let mut builder = SceneBuilder::new(base_dir);
+    if document.value != Value::Null {
+        for command in document.as_sequence()? {
+            builder.command(command)?;
+        }
+    }
+    builder.finish(&document)
+}
+
+struct SceneBuilder<'a> {
+    base_dir: &'a Path,
+    definitions: HashMap<String, Node>,
+    world: World,
+    camera: Option<Camera>,
+}
+
+impl<'a> SceneBuilder<'a> {
+    fn new(base_dir: &'a Path) -> Self {
+        SceneBuilder {
+            base_dir,
+            definitions: HashMap::new(),
+            world: World::empty(),
+            camera: None,
+        }
+    }
+
+    fn finish(mut self, document: &Node) -> Result<Scene, SceneError> {
+        let camera = self
+            .camera
+            .ok_or_else(|| document.error("the scene does not add a camera"))?;
+        self.world.finalize_scene();
+        Ok(Scene {
+            world: self.world,
+            camera,
+        })
+    }
+
+    fn command(&mut self, command: &Node) -> Result<(), SceneError> {
+        command.as_mapping()?;
+        if let Some(name) = command.get("define") {
+            self.define(name, command)
+        } else if let Some(what) = command.get("add") {
+            match what.as_str()? {
+                "camera" => self.add_camera(command),
+                "light" => self.add_light(command),
+                _ => {
+                    let item = self.build_item(command, None)?;
+                    self.world.add_item(item);
+                    Ok(())
+                }
+            }
+        } else {
+            Err(command.error("expected `add` or `define`"))
+        }
+    }
+
+    fn define(&mut self, name: &Node, command: &Node) -> Result<(), SceneError> {
+        check_keys(command, "define", &["define", "extend", "value"])?;
+        let mut value = required(command, "value")?.clone();
+        if let Some(base) = command.get("extend") {
+            value = merge(self.lookup(base)?, &value)?;
+        }
+        self.definitions.insert(name.as_str()?.to_string(), value);
+        Ok(())
+    }
+
+    fn lookup(&self, name: &Node) -> Result<&Node, SceneError> {
+        let key = name.as_str()?;
+        self.definitions
+            .get(key)
+            .ok_or_else(|| name.error(format!("`{}` has not been defined", key)))
+    }
+
+    fn add_camera(&mut self, node: &Node) -> Result<(), SceneError> {
+        check_keys(
+            node,
+            "camera",
+            &[
+                "add",
+                "width",
+                "height",
+                "projection",
+                "field-of-view",
+                "view-width",
+                "from",
+                "to",
+                "up",
+                "aperture",
+                "focal-distance",
+                "min-samples",
+                "allowed-error",
+                "progressive",
+            ],
+        )?;
+        if self.camera.is_some() {
+            return Err(node.error("the scene already has a camera"));
+        }
+
+        let from = to_point(required(node, "from")?)?;
+        let to = to_point(required(node, "to")?)?;
+        let up = to_vector(required(node, "up")?)?;
+
+        let width = required(node, "width")?.as_u32()?;
+        let height = required(node, "height")?.as_u32()?;
+        let projection = node
+            .get("projection")
+            .map(Node::as_str)
+            .unwrap_or(Ok("perspective"))?;
+        let camera = match projection {
+            "perspective" => Camera::new(width, height, required(node, "field-of-view")?.as_f64()?),
+            "orthographic" => {
+                let view_width = required(node, "view-width")?;
+                let value = view_width.as_f64()?;
+                if value <= 0.0 {
+                    return Err(view_width.error("`view-width` must be positive"));
+                }
+                Camera::orthographic(width, height, value)
+            }
+            other => {
+                return Err(node
+                    .get("projection")
+                    .unwrap()
+                    .error(format!("unknown projection `{}`", other)))
+            }
+        };
+        let mut camera = camera.with_view_transform(from, to, up);
+
+        if let Some(aperture) = node.get("aperture") {
+            camera.set_aperture_size(aperture.as_f64()?);
+            camera.set_focal_distance((to - from).len());
+        }
+        if let Some(distance) = node.get("focal-distance") {
+            camera.set_focal_distance(distance.as_f64()?);
+        }
+        if let Some(n) = node.get("min-samples") {
+            let n = n.as_u32()?;
+            camera.set_min_samples(n.min(u16::MAX as u32) as u16);
+        }
+        if let Some(se) = node.get("allowed-error") {
+            camera.set_allowed_standard_error(se.as_f64()?);
+        }
+        if let Some(progressive) = node.get("progressive") {
+            camera.set_progressive(progressive.as_bool()?);
+        }
+
+        self.camera = Some(camera);
+        Ok(())
+    }
+
+    fn add_light(&mut self, node: &Node) -> Result<(), SceneError> {
+        check_keys(
+            node,
+            "light",
+            &[
+                "add",
+                "type",
+                "at",
+                "intensity",
+                "radius",
+                "normal",
+                "up",
+                "right",
+            ],
+        )?;
+        let intensity = to_color(required(node, "intensity")?)?;
+        let kind = node.get("type").map(Node::as_str).unwrap_or(Ok("point"))?;
+        match kind {
+            "point" => self
+                .world
+                .add_light(PointLight::new(to_point(required(node, "at")?)?, intensity)),
+            "realistic" => self.world.add_light(RealisticPointLight::new(
+                to_point(required(node, "at")?)?,
+                intensity,
+            )),
+            "sphere" => self.world.add_light(SphereLight::new(
+                to_point(required(node, "at")?)?,
+                required(node, "radius")?.as_f64()?,
+                intensity,
+            )),
+            "disc" => self.world.add_light(DiscLight::new(
+                to_point(required(node, "at")?)?,
+                to_vector(required(node, "normal")?)?.normalized(),
+                required(node, "radius")?.as_f64()?,
+                intensity,
+            )),
+            "beam" => {
+                let up = to_vector(required(node, "up")?)?;
+                let right = to_vector(required(node, "right")?)?;
+                if up.dot(&right).abs() > 1e-6 {
+                    return Err(node.error("`up` and `right` of a beam must be orthogonal"));
+                }
+                self.world.add_light(Beam::new(
+                    to_point(required(node, "at")?)?,
+                    up,
+                    right,
+                    intensity,
+                ))
+            }
+            "ambient" => self.world.add_light(AmbientLight::new(intensity)),
+            _ => {
+                return Err(node
+                    .get("type")
+                    .unwrap()
+                    .error(format!("unknown light type `{}`", kind)))
+            }
+        }
+        Ok(())
+    }
+
+    /// Build an object. Objects without their own material inherit the material of the
+    /// enclosing group, if there is one.
+    fn build_item(&self, node: &Node, inherited: Option<&Phong>) -> Result<SceneItem, SceneError> {
+        node.as_mapping()?;
+        let what = required(node, "add")?;
+        let kind = what.as_str()?;
+
+        if let Some(definition) = self.definitions.get(kind) {
+            if definition.get("add").is_none() {
+                return Err(what.error(format!("`{}` does not define an object", kind)));
+            }
+            let instance = without_key(node, "add");
+            return self.build_item(&merge(definition, &instance)?, inherited);
+        }
+
+        let material = match node.get("material") {
+            Some(m) => Some(self.build_material(m)?),
+            None => inherited.cloned(),
+        };
+
+        const COMMON: &[&str] = &["add", "material", "transform", "shadow"];
+        let mut item: SceneItem = match kind {
+            "sphere" | "plane" | "cube" => {
+                check_keys(node, kind, COMMON)?;
+                match kind {
+                    "sphere" => sphere(),
+                    "plane" => plane(),
+                    _ => cube(),
+                }
+                .into()
+            }
+            "cylinder" | "cone" => {
+                check_keys(node, kind, &[COMMON, &["min", "max", "closed"]].concat())?;
+                let min = optional_f64(node, "min", f64::NEG_INFINITY)?;
+                let max = optional_f64(node, "max", f64::INFINITY)?;
+                let closed = match node.get("closed") {
+                    Some(c) => c.as_bool()?,
+                    None => false,
+                };
+                if kind == "cylinder" {
+                    Shape::new(Cylinder::new(min, max, closed)).into()
+                } else {
+                    Shape::new(Cone::new(min, max, closed)).into()
+                }
+            }
+            "triangle" => {
+                check_keys(node, kind, &[COMMON, &["p1", "p2", "p3"]].concat())?;
+                triangle(
+                    to_point(required(node, "p1")?)?,
+                    to_point(required(node, "p2")?)?,
+                    to_point(required(node, "p3")?)?,
+                )
+                .into()
+            }
+            "group" => {
+                check_keys(node, kind, &[COMMON, &["children"]].concat())?;
+                let mut group = Group::default();
+                if let Some(children) = node.get("children") {
+                    for child in children.as_sequence()? {
+                        group.add_child(self.build_item(child, material.as_ref())?);
+                    }
+                }
+                group.into()
+            }
+            "csg" => {
+                check_keys(
+                    node,
+                    kind,
+                    &[COMMON, &["operation", "left", "right"]].concat(),
+                )?;
+                let left = self.build_item(required(node, "left")?, material.as_ref())?;
+                let right = self.build_item(required(node, "right")?, material.as_ref())?;
+                let op = required(node, "operation")?;
+                match op.as_str()? {
+                    "union" => csg_union(left, right),
+                    "difference" => csg_difference(left, right),
+                    "intersection" => csg_intersection(left, right),
+                    other => return Err(op.error(format!("unknown CSG operation `{}`", other))),
+                }
+                .into()
+            }
+            "obj" => {
+                check_keys(node, kind, &[COMMON, &["file"]].concat())?;
+                let file = required(node, "file")?;
+                let path = self.base_dir.join(file.as_str()?);
+                let data = std::fs::read_to_string(&path).map_err(|e| {
+                    file.error(format!("unable to read `{}`: {}", path.display(), e))
+                })?;
+                let mut obj = ObjParser::parse_str(&data)
+                    .map_err(|e| file.error(format!("in `{}`, {}", path.display(), e)))?;
+
+                // material libraries and their textures are found relative to the files
+                // that name them
+                let obj_dir = path.parent().unwrap_or_else(|| Path::new(""));
+                let mut library = MaterialLibrary::new();
+                for mtl_file in obj.material_libraries() {
+                    let mtl_path = obj_dir.join(mtl_file);
+                    let mtl_dir = mtl_path.parent().unwrap_or_else(|| Path::new(""));
+                    let data = std::fs::read_to_string(&mtl_path).map_err(|e| {
+                        file.error(format!("unable to read `{}`: {}", mtl_path.display(), e))
+                    })?;
+                    let mtl = MaterialLibrary::parse_str(&data, |image| {
+                        Canvas::read_image(mtl_dir.join(image))
+                    })
+                    .map_err(|e| file.error(format!("in `{}`, {}", mtl_path.display(), e)))?;
+                    library.extend(mtl);
+                }
+                obj.set_materials(&library);
+
+                let mut group = obj.meshes();
+                if let Some(m) = &material {
+                    group.set_material(m.clone());
+                }
+                group.into()
+            }
+            "ply" | "stl" => {
+                check_keys(node, kind, &[COMMON, &["file"]].concat())?;
+                let file = required(node, "file")?;
+                let path = self.base_dir.join(file.as_str()?);
+                let data = std::fs::read(&path).map_err(|e| {
+                    file.error(format!("unable to read `{}`: {}", path.display(), e))
+                })?;
+                let in_file = |e: &dyn std::fmt::Display| {
+                    file.error(format!("in `{}`, {}", path.display(), e))
+                };
+                let mut group = if kind == "ply" {
+                    PlyParser::parse_bytes(&data)
+                        .map_err(|e| in_file(&e))?
+                        .meshes()
+                } else {
+                    StlParser::parse_bytes(&data)
+                        .map_err(|e| in_file(&e))?
+                        .meshes()
+                };
+                if let Some(m) = &material {
+                    group.set_material(m.clone());
+                }
+                group.into()
+            }
+            _ => return Err(what.error(format!("unknown object type `{}`", kind))),
+        };
+
+        if let (SceneItem::Primitive(shape), Some(m)) = (&mut item, material) {
+            shape.set_material(m);
+        }
+
+        if let Some(t) = node.get("transform") {
+            item = item.with_transform(self.build_transform(t)?);
+        }
+
+        if let Some(shadow) = node.get("shadow") {
+            item.set_cast_shadow(shadow.as_bool()?);
+        }
+
+        Ok(item)
+    }
+
+    fn build_material(&self, node: &Node) -> Result<Phong, SceneError> {
+        let node = match node.value {
+            Value::Scalar(_) => self.lookup(node)?,
+            _ => node,
+        };
+        check_keys(
+            node,
+            "material",
+            &[
+                "color",
+                "pattern",
+                "ambient",
+                "emissive",
+                "diffuse",
+                "specular",
+                "shininess",
+                "reflective",
+                "transparency",
+                "refractive-index",
+            ],
+        )?;
+
+        let aliases: Vec<&Node> = node
+            .as_mapping()?
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| {
+                EMISSIVE_KEYS
+                    .iter()
+                    .any(|&k| key.value == Value::Scalar(k.into()))
+            })
+            .collect();
+        if let [_, second, ..] = aliases[..] {
+            return Err(second.error("`ambient` and `emissive` are the same attribute"));
+        }
+
+        let mut material = Phong::default();
+        if let Some(c) = node.get("color") {
+            material.set_color(to_color(c)?);
+        }
+        if let Some(p) = node.get("pattern") {
+            material.set_pattern(self.build_pattern(p)?);
+        }
+        if let Some(x) = node.get("ambient").or_else(|| node.get("emissive")) {
+            material.set_emissive(x.as_f64()?);
+        }
+        if let Some(x) = node.get("diffuse") {
+            material.set_diffuse(x.as_f64()?);
+        }
+        if let Some(x) = node.get("specular") {
+            material.set_specular(x.as_f64()?);
+        }
+        if let Some(x) = node.get("shininess") {
+            material.set_shininess(x.as_f64()?);
+        }
+        if let Some(x) = node.get("reflective") {
+            material.set_reflective(x.as_f64()?);
+        }
+        if let Some(x) = node.get("transparency") {
+            material.set_transparency(x.as_f64()?);
+        }
+        if let Some(x) = node.get("refractive-index") {
+            material.set_refractive_index(x.as_f64()?);
+        }
+        Ok(material)
+    }
+
+    fn build_pattern(&self, node: &Node) -> Result<Pattern, SceneError> {
+        check_keys(node, "pattern", &["type", "colors", "transform"])?;
+        let colors = required(node, "colors")?;
+        let (a, b) = match colors.as_sequence()? {
+            [a, b] => (to_color(a)?, to_color(b)?),
+            _ => return Err(colors.error("expected a list of two colors")),
+        };
+        let kind = required(node, "type")?;
+        let mut pattern = match kind.as_str()? {
+            "stripes" | "stripe" => stripe_pattern(a, b),
+            "gradient" => gradient_pattern(a, b),
+            "rings" | "ring" => ring_pattern(a, b),
+            "checkers" => checkers_pattern(a, b),
+            other => return Err(kind.error(format!("unknown pattern type `{}`", other))),
+        };
+        if let Some(t) = node.get("transform") {
+            pattern.set_transform(self.build_transform(t)?);
+        }
+        Ok(pattern)
+    }
+
+    fn build_transform(&self, node: &Node) -> Result<Matrix, SceneError> {
+        let mut transform = Matrix::identity();
+        for step in node.as_sequence()? {
+            let m = match step.value {
+                Value::Scalar(_) => self.build_transform(self.lookup(step)?)?,
+                _ => build_transform_step(step)?,
+            };
+            transform = m * transform;
+        }
+        Ok(transform)
+    }
+}
+
+fn build_transform_step(step: &Node) -> Result<Matrix, SceneError> {
+    let items = step.as_sequence()?;
+    let op = items
+        .first()
+        .ok_or_else(|| step.error("expected a transform"))?;
+    let args = items[1..]
+        .iter()
+        .map(Node::as_f64)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let expect_args = |n: usize| {
+        if args.len() == n {
+            Ok(())
+        } else {
+            Err(step.error(format!("`{}` takes {} arguments", op.as_str()?, n)))
+        }
+    };
+
+    match op.as_str()? {
+        "translate" => expect_args(3).map(|_| translation(args[0], args[1], args[2])),
+        "scale" => expect_args(3).map(|_| scaling(args[0], args[1], args[2])),
+        "rotate-x" => expect_args(1).map(|_| rotation_x(args[0])),
+        "rotate-y" => expect_args(1).map(|_| rotation_y(args[0])),
+        "rotate-z" => expect_args(1).map(|_| rotation_z(args[0])),
+        "shear" => {
+            expect_args(6).map(|_| shearing(args[0], args[1], args[2], args[3], args[4], args[5]))
+        }
+        other => Err(op.error(format!("unknown transform `{}`", other))),
+    }
+}
+
+fn required<'n>(node: &'n Node, key: &str) -> Result<&'n Node, SceneError> {
+    node.get(key)
+        .ok_or_else(|| node.error(format!("missing attribute `{}`", key)))
+}
+
+fn optional_f64(node: &Node, key: &str, default: f64) -> Result<f64, SceneError> {
+    node.get(key).map(Node::as_f64).unwrap_or(Ok(default))
+}
+
+fn check_keys(node: &Node, what: &str, allowed: &[&str]) -> Result<(), SceneError> {
+    for (key, _) in node.as_mapping()? {
+        let name = key.as_str()?;
+        if !allowed.contains(&name) {
+            return Err(key.error(format!("unknown {} attribute `{}`", what, name)));
+        }
+    }
+    Ok(())
+}
+
+/// The names of the emissive term of materials
+const EMISSIVE_KEYS: [&str; 2] = ["ambient", "emissive"];
+
+/// Whether two keys name the same attribute
+fn same_key(a: &Node, b: &Node) -> bool {
+    let emissive = |key: &Node| {
+        EMISSIVE_KEYS
+            .iter()
+            .any(|&k| key.value == Value::Scalar(k.into()))
+    };
+    a.value == b.value || (emissive(a) && emissive(b))
+}
+
+/// Combine two mappings; entries in `overrides` replace entries with the same key in `base`.
+fn merge(base: &Node, overrides: &Node) -> Result<Node, SceneError> {
+    let mut entries = base.as_mapping()?.to_vec();
+    for (key, value) in overrides.as_mapping()? {
+        match entries.iter_mut().find(|(k, _)| same_key(k, key)) {
+            Some(entry) => *entry = (key.clone(), value.clone()),
+            None => entries.push((key.clone(), value.clone())),
+        }
+    }
+    Ok(Node::new(overrides.pos, Value::Mapping(entries)))
+}
+
+fn without_key(node: &Node, key: &str) -> Node {
+    let entries = match &node.value {
+        Value::Mapping(entries) => entries
+            .iter()
+            .filter(|(k, _)| k.value != Value::Scalar(key.to_string()))
+            .cloned()
+            .collect(),
+        _ => vec![],
+    };
+    Node::new(node.pos, Value::Mapping(entries))
+}
+
+fn to_point(node: &Node) -> Result<Point, SceneError> {
+    let [x, y, z] = node.as_triple()?;
+    Ok(point(x, y, z))
+}
+
+fn to_vector(node: &Node) -> Result<Vector, SceneError> {
+    let [x, y, z] = node.as_triple()?;
+    Ok(vector(x, y, z))
+}
+
+fn to_color(node: &Node) -> Result<Color, SceneError> {
+    let [r, g, b] = node.as_triple()?;
+    Ok(color(r, g, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+    use crate::camera::Projection;
+    use crate::scene::Position;
+    use std::f64::consts::PI;
+
+    const CAMERA: &str = "\
+- add: camera
+  width: 11
+  height: 11
+  field-of-view: 1.5707963
+  from: [0, 0, -5]
+  to: [0, 0, 0]
+  up: [0, 1, 0]
+";
+
+    fn parse(input: &str) -> Result<Scene, SceneError> {
+        parse_yaml_scene(input, Path::new("."))
+    }
+
+    fn error_at(input: &str) -> Position {
+        match parse(input) {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.position().unwrap(),
+        }
+    }
+
+    /// The camera is configured from the scene file
+    #[test]
+    fn camera() {
+        let scene = parse(CAMERA).unwrap();
+        assert_eq!(scene.camera.hsize(), 11);
+        assert_eq!(scene.camera.vsize(), 11);
+        assert_almost_eq!(scene.camera.field_of_view(), PI / 2.0);
+        assert_almost_eq!(
+            scene.camera.transform(),
+            Matrix::view(point(0, 0, -5), point(0, 0, 0), vector(0, 1, 0))
+        );
+    }
+
+    /// Orthographic cameras have a view width instead of a field of view
+    #[test]
+    fn orthographic_camera() {
+        let input = CAMERA.replace(
+            "field-of-view: 1.5707963",
+            "projection: orthographic\n  view-width: 4",
+        );
+        let scene = parse(&input).unwrap();
+        assert_eq!(scene.camera.projection(), Projection::Orthographic);
+        assert_almost_eq!(scene.camera.view_width(), 4.0);
+
+        let input = CAMERA.replace("field-of-view: 1.5707963", "projection: fisheye");
+        assert_eq!(error_at(&input), Position::new(4, 15));
+        for width in &["0", "-2"] {
+            let input = CAMERA.replace(
+                "field-of-view: 1.5707963",
+                &format!("projection: orthographic\n  view-width: {}", width),
+            );
+            assert_eq!(error_at(&input), Position::new(5, 15));
+        }
+    }
+
+    /// A scene file describing the default world renders like the default world
+    #[test]
+    fn default_world() {
+        let input = format!(
+            "{}
+- add: light
+  at: [-10, 10, -10]
+  intensity: [1, 1, 1]
+
+- define: outer
+  value:
+    color: [0.8, 1.0, 0.6]
+    ambient: 0.1
+    diffuse: 0.7
+    specular: 0.2
+
+- add: sphere
+  material: outer
+
+- add: sphere
+  material:
+    ambient: 0.1
+  transform:
+    - [scale, 0.5, 0.5, 0.5]
+",
+            CAMERA
+        );
+        let mut scene = parse(&input).unwrap();
+        scene.camera.set_min_samples(1);
+        scene.camera.set_allowed_standard_error(1.0);
+        let image = scene.render();
+        assert_almost_eq!(image.get_pixel(5, 5), color(0.38066, 0.47583, 0.2855));
+    }
+
+    /// Transforms are applied in the order they are listed
+    #[test]
+    fn transform_order() {
+        let input = "\
+- define: move
+  value:
+    - [translate, 1, 0, 0]
+- t:
+    - [scale, 2, 2, 2]
+    - move
+    - [rotate-y, 1.5]";
+        let document = yaml::parse(input).unwrap();
+        let mut builder = SceneBuilder::new(Path::new("."));
+        builder
+            .command(&document.as_sequence().unwrap()[0])
+            .unwrap();
+        let t = builder
+            .build_transform(document.as_sequence().unwrap()[1].get("t").unwrap())
+            .unwrap();
+        assert_almost_eq!(t, rotation_y(1.5) * translation(1, 0, 0) * scaling(2, 2, 2));
+    }
+
+    /// Definitions can extend earlier definitions
+    #[test]
+    fn extend_definition() {
+        let input = "\
+- define: base
+  value:
+    color: [1, 0, 0]
+    diffuse: 0.2
+    ambient: 0.3
+- define: derived
+  extend: base
+  value:
+    diffuse: 0.5
+    emissive: 0.1";
+        let document = yaml::parse(input).unwrap();
+        let mut builder = SceneBuilder::new(Path::new("."));
+        for command in document.as_sequence().unwrap() {
+            builder.command(command).unwrap();
+        }
+        let name = Node::new(Position::new(1, 1), Value::Scalar("derived".into()));
+        let m = builder.build_material(&name).unwrap();
+        assert_almost_eq!(
+            m,
+            Phong::default()
+                .with_rgb(1.0, 0.0, 0.0)
+                .with_diffuse(0.5)
+                .with_emissive(0.1)
+        );
+    }
+
+    /// `ambient` and `emissive` name the same attribute, so a material takes one of them
+    #[test]
+    fn ambient_and_emissive() {
+        let input = format!(
+            "{}
+- add: sphere
+  material:
+    emissive: 0.5
+    diffuse: 0.2
+    ambient: 0.1
+",
+            CAMERA
+        );
+        assert_eq!(error_at(&input), Position::new(13, 5));
+    }
+
+    /// Groups, CSG and defined objects are built recursively
+    #[test]
+    fn nested_objects() {
+        let input = format!(
+            "{}
+- define: unit-cube
+  value:
+    add: cube
+    material:
+      color: [0, 0, 1]
+
+- add: group
+  transform:
+    - [translate, 0, 1, 0]
+  children:
+    - add: unit-cube
+    - add: csg
+      operation: difference
+      left:
+        add: cylinder
+        min: 0
+        max: 1
+        closed: true
+      right:
+        add: sphere
+",
+            CAMERA
+        );
+        let scene = parse(&input).unwrap();
+        let r = crate::ray::Ray::new(point(0, 1, -5), vector(0, 0, 1));
+        let xs = scene.world.intersect(&r);
+        assert_eq!(xs.len(), 2);
+        assert_almost_eq!(xs[0].t, 4.0);
+        assert_almost_eq!(
+            xs[0]
+                .obj
+                .material()
+                .color_at(&xs[0].prepare_computations(&r, &xs)),
+            color(0, 0, 1)
+        );
+    }
+
+    /// OBJ files are loaded with the materials and textures of their material libraries
+    #[test]
+    fn obj_with_materials() {
+        let dir = std::env::temp_dir().join(format!("raytracing-obj-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("materials")).unwrap();
+        let write = |file: &str, data: &str| std::fs::write(dir.join(file), data).unwrap();
+        write(
+            "quad.obj",
+            "mtllib materials/quad.mtl
+             v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1
+             usemtl checker
+             f 1/1 2/2 3/3
+             usemtl red
+             f 1 3 4",
+        );
+        write(
+            "materials/quad.mtl",
+            "newmtl checker\nmap_Kd texture.ppm\nnewmtl red\nKd 1 0 0",
+        );
+        write("materials/texture.ppm", "P3 2 1 255 255 255 255 0 0 255");
+
+        let scene = parse_yaml_scene(&format!("{}- add: obj\n  file: quad.obj", CAMERA), &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let scene = scene.unwrap();
+
+        let color_at = |x, y| {
+            let r = crate::ray::Ray::new(point(x, y, -5), vector(0, 0, 1));
+            let xs = scene.world.intersect(&r);
+            let comps = xs[0].prepare_computations(&r, &xs);
+            comps.obj.material().color_at(&comps)
+        };
+        assert_almost_eq!(color_at(0.5, -0.5), color(0, 0, 1));
+        assert_almost_eq!(color_at(-0.5, 0.5), color(1, 0, 0));
+
+        let missing = parse(&format!("{}- add: obj\n  file: missing.obj", CAMERA));
+        assert!(missing.is_err());
+    }
+
+    /// PLY and STL files are loaded as meshes, with the vertex colors of PLY files
+    #[test]
+    fn ply_and_stl() {
+        let dir = std::env::temp_dir().join(format!("raytracing-ply-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |file: &str, data: &str| std::fs::write(dir.join(file), data).unwrap();
+        write(
+            "tri.ply",
+            "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\n\
+             property float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element face 1\nproperty list uchar int vertex_indices\nend_header\n\
+             -1 -1 0 255 0 0\n1 -1 0 255 0 0\n0 1 0 255 0 0\n3 0 1 2\n",
+        );
+        write(
+            "tri.stl",
+            "solid\nfacet normal 0 0 1\nouter loop\n\
+             vertex -1 -1 0\nvertex 1 -1 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid\n",
+        );
+        write("broken.stl", "solid\nfacet normal 0 0 1\n");
+
+        let load = |kind: &str| {
+            parse_yaml_scene(
+                &format!("{}- add: {}\n  file: tri.{}", CAMERA, kind, kind),
+                &dir,
+            )
+        };
+        let (ply, stl) = (load("ply"), load("stl"));
+        let broken = parse_yaml_scene(&format!("{}- add: stl\n  file: broken.stl", CAMERA), &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let r = crate::ray::Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let ply = ply.unwrap();
+        let xs = ply.world.intersect(&r);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert_almost_eq!(comps.obj.material().color_at(&comps), color(1, 0, 0));
+        let stl = stl.unwrap();
+        let xs = stl.world.intersect(&r);
+        assert_almost_eq!(xs[0].t, 5.0);
+        assert!(broken.is_err());
+    }
+
+    /// Errors point to the offending part of the file
+    #[test]
+    fn error_positions() {
+        assert_eq!(
+            error_at("- add: light\n  at: [1, 2]\n  intensity: [1, 1, 1]"),
+            Position::new(2, 7)
+        );
+        assert_eq!(error_at("- add: teapot"), Position::new(1, 8));
+        assert_eq!(
+            error_at("- add: sphere\n  material: chrome"),
+            Position::new(2, 13)
+        );
+        assert_eq!(
+            error_at("- add: sphere\n  colour: [1, 1, 1]"),
+            Position::new(2, 3)
+        );
+        assert_eq!(
+            error_at("- add: sphere\n  transform:\n    - [spin, 1]"),
+            Position::new(3, 8)
+        );
+        assert_eq!(error_at("- add: sphere"), Position::new(1, 1));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::aabb::Aabb;
+use crate::materials::Material;
 use crate::matrix::Matrix;
 use crate::ray::{sort_intersections, Intersection, Ray};
 use crate::shapes::{SceneItem, Shape};
@@ -79,6 +80,12 @@ impl CsgPair {
         self.items.1.update_transform(self.cumulative_transform);
     }
 
+    /// Make the cumulative transform the pair's own, so that it stays in place when it is
+    /// taken out of its group.
+    pub(crate) fn bake_transform(&mut self) {
+        self.transform = self.cumulative_transform;
+    }
+
     pub fn update_aabb(&mut self) -> Aabb {
         self.items
             .0
@@ -94,6 +101,15 @@ impl CsgPair {
         self.cast_shadow
     }
 
+    pub fn set_cast_shadow(&mut self, b: bool) {
+        self.cast_shadow = b
+    }
+
+    pub fn set_material(&mut self, material: impl Material + Clone) {
+        self.items.0.set_material(material.clone());
+        self.items.1.set_material(material);
+    }
+
     pub fn filter_intersections<'a>(&self, xs: Vec<Intersection<'a>>) -> Vec<Intersection<'a>> {
         let mut inl = false;
         let mut inr = false;
@@ -126,6 +142,11 @@ impl CsgPair {
     pub fn contains(&self, shape: &Shape) -> bool {
         self.items.0.contains(shape) || self.items.1.contains(shape)
     }
+
+    pub fn for_each_shape<'a>(&'a self, f: &mut dyn FnMut(&'a Shape)) {
+        self.items.0.for_each_shape(f);
+        self.items.1.for_each_shape(f);
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,355 @@
+use crate::aabb::Aabb;
+use crate::approx_eq::ApproximateEq;
+use crate::bvh::Bvh;
+use crate::color::Color;
+use crate::ray::{Intersection, Ray};
+use crate::shapes::triangle::{hit_triangle, interpolate_uv};
+use crate::shapes::{Geometry, Group, Shape, SmoothTriangle, Triangle};
+use crate::tuple::{Point, Vector};
+use std::any::Any;
+
+/// Maximum number of triangles in a leaf of the internal hierarchy
+const MAX_LEAF_SIZE: usize = 4;
+
+pub fn mesh(vertices: Vec<Point>, triangles: Vec<[u32; 3]>) -> Shape {
+    Shape::new(Mesh::new(vertices, triangles))
+}
+
+/// A triangle mesh with shared vertex data. Every triangle is three indices into the vertex
+/// arrays, so a vertex used by several triangles is stored once. Intersections report the
+/// index of the triangle in `Intersection::i` and the barycentric coordinates of the hit in
+/// `u` and `v`.
+#[derive(Debug, Clone)]
+pub struct Mesh {
+    vertices: Vec<Point>,
+    /// One normal per vertex, or none for flat shading
+    normals: Vec<Vector>,
+    /// One pair of texture coordinates per vertex, or none
+    texture_coords: Vec<(f64, f64)>,
+    /// One color per vertex, or none
+    colors: Vec<Color>,
+    triangles: Vec<[u32; 3]>,
+    bvh: Bvh,
+    aabb: Aabb,
+}
+
+impl Mesh {
+    /// Panics if a triangle refers to a vertex that does not exist.
+    pub fn new(vertices: Vec<Point>, triangles: Vec<[u32; 3]>) -> Self {
+        let aabbs: Vec<_> = triangles
+            .iter()
+            .map(|tri| {
+                let [a, b, c] = corners(&vertices, tri);
+                Aabb::empty_at(a).extend(b).extend(c)
+            })
+            .collect();
+        let aabb = aabbs.iter().fold(Aabb::empty(), |acc, bb| acc.merge(bb));
+        let bvh = Bvh::build(&aabbs, MAX_LEAF_SIZE);
+        Mesh {
+            vertices,
+            normals: vec![],
+            texture_coords: vec![],
+            colors: vec![],
+            triangles,
+            bvh,
+            aabb,
+        }
+    }
+
+    /// Shade the mesh smoothly by interpolating the given vertex normals across each triangle.
+    pub fn with_normals(self, normals: Vec<Vector>) -> Self {
+        assert_eq!(
+            normals.len(),
+            self.vertices.len(),
+            "a mesh needs one normal per vertex"
+        );
+        Mesh { normals, ..self }
+    }
+
+    /// Assign texture coordinates to the vertices, which are interpolated across each triangle.
+    pub fn with_texture_coords(self, texture_coords: Vec<(f64, f64)>) -> Self {
+        assert_eq!(
+            texture_coords.len(),
+            self.vertices.len(),
+            "a mesh needs one pair of texture coordinates per vertex"
+        );
+        Mesh {
+            texture_coords,
+            ..self
+        }
+    }
+
+    /// Assign colors to the vertices, which are interpolated across each triangle and tint the
+    /// color of the material.
+    pub fn with_colors(self, colors: Vec<Color>) -> Self {
+        assert_eq!(
+            colors.len(),
+            self.vertices.len(),
+            "a mesh needs one color per vertex"
+        );
+        Mesh { colors, ..self }
+    }
+
+    pub fn vertices(&self) -> &[Point] {
+        &self.vertices
+    }
+
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    /// A group with a separate shape for every triangle. Vertex colors are not kept.
+    pub fn to_triangles(&self) -> Group {
+        let mut group = Group::default();
+        for tri in &self.triangles {
+            let [p1, p2, p3] = corners(&self.vertices, tri);
+            let uvs = if self.texture_coords.is_empty() {
+                None
+            } else {
+                Some(corners(&self.texture_coords, tri))
+            };
+            let shape = if self.normals.is_empty() {
+                let t = Triangle::new(p1, p2, p3);
+                match uvs {
+                    Some(uvs) => Shape::new(t.with_texture_coords(uvs)),
+                    None => Shape::new(t),
+                }
+            } else {
+                let [n1, n2, n3] = corners(&self.normals, tri);
+                let t = SmoothTriangle::new(p1, p2, p3, n1, n2, n3);
+                match uvs {
+                    Some(uvs) => Shape::new(t.with_texture_coords(uvs)),
+                    None => Shape::new(t),
+                }
+            };
+            group.add_child(shape);
+        }
+        group
+    }
+
+    fn hit(&self, idx: usize, ray: &Ray) -> Option<(f64, f64, f64)> {
+        let [p1, p2, p3] = corners(&self.vertices, &self.triangles[idx]);
+        hit_triangle(p1, p2 - p1, p3 - p1, ray)
+    }
+}
+
+fn corners<T: Copy>(data: &[T], tri: &[u32; 3]) -> [T; 3] {
+    [
+        data[tri[0] as usize],
+        data[tri[1] as usize],
+        data[tri[2] as usize],
+    ]
+}
+
+impl Geometry for Mesh {
+    fn duplicate(&self) -> Box<dyn Geometry> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn is_similar(&self, other: &dyn Geometry) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Mesh>()
+            .map(|o| self.approx_eq(o))
+            .unwrap_or(false)
+    }
+
+    fn intersect<'a>(&self, obj: &'a Shape, local_ray: &Ray) -> Vec<Intersection<'a>> {
+        let mut xs = vec![];
+        self.bvh.traverse(local_ray, |idx| {
+            if let Some((t, u, v)) = self.hit(idx, local_ray) {
+                xs.push(Intersection::new_uv(t, u, v, obj).with_i(idx));
+            }
+        });
+        xs
+    }
+
+    fn normal_at(&self, _: Point, hit: &Intersection) -> Vector {
+        let tri = &self.triangles[hit.i];
+        if self.normals.is_empty() {
+            let [p1, p2, p3] = corners(&self.vertices, tri);
+            (p3 - p1).cross(&(p2 - p1)).normalized()
+        } else {
+            let [n1, n2, n3] = corners(&self.normals, tri);
+            (n2 * hit.u + n3 * hit.v + n1 * (1.0 - hit.u - hit.v)).normalized()
+        }
+    }
+
+    fn texture_coords(&self, hit: &Intersection) -> Option<(f64, f64)> {
+        if self.texture_coords.is_empty() {
+            return None;
+        }
+        let uvs = corners(&self.texture_coords, &self.triangles[hit.i]);
+        Some(interpolate_uv(&uvs, hit))
+    }
+
+    fn vertex_color(&self, hit: &Intersection) -> Option<Color> {
+        if self.colors.is_empty() {
+            return None;
+        }
+        let [c1, c2, c3] = corners(&self.colors, &self.triangles[hit.i]);
+        Some(c2 * hit.u + c3 * hit.v + c1 * (1.0 - hit.u - hit.v))
+    }
+
+    fn occludes(&self, _: &Shape, local_ray: &Ray, t_max: f64) -> bool {
+        self.bvh.any(
+            local_ray,
+            t_max,
+            |idx| matches!(self.hit(idx, local_ray), Some((t, _, _)) if t >= 0.0 && t < t_max),
+        )
+    }
+
+    fn aabb(&self) -> Aabb {
+        self.aabb.clone()
+    }
+}
+
+impl ApproximateEq for Mesh {
+    fn approx_eq(&self, other: &Mesh) -> bool {
+        self.triangles == other.triangles
+            && self.vertices.approx_eq(&other.vertices)
+            && self.normals.approx_eq(&other.normals)
+            && self.colors.approx_eq(&other.colors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::{sphere, triangle, Triangle};
+    use crate::tuple::{point, vector};
+
+    /// A unit square in the xy plane, split into two triangles
+    fn square() -> Mesh {
+        Mesh::new(
+            vec![
+                point(0, 0, 0),
+                point(1, 0, 0),
+                point(1, 1, 0),
+                point(0, 1, 0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+    }
+
+    /// A ray reports the triangle it hit and the barycentric coordinates
+    #[test]
+    fn intersect() {
+        let m = square();
+        let dummy_shape = sphere();
+        let r = Ray::new(point(0.2, 0.6, -2), vector(0, 0, 1));
+        let xs = m.intersect(&dummy_shape, &r);
+        assert_eq!(xs.len(), 1);
+        assert_almost_eq!(xs[0].t, 2.0);
+        assert_eq!(xs[0].i, 1);
+        assert_almost_eq!(xs[0].u, 0.2);
+        assert_almost_eq!(xs[0].v, 0.4);
+
+        let r = Ray::new(point(1.2, 0.6, -2), vector(0, 0, 1));
+        assert!(m.intersect(&dummy_shape, &r).is_empty());
+    }
+
+    /// Without vertex normals the mesh is flat shaded like a triangle
+    #[test]
+    fn flat_normal() {
+        let m = square();
+        let dummy_shape = sphere();
+        let i = Intersection::new_uv(1.0, 0.3, 0.3, &dummy_shape).with_i(1);
+        let tri = Triangle::new(point(0, 0, 0), point(1, 1, 0), point(0, 1, 0));
+        let expected = tri.normal_at(point(0, 0, 0), &i);
+        assert_almost_eq!(m.normal_at(point(0, 0, 0), &i), expected);
+        assert_almost_eq!(expected, vector(0, 0, -1));
+    }
+
+    /// Vertex normals and texture coordinates are interpolated across the triangle that was hit
+    #[test]
+    fn interpolate_vertex_data() {
+        let m = square()
+            .with_normals(vec![
+                vector(0, 0, -1),
+                vector(1, 0, 0),
+                vector(0, 0, -1),
+                vector(0, 1, 0),
+            ])
+            .with_texture_coords(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        let dummy_shape = sphere();
+        let i = Intersection::new_uv(1.0, 0.5, 0.0, &dummy_shape).with_i(0);
+        assert_almost_eq!(
+            m.normal_at(point(0, 0, 0), &i),
+            vector(0.5, 0, -0.5).normalized()
+        );
+        let (u, v) = m.texture_coords(&i).unwrap();
+        assert_almost_eq!(u, 0.5);
+        assert_almost_eq!(v, 0.0);
+
+        let i = i.with_i(1);
+        assert_almost_eq!(
+            m.normal_at(point(0, 0, 0), &i),
+            vector(0, 0, -1).normalized()
+        );
+        let (u, v) = m.texture_coords(&i).unwrap();
+        assert_almost_eq!(u, 0.5);
+        assert_almost_eq!(v, 0.5);
+        assert_eq!(square().texture_coords(&i), None);
+    }
+
+    /// The mesh finds the same hits as separate triangles, and answers shadow queries
+    #[test]
+    fn matches_separate_triangles() {
+        let n = 8;
+        let mut vertices = vec![];
+        for j in 0..=n {
+            for i in 0..=n {
+                let (x, y) = (i as f64 / n as f64, j as f64 / n as f64);
+                vertices.push(point(x, y, (x * 3.0).sin() * (y * 2.0).cos()));
+            }
+        }
+        let mut triangles = vec![];
+        for j in 0..n {
+            for i in 0..n {
+                let k = (j * (n + 1) + i) as u32;
+                let row = n as u32 + 1;
+                triangles.push([k, k + 1, k + row + 1]);
+                triangles.push([k, k + row + 1, k + row]);
+            }
+        }
+        let separate: Vec<_> = triangles
+            .iter()
+            .map(|tri| {
+                let [a, b, c] = corners(&vertices, tri);
+                triangle(a, b, c)
+            })
+            .collect();
+        let m = mesh(vertices, triangles);
+
+        for k in 0..50 {
+            let (x, y) = ((k * 7 % 50) as f64 / 45.0, (k * 13 % 50) as f64 / 45.0);
+            let r = Ray::new(point(x, y, -3), vector(0.1, -0.05, 1));
+            let mut expected: Vec<_> = separate
+                .iter()
+                .enumerate()
+                .flat_map(|(idx, s)| s.local_intersect(&r).into_iter().map(move |i| (idx, i.t)))
+                .collect();
+            let mut actual: Vec<_> = m
+                .local_intersect(&r)
+                .into_iter()
+                .map(|i| (i.i, i.t))
+                .collect();
+            expected.sort_by_key(|&(idx, _)| idx);
+            actual.sort_by_key(|&(idx, _)| idx);
+            assert_eq!(actual.len(), expected.len());
+            for ((ia, ta), (ie, te)) in actual.into_iter().zip(expected) {
+                assert_eq!(ia, ie);
+                assert_almost_eq!(ta, te);
+            }
+
+            let hit = m.local_intersect(&r).iter().any(|i| i.t >= 0.0);
+            assert_eq!(m.occludes(&r, 10.0), hit);
+            assert!(!m.occludes(&r, 1.0));
+        }
+    }
+}
@@ -2,23 +2,25 @@ mod cone;
 mod csg;
 mod cube;
 mod cylinder;
+mod mesh;
 pub mod planar_heightmap;
 mod plane;
 mod sphere;
 mod triangle;
 
-pub use cone::cone;
+pub use cone::{cone, Cone};
 pub use csg::{csg_difference, csg_intersection, csg_union, CsgOp, CsgPair};
 pub use cube::cube;
 pub use cylinder::{cylinder, Cylinder};
+pub use mesh::{mesh, Mesh};
 pub use planar_heightmap::planar_heightmap;
 pub use plane::plane;
 pub use sphere::{glass_sphere, sphere};
-pub use triangle::{smooth_triangle, triangle, Triangle, TriangleMesh};
+pub use triangle::{smooth_triangle, triangle, SmoothTriangle, Triangle};
 
 use crate::aabb::Aabb;
 use crate::approx_eq::ApproximateEq;
-use crate::color::Color;
+use crate::color::{Color, BLACK, WHITE};
 use crate::materials::{Material, Phong};
 use crate::matrix::Matrix;
 use crate::pattern::Pattern;
@@ -42,6 +44,30 @@ pub trait Geometry: 'static + std::fmt::Debug + Sync {
     fn normal_at(&self, local_point: Point, i: &Intersection) -> Vector;
     fn aabb(&self) -> Aabb;
 
+    /// The name of the geometry type, like `Sphere`
+    fn type_name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
+    /// The texture coordinates at an intersection, if the geometry has any.
+    fn texture_coords(&self, _i: &Intersection) -> Option<(f64, f64)> {
+        None
+    }
+
+    /// The color interpolated from per-vertex colors at an intersection, if the geometry has any.
+    fn vertex_color(&self, _i: &Intersection) -> Option<Color> {
+        None
+    }
+
+    /// Whether the ray hits the geometry at a distance in `[0, t_max)`. Shadow rays only need
+    /// to know that there is a blocker, which can be cheaper than finding all intersections.
+    fn occludes(&self, obj: &Shape, local_ray: &Ray, t_max: f64) -> bool {
+        self.intersect(obj, local_ray)
+            .iter()
+            .any(|i| blocks(i, t_max))
+    }
+
     fn contains(&self, _shape: &Shape) -> bool {
         false
     }
@@ -112,6 +138,58 @@ impl SceneItem {
         }
     }
 
+    /// Append the intersections with the ray to `xs`, without sorting them.
+    pub fn intersect_into<'a>(&'a self, world_ray: &Ray, xs: &mut Vec<Intersection<'a>>) {
+        match self {
+            SceneItem::Primitive(shape) => xs.extend(shape.intersect(world_ray)),
+            SceneItem::Compound(group) => group.intersect_into(world_ray, xs),
+            SceneItem::Bounded(group) => group.intersect_into(world_ray, xs),
+            SceneItem::CsgPair(pair) => xs.extend(pair.intersect(world_ray)),
+        }
+    }
+
+    /// Whether a shadow casting part of the item is hit by the ray at a distance in
+    /// `[0, t_max)`. Returns as soon as any blocker is found.
+    pub fn occludes(&self, world_ray: &Ray, t_max: f64) -> bool {
+        if !self.cast_shadow() {
+            return false;
+        }
+        match self {
+            SceneItem::Primitive(shape) => shape.occludes(world_ray, t_max),
+            SceneItem::Compound(group) => group.occludes(world_ray, t_max),
+            SceneItem::Bounded(group) => group.occludes(world_ray, t_max),
+            SceneItem::CsgPair(pair) => pair.intersect(world_ray).iter().any(|i| blocks(i, t_max)),
+        }
+    }
+
+    /// The fraction of light that passes through the item along the ray up to `t_max`. Every
+    /// surface crossed lets through the `shadow_transmittance` of its material. Returns as soon
+    /// as an opaque blocker is found.
+    pub fn shadow_transmittance(&self, world_ray: &Ray, t_max: f64) -> Color {
+        if !self.cast_shadow() {
+            return WHITE;
+        }
+        match self {
+            SceneItem::Primitive(shape) => shape.shadow_transmittance(world_ray, t_max),
+            SceneItem::Compound(group) => group.shadow_transmittance(world_ray, t_max),
+            SceneItem::Bounded(group) => group.shadow_transmittance(world_ray, t_max),
+            SceneItem::CsgPair(pair) => {
+                let mut transmittance = WHITE;
+                for i in pair
+                    .intersect(world_ray)
+                    .iter()
+                    .filter(|i| blocks(i, t_max))
+                {
+                    transmittance = transmittance * i.obj.material().shadow_transmittance();
+                    if transmittance.is_black() {
+                        break;
+                    }
+                }
+                transmittance
+            }
+        }
+    }
+
     pub fn cast_shadow(&self) -> bool {
         match self {
             SceneItem::Primitive(shape) => shape.cast_shadow(),
@@ -121,6 +199,24 @@ impl SceneItem {
         }
     }
 
+    pub fn set_cast_shadow(&mut self, b: bool) {
+        match self {
+            SceneItem::Primitive(shape) => shape.set_cast_shadow(b),
+            SceneItem::Compound(group) => group.cast_shadow = b,
+            SceneItem::Bounded(bgroup) => bgroup.group.cast_shadow = b,
+            SceneItem::CsgPair(pair) => pair.set_cast_shadow(b),
+        }
+    }
+
+    pub fn set_material(&mut self, material: impl Material + Clone) {
+        match self {
+            SceneItem::Primitive(shape) => shape.set_material(material),
+            SceneItem::Compound(group) => group.set_material(material),
+            SceneItem::Bounded(bgroup) => bgroup.group.set_material(material),
+            SceneItem::CsgPair(pair) => pair.set_material(material),
+        }
+    }
+
     pub fn update_transform(&mut self, t: Matrix) {
         match self {
             SceneItem::Primitive(shape) => shape.update_transform(t),
@@ -166,6 +262,31 @@ impl SceneItem {
         }
     }
 
+    /// Dissolve all groups, moving the shapes and CSG pairs they contain to `items`. The
+    /// items keep their place in the world, so this must be called after `update_transform`.
+    /// Shapes in groups that do not cast shadows do not cast shadows themselves.
+    pub fn flatten_into(self, items: &mut Vec<SceneItem>) {
+        match self {
+            SceneItem::Primitive(mut shape) => {
+                shape.bake_transform();
+                items.push(shape.into());
+            }
+            SceneItem::CsgPair(mut pair) => {
+                pair.bake_transform();
+                items.push(pair.into());
+            }
+            SceneItem::Compound(group) | SceneItem::Bounded(BoundingGroup { group, .. }) => {
+                let cast_shadow = group.cast_shadow;
+                for mut child in group.items {
+                    if !cast_shadow {
+                        child.set_cast_shadow(false);
+                    }
+                    child.flatten_into(items);
+                }
+            }
+        }
+    }
+
     pub fn contains(&self, shape: &Shape) -> bool {
         match self {
             SceneItem::Primitive(s) => std::ptr::eq(s, shape) || s.geometry.contains(shape),
@@ -175,6 +296,24 @@ impl SceneItem {
             SceneItem::CsgPair(pair) => pair.contains(shape),
         }
     }
+
+    /// Call `f` for every shape in the item, in the order in which they were added.
+    pub fn for_each_shape<'a>(&'a self, f: &mut dyn FnMut(&'a Shape)) {
+        match self {
+            SceneItem::Primitive(shape) => f(shape),
+            SceneItem::Compound(group) | SceneItem::Bounded(BoundingGroup { group, .. }) => {
+                for item in &group.items {
+                    item.for_each_shape(f);
+                }
+            }
+            SceneItem::CsgPair(pair) => pair.for_each_shape(f),
+        }
+    }
+}
+
+/// Whether an intersection lies between a shadow ray's origin and the light at distance `t_max`
+fn blocks(i: &Intersection, t_max: f64) -> bool {
+    i.t >= 0.0 && i.t < t_max
 }
 
 pub fn is_group_similar_to_shape(g: &Group, s: &Shape) -> bool {
@@ -256,12 +395,42 @@ impl Shape {
         self.geometry.intersect(&self, local_ray)
     }
 
+    pub fn occludes(&self, world_ray: &Ray, t_max: f64) -> bool {
+        self.geometry
+            .occludes(self, &world_ray.transform(*self.inv_transform()), t_max)
+    }
+
+    /// Opaque shapes only need an occlusion test; transparent ones attenuate the light at
+    /// every surface crossed.
+    pub fn shadow_transmittance(&self, world_ray: &Ray, t_max: f64) -> Color {
+        let transmittance = self.material().shadow_transmittance();
+        if transmittance.is_black() {
+            return if self.occludes(world_ray, t_max) {
+                BLACK
+            } else {
+                WHITE
+            };
+        }
+        self.intersect(world_ray)
+            .iter()
+            .filter(|i| blocks(i, t_max))
+            .fold(WHITE, |c, _| c * transmittance)
+    }
+
     pub fn normal_at(&self, world_point: Point, i: &Intersection) -> Vector {
         let obj_point = self.world_to_object(world_point);
         let obj_normal = self.geometry.normal_at(obj_point, i);
         self.normal_to_world(obj_normal)
     }
 
+    pub fn texture_coords(&self, i: &Intersection) -> Option<(f64, f64)> {
+        self.geometry.texture_coords(i)
+    }
+
+    pub fn vertex_color(&self, i: &Intersection) -> Option<Color> {
+        self.geometry.vertex_color(i)
+    }
+
     pub fn pattern_at(&self, pattern: &Pattern, world_point: Point) -> Color {
         pattern.at(self.world_to_object(world_point))
     }
@@ -294,6 +463,10 @@ impl Shape {
         self.material = Box::new(material);
     }
 
+    pub fn geometry(&self) -> &dyn Geometry {
+        &*self.geometry
+    }
+
     pub fn inv_transform(&self) -> &Matrix {
         &self.inv_cumulative_transform
     }
@@ -310,6 +483,12 @@ impl Shape {
         self.geometry.update_transform(self.cumulative_transform);
     }
 
+    /// Make the cumulative transform the shape's own, so that it stays in place when it is
+    /// taken out of its group.
+    pub(crate) fn bake_transform(&mut self) {
+        self.transform = self.cumulative_transform;
+    }
+
     pub fn world_to_object(&self, p: Point) -> Point {
         self.inv_cumulative_transform * p
     }
@@ -380,6 +559,15 @@ impl Group {
         !self.items.is_empty()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The number of direct children
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
     pub fn with_child(mut self, child: impl Into<SceneItem>) -> Self {
         self.add_child(child);
         self
@@ -394,12 +582,43 @@ impl Group {
         &self.items[idx]
     }
 
+    pub fn get_child_mut(&mut self, idx: usize) -> &mut SceneItem {
+        &mut self.items[idx]
+    }
+
+    /// Replace the material of all shapes in the group.
+    pub fn set_material(&mut self, material: impl Material + Clone) {
+        for child in &mut self.items {
+            child.set_material(material.clone());
+        }
+    }
+
     pub fn intersect(&self, world_ray: &Ray) -> Vec<Intersection> {
         let mut xs = vec![];
+        self.intersect_into(world_ray, &mut xs);
+        sort_intersections(xs)
+    }
+
+    /// Append the intersections with all children to `xs`, without sorting them.
+    pub fn intersect_into<'a>(&'a self, world_ray: &Ray, xs: &mut Vec<Intersection<'a>>) {
         for obj in &self.items {
-            xs.extend(obj.intersect(world_ray));
+            obj.intersect_into(world_ray, xs);
         }
-        sort_intersections(xs)
+    }
+
+    pub fn occludes(&self, world_ray: &Ray, t_max: f64) -> bool {
+        self.items.iter().any(|obj| obj.occludes(world_ray, t_max))
+    }
+
+    pub fn shadow_transmittance(&self, world_ray: &Ray, t_max: f64) -> Color {
+        let mut transmittance = WHITE;
+        for obj in &self.items {
+            transmittance = transmittance * obj.shadow_transmittance(world_ray, t_max);
+            if transmittance.is_black() {
+                break;
+            }
+        }
+        transmittance
     }
 
     pub fn with_transform(mut self, t: Matrix) -> Self {
@@ -467,10 +686,27 @@ impl BoundingGroup {
     }
 
     pub fn intersect(&self, world_ray: &Ray) -> Vec<Intersection> {
+        let mut xs = vec![];
+        self.intersect_into(world_ray, &mut xs);
+        sort_intersections(xs)
+    }
+
+    /// Append the intersections with all children to `xs`, without sorting them.
+    pub fn intersect_into<'a>(&'a self, world_ray: &Ray, xs: &mut Vec<Intersection<'a>>) {
         if self.aabb.intersect(world_ray).is_some() {
-            self.group.intersect(world_ray)
+            self.group.intersect_into(world_ray, xs)
+        }
+    }
+
+    pub fn occludes(&self, world_ray: &Ray, t_max: f64) -> bool {
+        self.aabb.intersect(world_ray).is_some() && self.group.occludes(world_ray, t_max)
+    }
+
+    pub fn shadow_transmittance(&self, world_ray: &Ray, t_max: f64) -> Color {
+        if self.aabb.intersect(world_ray).is_some() {
+            self.group.shadow_transmittance(world_ray, t_max)
         } else {
-            vec![]
+            WHITE
         }
     }
 }
@@ -484,6 +720,10 @@ impl From<Group> for BoundingGroup {
     }
 }
 
+/// Split a group into a tree of bounding groups at the median of the widest axis.
+///
+/// `World::finalize_scene` builds a bounding volume hierarchy over all shapes, so this is
+/// rarely needed.
 pub fn build_bounding_tree(mut group: Group, max_leaf_size: usize) -> BoundingGroup {
     let n = group.items.len();
 
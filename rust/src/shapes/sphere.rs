@@ -66,6 +66,22 @@ impl Geometry for Sphere {
         local_point - point(0, 0, 0)
     }
 
+    fn occludes(&self, _: &Shape, local_ray: &Ray, t_max: f64) -> bool {
+        let sphere_to_ray = local_ray.origin() - point(0, 0, 0);
+
+        let a = local_ray.direction().square_len();
+        let b = 2.0 * local_ray.direction().dot(&sphere_to_ray);
+        let c = sphere_to_ray.square_len() - 1.0;
+        let discriminant = b * b - 4.0 * a * c;
+
+        if discriminant < 0.0 {
+            return false;
+        }
+        let t1 = -(b + discriminant.sqrt()) / (2.0 * a);
+        let t2 = -(b - discriminant.sqrt()) / (2.0 * a);
+        (t1 >= 0.0 && t1 < t_max) || (t2 >= 0.0 && t2 < t_max)
+    }
+
     fn aabb(&self) -> Aabb {
         Aabb::new(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0)
     }
@@ -28,6 +28,7 @@ pub struct Triangle {
     e1: Vector,
     e2: Vector,
     normal: Vector,
+    texture_coords: Option<[(f64, f64); 3]>,
 }
 
 impl Triangle {
@@ -41,6 +42,15 @@ impl Triangle {
             e1,
             e2,
             normal: e2.cross(&e1).normalized(),
+            texture_coords: None,
+        }
+    }
+
+    /// Assign texture coordinates to the corners, which are interpolated across the triangle.
+    pub fn with_texture_coords(self, uvs: [(f64, f64); 3]) -> Self {
+        Triangle {
+            texture_coords: Some(uvs),
+            ..self
         }
     }
 }
@@ -70,6 +80,10 @@ impl Geometry for Triangle {
         self.normal
     }
 
+    fn texture_coords(&self, hit: &Intersection) -> Option<(f64, f64)> {
+        self.texture_coords.map(|uvs| interpolate_uv(&uvs, hit))
+    }
+
     fn aabb(&self) -> Aabb {
         Aabb::new(
             self.p1.x().min(self.p2.x()).min(self.p3.x()),
@@ -98,6 +112,7 @@ pub struct SmoothTriangle {
     n1: Vector,
     n2: Vector,
     n3: Vector,
+    texture_coords: Option<[(f64, f64); 3]>,
 }
 
 impl SmoothTriangle {
@@ -113,6 +128,15 @@ impl SmoothTriangle {
             n1,
             n2,
             n3,
+            texture_coords: None,
+        }
+    }
+
+    /// Assign texture coordinates to the corners, which are interpolated across the triangle.
+    pub fn with_texture_coords(self, uvs: [(f64, f64); 3]) -> Self {
+        SmoothTriangle {
+            texture_coords: Some(uvs),
+            ..self
         }
     }
 }
@@ -142,6 +166,10 @@ impl Geometry for SmoothTriangle {
         (self.n2 * hit.u + self.n3 * hit.v + self.n1 * (1.0 - hit.u - hit.v)).normalized()
     }
 
+    fn texture_coords(&self, hit: &Intersection) -> Option<(f64, f64)> {
+        self.texture_coords.map(|uvs| interpolate_uv(&uvs, hit))
+    }
+
     fn aabb(&self) -> Aabb {
         Aabb::new(
             self.p1.x().min(self.p2.x()).min(self.p3.x()),
@@ -160,74 +188,13 @@ impl ApproximateEq for SmoothTriangle {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct TriangleMesh {
-    faces: Vec<Triangle>,
-}
-
-impl TriangleMesh {
-    pub fn new() -> Self {
-        TriangleMesh { faces: vec![] }
-    }
-
-    pub fn from_mesh(vertices: Vec<Point>, triangles: Vec<(usize, usize, usize)>) -> Self {
-        let mut faces = vec![];
-        for (a, b, c) in triangles {
-            faces.push(Triangle::new(vertices[a], vertices[b], vertices[c]));
-        }
-        TriangleMesh { faces }
-    }
-
-    pub fn aint_empty(&self) -> bool {
-        !self.faces.is_empty()
-    }
-
-    pub fn add_triangle(&mut self, tri: Triangle) {
-        self.faces.push(tri)
-    }
-
-    pub fn get_triangle(&self, idx: usize) -> Triangle {
-        self.faces[idx].clone()
-    }
-}
-
-impl Geometry for TriangleMesh {
-    fn duplicate(&self) -> Box<dyn Geometry> {
-        Box::new(self.clone())
-    }
-
-    fn as_any(&self) -> &dyn Any {
-        self
-    }
-
-    fn is_similar(&self, _: &dyn Geometry) -> bool {
-        unimplemented!()
-    }
-
-    fn intersect<'a>(&self, obj: &'a Shape, local_ray: &Ray) -> Vec<Intersection<'a>> {
-        self.faces
-            .iter()
-            .enumerate()
-            .map(|(i, tri)| {
-                intersect_triangle(tri.p1, tri.e1, tri.e2, obj, local_ray)
-                    .into_iter()
-                    .map(move |x| x.with_i(i))
-            })
-            .flatten()
-            .collect()
-    }
-
-    fn normal_at(&self, p: Point, hit: &Intersection) -> Vector {
-        self.faces[hit.i].normal_at(p, hit)
-    }
-
-    fn aabb(&self) -> Aabb {
-        let mut aabb = self.faces[0].aabb();
-        for tri in &self.faces[1..] {
-            aabb = aabb.merge(&tri.aabb())
-        }
-        aabb
-    }
+/// Interpolate per-corner texture coordinates at the barycentric coordinates of a hit
+pub(super) fn interpolate_uv(uvs: &[(f64, f64); 3], hit: &Intersection) -> (f64, f64) {
+    let w = 1.0 - hit.u - hit.v;
+    (
+        uvs[0].0 * w + uvs[1].0 * hit.u + uvs[2].0 * hit.v,
+        uvs[0].1 * w + uvs[1].1 * hit.u + uvs[2].1 * hit.v,
+    )
 }
 
 fn intersect_triangle<'a>(
@@ -237,11 +204,25 @@ fn intersect_triangle<'a>(
     obj: &'a Shape,
     ray: &Ray,
 ) -> Vec<Intersection<'a>> {
+    match hit_triangle(p1, e1, e2, ray) {
+        Some((t, u, v)) => vec![Intersection::new_uv(t, u, v, obj)],
+        None => vec![],
+    }
+}
+
+/// The distance and barycentric coordinates `(t, u, v)` where the ray crosses the triangle
+/// spanned by the edges `e1` and `e2` from `p1`, if it does.
+pub(super) fn hit_triangle(
+    p1: Point,
+    e1: Vector,
+    e2: Vector,
+    ray: &Ray,
+) -> Option<(f64, f64, f64)> {
     let dir_cross_e2 = ray.direction().cross(&e2);
     let det = e1.dot(&dir_cross_e2);
 
     if det.abs() < EPSILON {
-        return vec![];
+        return None;
     }
 
     let f = 1.0 / det;
@@ -250,18 +231,18 @@ fn intersect_triangle<'a>(
     let u = f * p1_to_origin.dot(&dir_cross_e2);
 
     if u < 0.0 || u > 1.0 {
-        return vec![];
+        return None;
     }
 
     let origin_cross_e1 = p1_to_origin.cross(&e1);
     let v = f * ray.direction().dot(&origin_cross_e1);
 
     if v < 0.0 || (u + v) > 1.0 {
-        return vec![];
+        return None;
     }
 
     let t = f * e2.dot(&origin_cross_e1);
-    vec![Intersection::new_uv(t, u, v, obj)]
+    Some((t, u, v))
 }
 
 #[cfg(test)]
@@ -413,4 +394,18 @@ mod tests {
         let comps = i.prepare_computations(&r, &xs);
         assert_almost_eq!(comps.normalv, vector(-0.5547, 0.83205, 0));
     }
+
+    /// Texture coordinates are interpolated across a triangle
+    #[test]
+    fn texture_coords() {
+        let t = Triangle::new(point(0, 1, 0), point(-1, 0, 0), point(1, 0, 0));
+        let dummy_shape = sphere();
+        let i = Intersection::new_uv(1.0, 0.45, 0.25, &dummy_shape);
+        assert_eq!(t.texture_coords(&i), None);
+
+        let t = t.with_texture_coords([(0.5, 1.0), (0.0, 0.0), (1.0, 0.0)]);
+        let (u, v) = t.texture_coords(&i).unwrap();
+        assert_almost_eq!(u, 0.4);
+        assert_almost_eq!(v, 0.3);
+    }
 }
@@ -0,0 +1,467 @@
+//! Reading triangle meshes from STL files, in either the ASCII or the binary variant.
+//!
+//! STL stores every triangle with its own copy of the corners, so corners at exactly the same
+//! position are merged into shared vertices. The facet normals in the file are ignored and the
+//! triangles are flat shaded, since CAD tools often write them inaccurately or as zero.
+
+use crate::shapes::{Group, Mesh, SceneItem, Shape};
+use crate::tuple::{point, Point};
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::Peekable;
+
+/// Size of the header of a binary file, followed by the number of triangles
+const HEADER_SIZE: usize = 80;
+
+/// Size of a triangle in a binary file: normal and corners as 32 bit floats, attribute count
+const FACET_SIZE: usize = 50;
+
+pub struct StlParser {
+    name: Option<String>,
+    vertices: Vec<Point>,
+    triangles: Vec<[u32; 3]>,
+    indices: HashMap<[u64; 3], u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StlError {
+    /// A binary file with fewer triangles than its header announces
+    Truncated {
+        expected: usize,
+        found: usize,
+    },
+    /// A binary file without a triangle count, or an ASCII file that ends inside a solid
+    UnexpectedEnd {
+        expected: &'static str,
+    },
+    /// A keyword in an ASCII file that does not belong where it was found (line counted from 1)
+    Unexpected {
+        line: usize,
+        expected: &'static str,
+        found: String,
+    },
+    InvalidNumber {
+        line: usize,
+        value: String,
+    },
+    /// A number in an ASCII file that is infinite or not a number
+    NonFiniteNumber {
+        line: usize,
+        value: String,
+    },
+    /// A triangle of a binary file (counted from 1) with a corner that is not finite
+    NonFiniteTriangle {
+        triangle: usize,
+    },
+}
+
+impl fmt::Display for StlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StlError::Truncated { expected, found } => {
+                write!(f, "the file ends after {} of {} triangles", found, expected)
+            }
+            StlError::UnexpectedEnd { expected } => {
+                write!(f, "expected `{}`, found the end of the file", expected)
+            }
+            StlError::Unexpected {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected `{}`, found `{}`",
+                line, expected, found
+            ),
+            StlError::InvalidNumber { line, value } => {
+                write!(f, "line {}: expected a number, found `{}`", line, value)
+            }
+            StlError::NonFiniteNumber { line, value } => {
+                write!(f, "line {}: `{}` is not a finite number", line, value)
+            }
+            StlError::NonFiniteTriangle { triangle } => {
+                write!(f, "triangle {} has a corner that is not finite", triangle)
+            }
+        }
+    }
+}
+
+impl std::error::Error for StlError {}
+
+/// The whitespace separated words of an ASCII file, with their line numbers
+struct Tokens<'a> {
+    tokens: Peekable<Box<dyn Iterator<Item = (usize, &'a str)> + 'a>>,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(input: &'a str) -> Self {
+        let tokens = input
+            .lines()
+            .enumerate()
+            .flat_map(|(n, line)| line.split_whitespace().map(move |t| (n + 1, t)));
+        let tokens: Box<dyn Iterator<Item = _>> = Box::new(tokens);
+        Tokens {
+            tokens: tokens.peekable(),
+        }
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<(usize, &'a str), StlError> {
+        self.tokens
+            .next()
+            .ok_or(StlError::UnexpectedEnd { expected })
+    }
+
+    /// Read the given keyword and return its line
+    fn keyword(&mut self, keyword: &'static str) -> Result<usize, StlError> {
+        match self.next(keyword)? {
+            (line, t) if t == keyword => Ok(line),
+            (line, t) => Err(StlError::Unexpected {
+                line,
+                expected: keyword,
+                found: t.to_string(),
+            }),
+        }
+    }
+
+    fn number(&mut self) -> Result<f64, StlError> {
+        let (line, t) = self.next("a number")?;
+        match t.parse::<f64>() {
+            Ok(x) if x.is_finite() => Ok(x),
+            Ok(_) => Err(StlError::NonFiniteNumber {
+                line,
+                value: t.to_string(),
+            }),
+            Err(_) => Err(StlError::InvalidNumber {
+                line,
+                value: t.to_string(),
+            }),
+        }
+    }
+
+    /// The remaining words of a line, such as the name after `solid`
+    fn rest_of_line(&mut self, line: usize) -> Vec<&'a str> {
+        let mut rest = vec![];
+        while let Some(&(n, t)) = self.tokens.peek() {
+            if n != line {
+                break;
+            }
+            rest.push(t);
+            self.tokens.next();
+        }
+        rest
+    }
+}
+
+impl StlParser {
+    pub fn parse_bytes(data: &[u8]) -> Result<StlParser, StlError> {
+        let mut parser = StlParser {
+            name: None,
+            vertices: vec![],
+            triangles: vec![],
+            indices: HashMap::new(),
+        };
+        match std::str::from_utf8(data) {
+            Ok(text) if !is_binary(data) => parser.parse_ascii(text)?,
+            _ => parser.parse_binary(data)?,
+        }
+        Ok(parser)
+    }
+
+    /// The name given after `solid` in an ASCII file
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn mesh(&self) -> Mesh {
+        Mesh::new(self.vertices.clone(), self.triangles.clone())
+    }
+
+    /// A group holding the mesh, like `ObjParser::meshes`
+    pub fn meshes(&self) -> Group {
+        let mut group = Group::default();
+        group.add_child(Shape::new(self.mesh()));
+        group
+    }
+
+    fn parse_binary(&mut self, data: &[u8]) -> Result<(), StlError> {
+        if data.len() < HEADER_SIZE + 4 {
+            return Err(StlError::UnexpectedEnd {
+                expected: "the number of triangles",
+            });
+        }
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        let facets = data[HEADER_SIZE + 4..].chunks_exact(FACET_SIZE);
+        if facets.len() < count {
+            return Err(StlError::Truncated {
+                expected: count,
+                found: facets.len(),
+            });
+        }
+
+        for (i, facet) in facets.take(count).enumerate() {
+            let f = |k: usize| {
+                let b = &facet[4 * k..4 * k + 4];
+                f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            };
+            // the normal comes first
+            if !(3..12).all(|k| f(k).is_finite()) {
+                return Err(StlError::NonFiniteTriangle { triangle: i + 1 });
+            }
+            let corner = |c: usize| point(f(3 + 3 * c), f(4 + 3 * c), f(5 + 3 * c));
+            self.add_triangle([corner(0), corner(1), corner(2)]);
+        }
+        Ok(())
+    }
+
+    fn parse_ascii(&mut self, input: &str) -> Result<(), StlError> {
+        let mut tokens = Tokens::new(input);
+        let line = tokens.keyword("solid")?;
+        let name = tokens.rest_of_line(line).join(" ");
+        if !name.is_empty() {
+            self.name = Some(name);
+        }
+
+        loop {
+            match tokens.next("endsolid")? {
+                (_, "facet") => self.parse_facet(&mut tokens)?,
+                (line, "endsolid") => {
+                    tokens.rest_of_line(line);
+                    match tokens.next("solid") {
+                        Err(_) => return Ok(()),
+                        Ok((line, "solid")) => {
+                            tokens.rest_of_line(line);
+                        }
+                        Ok((line, t)) => {
+                            return Err(StlError::Unexpected {
+                                line,
+                                expected: "solid",
+                                found: t.to_string(),
+                            })
+                        }
+                    }
+                }
+                (line, t) => {
+                    return Err(StlError::Unexpected {
+                        line,
+                        expected: "facet",
+                        found: t.to_string(),
+                    })
+                }
+            }
+        }
+    }
+
+    /// A facet after its `facet` keyword. Facets with more than three corners are split into
+    /// triangle fans.
+    fn parse_facet(&mut self, tokens: &mut Tokens) -> Result<(), StlError> {
+        tokens.keyword("normal")?;
+        for _ in 0..3 {
+            tokens.number()?;
+        }
+        tokens.keyword("outer")?;
+        tokens.keyword("loop")?;
+
+        let mut corners = vec![];
+        loop {
+            match tokens.next("endloop")? {
+                (_, "vertex") => {
+                    let (x, y, z) = (tokens.number()?, tokens.number()?, tokens.number()?);
+                    corners.push(point(x, y, z));
+                }
+                (line, "endloop") if corners.len() < 3 => {
+                    return Err(StlError::Unexpected {
+                        line,
+                        expected: "vertex",
+                        found: "endloop".to_string(),
+                    })
+                }
+                (_, "endloop") => break,
+                (line, t) => {
+                    return Err(StlError::Unexpected {
+                        line,
+                        expected: "vertex",
+                        found: t.to_string(),
+                    })
+                }
+            }
+        }
+        tokens.keyword("endfacet")?;
+
+        for i in 1..corners.len() - 1 {
+            self.add_triangle([corners[0], corners[i], corners[i + 1]]);
+        }
+        Ok(())
+    }
+
+    fn add_triangle(&mut self, corners: [Point; 3]) {
+        let mut tri = [0; 3];
+        for (index, p) in tri.iter_mut().zip(&corners) {
+            let key = [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()];
+            let vertices = &mut self.vertices;
+            *index = *self.indices.entry(key).or_insert_with(|| {
+                vertices.push(*p);
+                vertices.len() as u32 - 1
+            });
+        }
+        self.triangles.push(tri);
+    }
+}
+
+/// Binary files may start with `solid`, too, so they are recognized by their size first.
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() >= HEADER_SIZE + 4 {
+        let count = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        if data.len() == HEADER_SIZE + 4 + count * FACET_SIZE {
+            return true;
+        }
+    }
+    let start = data
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(0);
+    !data[start..].starts_with(b"solid")
+}
+
+impl From<StlParser> for Group {
+    fn from(p: StlParser) -> Group {
+        p.mesh().to_triangles()
+    }
+}
+
+impl From<StlParser> for SceneItem {
+    fn from(p: StlParser) -> SceneItem {
+        SceneItem::Compound(p.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_eq::ApproximateEq;
+
+    const ASCII: &str = "\
+solid a square
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid a square
+";
+
+    fn binary(triangles: &[[[f32; 3]; 3]]) -> Vec<u8> {
+        let mut data = b"solid binary files may start with solid, too".to_vec();
+        data.resize(HEADER_SIZE, 0);
+        data.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for tri in triangles {
+            for &x in [0.0f32, 0.0, 1.0].iter().chain(tri.iter().flatten()) {
+                data.extend_from_slice(&x.to_le_bytes());
+            }
+            data.extend_from_slice(&[0, 0]);
+        }
+        data
+    }
+
+    /// Corners at the same position become shared vertices
+    #[test]
+    fn ascii() {
+        let parser = StlParser::parse_bytes(ASCII.as_bytes()).unwrap();
+        assert_eq!(parser.name(), Some("a square"));
+        assert_almost_eq!(
+            parser.vertices,
+            vec![
+                point(0, 0, 0),
+                point(1, 0, 0),
+                point(1, 1, 0),
+                point(0, 1, 0)
+            ]
+        );
+        assert_eq!(parser.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+
+        let group: Group = parser.into();
+        assert_eq!(group.len(), 2);
+    }
+
+    /// A binary file holds the same data as the ASCII one
+    #[test]
+    fn binary_file() {
+        let data = binary(&[
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+            [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+        ]);
+        let parser = StlParser::parse_bytes(&data).unwrap();
+        let ascii = StlParser::parse_bytes(ASCII.as_bytes()).unwrap();
+        assert_eq!(parser.name(), None);
+        assert_almost_eq!(parser.vertices, ascii.vertices);
+        assert_eq!(parser.triangles, ascii.triangles);
+        assert_almost_eq!(parser.meshes().get_child(0), ascii.meshes().get_child(0));
+    }
+
+    /// Truncated and malformed files are reported
+    #[test]
+    fn errors() {
+        let data = binary(&[[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]]; 3]);
+        assert_eq!(
+            StlParser::parse_bytes(&data[..data.len() - 10]).err(),
+            Some(StlError::Truncated {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(
+            StlParser::parse_bytes(&[0; 40]).err(),
+            Some(StlError::UnexpectedEnd {
+                expected: "the number of triangles"
+            })
+        );
+
+        let truncated = &ASCII[..ASCII.find("0 1 0").unwrap()];
+        assert_eq!(
+            StlParser::parse_bytes(truncated.as_bytes()).err(),
+            Some(StlError::UnexpectedEnd {
+                expected: "a number"
+            })
+        );
+        let err = StlParser::parse_bytes(ASCII.replace("outer loop", "outer").as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "line 4: expected `loop`, found `vertex`");
+        assert_eq!(
+            StlParser::parse_bytes(ASCII.replace("1 1 0", "1 l 0").as_bytes()).err(),
+            Some(StlError::InvalidNumber {
+                line: 6,
+                value: "l".to_string()
+            })
+        );
+        assert_eq!(
+            StlParser::parse_bytes(ASCII.replace("1 1 0", "1 inf 0").as_bytes()).err(),
+            Some(StlError::NonFiniteNumber {
+                line: 6,
+                value: "inf".to_string()
+            })
+        );
+        let nan = binary(&[
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+            [[0.0, 0.0, 0.0], [1.0, f32::NAN, 0.0], [1.0, 1.0, 0.0]],
+        ]);
+        assert_eq!(
+            StlParser::parse_bytes(&nan).err(),
+            Some(StlError::NonFiniteTriangle { triangle: 2 })
+        );
+        assert_eq!(
+            StlParser::parse_bytes(ASCII.replace("endsolid a square\n", "").as_bytes()).err(),
+            Some(StlError::UnexpectedEnd {
+                expected: "endsolid"
+            })
+        );
+    }
+}
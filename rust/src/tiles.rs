@@ -0,0 +1,698 @@
+//! Rendering in tiles, with checkpoints to resume interrupted renders.
+//!
+//! `Camera::render` only returns when the whole image is done. A `TiledRender` instead renders
+//! the image (or a sub-rectangle of it) one square tile after the other and keeps the sample
+//! statistics of every finished pixel in a `Checkpoint`. If it is given a checkpoint file, it
+//! saves the checkpoint there from time to time and, when started again, continues with the
+//! tiles that are still missing:
+//!
+//! ```no_run
+//! # use raytracing::{camera::Camera, world::World};
+//! use raytracing::tiles::TiledRender;
+//! use std::time::Duration;
+//!
+//! # let (camera, world) = (Camera::new(1, 1, 1.0), World::default());
+//! let checkpoint = TiledRender::new(&camera, &world)
+//!     .with_checkpoint_file("render.checkpoint", Duration::from_secs(60))
+//!     .render()?;
+//! checkpoint.image()?.write_image("render.png")?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! Pixels are sampled with their own random numbers, so a resumed render gives the same image
+//! as one that was never interrupted, as long as the scene is the same. Only the size of the
+//! image, the region, the tile size and the seed are stored in the checkpoint and checked.
+
+use crate::camera::Camera;
+use crate::canvas::Canvas;
+use crate::color::{color, Color, BLACK};
+use crate::progress::RenderControl;
+use crate::world::World;
+use rayon::prelude::*;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A rectangle of pixels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+
+    /// The coordinates of all pixels, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> {
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = *self;
+        (y..y + height).flat_map(move |py| (x..x + width).map(move |px| (px, py)))
+    }
+
+    /// Split the rectangle into tiles of `size` by `size` pixels, row by row. The tiles at the
+    /// right and bottom edges are smaller if the size does not divide the rectangle.
+    /// The number of pixels
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// The number of tiles of `tiles(size)`
+    pub fn tile_count(&self, size: u32) -> u64 {
+        let count = |length: u32| (length as u64).div_ceil(size as u64);
+        count(self.width) * count(self.height)
+    }
+
+    pub fn tiles(&self, size: u32) -> Vec<Rect> {
+        let mut tiles = vec![];
+        for y in (0..self.height).step_by(size as usize) {
+            for x in (0..self.width).step_by(size as usize) {
+                tiles.push(Rect::new(
+                    self.x + x,
+                    self.y + y,
+                    size.min(self.width - x),
+                    size.min(self.height - y),
+                ));
+            }
+        }
+        tiles
+    }
+}
+
+impl std::str::FromStr for Rect {
+    type Err = String;
+
+    /// Parse `x,y,width,height`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let numbers: Result<Vec<u32>, _> = s.split(',').map(|n| n.trim().parse()).collect();
+        match numbers.as_deref() {
+            Ok(&[x, y, width, height]) => Ok(Rect::new(x, y, width, height)),
+            _ => Err(format!(
+                "invalid rectangle `{}` (expected x,y,width,height)",
+                s
+            )),
+        }
+    }
+}
+
+/// The samples taken for a pixel, enough to compute its color and the standard error of it
+#[derive(Debug, Copy, Clone)]
+pub struct PixelStats {
+    pub sum: Color,
+    pub sum_of_squares: Color,
+    pub samples: u32,
+}
+
+impl Default for PixelStats {
+    fn default() -> Self {
+        PixelStats {
+            sum: BLACK,
+            sum_of_squares: BLACK,
+            samples: 0,
+        }
+    }
+}
+
+impl PixelStats {
+    pub fn add(&mut self, c: Color) {
+        self.sum = self.sum + c;
+        self.sum_of_squares = self.sum_of_squares + c * c;
+        self.samples += 1;
+    }
+
+    /// The variance of the mean color, averaged over the channels; the square of its standard
+    /// error
+    pub fn variance_of_mean(&self) -> f64 {
+        if self.samples == 0 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mean = self.sum / n;
+        let variance = self.sum_of_squares / n - mean * mean;
+        (variance.red() + variance.green() + variance.blue()) / 3.0 / n
+    }
+
+    /// The average of the samples, or black if there are none
+    pub fn mean(&self) -> Color {
+        if self.samples == 0 {
+            BLACK
+        } else {
+            self.sum / self.samples as f64
+        }
+    }
+}
+
+/// The progress of a tiled render: which tiles are done and the statistics of their pixels.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    width: u32,
+    height: u32,
+    region: Rect,
+    tile_size: u32,
+    seed: u64,
+    tiles: Vec<Rect>,
+    /// The pixels of every finished tile, row by row
+    stats: Vec<Option<Vec<PixelStats>>>,
+}
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCKPT01";
+
+/// The size of the statistics of a pixel in a checkpoint file
+const PIXEL_RECORD_SIZE: u64 = 2 * 3 * 8 + 4;
+
+/// Check that `region` is a non-empty part of a `width` x `height` image and that the tiles
+/// are not empty.
+fn check_region(width: u32, height: u32, region: Rect, tile_size: u32) -> std::io::Result<()> {
+    if tile_size == 0 {
+        return Err(invalid_input("the tile size must be positive"));
+    }
+    let inside = |start: u32, size: u32, limit: u32| {
+        size > 0 && start.checked_add(size).is_some_and(|end| end <= limit)
+    };
+    if !inside(region.x, region.width, width) || !inside(region.y, region.height, height) {
+        return Err(invalid_input(&format!(
+            "the region {},{},{},{} is not inside the {}x{} image",
+            region.x, region.y, region.width, region.height, width, height
+        )));
+    }
+    Ok(())
+}
+
+impl Checkpoint {
+    /// An empty checkpoint for rendering `region` of the image of `camera`
+    pub fn new(camera: &Camera, region: Rect, tile_size: u32) -> std::io::Result<Self> {
+        let (width, height) = (camera.hsize(), camera.vsize());
+        Self::empty(width, height, region, tile_size, camera.seed())
+    }
+
+    fn empty(
+        width: u32,
+        height: u32,
+        region: Rect,
+        tile_size: u32,
+        seed: u64,
+    ) -> std::io::Result<Self> {
+        check_region(width, height, region, tile_size)?;
+        let tiles = region.tiles(tile_size);
+        Ok(Checkpoint {
+            width,
+            height,
+            region,
+            tile_size,
+            seed,
+            stats: vec![None; tiles.len()],
+            tiles,
+        })
+    }
+
+    pub fn region(&self) -> Rect {
+        self.region
+    }
+
+    pub fn tiles(&self) -> &[Rect] {
+        &self.tiles
+    }
+
+    pub fn completed_tiles(&self) -> usize {
+        self.stats.iter().filter(|stats| stats.is_some()).count()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.stats.iter().all(Option::is_some)
+    }
+
+    /// The statistics of a pixel, if its tile is done
+    pub fn pixel_stats(&self, x: u32, y: u32) -> Option<PixelStats> {
+        if !self.region.contains(x, y) {
+            return None;
+        }
+        let i = self.tiles.iter().position(|t| t.contains(x, y))?;
+        let tile = self.tiles[i];
+        let stats = self.stats[i].as_ref()?;
+        Some(stats[((y - tile.y) * tile.width + (x - tile.x)) as usize])
+    }
+
+    /// Store the statistics of the pixels of a tile, in the order of `Rect::pixels`.
+    fn complete_tile(&mut self, tile: usize, stats: &[PixelStats]) {
+        self.stats[tile] = Some(stats.to_vec());
+    }
+
+    /// The image of the whole camera, where only the pixels of the finished tiles are set.
+    /// Fails if the image is too large, which a corrupt checkpoint file can claim.
+    pub fn image(&self) -> std::io::Result<Canvas> {
+        let mut canvas = Canvas::try_new(self.width, self.height)?;
+        for (tile, stats) in self.tiles.iter().zip(&self.stats) {
+            for ((x, y), s) in tile.pixels().zip(stats.iter().flatten()) {
+                canvas.set_pixel(x, y, s.mean());
+            }
+        }
+        Ok(canvas)
+    }
+
+    /// Whether `other` belongs to the same render
+    fn matches(&self, other: &Checkpoint) -> bool {
+        (
+            self.width,
+            self.height,
+            self.region,
+            self.tile_size,
+            self.seed,
+        ) == (
+            other.width,
+            other.height,
+            other.region,
+            other.tile_size,
+            other.seed,
+        )
+    }
+
+    /// Write the checkpoint in a little-endian binary format. Every tile is stored as a byte
+    /// that tells whether it is finished, followed by its pixels if it is.
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        let Rect {
+            x,
+            y,
+            width,
+            height,
+        } = self.region;
+        for &n in &[self.width, self.height, x, y, width, height, self.tile_size] {
+            writer.write_all(&n.to_le_bytes())?;
+        }
+        writer.write_all(&self.seed.to_le_bytes())?;
+        for stats in &self.stats {
+            writer.write_all(&[stats.is_some() as u8])?;
+            for s in stats.iter().flatten() {
+                for c in &[s.sum, s.sum_of_squares] {
+                    for v in &[c.red(), c.green(), c.blue()] {
+                        writer.write_all(&v.to_le_bytes())?;
+                    }
+                }
+                writer.write_all(&s.samples.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a checkpoint. The sizes in the header are checked against the length of the file
+    /// before anything is allocated for them.
+    pub fn read(reader: &mut impl Read) -> std::io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let reader = &mut &bytes[..];
+
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(invalid_data("not a checkpoint file"));
+        }
+        let mut header = [0; 7];
+        for n in header.iter_mut() {
+            *n = read_u32(reader)?;
+        }
+        let [width, height, x, y, region_width, region_height, tile_size] = header;
+        let mut seed = [0; 8];
+        reader.read_exact(&mut seed)?;
+
+        let region = Rect::new(x, y, region_width, region_height);
+        let seed = u64::from_le_bytes(seed);
+        check_region(width, height, region, tile_size).map_err(|e| invalid_data(&e.to_string()))?;
+        if region.tile_count(tile_size) > reader.len() as u64 {
+            return Err(invalid_data("unexpected end of checkpoint file"));
+        }
+        let mut checkpoint = Checkpoint::empty(width, height, region, tile_size, seed)?;
+
+        for tile in 0..checkpoint.tiles.len() {
+            let mut done = [0];
+            reader.read_exact(&mut done)?;
+            if done[0] == 0 {
+                continue;
+            }
+            let pixels = checkpoint.tiles[tile].area();
+            if pixels > reader.len() as u64 / PIXEL_RECORD_SIZE {
+                return Err(invalid_data("unexpected end of checkpoint file"));
+            }
+            let mut stats = Vec::with_capacity(pixels as usize);
+            for _ in 0..pixels {
+                let sum = read_color(reader)?;
+                let sum_of_squares = read_color(reader)?;
+                let samples = read_u32(reader)?;
+                stats.push(PixelStats {
+                    sum,
+                    sum_of_squares,
+                    samples,
+                });
+            }
+            checkpoint.complete_tile(tile, &stats);
+        }
+        Ok(checkpoint)
+    }
+
+    /// Write the checkpoint to a file. The file is replaced only after the new checkpoint is
+    /// written completely, so that an interrupted save does not lose the previous one.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(&temporary)?);
+            self.write(&mut file)?;
+            file.flush()?;
+        }
+        std::fs::rename(&temporary, path)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+        Self::read(&mut file)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_color(reader: &mut impl Read) -> std::io::Result<Color> {
+    let mut channels = [0.0; 3];
+    for channel in channels.iter_mut() {
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes)?;
+        *channel = f64::from_le_bytes(bytes);
+    }
+    Ok(color(channels[0], channels[1], channels[2]))
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn invalid_input(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// A render of an image, or a part of it, in tiles.
+pub struct TiledRender<'a> {
+    camera: &'a Camera,
+    world: &'a World,
+    region: Option<Rect>,
+    tile_size: u32,
+    checkpoint_file: Option<(PathBuf, Duration)>,
+    control: RenderControl<'a>,
+}
+
+impl<'a> TiledRender<'a> {
+    pub fn new(camera: &'a Camera, world: &'a World) -> Self {
+        TiledRender {
+            camera,
+            world,
+            region: None,
+            tile_size: 32,
+            checkpoint_file: None,
+            control: RenderControl::default(),
+        }
+    }
+
+    /// Render only the pixels in `region`
+    pub fn with_region(mut self, region: Rect) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    pub fn with_tile_size(mut self, size: u32) -> Self {
+        self.tile_size = size;
+        self
+    }
+
+    /// Resume from the checkpoint in `path` if it exists, save the progress there at most
+    /// every `interval` and once more when the render is done.
+    pub fn with_checkpoint_file(mut self, path: impl Into<PathBuf>, interval: Duration) -> Self {
+        self.checkpoint_file = Some((path.into(), interval));
+        self
+    }
+
+    /// Report the progress, and stop when cancelled or out of time (see `progress`). Tiles
+    /// that were interrupted by the cancellation are not stored in the checkpoint.
+    pub fn with_control(mut self, control: RenderControl<'a>) -> Self {
+        self.control = control;
+        self
+    }
+
+    /// Render all missing tiles. Fails if the checkpoint file cannot be read or written, or
+    /// belongs to a different render.
+    pub fn render(&self) -> std::io::Result<Checkpoint> {
+        let region = self
+            .region
+            .unwrap_or_else(|| Rect::new(0, 0, self.camera.hsize(), self.camera.vsize()));
+        let mut checkpoint = Checkpoint::new(self.camera, region, self.tile_size)?;
+        if let Some((path, _)) = &self.checkpoint_file {
+            if path.exists() {
+                let saved = Checkpoint::load(path)?;
+                if !saved.matches(&checkpoint) {
+                    return Err(invalid_data(&format!(
+                        "{} is the checkpoint of a different render",
+                        path.display()
+                    )));
+                }
+                log::info!(
+                    "Resuming render with {} of {} tiles done",
+                    saved.completed_tiles(),
+                    saved.tiles.len()
+                );
+                checkpoint = saved;
+            }
+        }
+
+        let missing: Vec<(usize, Rect)> = checkpoint
+            .tiles
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| checkpoint.stats[i].is_none())
+            .map(|(i, &tile)| (i, tile))
+            .collect();
+        let missing_pixels: u64 = missing.iter().map(|(_, t)| t.area()).sum();
+        let tracker = self.control.start(
+            region.area(),
+            region.area() - missing_pixels,
+            checkpoint.tiles.len(),
+            checkpoint.completed_tiles(),
+        );
+        let state = Mutex::new((checkpoint, Instant::now()));
+        missing
+            .par_iter()
+            .try_for_each(|&(i, tile)| -> std::io::Result<()> {
+                if tracker.is_cancelled() {
+                    return Ok(());
+                }
+                let pixels: Vec<_> = tile.pixels().collect();
+                let stats: Vec<_> = pixels
+                    .par_iter()
+                    .map(|&(x, y)| {
+                        let stats = self.camera.pixel_stats(x, y, self.world, &tracker);
+                        tracker.pixel_done(stats.samples);
+                        stats
+                    })
+                    .collect();
+                if tracker.is_cancelled() {
+                    return Ok(());
+                }
+
+                let mut state = state.lock().unwrap();
+                let (checkpoint, last_save) = &mut *state;
+                checkpoint.complete_tile(i, &stats);
+                tracker.tile_done();
+                if let Some((path, interval)) = &self.checkpoint_file {
+                    if last_save.elapsed() >= *interval {
+                        checkpoint.save(path)?;
+                        *last_save = Instant::now();
+                    }
+                }
+                Ok(())
+            })?;
+
+        tracker.finish();
+        let (checkpoint, _) = state.into_inner().unwrap();
+        if let Some((path, _)) = &self.checkpoint_file {
+            checkpoint.save(path)?;
+        }
+        Ok(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tuple::{point, vector};
+    use std::f64::consts::PI;
+
+    fn camera() -> Camera {
+        let mut camera = Camera::new(11, 9, PI / 2.0).with_view_transform(
+            point(0, 0, -5),
+            point(0, 0, 0),
+            vector(0, 1, 0),
+        );
+        camera.set_min_samples(2);
+        camera
+    }
+
+    /// Tiles cover a rectangle without overlapping
+    #[test]
+    fn tiles() {
+        let region = Rect::new(2, 1, 7, 5);
+        let tiles = region.tiles(3);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles[2], Rect::new(8, 1, 1, 3));
+        assert_eq!(tiles[5], Rect::new(8, 4, 1, 2));
+        for (x, y) in Rect::new(0, 0, 10, 8).pixels() {
+            let covering = tiles.iter().filter(|t| t.contains(x, y)).count();
+            assert_eq!(covering, region.contains(x, y) as usize);
+        }
+        assert_eq!("2,1,7,5".parse(), Ok(region));
+        assert!("2,1,7".parse::<Rect>().is_err());
+    }
+
+    /// A tiled render gives the same pixels as a normal render
+    #[test]
+    fn same_as_render() {
+        let (camera, world) = (camera(), World::default());
+        let expected = camera.render(&world);
+        let region = Rect::new(3, 2, 6, 5);
+        let checkpoint = TiledRender::new(&camera, &world)
+            .with_region(region)
+            .with_tile_size(4)
+            .render()
+            .unwrap();
+        assert!(checkpoint.is_complete());
+        let image = checkpoint.image().unwrap();
+        for (x, y) in Rect::new(0, 0, 11, 9).pixels() {
+            if region.contains(x, y) {
+                assert_eq!(image.get_pixel(x, y), expected.get_pixel(x, y));
+            } else {
+                assert_eq!(image.get_pixel(x, y), BLACK);
+            }
+        }
+        assert!(checkpoint.pixel_stats(3, 2).unwrap().samples >= 2);
+        assert!(checkpoint.pixel_stats(0, 0).is_none());
+        assert!(TiledRender::new(&camera, &world)
+            .with_region(Rect::new(8, 0, 4, 1))
+            .render()
+            .is_err());
+    }
+
+    /// Finished tiles of a checkpoint are not rendered again
+    #[test]
+    fn resume() {
+        let (camera, world) = (camera(), World::default());
+        let expected = camera.render(&world);
+        let dir = std::env::temp_dir().join(format!("raytracing-tiles-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("render.checkpoint");
+
+        let mut checkpoint = Checkpoint::new(&camera, Rect::new(0, 0, 11, 9), 5).unwrap();
+        let marker = PixelStats {
+            sum: color(3, 6, 9),
+            sum_of_squares: color(9, 36, 81),
+            samples: 3,
+        };
+        checkpoint.complete_tile(1, &[marker; 25]);
+        checkpoint.save(&path).unwrap();
+
+        let render = TiledRender::new(&camera, &world)
+            .with_tile_size(5)
+            .with_checkpoint_file(&path, Duration::from_secs(0));
+        let image = render.render().unwrap().image().unwrap();
+        let saved = Checkpoint::load(&path);
+        let mismatch = TiledRender::new(&camera, &world)
+            .with_tile_size(4)
+            .with_checkpoint_file(&path, Duration::from_secs(0))
+            .render();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(image.get_pixel(7, 2), color(1, 2, 3));
+        assert_eq!(image.get_pixel(2, 2), expected.get_pixel(2, 2));
+        assert_eq!(image.get_pixel(10, 8), expected.get_pixel(10, 8));
+        let saved = saved.unwrap();
+        assert!(saved.is_complete());
+        assert_eq!(
+            saved.image().unwrap().get_pixel(2, 7),
+            expected.get_pixel(2, 7)
+        );
+        assert_eq!(saved.pixel_stats(5, 0).unwrap().samples, 3);
+        assert_eq!(mismatch.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    /// Regions outside of the image and sizes that the file cannot hold are rejected
+    #[test]
+    fn invalid_checkpoints() {
+        let region = Rect::new(u32::MAX, 0, 2, 1);
+        let error = Checkpoint::empty(10, 10, region, 4, 0).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        let region = Rect::new(0, 5, 1, u32::MAX - 2);
+        assert!(Checkpoint::empty(10, 10, region, 4, 0).is_err());
+
+        let checkpoint = Checkpoint::empty(11, 9, Rect::new(0, 0, 11, 9), 5, 7).unwrap();
+        let mut bytes = vec![];
+        checkpoint.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 8 + 7 * 4 + 8 + 6);
+        let read = Checkpoint::read(&mut &bytes[..]).unwrap();
+        assert_eq!(read.completed_tiles(), 0);
+
+        // a corrupt image size is only noticed when the image is made
+        bytes[8..16].copy_from_slice(&[0xff; 8]);
+        let read = Checkpoint::read(&mut &bytes[..]).unwrap();
+        assert!(read.image().is_err());
+
+        let read_with = |header: [u32; 7], tail: &[u8]| {
+            let mut bytes = CHECKPOINT_MAGIC.to_vec();
+            for n in &header {
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0; 8]);
+            bytes.extend_from_slice(tail);
+            Checkpoint::read(&mut &bytes[..]).err().unwrap().kind()
+        };
+        let huge = u32::MAX;
+        // billions of tiles, or a tile of billions of pixels
+        assert_eq!(
+            read_with([huge, huge, 0, 0, huge, huge, 1], &[0; 6]),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read_with([huge, huge, 0, 0, huge, huge, huge], &[1; 6]),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read_with([10, 10, huge, 0, 2, 1, 4], &[]),
+            ErrorKind::InvalidData
+        );
+        // fewer tiles, or fewer pixels of a finished tile, than the header promises
+        assert_eq!(
+            read_with([11, 9, 0, 0, 11, 9, 5], &[0; 5]),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(
+            read_with([11, 9, 0, 0, 11, 9, 5], &[1; 100]),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Preparing rendered images for display.
+//!
+//! A render holds linear radiance values that can be far brighter than 1. Before it is written
+//! to an 8-bit format, a `ToneMapper` scales it by an exposure, compresses the highlights with a
+//! tone curve and encodes the result for sRGB displays:
+//!
+//! ```no_run
+//! # use raytracing::canvas::Canvas;
+//! use raytracing::tone_mapping::{ToneCurve, ToneMapper};
+//!
+//! # let mut image = Canvas::new(1, 1);
+//! let tone_mapper = ToneMapper::new()
+//!     .with_auto_exposure(0.18)
+//!     .with_curve(ToneCurve::Aces)
+//!     .with_srgb(true);
+//! tone_mapper.apply(&mut image);
+//! ```
+//!
+//! The default tone mapper clips colors to [0, 1] without any other change, which is what
+//! `Canvas::write_png` does by itself.
+
+use crate::canvas::Canvas;
+use crate::color::{color, Color};
+
+/// How colors brighter than 1 are brought into the displayable range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ToneCurve {
+    /// Cut off every channel at 1
+    Clip,
+    /// `x / (1 + x)`, which never saturates
+    Reinhard,
+    /// The filmic curve of John Hable (Uncharted 2), with a toe for the shadows
+    Filmic,
+    /// Krzysztof Narkowicz' fit of the ACES reference rendering transform
+    Aces,
+}
+
+impl ToneCurve {
+    /// Map a single non-negative channel value into [0, 1]
+    pub fn map(self, x: f64) -> f64 {
+        let y = match self {
+            ToneCurve::Clip => x,
+            ToneCurve::Reinhard => x / (1.0 + x),
+            ToneCurve::Filmic => {
+                const WHITE_POINT: f64 = 11.2;
+                const EXPOSURE_BIAS: f64 = 2.0;
+                hable(EXPOSURE_BIAS * x) / hable(WHITE_POINT)
+            }
+            ToneCurve::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        };
+        y.clamp(0.0, 1.0)
+    }
+}
+
+impl std::str::FromStr for ToneCurve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clip" => Ok(ToneCurve::Clip),
+            "reinhard" => Ok(ToneCurve::Reinhard),
+            "filmic" => Ok(ToneCurve::Filmic),
+            "aces" => Ok(ToneCurve::Aces),
+            _ => Err(format!(
+                "unknown tone curve `{}` (expected clip, reinhard, filmic or aces)",
+                s
+            )),
+        }
+    }
+}
+
+fn hable(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exposure {
+    /// A fixed exposure in stops: every stop doubles the brightness
+    Stops(f64),
+    /// Scale the image so that its average luminance becomes `key` (0.18 is middle gray)
+    Auto { key: f64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct ToneMapper {
+    exposure: Exposure,
+    curve: ToneCurve,
+    srgb: bool,
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        ToneMapper {
+            exposure: Exposure::Stops(0.0),
+            curve: ToneCurve::Clip,
+            srgb: false,
+        }
+    }
+}
+
+impl ToneMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_exposure(mut self, stops: f64) -> Self {
+        self.exposure = Exposure::Stops(stops);
+        self
+    }
+
+    pub fn with_auto_exposure(mut self, key: f64) -> Self {
+        self.exposure = Exposure::Auto { key };
+        self
+    }
+
+    pub fn with_curve(mut self, curve: ToneCurve) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    /// Whether to apply the sRGB transfer function, as expected by PNG viewers
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.srgb = srgb;
+        self
+    }
+
+    pub fn exposure(&self) -> Exposure {
+        self.exposure
+    }
+
+    pub fn curve(&self) -> ToneCurve {
+        self.curve
+    }
+
+    /// The factor that all colors of `image` are multiplied with before the tone curve
+    pub fn exposure_scale(&self, image: &Canvas) -> f64 {
+        match self.exposure {
+            Exposure::Stops(stops) => 2f64.powf(stops),
+            Exposure::Auto { key } => {
+                let average = luminance(image.average_brightness());
+                if average > 0.0 {
+                    key / average
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+
+    /// Map a linear color with a known exposure scale to a display color in [0, 1]
+    pub fn map_color(&self, c: Color, scale: f64) -> Color {
+        let channel = |x: f64| {
+            let y = self.curve.map((x * scale).max(0.0));
+            if self.srgb {
+                srgb_encode(y)
+            } else {
+                y
+            }
+        };
+        color(channel(c.red()), channel(c.green()), channel(c.blue()))
+    }
+
+    /// Tone map all pixels of an image in place.
+    pub fn apply(&self, image: &mut Canvas) {
+        let scale = self.exposure_scale(image);
+        for row in image.rows_mut() {
+            for pixel in row {
+                *pixel = self.map_color(*pixel, scale);
+            }
+        }
+    }
+}
+
+/// The relative luminance of a linear sRGB color
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.red() + 0.7152 * c.green() + 0.0722 * c.blue()
+}
+
+/// The sRGB transfer function, from linear intensity to the encoded value
+pub fn srgb_encode(x: f64) -> f64 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The inverse of `srgb_encode`
+pub fn srgb_decode(y: f64) -> f64 {
+    if y <= 0.040_45 {
+        y / 12.92
+    } else {
+        ((y + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All curves map black to black, grow monotonically and stay within [0, 1]
+    #[test]
+    fn curves() {
+        for &curve in &[
+            ToneCurve::Clip,
+            ToneCurve::Reinhard,
+            ToneCurve::Filmic,
+            ToneCurve::Aces,
+        ] {
+            assert!(curve.map(0.0).abs() < 1e-3, "{:?}", curve);
+            let mut previous = curve.map(0.0);
+            for i in 1..1000 {
+                let y = curve.map(i as f64 * 0.05);
+                assert!(y >= previous && y <= 1.0, "{:?}", curve);
+                previous = y;
+            }
+        }
+        assert_eq!(ToneCurve::Clip.map(2.0), 1.0);
+        assert_eq!(ToneCurve::Reinhard.map(1.0), 0.5);
+        assert!(ToneCurve::Reinhard.map(1000.0) < 1.0);
+        assert!((ToneCurve::Filmic.map(11.2 / 2.0) - 1.0).abs() < 1e-9);
+        assert_eq!("aces".parse(), Ok(ToneCurve::Aces));
+        assert!("gamma".parse::<ToneCurve>().is_err());
+    }
+
+    /// The sRGB transfer function and its inverse
+    #[test]
+    fn srgb() {
+        assert_eq!(srgb_encode(0.0), 0.0);
+        assert!((srgb_encode(1.0) - 1.0).abs() < 1e-12);
+        assert!((srgb_encode(0.18) - 0.461).abs() < 1e-3);
+        for &x in &[0.001, 0.01, 0.5, 0.9] {
+            assert!((srgb_decode(srgb_encode(x)) - x).abs() < 1e-12);
+        }
+    }
+
+    /// Fixed exposures are given in stops, automatic exposures scale the average luminance
+    #[test]
+    fn exposure() {
+        let mut image = Canvas::new(2, 1);
+        image.set_pixel(0, 0, color(1000, 1000, 1000));
+        image.set_pixel(1, 0, color(200, 200, 200));
+
+        let tone_mapper = ToneMapper::new().with_exposure(-2.0);
+        assert_eq!(tone_mapper.exposure_scale(&image), 0.25);
+        let tone_mapper = ToneMapper::new().with_auto_exposure(0.18);
+        assert!((tone_mapper.exposure_scale(&image) - 0.18 / 600.0).abs() < 1e-12);
+
+        tone_mapper.apply(&mut image);
+        assert!((image.get_pixel(0, 0).red() - 0.3).abs() < 1e-9);
+        assert!((image.get_pixel(1, 0).red() - 0.06).abs() < 1e-9);
+
+        let black = Canvas::new(1, 1);
+        assert_eq!(tone_mapper.exposure_scale(&black), 1.0);
+    }
+}
@@ -1,25 +1,21 @@
-use crate::approx_eq::EPSILON;
-use crate::color::{color, Color};
+use crate::bvh::{Bvh, BvhStats};
+use crate::color::{color, Color, BLACK, WHITE};
+use crate::integrators::{Integrator, Whitted};
 use crate::lights::{IncomingLight, Light, PointLight};
 use crate::materials::Phong;
 use crate::matrix::{scaling, Matrix};
-use crate::photon_map::{PhotonKind, PhotonMap, StoredPhoton, TravellingPhoton};
-use crate::ray::{hit, origin_object, Intersection, IntersectionState, Ray};
+use crate::random::Prng;
+use crate::ray::{sort_intersections, Intersection, IntersectionState, Ray};
 use crate::shapes::{sphere, SceneItem};
 use crate::tuple::{point, Point};
-use rand::distributions::WeightedIndex;
-use rand::{distributions::Distribution, thread_rng, Rng};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 pub struct World {
+    integrator: Box<dyn Integrator>,
     lights: Vec<Box<dyn Light>>,
     objects: Vec<SceneItem>,
-    max_reflection_depth: u32,
-    photon_map: Option<(PhotonMap, usize)>,
-    direct_illumination_enabled: bool,
-    direct_photon_map_enabled: bool,
-    diffuse_photon_map_enabled: bool,
-    caustic_photon_map_enabled: bool,
+    bvh: Option<Bvh>,
+    bvh_leaf_size: usize,
+    transparent_shadows: bool,
 }
 
 impl Default for World {
@@ -56,14 +52,12 @@ impl World {
     pub fn new(lights: Vec<Box<dyn Light>>, objects: Vec<SceneItem>) -> Self {
         log::info!("Creating new World object");
         World {
+            integrator: Box::new(Whitted::default()),
             lights,
             objects,
-            max_reflection_depth: 10,
-            photon_map: None,
-            direct_illumination_enabled: true,
-            direct_photon_map_enabled: false,
-            diffuse_photon_map_enabled: true,
-            caustic_photon_map_enabled: true,
+            bvh: None,
+            bvh_leaf_size: 4,
+            transparent_shadows: false,
         }
     }
 
@@ -71,20 +65,13 @@ impl World {
         World::new(vec![], vec![])
     }
 
-    pub fn enable_direct_illumination(&mut self, flag: bool) {
-        self.direct_illumination_enabled = flag;
+    /// Set how the light is computed when rendering.
+    pub fn set_integrator(&mut self, integrator: impl Integrator) {
+        self.integrator = Box::new(integrator);
     }
 
-    pub fn enable_direct_photon_map(&mut self, flag: bool) {
-        self.direct_photon_map_enabled = flag;
-    }
-
-    pub fn enable_diffuse_photon_map(&mut self, flag: bool) {
-        self.diffuse_photon_map_enabled = flag;
-    }
-
-    pub fn enable_caustic_photon_map(&mut self, flag: bool) {
-        self.caustic_photon_map_enabled = flag;
+    pub fn integrator(&self) -> &dyn Integrator {
+        &*self.integrator
     }
 
     pub fn add_light(&mut self, light: impl Light) {
@@ -93,197 +80,194 @@ impl World {
 
     pub fn add_item(&mut self, item: impl Into<SceneItem>) {
         self.objects.push(item.into());
+        self.bvh = None;
+    }
+
+    /// Set the maximum number of items in a leaf of the bounding volume hierarchy.
+    pub fn set_bvh_leaf_size(&mut self, n: usize) {
+        self.bvh_leaf_size = n;
     }
 
+    /// Let partially transparent objects cast lighter shadows, according to the
+    /// `shadow_transmittance` of their materials. By default, every object blocks light
+    /// completely, like in the book.
+    pub fn set_transparent_shadows(&mut self, enabled: bool) {
+        self.transparent_shadows = enabled;
+    }
+
+    /// Statistics of the bounding volume hierarchy, if the scene has been finalized.
+    pub fn bvh_stats(&self) -> Option<&BvhStats> {
+        self.bvh.as_ref().map(Bvh::stats)
+    }
+
+    /// Prepare the scene for rendering. All groups are dissolved and a bounding volume
+    /// hierarchy is built over the shapes they contained.
     pub fn finalize_scene(&mut self) {
         for obj in &mut self.objects {
             obj.update_transform(Matrix::identity());
         }
-        for obj in &mut self.objects {
-            obj.update_aabb();
+
+        let mut objects = Vec::with_capacity(self.objects.len());
+        for obj in self.objects.drain(..) {
+            obj.flatten_into(&mut objects);
         }
+        self.objects = objects;
+
+        let aabbs: Vec<_> = self
+            .objects
+            .iter_mut()
+            .map(SceneItem::update_aabb)
+            .collect();
+        let bvh = Bvh::build(&aabbs, self.bvh_leaf_size);
+        log::info!("Built BVH: {}", bvh.stats());
+        self.bvh = Some(bvh);
     }
 
     pub fn lights(&self) -> &[Box<dyn Light>] {
         &self.lights
     }
 
-    pub fn trace(&self, ray: &Ray) -> Option<Color> {
-        self.color_at(ray, self.max_reflection_depth)
-    }
-
-    pub fn color_at(&self, ray: &Ray, remaining_bounces: u32) -> Option<Color> {
-        let xs = self.intersect(ray);
-        hit(&xs).map(|i| self.shade_hit(i.prepare_computations(&ray, &xs), remaining_bounces))
+    /// The objects of the scene. After `finalize_scene`, these are the shapes and CSG pairs
+    /// without any groups.
+    pub fn objects(&self) -> &[SceneItem] {
+        &self.objects
     }
 
-    pub fn direct_illumination_enabled(&self) -> bool {
-        self.direct_illumination_enabled
+    pub fn trace(&self, ray: &Ray, rng: &mut Prng) -> Option<Color> {
+        self.integrator.trace(self, ray, rng)
     }
 
-    pub fn photon_map_enabled(&self) -> bool {
-        self.direct_photon_map_enabled
-            || self.caustic_photon_map_enabled
-            || self.diffuse_photon_map_enabled
-    }
-
-    pub fn direct_photon_map_only(&self) -> bool {
-        self.direct_photon_map_enabled
-            && !self.caustic_photon_map_enabled
-            && !self.diffuse_photon_map_enabled
-    }
-
-    pub fn get_photon_map(&self) -> Option<(&PhotonMap, usize)> {
-        self.photon_map.as_ref().map(|(pm, n)| (pm, *n))
+    /// The light that arrives directly from all light sources, as reflected by the surface.
+    pub fn direct_light(&self, comps: &IntersectionState, rng: &mut Prng) -> Color {
+        self.lights.iter().fold(BLACK, |color, light| {
+            let mut incoming_light = light.incoming_at(comps.over_point, rng);
+            let visibility = self.light_visibility(&incoming_light, comps.over_point);
+            if let IncomingLight::Ray(lr) = &mut incoming_light {
+                lr.color = lr.color * visibility;
+            }
+            color
+                + comps
+                    .obj
+                    .material()
+                    .lighting(incoming_light, comps, visibility.is_black())
+        })
     }
 
-    fn shade_hit(&self, comps: IntersectionState, remaining_bounces: u32) -> Color {
-        let material = comps.obj.material();
-        material.shade_hit(self, &comps, remaining_bounces)
+    pub fn intersect(&self, ray: &Ray) -> Vec<Intersection> {
+        let mut xs = vec![];
+        match &self.bvh {
+            Some(bvh) => bvh.traverse(ray, |i| self.objects[i].intersect_into(ray, &mut xs)),
+            None => {
+                for obj in &self.objects {
+                    obj.intersect_into(ray, &mut xs)
+                }
+            }
+        }
+        sort_intersections(xs)
     }
 
-    pub fn intersect(&self, ray: &Ray) -> Vec<Intersection> {
-        let mut xs: Vec<_> = self
-            .objects
-            .iter()
-            .flat_map(|obj| obj.intersect(ray))
-            .collect();
-        xs.sort_unstable_by(|a, b| {
-            a.t.partial_cmp(&b.t)
-                .expect("Unable to compare intersection distances")
-        });
-        xs
+    /// The intersections needed to shade the first surface that `ray` hits: all of them if the
+    /// surface is transparent, because its refractive indices depend on the objects the ray
+    /// passed through before, and only the nearest hit otherwise.
+    pub fn hit_intersections(&self, ray: &Ray) -> Vec<Intersection<'_>> {
+        match self.nearest_hit(ray) {
+            Some(hit) if !hit.obj.material().is_transparent() => vec![hit],
+            Some(_) => self.intersect(ray),
+            None => vec![],
+        }
     }
 
-    pub fn intersect_shadow(&self, ray: &Ray) -> Vec<Intersection> {
-        let mut xs: Vec<_> = self
-            .objects
-            .iter()
-            .filter(|obj| obj.cast_shadow())
-            .flat_map(|obj| obj.intersect(ray))
-            .collect();
-        xs.sort_unstable_by(|a, b| {
-            a.t.partial_cmp(&b.t)
-                .expect("Unable to compare intersection distances")
-        });
-        xs
+    /// The closest intersection in front of the ray origin.
+    ///
+    /// This is much cheaper than `intersect` in a finalized scene, because objects beyond the
+    /// closest hit are skipped. However, the intersection alone is not enough to compute
+    /// refractive indices, which depend on all objects the ray passed through before.
+    pub fn nearest_hit(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let mut xs = vec![];
+        let nearest_of = |i: usize| {
+            xs.clear();
+            self.objects[i].intersect_into(ray, &mut xs);
+            xs.iter()
+                .filter(|i| i.t >= 0.0)
+                .min_by(|a, b| compare_t(a.t, b.t))
+                .map(|&i| (i.t, i))
+        };
+        match &self.bvh {
+            Some(bvh) => bvh.nearest(ray, nearest_of),
+            None => (0..self.objects.len())
+                .filter_map(nearest_of)
+                .min_by(|a, b| compare_t(a.0, b.0)),
+        }
+        .map(|(_, i)| i)
     }
 
     pub fn is_shadowed(&self, light: &IncomingLight, p: Point) -> bool {
         match light {
-            IncomingLight::Ray(lr) => hit(&self.intersect_shadow(&Ray::new(p, lr.direction)))
-                .map(|i| i.t < (lr.origin - p).len())
-                .unwrap_or(false),
+            IncomingLight::Ray(lr) => {
+                self.occluded(&Ray::new(p, lr.direction), (lr.origin - p).len())
+            }
             IncomingLight::Omni(_) => false,
             IncomingLight::NoLight => true,
         }
     }
 
-    pub fn drop_photon_map(&mut self) {
-        self.photon_map = None;
-    }
-
-    pub fn compute_photon_map(
-        &mut self,
-        n_photons: usize,
-        n_nearest: usize,
-        max_search_radius: f64,
-    ) {
-        self.drop_photon_map(); // Save some memory
-
-        log::info!("Tracing {} photons", n_photons);
-        let photons = (0..n_photons)
-            .into_par_iter()
-            .map(|_| self.emit_photon(&mut thread_rng()))
-            .map(|photon| self.trace_photon(photon, &mut thread_rng()))
-            .flatten()
-            .map(|photon| photon.scale_power(1.0 / n_photons as f64))
-            .collect();
-
-        log::info!("Balancing photon map");
-        let mut map = PhotonMap::from_vec(photons);
-        map.set_max_search_radius(max_search_radius);
-        self.photon_map = Some((map, n_nearest));
-
-        log::info!("Photon map complete");
+    /// Whether a shadow casting object lies on the ray at a distance in `[0, distance)`. The
+    /// search stops at the first blocker found.
+    pub fn occluded(&self, ray: &Ray, distance: f64) -> bool {
+        match &self.bvh {
+            Some(bvh) => bvh.any(ray, distance, |i| self.objects[i].occludes(ray, distance)),
+            None => self.objects.iter().any(|obj| obj.occludes(ray, distance)),
+        }
     }
 
-    pub fn emit_photon(&self, rng: &mut impl Rng) -> TravellingPhoton {
-        let dist = WeightedIndex::new(self.lights.iter().map(|light| light.power())).unwrap();
-        let idx = dist.sample(rng);
-        self.lights[idx].emit_photon().into()
+    /// The fraction of light that passes along the ray up to `distance`, attenuated by every
+    /// shadow casting surface it crosses. The search stops at the first opaque blocker.
+    pub fn shadow_transmittance(&self, ray: &Ray, distance: f64) -> Color {
+        let mut transmittance = WHITE;
+        let mut attenuate = |obj: &SceneItem| {
+            transmittance = transmittance * obj.shadow_transmittance(ray, distance);
+            transmittance.is_black()
+        };
+        match &self.bvh {
+            Some(bvh) => bvh.any(ray, distance, |i| attenuate(&self.objects[i])),
+            None => self.objects.iter().any(attenuate),
+        };
+        transmittance
     }
 
-    pub fn trace_photon(
-        &self,
-        mut photon: TravellingPhoton,
-        _rng: &mut impl Rng,
-    ) -> Vec<StoredPhoton> {
-        let mut hits = vec![];
-        loop {
-            if photon.power() < EPSILON {
-                return hits;
-            }
-
-            match photon.kind() {
-                PhotonKind::Direct => {}
-                _ if self.direct_photon_map_only() => return hits,
-                _ => {}
-            }
-
-            let xs = self.intersect(photon.ray());
-
-            let maybe_hit = hit(&xs);
-
-            if let Some(obj) = origin_object(&xs) {
-                if let Some(p) = obj.material().transform_photon(photon, maybe_hit) {
-                    photon = p;
-                    continue;
-                }
-            }
-
-            let hit = match maybe_hit {
-                Some(h) => h,
-                None => return hits,
-            };
-
-            let comps = hit.prepare_computations(&photon.ray(), &xs);
-            let mat = hit.obj.material();
-
-            let (store_photon, next_photon) =
-                mat.photon_hit(photon, &comps, self.diffuse_photon_map_enabled);
-
-            if let Some(p) = store_photon {
-                if match p.kind() {
-                    PhotonKind::Direct if self.direct_photon_map_enabled => true,
-                    PhotonKind::Diffuse if self.diffuse_photon_map_enabled => true,
-                    PhotonKind::Caustic if self.caustic_photon_map_enabled => true,
-                    _ => false,
-                } {
-                    hits.push(p.store(comps.point));
-                }
-            }
-
-            if let Some(p) = next_photon {
-                photon = p;
-            } else {
-                return hits;
+    /// The fraction of the incoming light that reaches point `p`.
+    pub fn light_visibility(&self, light: &IncomingLight, p: Point) -> Color {
+        match light {
+            IncomingLight::Ray(lr) if self.transparent_shadows => {
+                self.shadow_transmittance(&Ray::new(p, lr.direction), (lr.origin - p).len())
             }
+            _ if self.is_shadowed(light, p) => BLACK,
+            _ => WHITE,
         }
     }
 }
 
+fn compare_t(a: f64, b: f64) -> std::cmp::Ordering {
+    a.partial_cmp(&b)
+        .expect("Unable to compare intersection distances")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::approx_eq::ApproximateEq;
     use crate::approx_eq::FindSimilar;
     use crate::color::{color, BLACK};
+    use crate::integrators::{PathTracing, RecursiveTracing};
     use crate::materials::{Phong, SurfaceColor};
+    use crate::matrix::{rotation_x, rotation_y};
     use crate::matrix::{scaling, translation};
+    use crate::obj_loader::ObjParser;
     use crate::pattern::Pattern;
+    use crate::shapes::{group, Group};
     use crate::shapes::{plane, sphere};
     use crate::tuple::{point, vector};
+    use std::f64::consts::PI;
     use std::f64::consts::{FRAC_1_SQRT_2, SQRT_2};
 
     /// Creating a world
@@ -336,7 +320,7 @@ mod tests {
         let i = Intersection::new(4.0, shape);
         let xs = [i];
         let comps = i.prepare_computations(&r, &xs);
-        let c = w.shade_hit(comps, 0);
+        let c = Whitted::default().shade_hit(&w, &comps, 0, &mut Prng::new(0));
         assert_almost_eq!(c, color(0.38066, 0.47583, 0.2855));
     }
 
@@ -350,7 +334,7 @@ mod tests {
         w.lights = vec![Box::new(PointLight::new(point(0, 0.25, 0), color(1, 1, 1)))];
         let xs = [i];
         let comps = i.prepare_computations(&r, &xs);
-        let c = w.shade_hit(comps, 0);
+        let c = Whitted::default().shade_hit(&w, &comps, 0, &mut Prng::new(0));
         assert_almost_eq!(c, color(0.90498, 0.90498, 0.90498));
     }
 
@@ -359,7 +343,7 @@ mod tests {
     fn miss() {
         let w = World::default();
         let r = Ray::new(point(0, 0, -5), vector(0, 1, 0));
-        let c = w.color_at(&r, 0);
+        let c = Whitted::default().color_at(&w, &r, 0, &mut Prng::new(0));
         assert_eq!(c, None);
     }
 
@@ -368,7 +352,7 @@ mod tests {
     fn hit() {
         let w = World::default();
         let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
-        let c = w.color_at(&r, 0);
+        let c = Whitted::default().color_at(&w, &r, 0, &mut Prng::new(0));
         assert_eq!(c, Some(color(0.38066, 0.47583, 0.2855)));
     }
 
@@ -400,7 +384,9 @@ mod tests {
             .as_shape_mut()
             .unwrap()
             .set_material(m1.clone());
-        let c = w.color_at(&r, 0).unwrap();
+        let c = Whitted::default()
+            .color_at(&w, &r, 0, &mut Prng::new(0))
+            .unwrap();
         assert_almost_eq!(SurfaceColor::Flat(c), m1.color());
     }
 
@@ -409,7 +395,7 @@ mod tests {
     fn shadow1() {
         let w = World::default();
         let p = point(0, 10, 0);
-        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p), p))
+        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p, &mut Prng::new(0)), p))
     }
 
     /// There is shadow when an object is between point and light
@@ -417,7 +403,7 @@ mod tests {
     fn shadow2() {
         let w = World::default();
         let p = point(10, -10, 10);
-        assert!(w.is_shadowed(&w.lights[0].incoming_at(p), p))
+        assert!(w.is_shadowed(&w.lights[0].incoming_at(p, &mut Prng::new(0)), p))
     }
 
     /// There is no shadow if the object is behind the light
@@ -425,7 +411,7 @@ mod tests {
     fn shadow3() {
         let w = World::default();
         let p = point(-20, 20, -20);
-        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p), p))
+        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p, &mut Prng::new(0)), p))
     }
 
     /// There is no shadow if the object is behind the point
@@ -433,7 +419,7 @@ mod tests {
     fn shadow4() {
         let w = World::default();
         let p = point(-2, 2, -2);
-        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p), p))
+        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p, &mut Prng::new(0)), p))
     }
 
     /// There is no shadow when the object between point and light does not cast shadows
@@ -443,7 +429,7 @@ mod tests {
         w.objects[0].as_shape_mut().unwrap().set_cast_shadow(false);
         w.objects[1].as_shape_mut().unwrap().set_cast_shadow(false);
         let p = point(10, -10, 10);
-        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p), p))
+        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p, &mut Prng::new(0)), p))
     }
 
     /// Shading an intersection in shadow
@@ -458,10 +444,67 @@ mod tests {
         let i = Intersection::new(4.0, w.objects[1].as_shape().unwrap());
         let xs = [i];
         let comps = i.prepare_computations(&r, &xs);
-        let c = w.shade_hit(comps, 0);
+        let c = Whitted::default().shade_hit(&w, &comps, 0, &mut Prng::new(0));
         assert_almost_eq!(c, BLACK);
     }
 
+    /// Occlusion queries only consider blockers before the given distance, with and without
+    /// bounding volume hierarchy
+    #[test]
+    fn occlusion_distance() {
+        let mut w = World::default();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        for _ in 0..2 {
+            assert!(!w.occluded(&r, 3.9));
+            assert!(w.occluded(&r, 4.1));
+            assert!(w.occluded(&r, 100.0));
+            assert!(!w.occluded(&Ray::new(point(0, 0, 5), vector(0, 0, 1)), 100.0));
+            w.finalize_scene();
+        }
+    }
+
+    /// Partially transparent objects attenuate the light at every surface the shadow ray
+    /// crosses, if transparent shadows are enabled
+    #[test]
+    fn transparent_shadows() {
+        let world = |blocker: Phong| {
+            let mut w = World::empty();
+            w.add_light(PointLight::new(point(0, 0, -10), color(1, 1, 1)));
+            w.add_item(sphere().with_material(blocker));
+            w.add_item(sphere().with_transform(translation(0, 0, 10)));
+            w
+        };
+        let light_at_back_sphere = |w: &World| {
+            let r = Ray::new(point(0, 0, 5), vector(0, 0, 1));
+            let i = Intersection::new(4.0, w.objects[1].as_shape().unwrap());
+            let xs = [i];
+            let comps = i.prepare_computations(&r, &xs);
+            w.direct_light(&comps, &mut Prng::new(0))
+        };
+        let glass = Phong::default().with_transparency(0.5);
+
+        let mut w = world(glass);
+        let to_light = Ray::new(point(0, 0, 8), vector(0, 0, -1));
+        assert_almost_eq!(
+            w.shadow_transmittance(&to_light, 18.0),
+            color(0.25, 0.25, 0.25)
+        );
+        assert_almost_eq!(w.shadow_transmittance(&to_light, 8.5), color(0.5, 0.5, 0.5));
+        assert_almost_eq!(light_at_back_sphere(&w), BLACK);
+
+        w.set_transparent_shadows(true);
+        let attenuated = light_at_back_sphere(&w);
+        w.objects[0].set_cast_shadow(false);
+        let unblocked = light_at_back_sphere(&w);
+        assert!(unblocked.red() > 0.1);
+        assert_almost_eq!(attenuated, unblocked * 0.25);
+
+        let mut w = world(Phong::default());
+        w.set_transparent_shadows(true);
+        assert_almost_eq!(w.shadow_transmittance(&to_light, 18.0), BLACK);
+        assert_almost_eq!(light_at_back_sphere(&w), BLACK);
+    }
+
     /// The reflected color of a nonreflective material
     #[test]
     fn reflect_nothing() {
@@ -472,14 +515,7 @@ mod tests {
         let i = Intersection::new(1.0, w.objects[1].as_shape().unwrap());
         let xs = [i];
         let comps = i.prepare_computations(&r, &xs);
-        let c = w.objects[1]
-            .as_shape()
-            .unwrap()
-            .material()
-            .as_any()
-            .downcast_ref::<Phong>()
-            .unwrap()
-            .reflected_color(&w, &comps, 1);
+        let c = Whitted::default().reflected_color(&w, &comps, 1, &mut Prng::new(0));
         assert_almost_eq!(c, color(0, 0, 0));
     }
 
@@ -495,14 +531,7 @@ mod tests {
         let i = Intersection::new(SQRT_2, w.objects[2].as_shape().unwrap());
         let xs = [i];
         let comps = i.prepare_computations(&r, &xs);
-        let c = w.objects[2]
-            .as_shape()
-            .unwrap()
-            .material()
-            .as_any()
-            .downcast_ref::<Phong>()
-            .unwrap()
-            .reflected_color(&w, &comps, 1);
+        let c = Whitted::default().reflected_color(&w, &comps, 1, &mut Prng::new(0));
         assert_almost_eq!(c, color(0.19033, 0.23792, 0.14274));
     }
 
@@ -518,7 +547,7 @@ mod tests {
         let i = Intersection::new(SQRT_2, w.objects[2].as_shape().unwrap());
         let xs = [i];
         let comps = i.prepare_computations(&r, &xs);
-        let c = w.shade_hit(comps, 1);
+        let c = Whitted::default().shade_hit(&w, &comps, 1, &mut Prng::new(0));
         assert_almost_eq!(c, color(0.77676, 0.82434, 0.72917));
     }
 
@@ -534,14 +563,7 @@ mod tests {
         let i = Intersection::new(SQRT_2, w.objects[2].as_shape().unwrap());
         let xs = [i];
         let comps = i.prepare_computations(&r, &xs);
-        let c = w.objects[2]
-            .as_shape()
-            .unwrap()
-            .material()
-            .as_any()
-            .downcast_ref::<Phong>()
-            .unwrap()
-            .reflected_color(&w, &comps, 0);
+        let c = Whitted::default().reflected_color(&w, &comps, 0, &mut Prng::new(0));
         assert_almost_eq!(c, color(0, 0, 0));
     }
 
@@ -570,7 +592,11 @@ mod tests {
             ),
         );
         w.add_light(PointLight::new(point(0, 0, 0), color(1, 1, 1)));
-        w.trace(&Ray::new(point(0, 0, 0), vector(0, 1, 0))).unwrap(); // just make sure the function returns
+        w.trace(
+            &Ray::new(point(0, 0, 0), vector(0, 1, 0)),
+            &mut Prng::new(0),
+        )
+        .unwrap(); // just make sure the function returns
     }
 
     /// The refracted color with an opaque surface
@@ -581,12 +607,7 @@ mod tests {
         let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
         let xs = intersections![Intersection::new(4.0, shape), Intersection::new(6.0, shape)];
         let comps = xs[0].prepare_computations(&r, &xs);
-        let c = shape
-            .material()
-            .as_any()
-            .downcast_ref::<Phong>()
-            .unwrap()
-            .refracted_color(&w, &comps, 5);
+        let c = Whitted::default().refracted_color(&w, &comps, 5, &mut Prng::new(0));
         assert_almost_eq!(c, color(0, 0, 0));
     }
 
@@ -603,12 +624,7 @@ mod tests {
         let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
         let xs = intersections![Intersection::new(4.0, shape), Intersection::new(6.0, shape)];
         let comps = xs[0].prepare_computations(&r, &xs);
-        let c = shape
-            .material()
-            .as_any()
-            .downcast_ref::<Phong>()
-            .unwrap()
-            .refracted_color(&w, &comps, 0);
+        let c = Whitted::default().refracted_color(&w, &comps, 0, &mut Prng::new(0));
         assert_almost_eq!(c, color(0, 0, 0));
     }
 
@@ -628,12 +644,7 @@ mod tests {
             Intersection::new(FRAC_1_SQRT_2, shape)
         ];
         let comps = xs[1].prepare_computations(&r, &xs);
-        let c = shape
-            .material()
-            .as_any()
-            .downcast_ref::<Phong>()
-            .unwrap()
-            .refracted_color(&w, &comps, 5);
+        let c = Whitted::default().refracted_color(&w, &comps, 5, &mut Prng::new(0));
         assert_almost_eq!(c, color(0, 0, 0));
     }
 
@@ -666,12 +677,7 @@ mod tests {
             Intersection::new(0.9899, a)
         ];
         let comps = xs[2].prepare_computations(&r, &xs);
-        let c = b
-            .material()
-            .as_any()
-            .downcast_ref::<Phong>()
-            .unwrap()
-            .refracted_color(&w, &comps, 5);
+        let c = Whitted::default().refracted_color(&w, &comps, 5, &mut Prng::new(0));
         assert_almost_eq!(c, color(0, 0.99888, 0.04722));
     }
 
@@ -697,7 +703,7 @@ mod tests {
         let r = Ray::new(point(0, 0, -3), vector(0, -FRAC_1_SQRT_2, FRAC_1_SQRT_2));
         let xs = intersections![Intersection::new(SQRT_2, floor),];
         let comps = xs[0].prepare_computations(&r, &xs);
-        let c = w.shade_hit(comps, 5);
+        let c = Whitted::default().shade_hit(&w, &comps, 5, &mut Prng::new(0));
         assert_almost_eq!(c, color(0.83642, 0.58642, 0.58642));
     }
 
@@ -724,7 +730,244 @@ mod tests {
         let r = Ray::new(point(0, 0, -3), vector(0, -FRAC_1_SQRT_2, FRAC_1_SQRT_2));
         let xs = intersections![Intersection::new(SQRT_2, floor),];
         let comps = xs[0].prepare_computations(&r, &xs);
-        let c = w.shade_hit(comps, 5);
+        let c = Whitted::default().shade_hit(&w, &comps, 5, &mut Prng::new(0));
         assert_almost_eq!(c, color(0.83391, 0.59643, 0.59243));
     }
+
+    /// Without other objects to bounce off, a path traced ray sees the emission and the direct
+    /// light of a ray traced one, with the direct light divided by π
+    #[test]
+    fn path_traced_direct_light() {
+        let mut w = World::default();
+        w.set_integrator(PathTracing::default());
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let c = w.trace(&r, &mut Prng::new(0));
+        let emission = color(0.08, 0.1, 0.06);
+        let direct = color(0.38066, 0.47583, 0.2855) - emission;
+        assert_almost_eq!(c.unwrap(), emission + direct / PI);
+        assert_eq!(
+            w.trace(
+                &Ray::new(point(0, 0, -5), vector(0, 1, 0)),
+                &mut Prng::new(0)
+            ),
+            None
+        );
+    }
+
+    /// Inside a glowing diffuse sphere, each bounce adds the reflected glow: with reflectance
+    /// 0.5 the radiance converges to 1 + 0.5 + 0.25 + ... = 2
+    #[test]
+    fn path_traced_interreflection() {
+        let mut w = World::empty();
+        w.add_item(
+            sphere().with_material(
+                Phong::default()
+                    .with_emissive(1.0)
+                    .with_diffuse(0.5)
+                    .with_specular(0.0),
+            ),
+        );
+        let r = Ray::new(point(0, 0, 0), vector(0, 0, 1));
+
+        assert_almost_eq!(w.trace(&r, &mut Prng::new(0)).unwrap(), color(1, 1, 1));
+
+        w.set_integrator(PathTracing::default());
+        let rng = &mut Prng::new(0);
+        let n = 10000;
+        let mean = (0..n).map(|_| w.trace(&r, rng).unwrap()).sum::<Color>() / n;
+        assert!((mean.red() - 2.0).abs() < 0.02, "{:?}", mean);
+    }
+
+    /// A diffuse plane under a white sky of radiance 1 reflects its albedo, no matter whether
+    /// the sky is an emissive dome that bounced paths hit or a hemisphere of point lights that
+    /// is sampled directly
+    #[test]
+    fn path_traced_white_sky() {
+        let floor = || {
+            plane().with_material(
+                Phong::default()
+                    .with_color(color(1, 0.5, 0.25))
+                    .with_diffuse(0.8)
+                    .with_specular(0.0),
+            )
+        };
+        let albedo = color(0.8, 0.4, 0.2);
+        let error = |c: Color| {
+            let d = c - albedo;
+            d.red().abs().max(d.green().abs()).max(d.blue().abs())
+        };
+        let r = Ray::new(point(0, 1, -1), vector(0, -1, 1));
+
+        let mut w = World::empty();
+        w.set_integrator(PathTracing::default());
+        w.add_item(floor());
+        w.add_item(
+            sphere()
+                .with_transform(scaling(100, 100, 100))
+                .with_material(
+                    Phong::default()
+                        .with_emissive(1.0)
+                        .with_diffuse(0.0)
+                        .with_specular(0.0),
+                ),
+        );
+        let rng = &mut Prng::new(0);
+        let n = 10000;
+        let mean = (0..n).map(|_| w.trace(&r, rng).unwrap()).sum::<Color>() / n;
+        assert!(error(mean) < 0.01, "{:?}", mean);
+
+        // Point lights in the directions of n_z * n_phi cells of equal solid angle, each
+        // standing for the 2π / (n_z * n_phi) steradians of sky around it
+        let mut w = World::empty();
+        w.set_integrator(PathTracing::default());
+        w.add_item(floor());
+        let (n_z, n_phi) = (50, 20);
+        let intensity = 2.0 * PI / (n_z * n_phi) as f64;
+        for i in 0..n_z {
+            for j in 0..n_phi {
+                let y = (i as f64 + 0.5) / n_z as f64;
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n_phi as f64;
+                let r = 1000.0 * (1.0 - y * y).sqrt();
+                w.add_light(PointLight::new(
+                    point(r * phi.cos(), 1000.0 * y, r * phi.sin()),
+                    color(intensity, intensity, intensity),
+                ));
+            }
+        }
+        let c = w.trace(&r, &mut Prng::new(0)).unwrap();
+        assert!(error(c) < 0.01, "{:?}", c);
+    }
+
+    /// Finalizing the scene dissolves groups into a bounding volume hierarchy without moving
+    /// anything
+    #[test]
+    fn finalize_flattens_groups() {
+        let mut inner = group().with_transform(translation(0, 2, 0));
+        inner.add_child(sphere().with_transform(scaling(0.5, 0.5, 0.5)));
+        inner.add_child(sphere().with_transform(translation(3, 0, 0)));
+        let mut outer = group().with_transform(rotation_y(PI / 2.0));
+        outer.add_child(inner);
+        outer.add_child(sphere());
+
+        let mut w = World::empty();
+        w.add_item(plane().with_transform(translation(0, -1, 0)));
+        w.add_item(outer);
+
+        let mut reference = World::empty();
+        reference.add_item(plane().with_transform(translation(0, -1, 0)));
+        reference.add_item(sphere().with_transform(translation(0, 2, 0) * scaling(0.5, 0.5, 0.5)));
+        reference.add_item(sphere().with_transform(translation(0, 2, -3)));
+        reference.add_item(sphere());
+
+        w.finalize_scene();
+        assert_eq!(w.objects.len(), 4);
+        let stats = w.bvh_stats().unwrap();
+        assert_eq!(stats.n_items, 4);
+        assert_eq!(stats.n_unbounded, 1);
+
+        // finalizing twice does not move the shapes again
+        w.finalize_scene();
+
+        for ray in &[
+            Ray::new(point(0, 2, -10), vector(0, 0, 1)),
+            Ray::new(point(0, 10, -3), vector(0, -1, 0)),
+            Ray::new(point(-5, 0, 0), vector(1, 0, 0)),
+        ] {
+            let xs = w.intersect(ray);
+            let expected = reference.intersect(ray);
+            assert_eq!(xs.len(), expected.len());
+            for (a, b) in xs.iter().zip(&expected) {
+                assert_almost_eq!(a.t, b.t);
+            }
+        }
+    }
+
+    /// Shapes in groups that do not cast shadows do not cast shadows after finalizing
+    #[test]
+    fn finalize_keeps_shadow_flags() {
+        let mut g = group();
+        g.add_child(sphere());
+        let mut w = World::default();
+        w.objects = vec![SceneItem::from(g)];
+        w.objects[0].set_cast_shadow(false);
+        w.finalize_scene();
+        let p = point(10, -10, 10);
+        assert!(!w.is_shadowed(&w.lights[0].incoming_at(p, &mut Prng::new(0)), p))
+    }
+
+    /// The bounding volume hierarchy finds the same intersections with a mesh as testing every
+    /// triangle
+    #[test]
+    fn teapot_bvh() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../data/teapot.obj");
+        let data = std::fs::read_to_string(path).unwrap();
+        let teapot: Group = ObjParser::parse_str(&data).unwrap().into();
+        let teapot = teapot.with_transform(rotation_x(-PI / 2.0) * scaling(0.2, 0.2, 0.2));
+
+        let mut brute_force = World::empty();
+        brute_force.add_item(teapot.clone());
+        brute_force.objects[0].update_transform(Matrix::identity());
+
+        let mut w = World::empty();
+        w.add_item(teapot);
+        w.finalize_scene();
+        let stats = w.bvh_stats().unwrap();
+        assert!(stats.n_items > 1000);
+        assert!(stats.max_leaf_items <= 4);
+
+        let mut n_hits = 0;
+        for i in 0..20 {
+            let x = i as f64 * 0.3 - 3.0;
+            let ray = Ray::new(point(x, 1.5, -10), vector(0.02, 0.01, 1));
+            let xs: Vec<_> = w.intersect(&ray).iter().map(|i| i.t).collect();
+            let expected: Vec<_> = brute_force.intersect(&ray).iter().map(|i| i.t).collect();
+            assert_eq!(xs, expected);
+            n_hits += xs.len();
+
+            let nearest = w.nearest_hit(&ray).map(|i| i.t);
+            assert_eq!(nearest, expected.iter().cloned().find(|&t| t >= 0.0));
+            assert_eq!(brute_force.nearest_hit(&ray).map(|i| i.t), nearest);
+        }
+        assert!(n_hits > 20);
+    }
+
+    /// The nearest hit is the first intersection in front of the ray origin
+    #[test]
+    fn nearest_hit() {
+        let mut w = World::default();
+        for finalized in &[false, true] {
+            if *finalized {
+                w.finalize_scene();
+            }
+            let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+            assert_almost_eq!(w.nearest_hit(&r).unwrap().t, 4.0);
+            let r = Ray::new(point(0, 0, 0), vector(0, 0, 1));
+            assert_almost_eq!(w.nearest_hit(&r).unwrap().t, 0.5);
+            let r = Ray::new(point(0, 0, -5), vector(0, 1, 0));
+            assert!(w.nearest_hit(&r).is_none());
+        }
+    }
+
+    /// All intersections are only computed when the hit surface is transparent
+    #[test]
+    fn hit_intersections() {
+        let mut w = World::default();
+        let r = Ray::new(point(0, 0, -5), vector(0, 0, 1));
+        let xs = w.hit_intersections(&r);
+        assert_eq!(xs.len(), 1);
+        assert_almost_eq!(xs[0].t, 4.0);
+
+        w.objects[0].as_shape_mut().unwrap().set_material(
+            default_material1()
+                .with_transparency(1.0)
+                .with_refractive_index(1.5),
+        );
+        let xs = w.hit_intersections(&r);
+        assert_eq!(xs.len(), 4);
+        let comps = xs[0].prepare_computations(&r, &xs);
+        assert_eq!((comps.n1, comps.n2), (1.0, 1.5));
+
+        let r = Ray::new(point(0, 0, -5), vector(0, 1, 0));
+        assert!(w.hit_intersections(&r).is_empty());
+    }
 }
@@ -0,0 +1,238 @@
+//! Regression tests that render small scenes and compare them to the images in
+//! `tests/golden/`. Rendering is deterministic, so any visible change of the output is
+//! detected.
+//!
+//! After an intended change of the renderer, update the images with
+//!
+//!     UPDATE_GOLDEN=1 cargo test --test golden_images
+//!
+//! and inspect the result before committing.
+
+use raytracing::camera::Camera;
+use raytracing::canvas::Canvas;
+use raytracing::color::color;
+use raytracing::image_diff::compare;
+use raytracing::integrators::PhotonMapping;
+use raytracing::lights::{DiscLight, SphereLight};
+use raytracing::materials::Phong;
+use raytracing::matrix::{rotation_x, rotation_y, rotation_z, translation};
+use raytracing::pattern::checkers_pattern;
+use raytracing::shapes::{cube, plane, sphere};
+use raytracing::tuple::{point, vector};
+use raytracing::world::World;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::path::Path;
+
+/// PNG files store 8 bits per channel, so the rendered colors are compared after rounding.
+const TOLERANCE: f64 = 1.5 / 255.0;
+
+fn check_golden(name: &str, image: &Canvas) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.png", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        image.write_png(&mut File::create(&path).unwrap()).unwrap();
+        return;
+    }
+
+    let golden = File::open(&path)
+        .and_then(|mut f| Canvas::read_png(&mut f))
+        .unwrap_or_else(|e| {
+            panic!(
+                "{}: {} (run with UPDATE_GOLDEN=1 to create it)",
+                path.display(),
+                e
+            )
+        });
+
+    let diff = compare(image, &golden, TOLERANCE);
+    if diff.mismatched_pixels > 0 {
+        let actual = std::env::temp_dir().join(format!("{}-actual.png", name));
+        let mask = std::env::temp_dir().join(format!("{}-mismatch.png", name));
+        image
+            .write_png(&mut File::create(&actual).unwrap())
+            .unwrap();
+        diff.mask
+            .write_png(&mut File::create(&mask).unwrap())
+            .unwrap();
+        panic!(
+            "{}: {} pixels differ from the golden image (max error {:.3}), see {} and {}",
+            name,
+            diff.mismatched_pixels,
+            diff.max_error,
+            actual.display(),
+            mask.display()
+        );
+    }
+}
+
+fn soft_shadow_scene() -> (World, Camera) {
+    let mut world = World::empty();
+    world.add_light(SphereLight::new(
+        point(-5, 4, -5),
+        1.5,
+        color(1.5, 1.5, 1.5),
+    ));
+
+    let white = Phong::default().with_diffuse(0.8).with_specular(0.0);
+    world.add_item(plane().with_material(white.clone()));
+    world.add_item(
+        cube()
+            .with_material(white)
+            .with_transform(translation(0, 1, 0) * rotation_y(0.4)),
+    );
+    world.finalize_scene();
+
+    let mut camera = Camera::new(48, 24, PI / 3.0).with_view_transform(
+        point(6, 8, -10),
+        point(0, 1, 0),
+        vector(0, 1, 0),
+    );
+    camera.set_min_samples(4);
+    camera.set_allowed_standard_error(0.05);
+    (world, camera)
+}
+
+fn depth_of_field_scene() -> (World, Camera) {
+    let mut world = World::default();
+    world.add_item(
+        plane()
+            .with_material(
+                Phong::default()
+                    .with_pattern(checkers_pattern(color(0.9, 0.9, 0.9), color(0.1, 0.1, 0.1))),
+            )
+            .with_transform(translation(0, -1, 0)),
+    );
+    world.add_item(
+        sphere()
+            .with_material(Phong::default().with_rgb(1.0, 0.2, 0.2))
+            .with_transform(translation(-2, 0, 4)),
+    );
+    world.finalize_scene();
+
+    let mut camera = Camera::new(48, 24, PI / 3.0).with_view_transform(
+        point(0, 1, -5),
+        point(0, 0, 0),
+        vector(0, 1, 0),
+    );
+    camera.set_aperture_size(0.5);
+    camera.set_focal_distance(5.0);
+    camera.set_min_samples(4);
+    camera.set_allowed_standard_error(0.05);
+    (world, camera)
+}
+
+fn photon_map_scene() -> (World, Camera) {
+    let mut world = World::empty();
+    world.add_light(DiscLight::new(
+        point(0, 4.9, 0),
+        vector(0, -1, 0),
+        0.5,
+        color(1, 1, 1) * 200,
+    ));
+
+    let white = Phong::new(color(1, 1, 1), 0.0, 0.5, 0.0, 100.0, 0.0, 0.0, 1.0);
+    let red = Phong::new(color(1, 0.3, 0.3), 0.0, 0.5, 0.0, 100.0, 0.0, 0.0, 1.0);
+    world.add_item(plane().with_material(white.clone()));
+    world.add_item(
+        plane()
+            .with_material(white.clone())
+            .with_transform(translation(0, 5, 0)),
+    );
+    world.add_item(
+        plane()
+            .with_material(white)
+            .with_transform(translation(0, 0, 3) * rotation_x(PI / 2.0)),
+    );
+    world.add_item(
+        plane()
+            .with_material(red)
+            .with_transform(translation(-3, 0, 0) * rotation_z(PI / 2.0)),
+    );
+    world.add_item(
+        sphere()
+            .with_material(
+                Phong::default()
+                    .with_diffuse(0.0)
+                    .with_specular(0.0)
+                    .with_transparency(0.9)
+                    .with_refractive_index(1.5),
+            )
+            .with_transform(translation(0.5, 1, 0)),
+    );
+    world.finalize_scene();
+    let mut integrator = PhotonMapping::new();
+    integrator.compute_photon_map(&world, 2000, 20, 1.0);
+    world.set_integrator(integrator);
+
+    let mut camera = Camera::new(32, 32, PI / 2.0).with_view_transform(
+        point(0, 2.5, -4),
+        point(0, 2, 0),
+        vector(0, 1, 0),
+    );
+    camera.set_min_samples(2);
+    camera.set_allowed_standard_error(0.1);
+    (world, camera)
+}
+
+fn render((world, camera): (World, Camera)) -> Canvas {
+    camera.render(&world)
+}
+
+#[test]
+fn default_world() {
+    let camera = Camera::new(32, 16, PI / 3.0).with_view_transform(
+        point(0, 0, -5),
+        point(0, 0, 0),
+        vector(0, 1, 0),
+    );
+    check_golden("default_world", &camera.render(&World::default()));
+}
+
+#[test]
+fn soft_shadow() {
+    check_golden("soft_shadow", &render(soft_shadow_scene()));
+}
+
+#[test]
+fn depth_of_field() {
+    check_golden("depth_of_field", &render(depth_of_field_scene()));
+}
+
+#[test]
+fn photon_map() {
+    check_golden("photon_map", &render(photon_map_scene()));
+}
+
+/// Rendering the same scene twice gives exactly the same image, and changing the seed changes
+/// the noise
+#[test]
+fn deterministic_rendering() {
+    let (world, mut camera) = soft_shadow_scene();
+    let a = camera.render(&world);
+    let b = camera.render(&world);
+    assert_eq!(compare(&a, &b, 0.0).max_error, 0.0);
+
+    camera.set_seed(1);
+    let c = camera.render(&world);
+    assert!(compare(&a, &c, 0.0).max_error > 0.0);
+}
+
+/// The photon map does not depend on how the photons are distributed over threads
+#[test]
+fn deterministic_photon_map() {
+    let (world, camera) = photon_map_scene();
+    let (mut other_world, _) = photon_map_scene();
+    let a = camera.render(&world);
+    let b = camera.render(&other_world);
+    assert_eq!(compare(&a, &b, 0.0).max_error, 0.0);
+
+    let mut integrator = PhotonMapping::new();
+    integrator.set_seed(1);
+    integrator.compute_photon_map(&other_world, 2000, 20, 1.0);
+    other_world.set_integrator(integrator);
+    let c = camera.render(&other_world);
+    assert!(compare(&a, &c, 0.0).max_error > 0.0);
+}
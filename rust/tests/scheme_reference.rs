@@ -0,0 +1,130 @@
+//! Compares renderings to the reference images in `scheme/`, which the Scheme implementation
+//! rendered from its chapter scenes. Like the Scheme renderer, every pixel is rendered with a
+//! single sample through its center, so the images are deterministic.
+
+use raytracing::camera::Camera;
+use raytracing::canvas::Canvas;
+use raytracing::color::color;
+use raytracing::image_diff::compare;
+use raytracing::lights::{AmbientLight, PointLight};
+use raytracing::scene::load_scene;
+use raytracing::tuple::point;
+use raytracing::world::World;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+#[allow(dead_code)]
+#[path = "../examples/chapter-07.rs"]
+mod chapter_07;
+#[allow(dead_code)]
+#[path = "../examples/chapter-08.rs"]
+mod chapter_08;
+#[allow(dead_code)]
+#[path = "../examples/chapter-09.rs"]
+mod chapter_09;
+
+/// The maximum RMSE and fraction of mismatching pixels of images that only differ by rounding
+const MAX_RMSE: f64 = 0.01;
+const MAX_MISMATCH_RATIO: f64 = 0.001;
+
+fn scheme_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../scheme")
+}
+
+fn check(chapter: &str, world: &World, camera: &Camera, max_rmse: f64, max_mismatch_ratio: f64) {
+    let path = scheme_dir().join(format!("chapter{}.ppm", chapter));
+    let reference = Canvas::read_ppm(&mut File::open(path).unwrap()).unwrap();
+
+    let mut camera = camera.resized(
+        reference.width(),
+        reference.height(),
+        camera.field_of_view(),
+    );
+    camera.set_min_samples(1);
+    camera.set_allowed_standard_error(f64::INFINITY);
+    let image = camera.render(world);
+
+    let diff = compare(&image, &reference, 2.0 / 255.0);
+    if diff.rmse > max_rmse || diff.mismatch_ratio() > max_mismatch_ratio {
+        let mask = std::env::temp_dir().join(format!("chapter{}-mismatch.png", chapter));
+        diff.mask
+            .write_png(&mut File::create(&mask).unwrap())
+            .unwrap();
+        panic!(
+            "chapter {}: rmse {:.4}, max error {:.3}, {:.2}% of the pixels mismatch (see {})",
+            chapter,
+            diff.rmse,
+            diff.max_error,
+            diff.mismatch_ratio() * 100.0,
+            mask.display()
+        );
+    }
+}
+
+/// The scene of a chapter example as the Scheme implementation renders it. The examples differ
+/// in a few details: the spheres of chapter 7 cast shadows, which the book only adds in
+/// chapter 8, and chapter 9 has a sky and a brighter ambient light.
+fn scheme_version(chapter: &str, world: &World) -> World {
+    let mut objects = world.objects().to_vec();
+    match chapter {
+        "07" => {
+            for object in &mut objects {
+                object.set_cast_shadow(false);
+            }
+        }
+        "09" => {
+            // the sky, added right after the floor
+            objects.remove(1);
+        }
+        _ => {}
+    }
+    World::new(
+        vec![
+            Box::new(AmbientLight::new(color(0.1, 0.1, 0.1))),
+            Box::new(PointLight::new(point(-10, 10, -10), color(1, 1, 1))),
+        ],
+        objects,
+    )
+}
+
+/// The examples of these chapters render the scenes of the Scheme implementation, at a higher
+/// resolution and with the differences undone by `scheme_version`.
+#[test]
+fn chapter_examples() {
+    let examples = [
+        ("07", chapter_07::scene()),
+        ("08", chapter_08::scene()),
+        ("09", chapter_09::scene()),
+    ];
+    for (chapter, (world, camera)) in &examples {
+        let world = scheme_version(chapter, world);
+        check(chapter, &world, camera, MAX_RMSE, MAX_MISMATCH_RATIO);
+    }
+}
+
+/// The examples of the other chapters show scenes of their own, so the reference images are
+/// compared to renderings of the Scheme scene files instead.
+#[test]
+fn scheme_scenes() {
+    let chapters = [
+        ("10", MAX_RMSE, MAX_MISMATCH_RATIO),
+        // The Scheme renderer computes the refractive indices from the hit alone, as if every
+        // ray entered the object it hits. Rays leaving the glass spheres refract differently.
+        ("11a", 0.15, 0.08),
+        ("11b", MAX_RMSE, MAX_MISMATCH_RATIO),
+        // the same for the transparent cone
+        ("13", MAX_RMSE, 0.01),
+        ("16", MAX_RMSE, MAX_MISMATCH_RATIO),
+    ];
+    for &(chapter, max_rmse, max_mismatch_ratio) in &chapters {
+        let path = scheme_dir().join(format!("chapter{}.scm", chapter));
+        let scene = load_scene(&path).unwrap();
+        check(
+            chapter,
+            &scene.world,
+            &scene.camera,
+            max_rmse,
+            max_mismatch_ratio,
+        );
+    }
+}